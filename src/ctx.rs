@@ -27,6 +27,8 @@
 
 use std::borrow::Cow;
 use std::error::Error;
+use std::ffi::OsStr;
+use std::ffi::OsString;
 use std::fmt;
 use std::io;
 use std::path::Path;
@@ -37,33 +39,47 @@ use crate::BaseFsCanonicalize;
 use crate::BaseFsChown;
 use crate::BaseFsCloneFile;
 use crate::BaseFsCopy;
+use crate::BaseFsCopyWithOptions;
 use crate::BaseFsCreateDir;
 use crate::BaseFsCreateJunction;
+use crate::BaseFsDiskSpace;
+use crate::BaseFsGetXattr;
 use crate::BaseFsHardLink;
+use crate::BaseFsListXattr;
 use crate::BaseFsMetadata;
 use crate::BaseFsOpen;
+use crate::BaseFsOpenDir;
 use crate::BaseFsRead;
 use crate::BaseFsReadDir;
 use crate::BaseFsReadLink;
+use crate::BaseFsReadShared;
 use crate::BaseFsRemoveDir;
 use crate::BaseFsRemoveDirAll;
 use crate::BaseFsRemoveFile;
+use crate::BaseFsRemoveXattr;
 use crate::BaseFsRename;
+use crate::BaseFsRenameWithOptions;
 use crate::BaseFsSetFileTimes;
 use crate::BaseFsSetPermissions;
 use crate::BaseFsSetSymlinkFileTimes;
+use crate::BaseFsSetXattr;
+use crate::BaseFsStatVfs;
 use crate::BaseFsSymlinkChown;
 use crate::BaseFsSymlinkDir;
 use crate::BaseFsSymlinkFile;
 use crate::BaseFsWrite;
+use crate::CopyOptions;
 use crate::CreateDirOptions;
+use crate::DiskSpace;
 use crate::FileType;
 use crate::FsFile;
 use crate::FsFileAsRaw;
 use crate::FsFileIsTerminal;
 use crate::FsFileLock;
 use crate::FsFileLockMode;
+use crate::FsFileLockRange;
 use crate::FsFileMetadata;
+use crate::FsFileMmap;
 use crate::FsFileSetLen;
 use crate::FsFileSetPermissions;
 use crate::FsFileSetTimes;
@@ -73,7 +89,10 @@ use crate::FsFileTimes;
 use crate::FsMetadata;
 use crate::FsMetadataValue;
 use crate::FsRead;
+use crate::FsSharedBytes;
 use crate::OpenOptions;
+use crate::RenameOptions;
+use crate::StatVfs;
 
 use crate::boxed::BoxedFsFile;
 use crate::boxed::BoxedFsMetadataValue;
@@ -260,6 +279,58 @@ impl<F: FsFileLock> FsFileLock for FsFileWithPathsInErrors<F> {
   }
 }
 
+impl<F: FsFileMmap> FsFileMmap for FsFileWithPathsInErrors<F> {
+  type Mmap = F::Mmap;
+  type MmapMut = F::MmapMut;
+
+  unsafe fn fs_file_mmap(&self) -> io::Result<Self::Mmap> {
+    self
+      .file
+      .fs_file_mmap()
+      .map_err(|e| self.wrap_err("mmap", e))
+  }
+
+  unsafe fn fs_file_mmap_mut(&mut self) -> io::Result<Self::MmapMut> {
+    self
+      .file
+      .fs_file_mmap_mut()
+      .map_err(|e| self.wrap_err("mmap mut", e))
+  }
+}
+
+impl<F: FsFileLockRange> FsFileLockRange for FsFileWithPathsInErrors<F> {
+  fn fs_file_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    self
+      .file
+      .fs_file_lock_range(mode, offset, len)
+      .map_err(|e| self.wrap_err("lock range", e))
+  }
+
+  fn fs_file_try_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    self
+      .file
+      .fs_file_try_lock_range(mode, offset, len)
+      .map_err(|e| self.wrap_err("try lock range", e))
+  }
+
+  fn fs_file_unlock_range(&mut self, offset: u64, len: u64) -> io::Result<()> {
+    self
+      .file
+      .fs_file_unlock_range(offset, len)
+      .map_err(|e| self.wrap_err("unlock range", e))
+  }
+}
+
 impl<F: FsFileMetadata> FsFileMetadata for FsFileWithPathsInErrors<F> {
   fn fs_file_metadata(&self) -> io::Result<BoxedFsMetadataValue> {
     self
@@ -446,6 +517,24 @@ impl<T: BaseFsCopy> SysWithPathsInErrors<'_, T> {
   }
 }
 
+// == FsCopyWithOptions ==
+
+impl<T: BaseFsCopyWithOptions> SysWithPathsInErrors<'_, T> {
+  pub fn fs_copy_with_options(
+    &self,
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    let from = from.as_ref();
+    let to = to.as_ref();
+    self
+      .0
+      .base_fs_copy_with_options(from, to, options)
+      .map_err(|e| err_with_two_paths("copy", from, to, e))
+  }
+}
+
 // == FsCreateDir ==
 
 impl<T: BaseFsCreateDir> SysWithPathsInErrors<'_, T> {
@@ -510,6 +599,30 @@ impl<T: BaseFsCreateJunction> SysWithPathsInErrors<'_, T> {
   }
 }
 
+// == FsDiskSpace ==
+
+impl<T: BaseFsDiskSpace> SysWithPathsInErrors<'_, T> {
+  pub fn fs_disk_space(&self, path: impl AsRef<Path>) -> io::Result<DiskSpace> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_disk_space(path)
+      .map_err(|e| err_with_path("get disk space", path, e))
+  }
+}
+
+// == FsStatVfs ==
+
+impl<T: BaseFsStatVfs> SysWithPathsInErrors<'_, T> {
+  pub fn fs_statvfs(&self, path: impl AsRef<Path>) -> io::Result<StatVfs> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_statvfs(path)
+      .map_err(|e| err_with_path("statvfs", path, e))
+  }
+}
+
 // == FsMetadata ==
 
 impl<T: BaseFsMetadata> SysWithPathsInErrors<'_, T> {
@@ -586,6 +699,21 @@ impl<T: BaseFsOpen> SysWithPathsInErrors<'_, T> {
   }
 }
 
+// == FsOpenDir ==
+
+impl<T: BaseFsOpenDir> SysWithPathsInErrors<'_, T> {
+  pub fn fs_open_dir(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<T::DirHandle> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_open_dir(path)
+      .map_err(|e| err_with_path("open dir", path, e))
+  }
+}
+
 // == FsOpenBoxed ==
 
 impl<T: FsOpenBoxed + ?Sized> SysWithPathsInErrors<'_, T> {
@@ -640,6 +768,21 @@ impl<T: BaseFsRead> SysWithPathsInErrors<'_, T> {
   }
 }
 
+// == FsReadShared ==
+
+impl<T: BaseFsReadShared> SysWithPathsInErrors<'_, T> {
+  pub fn fs_read_shared(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<FsSharedBytes> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_read_shared(path)
+      .map_err(|e| err_with_path("read", path, e))
+  }
+}
+
 // == FsReadDir ==
 
 impl<T: BaseFsReadDir> SysWithPathsInErrors<'_, T> {
@@ -720,6 +863,24 @@ impl<T: BaseFsRename> SysWithPathsInErrors<'_, T> {
   }
 }
 
+// == FsRenameWithOptions ==
+
+impl<T: BaseFsRenameWithOptions> SysWithPathsInErrors<'_, T> {
+  pub fn fs_rename_with_options(
+    &self,
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    options: &RenameOptions,
+  ) -> io::Result<()> {
+    let from = from.as_ref();
+    let to = to.as_ref();
+    self
+      .0
+      .base_fs_rename_with_options(from, to, options)
+      .map_err(|e| err_with_two_paths("rename", from, to, e))
+  }
+}
+
 // == FsSetFileTimes ==
 
 impl<T: BaseFsSetFileTimes> SysWithPathsInErrors<'_, T> {
@@ -820,6 +981,118 @@ impl<T: BaseFsWrite> SysWithPathsInErrors<'_, T> {
   }
 }
 
+// == FsGetXattr ==
+
+impl<T: BaseFsGetXattr> SysWithPathsInErrors<'_, T> {
+  pub fn fs_get_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<Option<Vec<u8>>> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_get_xattr(path, name.as_ref())
+      .map_err(|e| err_with_path("get xattr", path, e))
+  }
+
+  pub fn fs_symlink_get_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<Option<Vec<u8>>> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_symlink_get_xattr(path, name.as_ref())
+      .map_err(|e| err_with_path("get xattr", path, e))
+  }
+}
+
+// == FsListXattr ==
+
+impl<T: BaseFsListXattr> SysWithPathsInErrors<'_, T> {
+  pub fn fs_list_xattr(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Vec<OsString>> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_list_xattr(path)
+      .map_err(|e| err_with_path("list xattr", path, e))
+  }
+
+  pub fn fs_symlink_list_xattr(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Vec<OsString>> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_symlink_list_xattr(path)
+      .map_err(|e| err_with_path("list xattr", path, e))
+  }
+}
+
+// == FsRemoveXattr ==
+
+impl<T: BaseFsRemoveXattr> SysWithPathsInErrors<'_, T> {
+  pub fn fs_remove_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<()> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_remove_xattr(path, name.as_ref())
+      .map_err(|e| err_with_path("remove xattr", path, e))
+  }
+
+  pub fn fs_symlink_remove_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<()> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_symlink_remove_xattr(path, name.as_ref())
+      .map_err(|e| err_with_path("remove xattr", path, e))
+  }
+}
+
+// == FsSetXattr ==
+
+impl<T: BaseFsSetXattr> SysWithPathsInErrors<'_, T> {
+  pub fn fs_set_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+    value: impl AsRef<[u8]>,
+  ) -> io::Result<()> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_set_xattr(path, name.as_ref(), value.as_ref())
+      .map_err(|e| err_with_path("set xattr", path, e))
+  }
+
+  pub fn fs_symlink_set_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+    value: impl AsRef<[u8]>,
+  ) -> io::Result<()> {
+    let path = path.as_ref();
+    self
+      .0
+      .base_fs_symlink_set_xattr(path, name.as_ref(), value.as_ref())
+      .map_err(|e| err_with_path("set xattr", path, e))
+  }
+}
+
 #[cfg(all(test, feature = "memory"))]
 mod tests {
   use super::*;
@@ -938,6 +1211,26 @@ mod tests {
     assert_eq!(&*data, b"hello");
   }
 
+  #[test]
+  fn test_fs_xattr() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_write("/file.txt", b"data").unwrap();
+    let sys = sys.with_paths_in_errors();
+    sys.fs_set_xattr("/file.txt", "user.key", b"value").unwrap();
+    assert_eq!(
+      sys.fs_get_xattr("/file.txt", "user.key").unwrap(),
+      Some(b"value".to_vec())
+    );
+    let err = sys.fs_list_xattr("/missing.txt").unwrap_err();
+    let inner = err.get_ref().unwrap();
+    let op_err = inner.downcast_ref::<OperationError>().unwrap();
+    assert_eq!(op_err.operation(), "list xattr");
+    assert_eq!(
+      op_err.kind(),
+      &OperationErrorKind::WithPath("/missing.txt".to_string())
+    );
+  }
+
   #[test]
   fn test_fs_write_error() {
     let sys = InMemorySys::default();
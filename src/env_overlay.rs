@@ -0,0 +1,815 @@
+//! Environment overlay wrapper.
+//!
+//! This module provides [`EnvOverlaySys`], a wrapper that layers private
+//! environment variable overrides and removals on top of another system.
+//! Setting or removing a variable only affects the overlay and never the
+//! wrapped system.
+//!
+//! # Example
+//!
+//! ```
+//! use sys_traits::EnvOverlaySys;
+//! use sys_traits::EnvSetVar;
+//! use sys_traits::EnvVar;
+//! # #[cfg(feature = "real")]
+//! use sys_traits::impls::RealSys;
+//!
+//! # #[cfg(feature = "real")]
+//! # fn example() {
+//! let sys = EnvOverlaySys::new(RealSys);
+//! sys.env_set_var("HOME", "/tmp/fake_home");
+//! assert_eq!(sys.env_var("HOME").unwrap(), "/tmp/fake_home");
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use crate::impls::is_windows;
+use crate::BaseEnvRemoveVar;
+use crate::BaseEnvSetCurrentDir;
+use crate::BaseEnvSetVar;
+use crate::BaseEnvVar;
+use crate::BaseFsCanonicalize;
+use crate::BaseFsChown;
+use crate::BaseFsCloneFile;
+use crate::BaseFsCopy;
+use crate::BaseFsCopyWithOptions;
+use crate::BaseFsCreateDir;
+use crate::BaseFsCreateJunction;
+use crate::BaseFsDiskSpace;
+use crate::BaseFsGetXattr;
+use crate::BaseFsHardLink;
+use crate::BaseFsListXattr;
+use crate::BaseFsMetadata;
+use crate::BaseFsOpen;
+use crate::BaseFsOpenDir;
+use crate::BaseFsRead;
+use crate::BaseFsReadDir;
+use crate::BaseFsReadLink;
+use crate::BaseFsReadShared;
+use crate::BaseFsRemoveDir;
+use crate::BaseFsRemoveDirAll;
+use crate::BaseFsRemoveFile;
+use crate::BaseFsRemoveXattr;
+use crate::BaseFsRename;
+use crate::BaseFsRenameWithOptions;
+use crate::BaseFsSetFileTimes;
+use crate::BaseFsSetPermissions;
+use crate::BaseFsSetSymlinkFileTimes;
+use crate::BaseFsSetXattr;
+use crate::BaseFsStatVfs;
+use crate::BaseFsSymlinkChown;
+use crate::BaseFsSymlinkDir;
+use crate::BaseFsSymlinkFile;
+use crate::BaseFsWrite;
+use crate::CopyOptions;
+use crate::CreateDirOptions;
+use crate::DiskSpace;
+use crate::EnvCacheDir;
+use crate::EnvCurrentDir;
+use crate::EnvHomeDir;
+use crate::EnvProgramsDir;
+use crate::EnvSetUmask;
+use crate::EnvTempDir;
+use crate::EnvUmask;
+use crate::EnvVar;
+use crate::EnvVars;
+use crate::FsSharedBytes;
+use crate::OpenOptions;
+use crate::ProcessExit;
+use crate::RenameOptions;
+use crate::StatVfs;
+use crate::SystemRandom;
+use crate::SystemTimeNow;
+use crate::ThreadSleep;
+
+/// A wrapper that layers environment variable overrides on top of
+/// another system.
+///
+/// Clones share the same overlay.
+#[derive(Debug, Clone)]
+pub struct EnvOverlaySys<T> {
+  sys: T,
+  // keyed by the normalized key
+  overrides: Arc<RwLock<HashMap<OsString, EnvOverride>>>,
+}
+
+#[derive(Debug)]
+struct EnvOverride {
+  // the key as it was given
+  key: OsString,
+  // `None` for variables removed in the overlay
+  value: Option<OsString>,
+}
+
+impl<T> EnvOverlaySys<T> {
+  pub fn new(sys: T) -> Self {
+    Self {
+      sys,
+      overrides: Default::default(),
+    }
+  }
+
+  /// Returns a reference to the inner system.
+  pub fn inner(&self) -> &T {
+    &self.sys
+  }
+
+  /// Consumes the wrapper and returns the inner system.
+  pub fn into_inner(self) -> T {
+    self.sys
+  }
+
+  /// Discards all overrides and removals so the environment of the
+  /// inner system shows through again.
+  pub fn clear_overlay(&self) {
+    self.overrides.write().unwrap().clear();
+  }
+
+  fn override_for(&self, key: &OsStr) -> Option<Option<OsString>> {
+    self
+      .overrides
+      .read()
+      .unwrap()
+      .get(&*normalize_key(key))
+      .map(|o| o.value.clone())
+  }
+
+  fn is_overridden(&self, keys: &[&str]) -> bool {
+    let overrides = self.overrides.read().unwrap();
+    keys
+      .iter()
+      .any(|key| overrides.contains_key(&*normalize_key(OsStr::new(key))))
+  }
+
+  fn set_override(&self, key: &OsStr, value: Option<OsString>) {
+    self.overrides.write().unwrap().insert(
+      normalize_key(key).into_owned(),
+      EnvOverride {
+        key: key.to_os_string(),
+        value,
+      },
+    );
+  }
+}
+
+/// Gets the key overrides are compared by, since environment variables
+/// are case insensitive on Windows.
+fn normalize_key(key: &OsStr) -> Cow<'_, OsStr> {
+  if is_windows() {
+    Cow::Owned(OsString::from(key.to_string_lossy().to_uppercase()))
+  } else {
+    Cow::Borrowed(key)
+  }
+}
+
+// ==== Environment ====
+
+impl<T: EnvCurrentDir> EnvCurrentDir for EnvOverlaySys<T> {
+  #[inline]
+  fn env_current_dir(&self) -> io::Result<PathBuf> {
+    self.sys.env_current_dir()
+  }
+}
+
+impl<T: BaseEnvSetCurrentDir> BaseEnvSetCurrentDir for EnvOverlaySys<T> {
+  #[inline]
+  fn base_env_set_current_dir(&self, path: &Path) -> io::Result<()> {
+    self.sys.base_env_set_current_dir(path)
+  }
+}
+
+impl<T: BaseEnvVar> BaseEnvVar for EnvOverlaySys<T> {
+  fn base_env_var_os(&self, key: &OsStr) -> Option<OsString> {
+    match self.override_for(key) {
+      Some(value) => value,
+      None => self.sys.base_env_var_os(key),
+    }
+  }
+}
+
+impl<T: EnvVars> EnvVars for EnvOverlaySys<T> {
+  type EnvVarsOs = std::vec::IntoIter<(OsString, OsString)>;
+
+  fn env_vars_os(&self) -> Self::EnvVarsOs {
+    let overrides = self.overrides.read().unwrap();
+    let mut vars = self
+      .sys
+      .env_vars_os()
+      .filter(|(key, _)| !overrides.contains_key(&*normalize_key(key)))
+      .collect::<Vec<_>>();
+    vars.extend(overrides.values().filter_map(|o| {
+      o.value.as_ref().map(|value| (o.key.clone(), value.clone()))
+    }));
+    vars.into_iter()
+  }
+}
+
+impl<T> BaseEnvRemoveVar for EnvOverlaySys<T> {
+  fn base_env_remove_var(&self, key: &OsStr) {
+    self.set_override(key, None);
+  }
+}
+
+impl<T> BaseEnvSetVar for EnvOverlaySys<T> {
+  fn base_env_set_var(&self, key: &OsStr, value: &OsStr) {
+    self.set_override(key, Some(value.to_os_string()));
+  }
+}
+
+impl<T: EnvUmask> EnvUmask for EnvOverlaySys<T> {
+  #[inline]
+  fn env_umask(&self) -> io::Result<u32> {
+    self.sys.env_umask()
+  }
+}
+
+impl<T: EnvSetUmask> EnvSetUmask for EnvOverlaySys<T> {
+  #[inline]
+  fn env_set_umask(&self, umask: u32) -> io::Result<u32> {
+    self.sys.env_set_umask(umask)
+  }
+}
+
+impl<T: BaseEnvVar + EnvCacheDir + EnvHomeDir> EnvCacheDir
+  for EnvOverlaySys<T>
+{
+  fn env_cache_dir(&self) -> Option<PathBuf> {
+    // the cache directory doesn't come from a variable on Windows
+    #[cfg(any(
+      all(feature = "real", target_os = "windows", feature = "winapi"),
+      all(feature = "real", unix, feature = "libc")
+    ))]
+    if !is_windows() && self.is_overridden(&["XDG_CACHE_HOME", "HOME"]) {
+      return crate::impls::real_cache_dir_with_env(self);
+    }
+    self.sys.env_cache_dir()
+  }
+}
+
+impl<T: BaseEnvVar + EnvHomeDir> EnvHomeDir for EnvOverlaySys<T> {
+  fn env_home_dir(&self) -> Option<PathBuf> {
+    #[cfg(any(
+      all(feature = "real", target_os = "windows", feature = "winapi"),
+      all(feature = "real", unix, feature = "libc")
+    ))]
+    if self.is_overridden(&[if is_windows() { "USERPROFILE" } else { "HOME" }])
+    {
+      return crate::impls::real_home_dir_with_env(self);
+    }
+    self.sys.env_home_dir()
+  }
+}
+
+impl<T: EnvProgramsDir> EnvProgramsDir for EnvOverlaySys<T> {
+  #[inline]
+  fn env_programs_dir(&self) -> Option<PathBuf> {
+    self.sys.env_programs_dir()
+  }
+}
+
+impl<T: BaseEnvVar + EnvTempDir> EnvTempDir for EnvOverlaySys<T> {
+  fn env_temp_dir(&self) -> io::Result<PathBuf> {
+    // same variables and order that std::env::temp_dir() uses
+    let keys: &[&str] = if is_windows() {
+      &["TMP", "TEMP", "USERPROFILE"]
+    } else {
+      &["TMPDIR"]
+    };
+    if !self.is_overridden(keys) {
+      return self.sys.env_temp_dir();
+    }
+    for key in keys {
+      if let Some(path) = self.env_var_path(key) {
+        return Ok(path);
+      }
+    }
+    if is_windows() {
+      self.sys.env_temp_dir()
+    } else {
+      Ok(PathBuf::from("/tmp"))
+    }
+  }
+}
+
+// ==== File System ====
+
+impl<T: BaseFsCanonicalize> BaseFsCanonicalize for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+    self.sys.base_fs_canonicalize(path)
+  }
+}
+
+impl<T: BaseFsChown> BaseFsChown for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    self.sys.base_fs_chown(path, uid, gid)
+  }
+}
+
+impl<T: BaseFsSymlinkChown> BaseFsSymlinkChown for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_symlink_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    self.sys.base_fs_symlink_chown(path, uid, gid)
+  }
+}
+
+impl<T: BaseFsCloneFile> BaseFsCloneFile for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_clone_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+    self.sys.base_fs_clone_file(from, to)
+  }
+}
+
+impl<T: BaseFsCopy> BaseFsCopy for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+    self.sys.base_fs_copy(from, to)
+  }
+}
+
+impl<T: BaseFsCopyWithOptions> BaseFsCopyWithOptions for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    self.sys.base_fs_copy_with_options(from, to, options)
+  }
+}
+
+impl<T: BaseFsCreateDir> BaseFsCreateDir for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_create_dir(
+    &self,
+    path: &Path,
+    options: &CreateDirOptions,
+  ) -> io::Result<()> {
+    self.sys.base_fs_create_dir(path, options)
+  }
+}
+
+impl<T: BaseFsHardLink> BaseFsHardLink for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+    self.sys.base_fs_hard_link(src, dst)
+  }
+}
+
+impl<T: BaseFsCreateJunction> BaseFsCreateJunction for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_create_junction(
+    &self,
+    original: &Path,
+    junction: &Path,
+  ) -> io::Result<()> {
+    self.sys.base_fs_create_junction(original, junction)
+  }
+}
+
+impl<T: BaseFsDiskSpace> BaseFsDiskSpace for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+    self.sys.base_fs_disk_space(path)
+  }
+}
+
+impl<T: BaseFsStatVfs> BaseFsStatVfs for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs> {
+    self.sys.base_fs_statvfs(path)
+  }
+}
+
+impl<T: BaseFsMetadata> BaseFsMetadata for EnvOverlaySys<T> {
+  type Metadata = T::Metadata;
+
+  #[inline]
+  fn base_fs_metadata(&self, path: &Path) -> io::Result<Self::Metadata> {
+    self.sys.base_fs_metadata(path)
+  }
+
+  #[inline]
+  fn base_fs_symlink_metadata(
+    &self,
+    path: &Path,
+  ) -> io::Result<Self::Metadata> {
+    self.sys.base_fs_symlink_metadata(path)
+  }
+
+  #[inline]
+  fn base_fs_exists(&self, path: &Path) -> io::Result<bool> {
+    self.sys.base_fs_exists(path)
+  }
+
+  #[inline]
+  fn base_fs_exists_no_err(&self, path: &Path) -> bool {
+    self.sys.base_fs_exists_no_err(path)
+  }
+}
+
+impl<T: BaseFsOpen> BaseFsOpen for EnvOverlaySys<T> {
+  type File = T::File;
+
+  #[inline]
+  fn base_fs_open(
+    &self,
+    path: &Path,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    self.sys.base_fs_open(path, options)
+  }
+}
+
+impl<T: BaseFsOpenDir> BaseFsOpenDir for EnvOverlaySys<T> {
+  type DirHandle = T::DirHandle;
+
+  #[inline]
+  fn base_fs_open_dir(&self, path: &Path) -> io::Result<Self::DirHandle> {
+    self.sys.base_fs_open_dir(path)
+  }
+}
+
+impl<T: BaseFsRead> BaseFsRead for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_read(&self, path: &Path) -> io::Result<Cow<'static, [u8]>> {
+    self.sys.base_fs_read(path)
+  }
+}
+
+impl<T: BaseFsReadShared> BaseFsReadShared for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_read_shared(&self, path: &Path) -> io::Result<FsSharedBytes> {
+    self.sys.base_fs_read_shared(path)
+  }
+}
+
+impl<T: BaseFsReadDir> BaseFsReadDir for EnvOverlaySys<T> {
+  type ReadDirEntry = T::ReadDirEntry;
+
+  #[inline]
+  fn base_fs_read_dir(
+    &self,
+    path: &Path,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    self.sys.base_fs_read_dir(path)
+  }
+}
+
+impl<T: BaseFsReadLink> BaseFsReadLink for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_read_link(&self, path: &Path) -> io::Result<PathBuf> {
+    self.sys.base_fs_read_link(path)
+  }
+}
+
+impl<T: BaseFsRemoveDir> BaseFsRemoveDir for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_remove_dir(&self, path: &Path) -> io::Result<()> {
+    self.sys.base_fs_remove_dir(path)
+  }
+}
+
+impl<T: BaseFsRemoveDirAll> BaseFsRemoveDirAll for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_remove_dir_all(&self, path: &Path) -> io::Result<()> {
+    self.sys.base_fs_remove_dir_all(path)
+  }
+}
+
+impl<T: BaseFsRemoveFile> BaseFsRemoveFile for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_remove_file(&self, path: &Path) -> io::Result<()> {
+    self.sys.base_fs_remove_file(path)
+  }
+}
+
+impl<T: BaseFsRename> BaseFsRename for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+    self.sys.base_fs_rename(from, to)
+  }
+}
+
+impl<T: BaseFsRenameWithOptions> BaseFsRenameWithOptions for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> io::Result<()> {
+    self.sys.base_fs_rename_with_options(from, to, options)
+  }
+}
+
+impl<T: BaseFsSetFileTimes> BaseFsSetFileTimes for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_set_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    self.sys.base_fs_set_file_times(path, atime, mtime)
+  }
+}
+
+impl<T: BaseFsSetSymlinkFileTimes> BaseFsSetSymlinkFileTimes
+  for EnvOverlaySys<T>
+{
+  #[inline]
+  fn base_fs_set_symlink_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    self.sys.base_fs_set_symlink_file_times(path, atime, mtime)
+  }
+}
+
+impl<T: BaseFsSetPermissions> BaseFsSetPermissions for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+    self.sys.base_fs_set_permissions(path, mode)
+  }
+}
+
+impl<T: BaseFsSymlinkDir> BaseFsSymlinkDir for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_symlink_dir(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.sys.base_fs_symlink_dir(original, link)
+  }
+}
+
+impl<T: BaseFsSymlinkFile> BaseFsSymlinkFile for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_symlink_file(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.sys.base_fs_symlink_file(original, link)
+  }
+}
+
+impl<T: BaseFsWrite> BaseFsWrite for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+    self.sys.base_fs_write(path, data)
+  }
+}
+
+impl<T: BaseFsGetXattr> BaseFsGetXattr for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.sys.base_fs_get_xattr(path, name)
+  }
+
+  #[inline]
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.sys.base_fs_symlink_get_xattr(path, name)
+  }
+}
+
+impl<T: BaseFsListXattr> BaseFsListXattr for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>> {
+    self.sys.base_fs_list_xattr(path)
+  }
+
+  #[inline]
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    self.sys.base_fs_symlink_list_xattr(path)
+  }
+}
+
+impl<T: BaseFsRemoveXattr> BaseFsRemoveXattr for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+    self.sys.base_fs_remove_xattr(path, name)
+  }
+
+  #[inline]
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()> {
+    self.sys.base_fs_symlink_remove_xattr(path, name)
+  }
+}
+
+impl<T: BaseFsSetXattr> BaseFsSetXattr for EnvOverlaySys<T> {
+  #[inline]
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.sys.base_fs_set_xattr(path, name, value)
+  }
+
+  #[inline]
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.sys.base_fs_symlink_set_xattr(path, name, value)
+  }
+}
+
+// ==== System ====
+
+impl<T: SystemTimeNow> SystemTimeNow for EnvOverlaySys<T> {
+  #[inline]
+  fn sys_time_now(&self) -> SystemTime {
+    self.sys.sys_time_now()
+  }
+}
+
+impl<T: SystemRandom> SystemRandom for EnvOverlaySys<T> {
+  #[inline]
+  fn sys_random(&self, buf: &mut [u8]) -> io::Result<()> {
+    self.sys.sys_random(buf)
+  }
+}
+
+impl<T: ProcessExit> ProcessExit for EnvOverlaySys<T> {
+  #[inline]
+  fn process_exit(&self, code: i32) -> ! {
+    self.sys.process_exit(code)
+  }
+}
+
+impl<T: ThreadSleep> ThreadSleep for EnvOverlaySys<T> {
+  #[inline]
+  fn thread_sleep(&self, duration: std::time::Duration) {
+    self.sys.thread_sleep(duration)
+  }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+  use super::*;
+  use crate::impls::InMemorySys;
+  use crate::EnvRemoveVar;
+  use crate::EnvSetVar;
+
+  #[test]
+  fn test_set_var_only_affects_overlay() {
+    let inner = InMemorySys::default();
+    inner.env_set_var("KEEP", "inner");
+    inner.env_set_var("CHANGE", "inner");
+    let sys = EnvOverlaySys::new(inner.clone());
+    sys.env_set_var("CHANGE", "overlay");
+    sys.env_set_var("NEW", "overlay");
+
+    assert_eq!(sys.env_var("KEEP").unwrap(), "inner");
+    assert_eq!(sys.env_var("CHANGE").unwrap(), "overlay");
+    assert_eq!(sys.env_var("NEW").unwrap(), "overlay");
+    assert_eq!(inner.env_var("CHANGE").unwrap(), "inner");
+    assert!(inner.env_var("NEW").is_err());
+  }
+
+  #[test]
+  fn test_remove_var_only_affects_overlay() {
+    let inner = InMemorySys::default();
+    inner.env_set_var("VALUE", "inner");
+    let sys = EnvOverlaySys::new(inner.clone());
+    sys.env_remove_var("VALUE");
+    assert!(sys.env_var_os("VALUE").is_none());
+    assert_eq!(inner.env_var("VALUE").unwrap(), "inner");
+
+    sys.clear_overlay();
+    assert_eq!(sys.env_var("VALUE").unwrap(), "inner");
+  }
+
+  #[test]
+  fn test_env_vars() {
+    let inner = InMemorySys::default();
+    inner.env_set_var("A", "1");
+    inner.env_set_var("B", "2");
+    inner.env_set_var("C", "3");
+    let sys = EnvOverlaySys::new(inner);
+    sys.env_set_var("B", "overlay");
+    sys.env_remove_var("C");
+    sys.env_set_var("D", "4");
+
+    let mut vars = sys.env_vars().collect::<Vec<_>>();
+    vars.sort();
+    assert_eq!(
+      vars,
+      vec![
+        ("A".to_string(), "1".to_string()),
+        ("B".to_string(), "overlay".to_string()),
+        ("D".to_string(), "4".to_string()),
+      ]
+    );
+  }
+
+  #[cfg(windows)]
+  #[test]
+  fn test_keys_case_insensitive() {
+    let inner = InMemorySys::default();
+    inner.env_set_var("PATH", "inner");
+    let sys = EnvOverlaySys::new(inner);
+    sys.env_set_var("Path", "overlay");
+    assert_eq!(sys.env_var("PATH").unwrap(), "overlay");
+    // the key keeps the case it was set with
+    assert_eq!(
+      sys.env_vars().collect::<Vec<_>>(),
+      vec![("Path".to_string(), "overlay".to_string())]
+    );
+  }
+
+  #[test]
+  fn test_clones_share_overlay() {
+    let sys = EnvOverlaySys::new(InMemorySys::default());
+    let clone = sys.clone();
+    clone.env_set_var("VALUE", "1");
+    assert_eq!(sys.env_var("VALUE").unwrap(), "1");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_temp_dir() {
+    let sys = EnvOverlaySys::new(InMemorySys::new_with_cwd("/"));
+    assert_eq!(sys.env_temp_dir().unwrap(), PathBuf::from("/tmp"));
+    sys.env_set_var("TMPDIR", "/custom/tmp");
+    assert_eq!(sys.env_temp_dir().unwrap(), PathBuf::from("/custom/tmp"));
+    sys.env_remove_var("TMPDIR");
+    assert_eq!(sys.env_temp_dir().unwrap(), PathBuf::from("/tmp"));
+  }
+
+  #[cfg(all(unix, feature = "real", feature = "libc"))]
+  #[test]
+  fn test_home_and_cache_dir() {
+    use crate::impls::RealSys;
+
+    let sys = EnvOverlaySys::new(RealSys);
+    assert_eq!(sys.env_home_dir(), RealSys.env_home_dir());
+    assert_eq!(sys.env_cache_dir(), RealSys.env_cache_dir());
+    sys.env_set_var("HOME", "/home/overlay");
+    sys.env_remove_var("XDG_CACHE_HOME");
+    assert_eq!(sys.env_home_dir().unwrap(), PathBuf::from("/home/overlay"));
+    if cfg!(target_os = "macos") {
+      assert_eq!(
+        sys.env_cache_dir().unwrap(),
+        PathBuf::from("/home/overlay/Library/Caches")
+      );
+    } else {
+      assert_eq!(
+        sys.env_cache_dir().unwrap(),
+        PathBuf::from("/home/overlay/.cache")
+      );
+      sys.env_set_var("XDG_CACHE_HOME", "/cache");
+      assert_eq!(sys.env_cache_dir().unwrap(), PathBuf::from("/cache"));
+    }
+    // like the real env, removing it falls back to the passwd entry
+    sys.env_remove_var("HOME");
+    let home_dir = sys.env_home_dir().unwrap();
+    assert_ne!(home_dir, PathBuf::from("/home/overlay"));
+  }
+}
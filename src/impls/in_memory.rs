@@ -1,15 +1,24 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::io::Error;
 use std::io::ErrorKind;
 use std::io::Result;
 use std::path::Component;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Weak;
+use std::time::Duration;
 use std::time::SystemTime;
 
+use parking_lot::Condvar;
+use parking_lot::Mutex;
 use parking_lot::RwLock;
 
+use super::windows_path;
+use super::windows_path::WindowsPrefix;
 use crate::*;
 
 #[derive(Debug, Clone)]
@@ -17,28 +26,525 @@ pub struct InMemoryFile {
   sys: InMemorySys,
   path: PathBuf,
   inner: Arc<RwLock<FileInner>>,
-  pos: usize,
+  pos: u64,
+  lock: Arc<OpenFileLock>,
+  readable: bool,
+  writable: bool,
 }
 
 impl FsFile for InMemoryFile {}
 
+impl InMemoryFile {
+  /// Replaces the file's data, failing when the growth would exceed a
+  /// quota.
+  fn set_data(&self, data: FileData, time: SystemTime) -> Result<()> {
+    // keeps the quotas from being recomputed until the file is charged
+    let _sys = self.sys.0.read();
+    let mut inner = self.inner.write();
+    inner.charge_stored(data.stored_len())?;
+    inner.data = data;
+    inner.touch_modified(time);
+    Ok(())
+  }
+}
+
 #[derive(Debug)]
 struct FileInner {
   accessed: SystemTime,
   created: SystemTime,
   changed: SystemTime,
   modified: SystemTime,
-  data: Vec<u8>,
+  /// Shared with clones until either is written to.
+  data: FileData,
   mode: u32,
+  xattrs: BTreeMap<OsString, Vec<u8>>,
+  lock: Arc<FileLock>,
+  /// The quotas the file's data counts against, which is updated when
+  /// it moves so open handles charge the right ones.
+  quotas: Vec<Arc<QuotaUsage>>,
+}
+
+/// Granularity that runs of file data are allocated in when reporting
+/// the blocks used by a file.
+const ALLOCATION_SIZE: u64 = 4096;
+
+/// Granularity that shared runs of file data are copied in when they're
+/// written to, so the first write after sharing doesn't copy the whole
+/// file.
+const COPY_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Contents of a file, stored sparsely.
+///
+/// The data is kept as non-overlapping runs of bytes keyed by their
+/// offset. The gaps between runs are holes that read as zeros and don't
+/// use any memory, so extending a file or writing far past its end only
+/// allocates what's written. Runs are views of reference counted
+/// buffers, so cloning the data is cheap and writing to shared data only
+/// copies the chunks that are written to.
+#[derive(Debug, Clone, Default)]
+struct FileData {
+  len: u64,
+  runs: BTreeMap<u64, Run>,
+}
+
+/// A run of file data, which is a view of a buffer that may be shared
+/// with other runs, clones of the data, and readers.
+#[derive(Debug, Clone)]
+struct Run {
+  buf: Arc<Vec<u8>>,
+  start: usize,
+  len: usize,
+}
+
+impl Run {
+  fn new(buf: Vec<u8>) -> Self {
+    Self::from_shared(Arc::new(buf))
+  }
+
+  fn from_shared(buf: Arc<Vec<u8>>) -> Self {
+    Self {
+      start: 0,
+      len: buf.len(),
+      buf,
+    }
+  }
+
+  fn as_slice(&self) -> &[u8] {
+    &self.buf[self.start..self.start + self.len]
+  }
+
+  /// Gets a view of part of the run.
+  fn slice(&self, range: std::ops::Range<usize>) -> Run {
+    Run {
+      buf: self.buf.clone(),
+      start: self.start + range.start,
+      len: range.len(),
+    }
+  }
+
+  /// Takes the run's buffer so it can be written to, which is only
+  /// possible when nothing else shares it.
+  fn into_owned(self) -> std::result::Result<Vec<u8>, Run> {
+    if self.start != 0 {
+      return Err(self);
+    }
+    match Arc::try_unwrap(self.buf) {
+      Ok(mut buf) => {
+        buf.truncate(self.len);
+        Ok(buf)
+      }
+      Err(buf) => Err(Run { buf, ..self }),
+    }
+  }
+}
+
+impl FileData {
+  fn new(data: Arc<Vec<u8>>) -> Self {
+    let len = data.len() as u64;
+    let mut runs = BTreeMap::new();
+    if len > 0 {
+      runs.insert(0, Run::from_shared(data));
+    }
+    Self { len, runs }
+  }
+
+  fn len(&self) -> u64 {
+    self.len
+  }
+
+  /// Gets the number of bytes stored, which excludes holes.
+  fn stored_len(&self) -> u64 {
+    self.runs.values().map(|run| run.len as u64).sum()
+  }
+
+  /// Gets the number of bytes stored within the range.
+  fn stored_len_in(&self, start: u64, end: u64) -> u64 {
+    // start from the run that might contain the start
+    let first = self
+      .runs
+      .range(..=start)
+      .next_back()
+      .map(|(run_offset, _)| *run_offset)
+      .unwrap_or(start);
+    self
+      .runs
+      .range(first..end)
+      .map(|(run_offset, run)| {
+        let run_end = run_offset + run.len as u64;
+        end.min(run_end).saturating_sub(start.max(*run_offset))
+      })
+      .sum()
+  }
+
+  /// Gets the number of bytes stored after writing `len` bytes at the
+  /// offset, which fills in any holes in that range.
+  fn stored_len_after_write(&self, offset: u64, len: u64) -> u64 {
+    self.stored_len() + len - self.stored_len_in(offset, offset + len)
+  }
+
+  /// Gets the number of bytes stored after changing the length.
+  fn stored_len_after_set_len(&self, len: u64) -> u64 {
+    if len < self.len {
+      self.stored_len_in(0, len)
+    } else {
+      self.stored_len()
+    }
+  }
+
+  /// Gets the number of 512 byte blocks allocated for the data, which
+  /// excludes holes.
+  fn blocks(&self) -> u64 {
+    self
+      .runs
+      .values()
+      .map(|run| (run.len as u64).div_ceil(ALLOCATION_SIZE))
+      .sum::<u64>()
+      * (ALLOCATION_SIZE / 512)
+  }
+
+  /// Gets the data as a single buffer, which is only copied when its
+  /// runs aren't consecutive views of the same buffer.
+  fn to_shared(&self) -> FsSharedBytes {
+    let mut runs = self.runs.iter();
+    if let Some((0, first)) = runs.next() {
+      let mut end = first.len as u64;
+      let is_contiguous = runs.all(|(offset, run)| {
+        let is_next = *offset == end
+          && Arc::ptr_eq(&run.buf, &first.buf)
+          && run.start as u64 == first.start as u64 + offset;
+        end += run.len as u64;
+        is_next
+      });
+      if is_contiguous && end == self.len {
+        let start = first.start;
+        return FsSharedBytes::from_shared(
+          first.buf.clone(),
+          start..start + self.len as usize,
+        );
+      }
+    }
+    FsSharedBytes::from(self.to_vec())
+  }
+
+  fn to_vec(&self) -> Vec<u8> {
+    let mut data = vec![0; self.len as usize];
+    for (offset, run) in &self.runs {
+      data[*offset as usize..][..run.len].copy_from_slice(run.as_slice());
+    }
+    data
+  }
+
+  /// Reads from the offset into the buffer, returning the number of
+  /// bytes read.
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> usize {
+    if offset >= self.len {
+      return 0;
+    }
+    let len = (self.len - offset).min(buf.len() as u64) as usize;
+    let buf = &mut buf[..len];
+    let end = offset + len as u64;
+    buf.fill(0);
+    // start from the run that might contain the offset
+    let first = self
+      .runs
+      .range(..=offset)
+      .next_back()
+      .map(|(run_offset, _)| *run_offset)
+      .unwrap_or(offset);
+    for (run_offset, run) in self.runs.range(first..end) {
+      let run_end = run_offset + run.len as u64;
+      let from = offset.max(*run_offset);
+      let to = end.min(run_end);
+      if from < to {
+        buf[(from - offset) as usize..(to - offset) as usize].copy_from_slice(
+          &run.as_slice()
+            [(from - run_offset) as usize..(to - run_offset) as usize],
+        );
+      }
+    }
+    len
+  }
+
+  fn write_at(&mut self, offset: u64, data: &[u8]) {
+    if data.is_empty() {
+      return;
+    }
+    let end = offset + data.len() as u64;
+    // write into the run that contains or touches the offset
+    let base = self
+      .runs
+      .range(..=offset)
+      .next_back()
+      .filter(|(run_offset, run)| **run_offset + run.len as u64 >= offset)
+      .map(|(run_offset, _)| *run_offset);
+    let (run_offset, mut run) = match base {
+      Some(run_offset) => {
+        let run = self.runs.remove(&run_offset).unwrap();
+        match run.into_owned() {
+          Ok(run) => (run_offset, run),
+          Err(run) => self.copy_written_chunks(run_offset, run, offset, end),
+        }
+      }
+      None => (offset, Vec::new()),
+    };
+    let start = (offset - run_offset) as usize;
+    if run.len() < start + data.len() {
+      run.resize(start + data.len(), 0);
+    }
+    run[start..start + data.len()].copy_from_slice(data);
+    // merge the runs that are overlapped or touched, where large
+    // remainders are kept as views rather than copied
+    let following = self
+      .runs
+      .range(offset..=end)
+      .map(|(run_offset, _)| *run_offset)
+      .collect::<Vec<_>>();
+    for following_offset in following {
+      let following = self.runs.remove(&following_offset).unwrap();
+      let following_end = following_offset + following.len as u64;
+      if following_end > end {
+        let rest =
+          following.slice((end - following_offset) as usize..following.len);
+        if rest.len as u64 <= COPY_CHUNK_SIZE {
+          run.extend_from_slice(rest.as_slice());
+        } else {
+          self.runs.insert(end, rest);
+        }
+      }
+    }
+    self.runs.insert(run_offset, Run::new(run));
+    self.len = self.len.max(end);
+  }
+
+  /// Copies the chunks of a shared run that are written to between
+  /// `offset` and `end`, keeping the rest of it as views of the shared
+  /// buffer, and returns the copy along with its offset.
+  fn copy_written_chunks(
+    &mut self,
+    run_offset: u64,
+    run: Run,
+    offset: u64,
+    end: u64,
+  ) -> (u64, Vec<u8>) {
+    let run_end = run_offset + run.len as u64;
+    let copy_start = run_offset.max(offset - offset % COPY_CHUNK_SIZE);
+    let copy_end = run_end.min(
+      end
+        .div_ceil(COPY_CHUNK_SIZE)
+        .saturating_mul(COPY_CHUNK_SIZE),
+    );
+    let relative = |offset: u64| (offset - run_offset) as usize;
+    if copy_start > run_offset {
+      self
+        .runs
+        .insert(run_offset, run.slice(0..relative(copy_start)));
+    }
+    if copy_end < run_end {
+      self
+        .runs
+        .insert(copy_end, run.slice(relative(copy_end)..run.len));
+    }
+    let copy =
+      run.as_slice()[relative(copy_start)..relative(copy_end)].to_vec();
+    (copy_start, copy)
+  }
+
+  fn set_len(&mut self, len: u64) {
+    if len < self.len {
+      self.runs.split_off(&len);
+      if let Some((run_offset, run)) = self.runs.iter_mut().next_back() {
+        if *run_offset + run.len as u64 > len {
+          // the buffer is truncated once it's written to in place
+          run.len = (len - run_offset) as usize;
+        }
+      }
+    }
+    self.len = len;
+  }
+}
+
+/// Advisory locks held on a file node by its open files.
+#[derive(Debug, Default)]
+struct FileLock {
+  holders: Mutex<FileLockHolders>,
+  released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct FileLockHolders {
+  shared: Vec<u64>,
+  exclusive: Option<u64>,
+  /// Byte range locks, which are independent of the whole file locks.
+  ranges: Vec<RangeLock>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RangeLock {
+  id: u64,
+  start: u64,
+  /// Exclusive end, which is `u64::MAX` when locked to the end of the file.
+  end: u64,
+  exclusive: bool,
+}
+
+impl FileLockHolders {
+  fn try_acquire(&mut self, id: u64, mode: &FsFileLockMode) -> bool {
+    let available = match mode {
+      FsFileLockMode::Shared => self.exclusive.is_none_or(|h| h == id),
+      FsFileLockMode::Exclusive => {
+        self.exclusive.is_none_or(|h| h == id)
+          && self.shared.iter().all(|h| *h == id)
+      }
+    };
+    if available {
+      // converts any lock already held by this file
+      self.release(id);
+      match mode {
+        FsFileLockMode::Shared => self.shared.push(id),
+        FsFileLockMode::Exclusive => self.exclusive = Some(id),
+      }
+    }
+    available
+  }
+
+  fn release(&mut self, id: u64) {
+    self.shared.retain(|h| *h != id);
+    if self.exclusive == Some(id) {
+      self.exclusive = None;
+    }
+  }
+
+  fn try_acquire_range(
+    &mut self,
+    id: u64,
+    mode: &FsFileLockMode,
+    start: u64,
+    end: u64,
+  ) -> bool {
+    let exclusive = matches!(mode, FsFileLockMode::Exclusive);
+    let conflicts = self.ranges.iter().any(|range| {
+      range.id != id
+        && range.start < end
+        && start < range.end
+        && (exclusive || range.exclusive)
+    });
+    if !conflicts {
+      // replaces the part of any range already held by this file
+      self.release_range(id, start, end);
+      self.ranges.push(RangeLock {
+        id,
+        start,
+        end,
+        exclusive,
+      });
+    }
+    !conflicts
+  }
+
+  fn release_range(&mut self, id: u64, start: u64, end: u64) {
+    let mut ranges = Vec::with_capacity(self.ranges.len());
+    for range in self.ranges.drain(..) {
+      if range.id != id || range.end <= start || end <= range.start {
+        ranges.push(range);
+        continue;
+      }
+      // keep the parts outside the released range
+      if range.start < start {
+        ranges.push(RangeLock {
+          end: start,
+          ..range
+        });
+      }
+      if end < range.end {
+        ranges.push(RangeLock {
+          start: end,
+          ..range
+        });
+      }
+    }
+    self.ranges = ranges;
+  }
+}
+
+impl FileLock {
+  fn lock(&self, mut try_acquire: impl FnMut(&mut FileLockHolders) -> bool) {
+    let mut holders = self.holders.lock();
+    while !try_acquire(&mut holders) {
+      self.released.wait(&mut holders);
+    }
+    drop(holders);
+    // converting a held lock may have released part of it
+    self.released.notify_all();
+  }
+
+  fn try_lock(
+    &self,
+    try_acquire: impl FnOnce(&mut FileLockHolders) -> bool,
+  ) -> Result<()> {
+    if try_acquire(&mut self.holders.lock()) {
+      // converting a held lock may have released part of it
+      self.released.notify_all();
+      Ok(())
+    } else {
+      Err(Error::new(
+        ErrorKind::WouldBlock,
+        "File is locked by another open file",
+      ))
+    }
+  }
+
+  fn unlock(&self, release: impl FnOnce(&mut FileLockHolders)) {
+    release(&mut self.holders.lock());
+    self.released.notify_all();
+  }
+}
+
+/// Gets the exclusive end of a locked byte range.
+fn lock_range_end(offset: u64, len: u64) -> u64 {
+  if len == 0 {
+    u64::MAX
+  } else {
+    offset.saturating_add(len)
+  }
 }
 
+/// Identifies an open file when locking. Clones of an `InMemoryFile` share
+/// it like duplicated file descriptors and its locks are released once
+/// they're all dropped.
 #[derive(Debug)]
+struct OpenFileLock {
+  id: u64,
+  lock: Arc<FileLock>,
+}
+
+impl OpenFileLock {
+  fn new(inner: &RwLock<FileInner>) -> Arc<Self> {
+    Arc::new(Self {
+      id: NEXT_OPEN_FILE_ID.fetch_add(1, Ordering::Relaxed),
+      lock: inner.read().lock.clone(),
+    })
+  }
+}
+
+impl Drop for OpenFileLock {
+  fn drop(&mut self) {
+    let id = self.id;
+    self.lock.unlock(|holders| {
+      holders.release(id);
+      holders.release_range(id, 0, u64::MAX);
+    });
+  }
+}
+
+#[derive(Debug, Clone)]
 struct File {
   name: String,
   inner: Arc<RwLock<FileInner>>,
 }
 
-#[derive(Debug)]
+/// An entry in a directory, which is cheap to clone so that lookups can
+/// hand it out without holding the directory's lock.
+#[derive(Debug, Clone)]
 enum DirectoryEntry {
   File(File),
   Directory(Directory),
@@ -54,6 +560,22 @@ impl DirectoryEntry {
     }
   }
 
+  /// Gets if both entries are for the same file, directory, or symlink.
+  fn is_same(&self, other: &DirectoryEntry) -> bool {
+    match (self, other) {
+      (DirectoryEntry::File(a), DirectoryEntry::File(b)) => {
+        Arc::ptr_eq(&a.inner, &b.inner)
+      }
+      (DirectoryEntry::Directory(a), DirectoryEntry::Directory(b)) => {
+        Arc::ptr_eq(&a.entries, &b.entries)
+      }
+      (DirectoryEntry::Symlink(a), DirectoryEntry::Symlink(b)) => {
+        Arc::ptr_eq(&a.inner, &b.inner)
+      }
+      _ => false,
+    }
+  }
+
   fn file_type(&self) -> FileType {
     match self {
       DirectoryEntry::File(_) => FileType::File,
@@ -64,7 +586,15 @@ impl DirectoryEntry {
 
   fn len(&self) -> u64 {
     match self {
-      DirectoryEntry::File(f) => f.inner.read().data.len() as u64,
+      DirectoryEntry::File(f) => f.inner.read().data.len(),
+      DirectoryEntry::Directory(_) => 0,
+      DirectoryEntry::Symlink(_) => 0,
+    }
+  }
+
+  fn blocks(&self) -> u64 {
+    match self {
+      DirectoryEntry::File(f) => f.inner.read().data.blocks(),
       DirectoryEntry::Directory(_) => 0,
       DirectoryEntry::Symlink(_) => 0,
     }
@@ -110,31 +640,41 @@ impl DirectoryEntry {
     }
   }
 
-  fn set_filetimes(&self, atime: SystemTime, mtime: SystemTime) {
+  fn with_xattrs<R>(
+    &self,
+    f: impl FnOnce(&mut BTreeMap<OsString, Vec<u8>>) -> R,
+  ) -> R {
     match self {
-      DirectoryEntry::Directory(entry) => {
-        let mut entry = entry.inner.write();
-        entry.accessed = atime;
-        entry.changed = atime;
-        entry.created = atime;
-        entry.modified = mtime;
-      }
-      DirectoryEntry::File(entry) => {
-        let mut entry = entry.inner.write();
-        entry.accessed = atime;
-        entry.changed = atime;
-        entry.created = atime;
-        entry.modified = mtime;
+      DirectoryEntry::File(file) => f(&mut file.inner.write().xattrs),
+      DirectoryEntry::Directory(d) => f(&mut d.inner.write().xattrs),
+      DirectoryEntry::Symlink(s) => f(&mut s.inner.write().xattrs),
+    }
+  }
+
+  fn set_filetimes(
+    &self,
+    atime: SystemTime,
+    mtime: SystemTime,
+    now: SystemTime,
+  ) {
+    match self {
+      DirectoryEntry::Directory(d) => {
+        d.inner.write().set_times(atime, mtime, now)
       }
-      DirectoryEntry::Symlink(symlink) => {
-        let mut inner = symlink.inner.write();
-        inner.accessed = atime;
-        inner.changed = atime;
-        inner.created = atime;
-        inner.modified = mtime;
+      DirectoryEntry::File(f) => f.inner.write().set_times(atime, mtime, now),
+      DirectoryEntry::Symlink(s) => {
+        s.inner.write().set_times(atime, mtime, now)
       }
     }
   }
+
+  fn touch_changed(&self, time: SystemTime) {
+    match self {
+      DirectoryEntry::Directory(d) => d.inner.write().touch_changed(time),
+      DirectoryEntry::File(f) => f.inner.write().touch_changed(time),
+      DirectoryEntry::Symlink(s) => s.inner.write().touch_changed(time),
+    }
+  }
 }
 
 #[derive(Debug)]
@@ -144,13 +684,14 @@ struct SymlinkInner {
   changed: SystemTime,
   modified: SystemTime,
   mode: u32,
+  xattrs: BTreeMap<OsString, Vec<u8>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Symlink {
   name: String,
   target: PathBuf,
-  inner: RwLock<SymlinkInner>,
+  inner: Arc<RwLock<SymlinkInner>>,
 }
 
 #[derive(Debug)]
@@ -160,60 +701,824 @@ struct DirectoryInner {
   changed: SystemTime,
   modified: SystemTime,
   mode: u32,
+  xattrs: BTreeMap<OsString, Vec<u8>>,
+}
+
+macro_rules! impl_entry_times {
+  ($($ty:ty),*) => {
+    $(
+      impl $ty {
+        /// Updates the times after only the metadata changed.
+        fn touch_changed(&mut self, time: SystemTime) {
+          self.changed = time;
+        }
+
+        /// Updates the times after a read.
+        fn touch_accessed(
+          &mut self,
+          mode: InMemoryTimestampMode,
+          time: SystemTime,
+        ) {
+          if mode.updates_accessed(
+            self.accessed,
+            self.modified,
+            self.changed,
+            time,
+          ) {
+            self.accessed = time;
+          }
+        }
+
+        /// Explicitly sets the access and modification times, which
+        /// is a change to the metadata.
+        fn set_times(
+          &mut self,
+          accessed: SystemTime,
+          modified: SystemTime,
+          time: SystemTime,
+        ) {
+          self.accessed = accessed;
+          self.modified = modified;
+          self.changed = time;
+        }
+      }
+    )*
+  };
+}
+
+impl_entry_times!(FileInner, DirectoryInner, SymlinkInner);
+
+impl FileInner {
+  /// Updates the times after the data changed.
+  fn touch_modified(&mut self, time: SystemTime) {
+    self.modified = time;
+    self.changed = time;
+  }
+
+  /// Updates the usage of the file's quotas for the bytes its data
+  /// stores changing to `stored`, failing when that would exceed one
+  /// of them.
+  fn charge_stored(&self, stored: u64) -> Result<()> {
+    charge_quotas(
+      &self.quotas,
+      DiskUsage {
+        bytes: self.data.stored_len(),
+        inodes: 0,
+      },
+      DiskUsage {
+        bytes: stored,
+        inodes: 0,
+      },
+    )
+  }
+}
+
+impl DirectoryInner {
+  /// Updates the times after an entry was added or removed.
+  fn touch_modified(&mut self, time: SystemTime) {
+    self.modified = time;
+    self.changed = time;
+  }
+}
+
+/// Source of the ids used to identify open files when locking.
+static NEXT_OPEN_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The sorted entries of a directory.
+///
+/// Each directory has its own lock so operations in different
+/// directories don't block each other. Only one of these locks is held
+/// at a time, except when locking a directory and then its descendants,
+/// which can't deadlock.
+type DirectoryEntries = Arc<RwLock<DirectoryEntryList>>;
+
+#[derive(Debug, Default)]
+struct DirectoryEntryList {
+  entries: Vec<DirectoryEntry>,
+  /// Set when the directory is removed, so operations that found it
+  /// beforehand can't add entries that would be lost.
+  unlinked: bool,
+}
+
+impl DirectoryEntryList {
+  fn check_linked(&self) -> Result<()> {
+    if self.unlinked {
+      Err(Error::new(ErrorKind::NotFound, "Directory was removed"))
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl std::ops::Deref for DirectoryEntryList {
+  type Target = Vec<DirectoryEntry>;
+
+  fn deref(&self) -> &Vec<DirectoryEntry> {
+    &self.entries
+  }
+}
+
+impl std::ops::DerefMut for DirectoryEntryList {
+  fn deref_mut(&mut self) -> &mut Vec<DirectoryEntry> {
+    &mut self.entries
+  }
 }
 
+/// Marks a removed directory and its descendants as unlinked.
+fn unlink_directory(dir: &Directory) {
+  let children = {
+    let mut entries = dir.entries.write();
+    entries.unlinked = true;
+    child_directories(&entries)
+  };
+  for child in children {
+    unlink_directory(&child);
+  }
+}
+
+/// Where a directory currently is, which is updated when it's moved so
+/// handles can get its path without searching for it.
 #[derive(Debug)]
+struct DirectoryLocation {
+  /// `None` for the root directories.
+  parent: Option<Weak<RwLock<DirectoryLocation>>>,
+  name: String,
+}
+
+#[derive(Debug, Clone)]
 struct Directory {
   name: String,
-  inner: RwLock<DirectoryInner>,
-  entries: Vec<DirectoryEntry>,
+  location: Arc<RwLock<DirectoryLocation>>,
+  inner: Arc<RwLock<DirectoryInner>>,
+  entries: DirectoryEntries,
+}
+
+impl Directory {
+  fn new(name: String, parent: Option<&Directory>, time: SystemTime) -> Self {
+    Directory {
+      location: Arc::new(RwLock::new(DirectoryLocation {
+        parent: parent.map(|p| Arc::downgrade(&p.location)),
+        name: name.clone(),
+      })),
+      name,
+      inner: Arc::new(RwLock::new(DirectoryInner {
+        accessed: time,
+        changed: time,
+        created: time,
+        modified: time,
+        mode: 0o755,
+        xattrs: Default::default(),
+      })),
+      entries: Default::default(),
+    }
+  }
+}
+
+enum LookupEntry {
+  NotFound(PathBuf),
+  Found(PathBuf, DirectoryEntry),
+}
+
+enum LookupNoFollowEntry {
+  NotFound(PathBuf),
+  Symlink {
+    target_path: PathBuf,
+    entry: Symlink,
+  },
+  Found(PathBuf, DirectoryEntry),
+}
+
+enum WalkPath {
+  Done(LookupNoFollowEntry),
+  /// A symlink was encountered before the final component.
+  Redirect(PathBuf),
+}
+
+/// How entry names are compared when looking up paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseSensitivity {
+  Sensitive,
+  /// Case-insensitive, but case-preserving (ex. macOS and Windows).
+  Insensitive,
+}
+
+impl CaseSensitivity {
+  fn cmp(self, a: &str, b: &str) -> std::cmp::Ordering {
+    match self {
+      CaseSensitivity::Sensitive => a.cmp(b),
+      CaseSensitivity::Insensitive => a
+        .chars()
+        .flat_map(char::to_lowercase)
+        .cmp(b.chars().flat_map(char::to_lowercase)),
+    }
+  }
+
+  /// Gets if `path` is strictly inside `ancestor`.
+  fn is_descendant(self, path: &Path, ancestor: &Path) -> bool {
+    self.starts_with(path, ancestor)
+      && path.components().count() > ancestor.components().count()
+  }
+
+  /// Gets if `path` is `base` or inside it.
+  fn starts_with(self, path: &Path, base: &Path) -> bool {
+    let mut path_comps = path.components();
+    for base_comp in base.components() {
+      match path_comps.next() {
+        Some(comp)
+          if self.cmp(
+            &comp.as_os_str().to_string_lossy(),
+            &base_comp.as_os_str().to_string_lossy(),
+          ) == std::cmp::Ordering::Equal => {}
+        _ => return false,
+      }
+    }
+    true
+  }
+
+  fn sort_entries(self, entries: &DirectoryEntries) {
+    let dirs = {
+      let mut entries = entries.write();
+      entries.sort_by(|a, b| self.cmp(a.name(), b.name()));
+      child_directories(&entries)
+    };
+    for dir in dirs {
+      self.sort_entries(&dir.entries);
+    }
+  }
+}
+
+/// A fixed time provided via `set_time`, which optionally advances by
+/// a step for each change. It stops advancing instead of overflowing.
+#[derive(Debug, Default)]
+struct Clock {
+  time: Option<SystemTime>,
+  step: Duration,
+  /// If a change used the current time, so the next change steps it.
+  used: bool,
+}
+
+impl Clock {
+  /// Gets the current time without advancing it.
+  fn now(&self) -> SystemTime {
+    self.time.unwrap_or_else(SystemTime::now)
+  }
+
+  /// Gets the time for a change, which is a step after the time of the
+  /// previous change.
+  fn next(&mut self) -> SystemTime {
+    match &mut self.time {
+      Some(time) => {
+        if std::mem::replace(&mut self.used, true) {
+          *time = time.checked_add(self.step).unwrap_or(*time);
+        }
+        *time
+      }
+      None => SystemTime::now(),
+    }
+  }
+
+  fn advance(&mut self, duration: Duration) {
+    if let Some(time) = &mut self.time {
+      *time = time.checked_add(duration).unwrap_or(*time);
+    }
+  }
+}
+
+/// The SplitMix64 pseudo-random number generator, which is small and
+/// fast while still passing statistical tests.
+#[derive(Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+  fn next_u64(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+  }
+
+  fn fill(&mut self, buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+      let bytes = self.next_u64().to_le_bytes();
+      chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+  }
+}
+
+/// When reads update the access time of files and directories, similar
+/// to the mount options on Linux.
+///
+/// Regardless of the mode, writes update the modification and change
+/// times, metadata changes only update the change time, and adding,
+/// removing, or renaming entries updates the modification and change
+/// times of their directories.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InMemoryTimestampMode {
+  /// Every read updates the access time (`strictatime`).
+  Posix,
+  /// Reads only update the access time when it's older than the
+  /// modification or change time, or more than a day old. This is the
+  /// default on Linux.
+  #[default]
+  Relatime,
+  /// Reads never update the access time.
+  Noatime,
+}
+
+impl InMemoryTimestampMode {
+  fn updates_accessed(
+    self,
+    accessed: SystemTime,
+    modified: SystemTime,
+    changed: SystemTime,
+    now: SystemTime,
+  ) -> bool {
+    const DAY: std::time::Duration =
+      std::time::Duration::from_secs(60 * 60 * 24);
+    match self {
+      InMemoryTimestampMode::Posix => true,
+      InMemoryTimestampMode::Relatime => {
+        accessed <= modified
+          || accessed <= changed
+          || now.duration_since(accessed).is_ok_and(|age| age >= DAY)
+      }
+      InMemoryTimestampMode::Noatime => false,
+    }
+  }
+}
+
+/// Limits on the data stored in an `InMemorySys` or one of its
+/// directories.
+///
+/// Operations that would exceed a limit fail with `ErrorKind::StorageFull`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InMemoryQuota {
+  /// Maximum total size of all files in bytes, where holes in sparse
+  /// files aren't counted.
+  pub max_bytes: Option<u64>,
+  /// Maximum number of files, directories, and symlinks.
+  pub max_inodes: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct DiskUsage {
+  bytes: u64,
+  inodes: u64,
+}
+
+impl DiskUsage {
+  /// Gets the usage after replacing the `freed` usage with `added`.
+  fn replace(self, freed: DiskUsage, added: DiskUsage) -> DiskUsage {
+    DiskUsage {
+      bytes: self
+        .bytes
+        .saturating_sub(freed.bytes)
+        .saturating_add(added.bytes),
+      inodes: self
+        .inodes
+        .saturating_sub(freed.inodes)
+        .saturating_add(added.inodes),
+    }
+  }
+}
+
+/// A quota along with the space currently used within its directory,
+/// which is kept up to date as entries change.
+#[derive(Debug)]
+struct QuotaUsage {
+  quota: InMemoryQuota,
+  used: Mutex<DiskUsage>,
+}
+
+impl QuotaUsage {
+  /// Gets if replacing the `freed` usage with `added` would exceed the
+  /// quota, where shrinking is always allowed.
+  fn is_exceeded_by(
+    &self,
+    used: &DiskUsage,
+    freed: DiskUsage,
+    added: DiskUsage,
+  ) -> bool {
+    let new = used.replace(freed, added);
+    let exceeds = |max: Option<u64>, freed: u64, added: u64, new: u64| {
+      added > freed && max.is_some_and(|max| new > max)
+    };
+    exceeds(self.quota.max_bytes, freed.bytes, added.bytes, new.bytes)
+      || exceeds(
+        self.quota.max_inodes,
+        freed.inodes,
+        added.inodes,
+        new.inodes,
+      )
+  }
 }
 
-enum LookupEntry<'a> {
-  NotFound(PathBuf),
-  Found(PathBuf, &'a DirectoryEntry),
-}
+fn quota_exceeded_error() -> Error {
+  Error::new(
+    ErrorKind::StorageFull,
+    "No space left on device (quota exceeded)",
+  )
+}
+
+/// Gets if replacing the `freed` usage with `added` would exceed any
+/// of the quotas.
+fn exceeds_quotas(
+  quotas: &[Arc<QuotaUsage>],
+  freed: DiskUsage,
+  added: DiskUsage,
+) -> bool {
+  quotas
+    .iter()
+    .any(|quota| quota.is_exceeded_by(&quota.used.lock(), freed, added))
+}
+
+/// Replaces the `freed` usage with `added` in each of the quotas,
+/// failing without changing any of them when that would exceed one.
+///
+/// Quotas are always locked in the order they were added, so this
+/// can't deadlock.
+fn charge_quotas(
+  quotas: &[Arc<QuotaUsage>],
+  freed: DiskUsage,
+  added: DiskUsage,
+) -> Result<()> {
+  let mut used = quotas
+    .iter()
+    .map(|quota| quota.used.lock())
+    .collect::<Vec<_>>();
+  if quotas
+    .iter()
+    .zip(&used)
+    .any(|(quota, used)| quota.is_exceeded_by(used, freed, added))
+  {
+    return Err(quota_exceeded_error());
+  }
+  for used in &mut used {
+    **used = used.replace(freed, added);
+  }
+  Ok(())
+}
+
+fn release_quotas(quotas: &[Arc<QuotaUsage>], freed: DiskUsage) {
+  for quota in quotas {
+    let mut used = quota.used.lock();
+    *used = used.replace(freed, DiskUsage::default());
+  }
+}
+
+/// Gets the quotas in `quotas` that aren't in `other`.
+fn quotas_difference(
+  quotas: &[Arc<QuotaUsage>],
+  other: &[Arc<QuotaUsage>],
+) -> Vec<Arc<QuotaUsage>> {
+  quotas
+    .iter()
+    .filter(|quota| !other.iter().any(|other| Arc::ptr_eq(quota, other)))
+    .cloned()
+    .collect()
+}
+
+/// Sets the quotas that the files within an entry count against,
+/// returning the space used by the entry and the entries within it.
+fn set_entry_quotas(
+  entry: &DirectoryEntry,
+  quotas: &[Arc<QuotaUsage>],
+) -> DiskUsage {
+  visit_entry_usage(entry, &mut |file| file.quotas = quotas.to_vec())
+}
+
+/// Gets the space used by an entry and the entries within it.
+fn entry_usage(entry: &DirectoryEntry) -> DiskUsage {
+  visit_entry_usage(entry, &mut |_| {})
+}
+
+fn visit_entry_usage(
+  entry: &DirectoryEntry,
+  visit_file: &mut impl FnMut(&mut FileInner),
+) -> DiskUsage {
+  let mut usage = DiskUsage {
+    bytes: 0,
+    inodes: 1,
+  };
+  match entry {
+    DirectoryEntry::File(file) => {
+      let mut file = file.inner.write();
+      visit_file(&mut file);
+      usage.bytes = file.data.stored_len();
+    }
+    DirectoryEntry::Directory(dir) => {
+      let entries = dir.entries.read().iter().cloned().collect::<Vec<_>>();
+      for entry in &entries {
+        let entry_usage = visit_entry_usage(entry, visit_file);
+        usage = usage.replace(DiskUsage::default(), entry_usage);
+      }
+    }
+    DirectoryEntry::Symlink(_) => {}
+  }
+  usage
+}
+
+/// Operations on entries take this lock for reading and then lock the
+/// directories they change, while configuration changes and renames,
+/// which may move entries between directories, take it for writing.
+#[derive(Debug)]
+struct InMemorySysInner {
+  // Linux/Mac will always have one dir here, but Windows
+  // may have multiple per drive.
+  system_root: DirectoryEntries,
+  case_sensitivity: CaseSensitivity,
+  /// If paths are parsed and returned using Windows semantics.
+  ///
+  /// Internally, drives are stored at `/C` and UNC paths are
+  /// stored at `/UNC/server/share`.
+  windows_paths: bool,
+  /// The last current directory of each drive when emulating Windows.
+  drive_cwds: HashMap<char, PathBuf>,
+  /// Quotas keyed by the internal path they apply to, in the order
+  /// they're locked in.
+  quotas: Vec<(PathBuf, Arc<QuotaUsage>)>,
+  cwd: PathBuf,
+  thread_sleep_enabled: bool,
+  /// The generator used when a seed was provided via `set_seed`.
+  random: Mutex<Option<SplitMix64>>,
+  envs: HashMap<OsString, OsString>,
+  /// Locked separately so the time can advance while the file system
+  /// is only locked for reading.
+  clock: Mutex<Clock>,
+  umask: u32,
+  timestamp_mode: InMemoryTimestampMode,
+}
+
+impl InMemorySysInner {
+  fn to_absolute_path(&self, p: &Path) -> PathBuf {
+    if self.windows_paths {
+      self.windows_to_internal_path(&self.cwd, p)
+    } else if p.is_absolute() {
+      normalize_path(p)
+    } else {
+      normalize_path(&self.cwd.join(p))
+    }
+  }
+
+  /// Resolves a Windows path to the internal path, where `base` is the
+  /// internal path that relative paths are resolved against.
+  fn windows_to_internal_path(&self, base: &Path, path: &Path) -> PathBuf {
+    let path = windows_path::parse(&path.to_string_lossy());
+    let mut components = match (&path.prefix, path.has_root) {
+      (Some(WindowsPrefix::Disk(letter)), false) => {
+        // drive relative paths (ex. `C:file`) resolve against the
+        // current directory of that drive
+        let base = internal_components(base);
+        if base.first().map(|c| c.as_str()) == Some(&*letter.to_string()) {
+          base
+        } else {
+          match self.drive_cwds.get(letter) {
+            Some(cwd) => internal_components(cwd),
+            None => vec![letter.to_string()],
+          }
+        }
+      }
+      (Some(WindowsPrefix::Disk(letter)), true) => vec![letter.to_string()],
+      (Some(WindowsPrefix::Unc { server, share }), _) => {
+        vec!["UNC".to_string(), server.clone(), share.clone()]
+      }
+      (None, true) => {
+        let mut base = internal_components(base);
+        base.truncate(internal_prefix_len(&base));
+        base
+      }
+      (None, false) => internal_components(base),
+    };
+    let prefix_len = internal_prefix_len(&components);
+    for component in path.components {
+      match component.as_str() {
+        "." => {}
+        ".." => {
+          // can't go above the drive or share
+          if components.len() > prefix_len {
+            components.pop();
+          }
+        }
+        _ => components.push(component),
+      }
+    }
+    let mut result = PathBuf::from("/");
+    result.extend(components);
+    result
+  }
+
+  /// Converts an internal path to the path returned to the caller.
+  fn to_external_path(&self, path: PathBuf, verbatim: bool) -> PathBuf {
+    if !self.windows_paths {
+      return path;
+    }
+    let components = internal_components(&path);
+    let (prefix, rest) = match components.first().map(|c| c.as_str()) {
+      Some("UNC") if components.len() >= 3 => (
+        WindowsPrefix::Unc {
+          server: components[1].clone(),
+          share: components[2].clone(),
+        },
+        &components[3..],
+      ),
+      Some(drive) => (
+        WindowsPrefix::Disk(drive.chars().next().unwrap()),
+        &components[1..],
+      ),
+      None => return PathBuf::from(r"\"),
+    };
+    let path = windows_path::format(&prefix, rest, verbatim);
+    if verbatim && cfg!(feature = "strip_unc") {
+      PathBuf::from(windows_path::strip_unc_prefix(&path))
+    } else {
+      PathBuf::from(path)
+    }
+  }
+
+  fn resolve_symlink_target(
+    &self,
+    symlink_path: &Path,
+    target: &Path,
+  ) -> PathBuf {
+    if self.windows_paths {
+      self.windows_to_internal_path(symlink_path, target)
+    } else {
+      normalize_path(&symlink_path.join(target))
+    }
+  }
+
+  /// Gets the current time, which reads and changes that already got
+  /// their time from `next_time` use so they don't advance it.
+  fn time_now(&self) -> SystemTime {
+    self.clock.lock().now()
+  }
+
+  /// Gets the time for a change, which is called once per change.
+  fn next_time(&self) -> SystemTime {
+    self.clock.lock().next()
+  }
 
-enum LookupNoFollowEntry<'a> {
-  NotFound(PathBuf),
-  Symlink {
-    current_path: PathBuf,
-    target_path: PathBuf,
-    entry: &'a Symlink,
-  },
-  Found(PathBuf, &'a DirectoryEntry),
-}
+  fn set_quota(&mut self, path: PathBuf, quota: Option<InMemoryQuota>) {
+    self.quotas.retain(|(quota_path, _)| *quota_path != path);
+    if let Some(quota) = quota {
+      self.quotas.push((
+        path,
+        Arc::new(QuotaUsage {
+          quota,
+          used: Default::default(),
+        }),
+      ));
+    }
+    self.refresh_quotas();
+  }
+
+  /// Recomputes the space used within each quota's directory and the
+  /// quotas each file counts against.
+  ///
+  /// This walks the entire file system, so it's only done when quotas
+  /// change or a quota's directory is moved.
+  fn refresh_quotas(&self) {
+    fn visit(
+      inner: &InMemorySysInner,
+      entries: &DirectoryEntries,
+      path: &Path,
+    ) {
+      let entries = entries.read().iter().cloned().collect::<Vec<_>>();
+      for entry in entries {
+        let path = path.join(entry.name());
+        let quotas = inner.quotas_for(&path);
+        let mut usage = DiskUsage {
+          bytes: 0,
+          inodes: 1,
+        };
+        match &entry {
+          DirectoryEntry::File(file) => {
+            let mut file = file.inner.write();
+            usage.bytes = file.data.stored_len();
+            file.quotas = quotas.clone();
+          }
+          DirectoryEntry::Directory(dir) => visit(inner, &dir.entries, &path),
+          DirectoryEntry::Symlink(_) => {}
+        }
+        for quota in &quotas {
+          let mut used = quota.used.lock();
+          *used = used.replace(DiskUsage::default(), usage);
+        }
+      }
+    }
 
-#[derive(Debug)]
-struct InMemorySysInner {
-  // Linux/Mac will always have one dir here, but Windows
-  // may have multiple per drive.
-  system_root: Vec<DirectoryEntry>,
-  cwd: PathBuf,
-  thread_sleep_enabled: bool,
-  random_seed: Option<u64>,
-  envs: HashMap<OsString, OsString>,
-  time: Option<SystemTime>,
-  umask: u32,
-}
+    for (_, quota) in &self.quotas {
+      *quota.used.lock() = DiskUsage::default();
+    }
+    for root in child_directories(&self.system_root.read()) {
+      visit(self, &root.entries, &internal_directory_path(&root));
+    }
+  }
 
-impl InMemorySysInner {
-  fn to_absolute_path(&self, p: &Path) -> PathBuf {
-    if p.is_absolute() {
-      normalize_path(p)
-    } else {
-      normalize_path(&self.cwd.join(p))
+  /// Gets the quotas whose directories contain the path.
+  fn quotas_for(&self, path: &Path) -> Vec<Arc<QuotaUsage>> {
+    self
+      .quotas
+      .iter()
+      .filter(|(quota_path, _)| {
+        self.case_sensitivity.is_descendant(path, quota_path)
+      })
+      .map(|(_, quota)| quota.clone())
+      .collect()
+  }
+
+  /// Gets the quotas that an entry with the name in `parent` counts
+  /// against.
+  fn quotas_in(&self, parent: &Directory, name: &str) -> Vec<Arc<QuotaUsage>> {
+    if self.quotas.is_empty() {
+      return Vec::new();
     }
+    self.quotas_for(&internal_directory_path(parent).join(name))
   }
 
-  fn time_now(&self) -> SystemTime {
-    self.time.unwrap_or_else(SystemTime::now)
+  /// Gets if a quota's directory is the path or within it.
+  fn has_quota_within(&self, path: &Path) -> bool {
+    self.quotas.iter().any(|(quota_path, _)| {
+      self.case_sensitivity.starts_with(quota_path, path)
+    })
   }
 
-  fn lookup_entry<'a>(
-    &'a self,
-    path: &Path,
-  ) -> Result<(PathBuf, &'a DirectoryEntry)> {
+  /// Moves the usage of an entry that's being renamed to the quotas at
+  /// its new location, failing when that would exceed one of them.
+  ///
+  /// Returns `true` when a quota's directory is affected, in which case
+  /// `refresh_quotas` needs to be called once the rename is done.
+  fn move_quota_usage(
+    &self,
+    (from_parent, from_name): (&Directory, &str),
+    (to_parent, to_name): (&Directory, &str),
+    entry: &DirectoryEntry,
+    replaced: Option<&DirectoryEntry>,
+  ) -> Result<bool> {
+    if self.quotas.is_empty() {
+      return Ok(false);
+    }
+    let from = internal_directory_path(from_parent).join(from_name);
+    let to = internal_directory_path(to_parent).join(to_name);
+    let from_quotas = self.quotas_for(&from);
+    let to_quotas = self.quotas_for(&to);
+    let moved = entry_usage(entry);
+    let replaced_usage = replaced.map(entry_usage).unwrap_or_default();
+    let to_only = quotas_difference(&to_quotas, &from_quotas);
+    charge_quotas(&to_only, replaced_usage, moved)?;
+    if self.has_quota_within(&from) || self.has_quota_within(&to) {
+      return Ok(true);
+    }
+    release_quotas(&quotas_difference(&to_quotas, &to_only), replaced_usage);
+    release_quotas(&quotas_difference(&from_quotas, &to_quotas), moved);
+    set_entry_quotas(entry, &to_quotas);
+    if let Some(replaced) = replaced {
+      set_entry_quotas(replaced, &[]);
+    }
+    Ok(false)
+  }
+
+  /// Swaps the usage of two entries that are being exchanged between
+  /// the quotas at their locations, failing when that would exceed one
+  /// of them.
+  ///
+  /// Returns `true` when a quota's directory is affected, in which case
+  /// `refresh_quotas` needs to be called once the exchange is done.
+  fn exchange_quota_usage(
+    &self,
+    (a_parent, a_name, a): (&Directory, &str, &DirectoryEntry),
+    (b_parent, b_name, b): (&Directory, &str, &DirectoryEntry),
+  ) -> Result<bool> {
+    if self.quotas.is_empty() {
+      return Ok(false);
+    }
+    let a_path = internal_directory_path(a_parent).join(a_name);
+    let b_path = internal_directory_path(b_parent).join(b_name);
+    let a_quotas = self.quotas_for(&a_path);
+    let b_quotas = self.quotas_for(&b_path);
+    let a_only = quotas_difference(&a_quotas, &b_quotas);
+    let b_only = quotas_difference(&b_quotas, &a_quotas);
+    let a_usage = entry_usage(a);
+    let b_usage = entry_usage(b);
+    // nothing else changes usage while renames hold the write lock, so
+    // check both sides before charging either
+    if exceeds_quotas(&a_only, a_usage, b_usage)
+      || exceeds_quotas(&b_only, b_usage, a_usage)
+    {
+      return Err(quota_exceeded_error());
+    }
+    if self.has_quota_within(&a_path) || self.has_quota_within(&b_path) {
+      return Ok(true);
+    }
+    charge_quotas(&a_only, a_usage, b_usage)?;
+    charge_quotas(&b_only, b_usage, a_usage)?;
+    set_entry_quotas(a, &b_quotas);
+    set_entry_quotas(b, &a_quotas);
+    Ok(false)
+  }
+
+  fn lookup_entry(&self, path: &Path) -> Result<(PathBuf, DirectoryEntry)> {
     match self.lookup_entry_detail(path)? {
       LookupEntry::Found(path, entry) => Ok((path, entry)),
       LookupEntry::NotFound(_) => Err(Error::new(
@@ -223,33 +1528,21 @@ impl InMemorySysInner {
     }
   }
 
-  fn lookup_entry_detail<'a>(&'a self, path: &Path) -> Result<LookupEntry<'a>> {
-    let mut seen_entries = HashSet::new();
+  fn lookup_entry_detail(&self, path: &Path) -> Result<LookupEntry> {
+    let mut hops = 0;
     let mut path = Cow::Borrowed(path);
-
     loop {
-      match self.lookup_entry_detail_no_follow(&path)? {
+      match self.lookup_entry_detail_no_follow_inner(&path, &mut hops)? {
         LookupNoFollowEntry::NotFound(path) => {
           return Ok(LookupEntry::NotFound(path));
         }
         LookupNoFollowEntry::Found(path, entry) => {
           return Ok(LookupEntry::Found(path, entry));
         }
-        LookupNoFollowEntry::Symlink {
-          current_path,
-          target_path,
-          ..
-        } => {
-          if seen_entries.is_empty() {
-            // add the original path at this point in order to avoid allocating when we
-            // don't have symlinks
-            seen_entries.insert(current_path.clone());
-          }
-          if !seen_entries.insert(target_path.clone()) {
-            return Err(Error::new(
-              ErrorKind::Other,
-              format!("Symlink loop detected resolving '{}'", path.display()),
-            ));
+        LookupNoFollowEntry::Symlink { target_path, .. } => {
+          hops += 1;
+          if hops > MAX_SYMLINK_HOPS {
+            return Err(symlink_loop_error(&path));
           }
           path = Cow::Owned(target_path);
         }
@@ -257,56 +1550,99 @@ impl InMemorySysInner {
     }
   }
 
-  fn lookup_entry_detail_no_follow<'a>(
-    &'a self,
+  /// Looks up the entry at the path, following symlinks in all but the
+  /// final component.
+  fn lookup_entry_detail_no_follow(
+    &self,
+    path: &Path,
+  ) -> Result<LookupNoFollowEntry> {
+    self.lookup_entry_detail_no_follow_inner(path, &mut 0)
+  }
+
+  fn lookup_entry_detail_no_follow_inner(
+    &self,
     path: &Path,
-  ) -> Result<LookupNoFollowEntry<'a>> {
-    let mut final_path = Vec::new();
+    hops: &mut usize,
+  ) -> Result<LookupNoFollowEntry> {
+    let mut path = Cow::Borrowed(path);
+    loop {
+      match self.walk_path(&path)? {
+        WalkPath::Done(entry) => return Ok(entry),
+        WalkPath::Redirect(new_path) => {
+          *hops += 1;
+          if *hops > MAX_SYMLINK_HOPS {
+            return Err(symlink_loop_error(&path));
+          }
+          path = Cow::Owned(new_path);
+        }
+      }
+    }
+  }
+
+  /// Walks the components of the path until reaching the final entry or
+  /// a symlink that needs to be resolved before continuing.
+  fn walk_path(&self, path: &Path) -> Result<WalkPath> {
+    let mut final_path = PathBuf::new();
     let mut comps = path.components().peekable();
     if comps.peek().is_none() {
       return Err(Error::new(ErrorKind::NotFound, "Empty path"));
     }
 
-    let mut entries = &self.system_root;
+    let mut entries = self.system_root.clone();
     while let Some(comp) = comps.next() {
-      final_path.push(comp);
-      let comp = match comp {
-        Component::RootDir => Cow::Borrowed(""),
+      let (comp, is_normal) = match comp {
+        Component::RootDir => {
+          final_path.push(comp);
+          (Cow::Borrowed(""), false)
+        }
         Component::Prefix(component) => {
+          final_path.push(comp);
           let component = component.as_os_str().to_string_lossy();
           if let Some(comp) = comps.next() {
             final_path.push(comp);
           }
-          component
+          (component, false)
         }
-        component => component.as_os_str().to_string_lossy(),
+        component => (component.as_os_str().to_string_lossy(), true),
       };
-      let pos = match entries.binary_search_by(|e| e.name().cmp(&comp)) {
-        Ok(p) => p,
-        Err(_) => {
-          return Ok(LookupNoFollowEntry::NotFound(
-            final_path.into_iter().chain(comps).collect(),
-          ));
+      let entry = {
+        let entries = entries.read();
+        match entries
+          .binary_search_by(|e| self.case_sensitivity.cmp(e.name(), &comp))
+        {
+          Ok(pos) => entries[pos].clone(),
+          Err(_) => {
+            if is_normal {
+              final_path.push(comp.as_ref());
+            }
+            final_path.extend(comps);
+            return Ok(WalkPath::Done(LookupNoFollowEntry::NotFound(
+              final_path,
+            )));
+          }
         }
       };
+      if is_normal {
+        // use the stored name in order to preserve its casing
+        final_path.push(entry.name());
+      }
 
-      match &entries[pos] {
+      match entry {
         DirectoryEntry::Directory(dir) => {
           if comps.peek().is_none() {
-            return Ok(LookupNoFollowEntry::Found(
-              final_path.into_iter().collect(),
-              &entries[pos],
-            ));
+            return Ok(WalkPath::Done(LookupNoFollowEntry::Found(
+              final_path,
+              DirectoryEntry::Directory(dir),
+            )));
           } else {
-            entries = &dir.entries;
+            entries = dir.entries;
           }
         }
         DirectoryEntry::File(_) => {
           if comps.peek().is_none() {
-            return Ok(LookupNoFollowEntry::Found(
-              final_path.into_iter().collect(),
-              &entries[pos],
-            ));
+            return Ok(WalkPath::Done(LookupNoFollowEntry::Found(
+              final_path, entry,
+            )));
           } else {
             return Err(Error::new(
               ErrorKind::Other,
@@ -315,41 +1651,55 @@ impl InMemorySysInner {
           }
         }
         DirectoryEntry::Symlink(symlink) => {
-          let current_path = final_path.into_iter().collect::<PathBuf>();
-          let target_path = normalize_path(&current_path.join(&symlink.target));
-          return Ok(LookupNoFollowEntry::Symlink {
-            current_path,
-            target_path,
-            entry: symlink,
-          });
+          let mut target_path =
+            self.resolve_symlink_target(&final_path, &symlink.target);
+          if comps.peek().is_none() {
+            return Ok(WalkPath::Done(LookupNoFollowEntry::Symlink {
+              target_path,
+              entry: symlink,
+            }));
+          }
+          // continue with the remaining components from the target
+          target_path.extend(comps);
+          return Ok(WalkPath::Redirect(target_path));
         }
       }
     }
 
-    Ok(LookupNoFollowEntry::NotFound(
-      final_path.into_iter().collect(),
-    ))
+    Ok(WalkPath::Done(LookupNoFollowEntry::NotFound(final_path)))
+  }
+
+  /// Gets the current path of a directory, which may have been moved
+  /// since it was looked up.
+  fn directory_path(&self, dir: &Directory) -> Result<PathBuf> {
+    dir.entries.read().check_linked()?;
+    Ok(self.to_external_path(internal_directory_path(dir), false))
   }
 
-  fn find_directory_mut<'a>(
-    &'a mut self,
+  /// Finds the directory at the path, optionally creating it and its
+  /// ancestors at `create_time`.
+  ///
+  /// The returned directory's entries need to be locked in order to
+  /// modify them.
+  fn find_directory(
+    &self,
     path: &Path,
-    create_dirs: bool,
-  ) -> Result<&'a mut Directory> {
-    // ran into a lot of issues with the borrow checker... recommendation was to
-    // resolve symlinks first then resolve the path
+    create_time: Option<SystemTime>,
+  ) -> Result<Directory> {
+    // resolve symlinks first then walk the resolved path
     let path = match self.lookup_entry_detail(path)? {
       LookupEntry::Found(path, _) => path,
       LookupEntry::NotFound(path) => path,
     };
 
-    let time = self.time_now();
+    let case_sensitivity = self.case_sensitivity;
     let mut comps = path.components().peekable();
     if comps.peek().is_none() {
       return Err(Error::new(ErrorKind::NotFound, "Empty path"));
     }
 
-    let mut entries = &mut self.system_root;
+    let mut entries = self.system_root.clone();
+    let mut parent: Option<Directory> = None;
     while let Some(comp) = comps.next() {
       let comp = match comp {
         Component::RootDir => Cow::Borrowed(""),
@@ -360,35 +1710,58 @@ impl InMemorySysInner {
         }
         component => component.as_os_str().to_string_lossy(),
       };
-      let pos = match entries.binary_search_by(|e| e.name().cmp(&comp)) {
-        Ok(p) => p,
-        Err(insert_pos) => {
-          if create_dirs {
-            let new_dir = Directory {
-              name: comp.into_owned(),
-              inner: RwLock::new(DirectoryInner {
-                accessed: time,
-                changed: time,
-                created: time,
-                modified: time,
-                mode: 0o755,
-              }),
-              entries: vec![],
-            };
-            entries.insert(insert_pos, DirectoryEntry::Directory(new_dir));
-            insert_pos
-          } else {
-            return Err(Error::new(ErrorKind::NotFound, "Path not found"));
+      let search = |entries: &[DirectoryEntry]| {
+        entries.binary_search_by(|e| case_sensitivity.cmp(e.name(), &comp))
+      };
+      let found = {
+        let entries = entries.read();
+        search(&entries).ok().map(|pos| entries[pos].clone())
+      };
+      let entry = match (found, create_time) {
+        (Some(entry), _) => entry,
+        (None, Some(time)) => {
+          // another thread may have created it since the read lock
+          // was released, so search again
+          let mut entries = entries.write();
+          match search(&entries) {
+            Ok(pos) => entries[pos].clone(),
+            Err(insert_pos) => {
+              entries.check_linked()?;
+              if let Some(parent) = &parent {
+                charge_quotas(
+                  &self.quotas_in(parent, &comp),
+                  DiskUsage::default(),
+                  DiskUsage {
+                    bytes: 0,
+                    inodes: 1,
+                  },
+                )?;
+              }
+              let new_dir = DirectoryEntry::Directory(Directory::new(
+                comp.into_owned(),
+                parent.as_ref(),
+                time,
+              ));
+              entries.insert(insert_pos, new_dir.clone());
+              if let Some(parent) = &parent {
+                parent.inner.write().touch_modified(time);
+              }
+              new_dir
+            }
           }
         }
+        (None, None) => {
+          return Err(Error::new(ErrorKind::NotFound, "Path not found"));
+        }
       };
 
-      match &mut entries[pos] {
+      match entry {
         DirectoryEntry::Directory(dir) => {
           if comps.peek().is_none() {
             return Ok(dir);
           } else {
-            entries = &mut dir.entries;
+            entries = dir.entries.clone();
+            parent = Some(dir);
           }
         }
         DirectoryEntry::File(_) | DirectoryEntry::Symlink { .. } => {
@@ -404,6 +1777,44 @@ impl InMemorySysInner {
   }
 }
 
+/// Gets the internal path of a directory from its current location.
+fn internal_directory_path(dir: &Directory) -> PathBuf {
+  let mut names = Vec::new();
+  let mut location = dir.location.clone();
+  loop {
+    let parent = {
+      let location = location.read();
+      names.push(location.name.clone());
+      location.parent.as_ref().and_then(|p| p.upgrade())
+    };
+    match parent {
+      Some(parent) => location = parent,
+      None => break,
+    }
+  }
+  let root = names.pop().unwrap();
+  // the root is named after the prefix on Windows hosts
+  let mut path = if root.is_empty() {
+    PathBuf::from("/")
+  } else {
+    PathBuf::from(format!("{}{}", root, std::path::MAIN_SEPARATOR))
+  };
+  path.extend(names.iter().rev());
+  path
+}
+
+/// Gets the directories within the entries so they can be visited after
+/// the lock on the entries is released.
+fn child_directories(entries: &[DirectoryEntry]) -> Vec<Directory> {
+  entries
+    .iter()
+    .filter_map(|entry| match entry {
+      DirectoryEntry::Directory(dir) => Some(dir.clone()),
+      _ => None,
+    })
+    .collect()
+}
+
 /// An in-memory system implementation useful for testing.
 ///
 /// This is extremely untested and sloppily implemented. Use with extreme caution
@@ -416,12 +1827,17 @@ impl Default for InMemorySys {
   fn default() -> Self {
     Self(Arc::new(RwLock::new(InMemorySysInner {
       envs: Default::default(),
-      system_root: vec![],
+      system_root: Default::default(),
+      case_sensitivity: CaseSensitivity::Sensitive,
+      windows_paths: false,
+      drive_cwds: Default::default(),
+      quotas: Vec::new(),
       cwd: PathBuf::from("/"),
       thread_sleep_enabled: true,
-      random_seed: None,
-      time: None,
+      random: Default::default(),
+      clock: Default::default(),
       umask: 0o666,
+      timestamp_mode: Default::default(),
     })))
   }
 }
@@ -434,23 +1850,125 @@ impl InMemorySys {
     sys
   }
 
+  /// Creates an in-memory system that emulates Windows path semantics
+  /// regardless of the host operating system.
+  ///
+  /// Paths may use drive letters, either separator, and UNC or verbatim
+  /// (`\\?\`) prefixes. Each drive remembers its own current directory
+  /// for drive relative paths (ex. `D:file.txt`) and lookups are
+  /// case-insensitive.
+  pub fn new_windows_with_cwd(cwd: impl AsRef<Path>) -> Self {
+    let sys = InMemorySys::default();
+    {
+      let mut inner = sys.0.write();
+      inner.windows_paths = true;
+      inner.case_sensitivity = CaseSensitivity::Insensitive;
+      inner.cwd = PathBuf::from("/C");
+    }
+    sys.fs_create_dir_all(cwd.as_ref()).unwrap();
+    sys.env_set_current_dir(cwd.as_ref()).unwrap();
+    sys
+  }
+
+  /// Creates an in-memory system that emulates Windows path semantics
+  /// with a current directory of `C:\`.
+  ///
+  /// See `new_windows_with_cwd` for more details.
+  pub fn new_windows() -> Self {
+    Self::new_windows_with_cwd("C:\\")
+  }
+
+  /// Makes random data reproducible by generating it from the seed,
+  /// restarting the sequence each time this is called.
+  ///
+  /// Provide `None` to use the operating system's random data again.
   pub fn set_seed(&self, seed: Option<u64>) {
-    self.0.write().random_seed = seed;
+    *self.0.read().random.lock() = seed.map(SplitMix64);
   }
 
   pub fn set_time(&self, time: Option<SystemTime>) {
-    self.0.write().time = time;
+    let inner = self.0.read();
+    let mut clock = inner.clock.lock();
+    clock.time = time;
+    clock.used = false;
+  }
+
+  /// Makes the time provided via `set_time` advance by `step` for each
+  /// change to the file system and each call to `sys_time_now`, so that
+  /// timestamps are distinct while staying reproducible. Reads don't
+  /// advance it.
+  ///
+  /// Provide `Duration::ZERO` to keep the time fixed, which is the
+  /// default.
+  pub fn set_time_step(&self, step: Duration) {
+    self.0.read().clock.lock().step = step;
+  }
+
+  /// Advances the time provided via `set_time` by the duration.
+  ///
+  /// Does nothing when no time was provided.
+  pub fn advance_time(&self, duration: Duration) {
+    self.0.read().clock.lock().advance(duration);
+  }
+
+  /// Sets when reads update access times, which defaults to
+  /// `InMemoryTimestampMode::Relatime`.
+  pub fn set_timestamp_mode(&self, mode: InMemoryTimestampMode) {
+    self.0.write().timestamp_mode = mode;
+  }
+
+  /// Makes path lookups case-insensitive while preserving the original
+  /// casing of entries, similar to the default file systems on macOS
+  /// and Windows.
+  ///
+  /// This should be set before adding entries because entries whose
+  /// names only differ by case will collide once enabled.
+  pub fn set_case_insensitive(&self, value: bool) {
+    let mut inner = self.0.write();
+    inner.case_sensitivity = if value {
+      CaseSensitivity::Insensitive
+    } else {
+      CaseSensitivity::Sensitive
+    };
+    let case_sensitivity = inner.case_sensitivity;
+    case_sensitivity.sort_entries(&inner.system_root);
+    // quota paths may now match different entries
+    if !inner.quotas.is_empty() {
+      inner.refresh_quotas();
+    }
+  }
+
+  /// Limits the space used by the entire file system.
+  ///
+  /// Provide `None` to remove the quota.
+  pub fn set_quota(&self, quota: Option<InMemoryQuota>) {
+    self.0.write().set_quota(PathBuf::from("/"), quota);
+  }
+
+  /// Limits the space used within a directory, similar to a mount
+  /// with its own size.
+  ///
+  /// Provide `None` to remove the quota.
+  pub fn set_path_quota(
+    &self,
+    path: impl AsRef<Path>,
+    quota: Option<InMemoryQuota>,
+  ) {
+    let mut inner = self.0.write();
+    let path = inner.to_absolute_path(path.as_ref());
+    inner.set_quota(path, quota);
   }
 
   /// Makes thread sleeping a no-op.
+  ///
+  /// If a time was provided via `set_time`, sleeping will advance
+  /// that time by the sleep duration instead.
   pub fn disable_thread_sleep(&self) {
     self.0.write().thread_sleep_enabled = false;
   }
 
   pub fn fs_insert(&self, path: impl AsRef<Path>, data: impl AsRef<[u8]>) {
-    self
-      .fs_create_dir_all(path.as_ref().parent().unwrap())
-      .unwrap();
+    self.create_parent_dir_all(path.as_ref());
     self.fs_write(path, data).unwrap();
   }
 
@@ -461,25 +1979,49 @@ impl InMemorySys {
     path: impl AsRef<Path>,
     json: serde_json::Value,
   ) {
-    self
-      .fs_create_dir_all(path.as_ref().parent().unwrap())
-      .unwrap();
+    self.create_parent_dir_all(path.as_ref());
     self
       .fs_write(path, serde_json::to_string(&json).unwrap())
       .unwrap();
   }
+
+  /// Gets what's used to update access times after a read.
+  fn read_time(&self) -> (InMemoryTimestampMode, SystemTime) {
+    let inner = self.0.read();
+    (inner.timestamp_mode, inner.time_now())
+  }
+
+  fn create_parent_dir_all(&self, path: &Path) {
+    let inner = self.0.read();
+    let path = inner.to_absolute_path(path);
+    let time = inner.next_time();
+    inner
+      .find_directory(path.parent().unwrap(), Some(time))
+      .unwrap();
+  }
 }
 
 impl EnvCurrentDir for InMemorySys {
   fn env_current_dir(&self) -> std::io::Result<PathBuf> {
-    Ok(self.0.read().cwd.clone())
+    let inner = self.0.read();
+    Ok(inner.to_external_path(inner.cwd.clone(), false))
   }
 }
 
 impl BaseEnvSetCurrentDir for InMemorySys {
   fn base_env_set_current_dir(&self, path: &Path) -> std::io::Result<()> {
-    let path = self.fs_canonicalize(path)?; // cause an error if not exists
-    self.0.write().cwd = path;
+    if path.as_os_str().is_empty() {
+      return Err(Error::new(ErrorKind::NotFound, "No such file or directory"));
+    }
+    let mut inner = self.0.write();
+    let path = inner.to_absolute_path(path);
+    let path = inner.lookup_entry(&path)?.0; // cause an error if not exists
+    if inner.windows_paths {
+      if let Some(drive) = internal_drive_letter(&path) {
+        inner.drive_cwds.insert(drive, path.clone());
+      }
+    }
+    inner.cwd = path;
     Ok(())
   }
 }
@@ -529,7 +2071,16 @@ impl EnvHomeDir for InMemorySys {
 impl EnvTempDir for InMemorySys {
   fn env_temp_dir(&self) -> std::io::Result<PathBuf> {
     let inner = self.0.read();
-    if let Some(first_dir) = inner.system_root.first() {
+    if inner.windows_paths {
+      let mut components = internal_components(&inner.cwd);
+      components.truncate(internal_prefix_len(&components));
+      components.push("tmp".to_string());
+      let mut path = PathBuf::from("/");
+      path.extend(components);
+      return Ok(inner.to_external_path(path, false));
+    }
+    let system_root = inner.system_root.read();
+    if let Some(first_dir) = system_root.first() {
       let name = first_dir.name();
       let name = if name.is_empty() { "/" } else { name };
       Ok(PathBuf::from(name).join("tmp"))
@@ -564,7 +2115,7 @@ impl BaseFsCanonicalize for InMemorySys {
     let inner = self.0.read();
     let path = inner.to_absolute_path(path);
     let (path, _) = inner.lookup_entry(&path)?;
-    Ok(path)
+    Ok(inner.to_external_path(path, true))
   }
 }
 
@@ -597,17 +2148,135 @@ impl BaseFsSymlinkChown for InMemorySys {
 }
 
 impl BaseFsCopy for InMemorySys {
+  #[inline]
   fn base_fs_copy(&self, from: &Path, to: &Path) -> Result<u64> {
-    let data = self.fs_read(from)?;
+    self.base_fs_copy_with_options(from, to, &CopyOptions::new())
+  }
+}
+
+impl BaseFsCopyWithOptions for InMemorySys {
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> Result<u64> {
+    let inner = self.0.read();
+    let from = inner.to_absolute_path(from);
+    let to = inner.to_absolute_path(to);
+    if options.create_new
+      && !matches!(
+        inner.lookup_entry_detail_no_follow(&to),
+        Ok(LookupNoFollowEntry::NotFound(_))
+      )
+    {
+      return Err(Error::new(
+        ErrorKind::AlreadyExists,
+        format!("Path already exists: '{}'", to.display()),
+      ));
+    }
+    if options.copy_symlinks {
+      if let LookupNoFollowEntry::Symlink { entry, .. } =
+        inner.lookup_entry_detail_no_follow(&from)?
+      {
+        let target = entry.target.clone();
+        let (accessed, modified) = {
+          let entry = entry.inner.read();
+          (entry.accessed, entry.modified)
+        };
+        drop(inner);
+        self.base_fs_symlink_file(&target, &to)?;
+        if options.preserve_times {
+          self.base_fs_set_symlink_file_times(&to, accessed, modified)?;
+        }
+        return Ok(0);
+      }
+    }
+    // ownership isn't tracked, so preserve_ownership and sparse
+    // (which only affects storage) are no-ops
+    let time = inner.next_time();
+    let (data, mode, accessed, modified) = match inner.lookup_entry(&from)? {
+      (_, DirectoryEntry::File(file)) => {
+        let mut file = file.inner.write();
+        let result =
+          (file.data.clone(), file.mode, file.accessed, file.modified);
+        file.touch_accessed(inner.timestamp_mode, time);
+        result
+      }
+      _ => {
+        return Err(Error::new(
+          ErrorKind::InvalidInput,
+          "the source path is not an existing regular file",
+        ));
+      }
+    };
+    drop(inner);
     let len = data.len();
-    self.fs_write(to, data)?;
-    Ok(len as u64)
+    // a created destination starts out with the data, so it's not
+    // created when that would exceed a quota
+    let (file, created) = self.open_file(
+      &to,
+      &OpenOptions {
+        write: true,
+        create: true,
+        create_new: options.create_new,
+        mode: Some(mode),
+        ..Default::default()
+      },
+      data.clone(),
+      time,
+    )?;
+    if !created {
+      file.set_data(data, time)?;
+    }
+    {
+      let mut inner = file.inner.write();
+      inner.mode = mode;
+      inner.touch_modified(time);
+      if options.preserve_times {
+        inner.accessed = accessed;
+        inner.modified = modified;
+      }
+    }
+    options.report_progress(len);
+    Ok(len)
   }
 }
 
 impl BaseFsCloneFile for InMemorySys {
   fn base_fs_clone_file(&self, from: &Path, to: &Path) -> Result<()> {
-    self.base_fs_copy(from, to).map(|_| ())
+    let (data, mode, time) = {
+      let inner = self.0.read();
+      let from = inner.to_absolute_path(from);
+      let (_, entry) = inner.lookup_entry(&from)?;
+      match entry {
+        DirectoryEntry::File(file) => {
+          let file = file.inner.read();
+          (file.data.clone(), file.mode, inner.next_time())
+        }
+        DirectoryEntry::Directory(_) | DirectoryEntry::Symlink(_) => {
+          return Err(Error::new(
+            ErrorKind::Other,
+            "Cannot clone directories or symlinks",
+          ));
+        }
+      }
+    };
+    // like clonefile, this fails when the destination exists, and
+    // clones still count against quotas like with reflinks
+    self.open_file(
+      to,
+      &OpenOptions {
+        write: true,
+        create: true,
+        create_new: true,
+        mode: Some(mode),
+        ..Default::default()
+      },
+      data,
+      time,
+    )?;
+    Ok(())
   }
 }
 
@@ -618,9 +2287,9 @@ impl BaseFsCreateDir for InMemorySys {
     // todo: implement this properly
     _options: &CreateDirOptions,
   ) -> Result<()> {
-    let mut inner = self.0.write();
+    let inner = self.0.read();
     let abs = inner.to_absolute_path(path);
-    inner.find_directory_mut(&abs, true)?;
+    inner.find_directory(&abs, Some(inner.next_time()))?;
     Ok(())
   }
 }
@@ -629,16 +2298,15 @@ impl BaseFsHardLink for InMemorySys {
   fn base_fs_hard_link(&self, src: &Path, dst: &Path) -> Result<()> {
     let inner = self.0.read();
     let src = inner.to_absolute_path(src.as_ref());
-    let dst = inner.to_absolute_path(dst.as_ref());
     let (_, entry) = inner.lookup_entry(&src)?;
     match entry {
       DirectoryEntry::File(file) => {
         let data = {
           let inner = file.inner.read();
-          inner.data.clone()
+          inner.data.to_vec()
         };
         drop(inner);
-        self.fs_write(&dst, data)?;
+        self.fs_write(dst, data)?;
       }
       DirectoryEntry::Directory(_) | DirectoryEntry::Symlink(_) => {
         return Err(Error::new(
@@ -668,6 +2336,7 @@ impl BaseFsCreateJunction for InMemorySys {
 pub struct InMemoryMetadata {
   file_type: FileType,
   len: u64,
+  blocks: u64,
   accessed: SystemTime,
   changed: SystemTime,
   created: SystemTime,
@@ -723,6 +2392,11 @@ impl FsMetadataValue for InMemoryMetadata {
     Ok(self.mode)
   }
 
+  #[inline]
+  fn blocks(&self) -> Result<u64> {
+    Ok(self.blocks)
+  }
+
   not_supported_metadata_prop!(dev, u64);
   not_supported_metadata_prop!(ino, u64);
   not_supported_metadata_prop!(nlink, u64);
@@ -730,7 +2404,6 @@ impl FsMetadataValue for InMemoryMetadata {
   not_supported_metadata_prop!(gid, u32);
   not_supported_metadata_prop!(rdev, u64);
   not_supported_metadata_prop!(blksize, u64);
-  not_supported_metadata_prop!(blocks, u64);
   not_supported_metadata_prop!(is_block_device, bool);
   not_supported_metadata_prop!(is_char_device, bool);
   not_supported_metadata_prop!(is_fifo, bool);
@@ -738,6 +2411,59 @@ impl FsMetadataValue for InMemoryMetadata {
   not_supported_metadata_prop!(file_attributes, u32);
 }
 
+impl BaseFsDiskSpace for InMemorySys {
+  #[inline]
+  fn base_fs_disk_space(&self, path: &Path) -> Result<DiskSpace> {
+    let stat = self.base_fs_statvfs(path)?;
+    Ok(DiskSpace {
+      total: stat.total_bytes,
+      free: stat.free_bytes,
+      available: stat.available_bytes,
+    })
+  }
+}
+
+impl BaseFsStatVfs for InMemorySys {
+  fn base_fs_statvfs(&self, path: &Path) -> Result<StatVfs> {
+    let inner = self.0.read();
+    let path = inner.to_absolute_path(path);
+    let (path, _) = inner.lookup_entry(&path)?;
+    // report the most restrictive quotas or unlimited space
+    let mut bytes = (u64::MAX, u64::MAX);
+    let mut inodes = (u64::MAX, u64::MAX);
+    for (quota_path, quota) in &inner.quotas {
+      if !inner.case_sensitivity.starts_with(&path, quota_path) {
+        continue;
+      }
+      let usage = *quota.used.lock();
+      let quota = quota.quota;
+      if let Some(max_bytes) = quota.max_bytes {
+        let free = max_bytes.saturating_sub(usage.bytes);
+        if free < bytes.1 {
+          bytes = (max_bytes, free);
+        }
+      }
+      if let Some(max_inodes) = quota.max_inodes {
+        let free = max_inodes.saturating_sub(usage.inodes);
+        if free < inodes.1 {
+          inodes = (max_inodes, free);
+        }
+      }
+    }
+    Ok(StatVfs {
+      total_bytes: bytes.0,
+      free_bytes: bytes.1,
+      available_bytes: bytes.1,
+      total_inodes: inodes.0,
+      free_inodes: inodes.1,
+      available_inodes: inodes.1,
+      block_size: 4096,
+      max_name_len: Some(255),
+      read_only: Some(false),
+    })
+  }
+}
+
 impl BaseFsMetadata for InMemorySys {
   type Metadata = InMemoryMetadata;
 
@@ -748,6 +2474,7 @@ impl BaseFsMetadata for InMemorySys {
     Ok(InMemoryMetadata {
       file_type: entry.file_type(),
       len: entry.len(),
+      blocks: entry.blocks(),
       accessed: entry.accessed(),
       changed: entry.changed(),
       created: entry.created(),
@@ -773,6 +2500,7 @@ impl BaseFsMetadata for InMemorySys {
         Ok(InMemoryMetadata {
           file_type: FileType::Symlink,
           len: 0,
+          blocks: 0,
           accessed: inner.accessed,
           changed: inner.changed,
           created: inner.created,
@@ -783,6 +2511,7 @@ impl BaseFsMetadata for InMemorySys {
       LookupNoFollowEntry::Found(_, entry) => Ok(InMemoryMetadata {
         file_type: entry.file_type(),
         len: entry.len(),
+        blocks: entry.blocks(),
         accessed: entry.accessed(),
         changed: entry.changed(),
         created: entry.created(),
@@ -793,18 +2522,174 @@ impl BaseFsMetadata for InMemorySys {
   }
 }
 
+/// A handle to a directory in an `InMemorySys` that keeps referring to
+/// the same directory when it's moved.
+#[derive(Debug, Clone)]
+pub struct InMemoryDirHandle {
+  sys: InMemorySys,
+  dir: Directory,
+}
+
+impl InMemoryDirHandle {
+  fn join(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+    let dir_path = self.sys.0.read().directory_path(&self.dir)?;
+    Ok(dir_path.join(path))
+  }
+}
+
+impl FsDirHandle for InMemoryDirHandle {
+  type File = InMemoryFile;
+  type Metadata = InMemoryMetadata;
+  type ReadDirEntry = InMemoryDirEntry;
+
+  fn open_at(
+    &self,
+    path: impl AsRef<Path>,
+    options: &OpenOptions,
+  ) -> Result<Self::File> {
+    self.sys.fs_open(self.join(path)?, options)
+  }
+
+  fn open_dir_at(&self, path: impl AsRef<Path>) -> Result<Self> {
+    self.sys.fs_open_dir(self.join(path)?)
+  }
+
+  fn metadata_at(&self, path: impl AsRef<Path>) -> Result<Self::Metadata> {
+    self.sys.fs_metadata(self.join(path)?)
+  }
+
+  fn symlink_metadata_at(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> Result<Self::Metadata> {
+    self.sys.fs_symlink_metadata(self.join(path)?)
+  }
+
+  fn read_dir(
+    &self,
+  ) -> Result<Box<dyn Iterator<Item = Result<Self::ReadDirEntry>>>> {
+    self.sys.fs_read_dir(self.join("")?)
+  }
+
+  fn create_dir_at(&self, path: impl AsRef<Path>) -> Result<()> {
+    self
+      .sys
+      .fs_create_dir(self.join(path)?, &CreateDirOptions::default())
+  }
+
+  fn remove_at(&self, path: impl AsRef<Path>) -> Result<()> {
+    let path = self.join(path)?;
+    if self.sys.fs_symlink_metadata(&path)?.file_type().is_dir() {
+      self.sys.fs_remove_dir(path)
+    } else {
+      self.sys.fs_remove_file(path)
+    }
+  }
+
+  fn rename_at(
+    &self,
+    from: impl AsRef<Path>,
+    to_dir: &Self,
+    to: impl AsRef<Path>,
+  ) -> Result<()> {
+    self.sys.fs_rename(self.join(from)?, to_dir.join(to)?)
+  }
+
+  fn symlink_at(
+    &self,
+    original: impl AsRef<Path>,
+    link: impl AsRef<Path>,
+  ) -> Result<()> {
+    self.sys.fs_symlink_file(original, self.join(link)?)
+  }
+}
+
+impl BaseFsOpenDir for InMemorySys {
+  type DirHandle = InMemoryDirHandle;
+
+  fn base_fs_open_dir(&self, path: &Path) -> Result<Self::DirHandle> {
+    let inner = self.0.read();
+    let path = inner.to_absolute_path(path);
+    match inner.lookup_entry(&path)? {
+      (_, DirectoryEntry::Directory(dir)) => Ok(InMemoryDirHandle {
+        sys: self.clone(),
+        dir,
+      }),
+      _ => Err(Error::new(
+        ErrorKind::NotADirectory,
+        format!("Path is not a directory: '{}'", path.display()),
+      )),
+    }
+  }
+}
+
 impl BaseFsOpen for InMemorySys {
   type File = InMemoryFile;
 
+  #[inline]
   fn base_fs_open(
     &self,
     path: &Path,
     options: &OpenOptions,
   ) -> std::io::Result<InMemoryFile> {
-    let mut inner = self.0.write();
-    let time_now = inner.time_now();
+    let time = {
+      let inner = self.0.read();
+      // only opens that may change the file advance the time
+      if options.create || options.truncate {
+        inner.next_time()
+      } else {
+        inner.time_now()
+      }
+    };
+    let (file, _) = self.open_file(path, options, FileData::default(), time)?;
+    Ok(file)
+  }
+}
+
+impl InMemorySys {
+  /// Opens a file, where a created file starts out with `data` so it's
+  /// counted against quotas before the file exists, and `time_now` is
+  /// used for any changes.
+  ///
+  /// Returns whether the file was created.
+  fn open_file(
+    &self,
+    path: &Path,
+    options: &OpenOptions,
+    data: FileData,
+    time_now: SystemTime,
+  ) -> Result<(InMemoryFile, bool)> {
+    let inner = self.0.read();
+    let case_sensitivity = inner.case_sensitivity;
     let umask = inner.umask;
     let path = inner.to_absolute_path(path);
+    let path = match inner.lookup_entry_detail_no_follow(&path)? {
+      LookupNoFollowEntry::Symlink { .. } if is_no_follow(options) => {
+        return Err(symlink_loop_error(&path));
+      }
+      LookupNoFollowEntry::Symlink { .. } if options.create_new => {
+        return Err(Error::new(
+          ErrorKind::AlreadyExists,
+          "Symlink already exists (create_new=true)",
+        ));
+      }
+      // open what the symlink points to, creating it when dangling
+      LookupNoFollowEntry::Symlink { .. } => {
+        match inner.lookup_entry_detail(&path)? {
+          LookupEntry::Found(path, _) | LookupEntry::NotFound(path) => path,
+        }
+      }
+      LookupNoFollowEntry::Found(..) | LookupNoFollowEntry::NotFound(_) => path,
+    };
+    if options.directory {
+      return match inner.lookup_entry(&path)? {
+        (_, DirectoryEntry::Directory(_)) => Err(Error::new(
+          ErrorKind::Unsupported,
+          "Opening directories as files is not supported in InMemorySys, so use fs_open_dir instead",
+        )),
+        _ => Err(Error::new(ErrorKind::NotADirectory, "Not a directory")),
+      };
+    }
 
     // Edge case: If `parent()` is None, path might be root or invalid
     // The minimal fix is to check for that scenario
@@ -818,7 +2703,7 @@ impl BaseFsOpen for InMemorySys {
       }
     };
 
-    let parent = inner.find_directory_mut(parent_path, false)?;
+    let parent = inner.find_directory(parent_path, None)?;
     let file_name = match path.file_name() {
       Some(n) => n.to_string_lossy(),
       None => {
@@ -826,11 +2711,11 @@ impl BaseFsOpen for InMemorySys {
       }
     };
 
-    match parent
-      .entries
-      .binary_search_by(|e| e.name().cmp(&file_name))
+    let mut entries = parent.entries.write();
+    match entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), &file_name))
     {
-      Ok(pos) => match &mut parent.entries[pos] {
+      Ok(pos) => match &entries[pos] {
         DirectoryEntry::File(f) => {
           if options.create_new {
             return Err(Error::new(
@@ -840,10 +2725,17 @@ impl BaseFsOpen for InMemorySys {
           }
           if options.truncate {
             let mut fi = f.inner.write();
-            fi.data.clear();
-            fi.modified = time_now;
+            release_quotas(
+              &fi.quotas,
+              DiskUsage {
+                bytes: fi.data.stored_len(),
+                inodes: 0,
+              },
+            );
+            fi.data = Default::default();
+            fi.touch_modified(time_now);
           }
-          Ok(InMemoryFile {
+          let file = InMemoryFile {
             sys: self.clone(),
             inner: f.inner.clone(),
             path,
@@ -852,7 +2744,11 @@ impl BaseFsOpen for InMemorySys {
             } else {
               0
             },
-          })
+            lock: OpenFileLock::new(&f.inner),
+            readable: options.read,
+            writable: options.write || options.append,
+          };
+          Ok((file, false))
         }
         _ => Err(Error::new(ErrorKind::Other, "Path is not a file")),
       },
@@ -860,6 +2756,16 @@ impl BaseFsOpen for InMemorySys {
         if !options.create {
           return Err(Error::new(ErrorKind::NotFound, "File not found"));
         }
+        entries.check_linked()?;
+        let quotas = inner.quotas_in(&parent, &file_name);
+        charge_quotas(
+          &quotas,
+          DiskUsage::default(),
+          DiskUsage {
+            bytes: data.stored_len(),
+            inodes: 1,
+          },
+        )?;
         let new_file = File {
           name: file_name.into_owned(),
           inner: Arc::new(RwLock::new(FileInner {
@@ -867,8 +2773,11 @@ impl BaseFsOpen for InMemorySys {
             changed: time_now,
             created: time_now,
             modified: time_now,
-            data: vec![],
+            data,
             mode: options.mode.unwrap_or(umask),
+            xattrs: Default::default(),
+            lock: Default::default(),
+            quotas,
           })),
         };
         let result = InMemoryFile {
@@ -880,11 +2789,13 @@ impl BaseFsOpen for InMemorySys {
           } else {
             0
           },
+          lock: OpenFileLock::new(&new_file.inner),
+          readable: options.read,
+          writable: options.write || options.append,
         };
-        parent
-          .entries
-          .insert(insert_pos, DirectoryEntry::File(new_file));
-        Ok(result)
+        entries.insert(insert_pos, DirectoryEntry::File(new_file));
+        parent.inner.write().touch_modified(time_now);
+        Ok((result, true))
       }
     }
   }
@@ -892,9 +2803,26 @@ impl BaseFsOpen for InMemorySys {
 
 impl BaseFsRead for InMemorySys {
   fn base_fs_read(&self, path: &Path) -> std::io::Result<Cow<'static, [u8]>> {
+    let (mode, time) = self.read_time();
     let arc_file = self.fs_open(path, &OpenOptions::new_read())?;
-    let inner = arc_file.inner.read();
-    Ok(Cow::Owned(inner.data.clone()))
+    let data = {
+      let mut inner = arc_file.inner.write();
+      inner.touch_accessed(mode, time);
+      // cloning only shares the runs, so the file isn't locked while
+      // the bytes are copied
+      inner.data.clone()
+    };
+    Ok(Cow::Owned(data.to_vec()))
+  }
+}
+
+impl BaseFsReadShared for InMemorySys {
+  fn base_fs_read_shared(&self, path: &Path) -> Result<FsSharedBytes> {
+    let (mode, time) = self.read_time();
+    let file = self.fs_open(path, &OpenOptions::new_read())?;
+    let mut inner = file.inner.write();
+    inner.touch_accessed(mode, time);
+    Ok(inner.data.to_shared())
   }
 }
 
@@ -909,17 +2837,23 @@ impl BaseFsReadDir for InMemorySys {
   > {
     let inner = self.0.read();
     let abs_path = inner.to_absolute_path(path);
+    let windows_paths = inner.windows_paths;
 
     let (_, entry) = inner.lookup_entry(&abs_path)?;
     match entry {
-      DirectoryEntry::Directory(dir) => Ok(Box::new(
-        dir
+      DirectoryEntry::Directory(dir) => {
+        let entries = dir
           .entries
+          .read()
           .iter()
-          .map(|entry| Ok(InMemoryDirEntry::new(path, entry)))
-          .collect::<Vec<_>>()
-          .into_iter(),
-      )),
+          .map(|entry| Ok(InMemoryDirEntry::new(path, entry, windows_paths)))
+          .collect::<Vec<_>>();
+        dir
+          .inner
+          .write()
+          .touch_accessed(inner.timestamp_mode, inner.time_now());
+        Ok(Box::new(entries.into_iter()))
+      }
       _ => Err(std::io::Error::new(
         std::io::ErrorKind::Other,
         "Path is not a directory",
@@ -931,7 +2865,8 @@ impl BaseFsReadDir for InMemorySys {
 impl BaseFsReadLink for InMemorySys {
   fn base_fs_read_link(&self, path: &Path) -> io::Result<PathBuf> {
     let inner = self.0.read();
-    let detail = inner.lookup_entry_detail_no_follow(path)?;
+    let path = inner.to_absolute_path(path);
+    let detail = inner.lookup_entry_detail_no_follow(&path)?;
     match detail {
       LookupNoFollowEntry::NotFound(path) => Err(Error::new(
         ErrorKind::NotFound,
@@ -941,7 +2876,13 @@ impl BaseFsReadLink for InMemorySys {
         ErrorKind::InvalidInput,
         format!("Path is not a symlink: '{}'", path.display()),
       )),
-      LookupNoFollowEntry::Symlink { target_path, .. } => Ok(target_path),
+      LookupNoFollowEntry::Symlink { target_path, entry } => {
+        entry
+          .inner
+          .write()
+          .touch_accessed(inner.timestamp_mode, inner.time_now());
+        Ok(inner.to_external_path(target_path, false))
+      }
     }
   }
 }
@@ -952,6 +2893,7 @@ pub struct InMemoryDirEntry {
   path: PathBuf,
   file_type: FileType,
   len: u64,
+  blocks: u64,
   accessed: SystemTime,
   created: SystemTime,
   changed: SystemTime,
@@ -960,12 +2902,25 @@ pub struct InMemoryDirEntry {
 }
 
 impl InMemoryDirEntry {
-  fn new(initial_path: &Path, entry: &DirectoryEntry) -> Self {
+  fn new(
+    initial_path: &Path,
+    entry: &DirectoryEntry,
+    windows_paths: bool,
+  ) -> Self {
+    let path = if windows_paths {
+      PathBuf::from(windows_path::join(
+        &initial_path.to_string_lossy(),
+        entry.name(),
+      ))
+    } else {
+      initial_path.join(entry.name())
+    };
     Self {
       name: entry.name().to_string(),
-      path: initial_path.join(entry.name()),
+      path,
       file_type: entry.file_type(),
       len: entry.len(),
+      blocks: entry.blocks(),
       accessed: entry.accessed(),
       changed: entry.changed(),
       created: entry.created(),
@@ -990,6 +2945,7 @@ impl FsDirEntry for InMemoryDirEntry {
     Ok(InMemoryMetadata {
       file_type: self.file_type,
       len: self.len,
+      blocks: self.blocks,
       accessed: self.accessed,
       created: self.created,
       changed: self.changed,
@@ -1005,7 +2961,8 @@ impl FsDirEntry for InMemoryDirEntry {
 
 impl BaseFsRemoveDir for InMemorySys {
   fn base_fs_remove_dir(&self, path: &Path) -> io::Result<()> {
-    let mut inner = self.0.write();
+    let inner = self.0.read();
+    let case_sensitivity = inner.case_sensitivity;
     let abs_path = inner.to_absolute_path(path);
     let parent_path = match abs_path.parent() {
       Some(p) if !p.as_os_str().is_empty() => p,
@@ -1016,7 +2973,7 @@ impl BaseFsRemoveDir for InMemorySys {
         ));
       }
     };
-    let parent = inner.find_directory_mut(parent_path, false)?;
+    let parent = inner.find_directory(parent_path, None)?;
     let dir_name = match abs_path.file_name() {
       Some(n) => n.to_string_lossy(),
       None => {
@@ -1024,13 +2981,28 @@ impl BaseFsRemoveDir for InMemorySys {
       }
     };
 
-    match parent.entries.binary_search_by(|e| e.name().cmp(&dir_name)) {
-      Ok(pos) => match &parent.entries[pos] {
+    let mut entries = parent.entries.write();
+    match entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), &dir_name))
+    {
+      Ok(pos) => match &entries[pos] {
         DirectoryEntry::Directory(dir) => {
-          if !dir.entries.is_empty() {
+          // keep it locked until it's removed so nothing can be added
+          let dir_entries = dir.entries.clone();
+          let mut dir_entries = dir_entries.write();
+          if !dir_entries.is_empty() {
             return Err(Error::new(ErrorKind::Other, "Directory is not empty"));
           }
-          parent.entries.remove(pos);
+          dir_entries.unlinked = true;
+          entries.remove(pos);
+          release_quotas(
+            &inner.quotas_in(&parent, &dir_name),
+            DiskUsage {
+              bytes: 0,
+              inodes: 1,
+            },
+          );
+          parent.inner.write().touch_modified(inner.next_time());
           Ok(())
         }
         _ => Err(Error::new(ErrorKind::Other, "Not a directory")),
@@ -1042,7 +3014,8 @@ impl BaseFsRemoveDir for InMemorySys {
 
 impl BaseFsRemoveDirAll for InMemorySys {
   fn base_fs_remove_dir_all(&self, path: &Path) -> io::Result<()> {
-    let mut inner = self.0.write();
+    let inner = self.0.read();
+    let case_sensitivity = inner.case_sensitivity;
     let abs_path = inner.to_absolute_path(path);
     let parent_path = match abs_path.parent() {
       Some(p) if !p.as_os_str().is_empty() => p,
@@ -1053,7 +3026,7 @@ impl BaseFsRemoveDirAll for InMemorySys {
         ));
       }
     };
-    let parent = inner.find_directory_mut(parent_path, false)?;
+    let parent = inner.find_directory(parent_path, None)?;
     let dir_name = match abs_path.file_name() {
       Some(n) => n.to_string_lossy(),
       None => {
@@ -1061,10 +3034,26 @@ impl BaseFsRemoveDirAll for InMemorySys {
       }
     };
 
-    match parent.entries.binary_search_by(|e| e.name().cmp(&dir_name)) {
-      Ok(pos) => match &parent.entries[pos] {
-        DirectoryEntry::Directory(_) => {
-          parent.entries.remove(pos);
+    let mut entries = parent.entries.write();
+    match entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), &dir_name))
+    {
+      Ok(pos) => match &entries[pos] {
+        DirectoryEntry::Directory(dir) => {
+          unlink_directory(dir);
+          let entry = entries.remove(pos);
+          if !inner.quotas.is_empty() {
+            let usage = set_entry_quotas(&entry, &[]);
+            release_quotas(&inner.quotas_in(&parent, &dir_name), usage);
+            // nothing is left within the quotas inside the directory
+            let path = internal_directory_path(&parent).join(&*dir_name);
+            for (quota_path, quota) in &inner.quotas {
+              if case_sensitivity.starts_with(quota_path, &path) {
+                *quota.used.lock() = DiskUsage::default();
+              }
+            }
+          }
+          parent.inner.write().touch_modified(inner.next_time());
           Ok(())
         }
         _ => Err(Error::new(ErrorKind::Other, "Not a directory")),
@@ -1076,7 +3065,8 @@ impl BaseFsRemoveDirAll for InMemorySys {
 
 impl BaseFsRemoveFile for InMemorySys {
   fn base_fs_remove_file(&self, path: &Path) -> std::io::Result<()> {
-    let mut inner = self.0.write();
+    let inner = self.0.read();
+    let case_sensitivity = inner.case_sensitivity;
     let path = inner.to_absolute_path(path);
     let parent_path = match path.parent() {
       Some(p) if !p.as_os_str().is_empty() => p,
@@ -1087,7 +3077,7 @@ impl BaseFsRemoveFile for InMemorySys {
         ));
       }
     };
-    let parent = inner.find_directory_mut(parent_path, false)?;
+    let parent = inner.find_directory(parent_path, None)?;
     let file_name = match path.file_name() {
       Some(n) => n.to_string_lossy(),
       None => {
@@ -1095,16 +3085,23 @@ impl BaseFsRemoveFile for InMemorySys {
       }
     };
 
-    match parent
-      .entries
-      .binary_search_by(|e| e.name().cmp(&file_name))
+    let mut entries = parent.entries.write();
+    match entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), &file_name))
     {
-      Ok(pos) => match &parent.entries[pos] {
-        DirectoryEntry::File(_) => {
-          parent.entries.remove(pos);
+      Ok(pos) => match &entries[pos] {
+        DirectoryEntry::File(_) | DirectoryEntry::Symlink(_) => {
+          let entry = entries.remove(pos);
+          // open handles can still write to the file, but it no longer
+          // counts against quotas
+          let usage = set_entry_quotas(&entry, &[]);
+          release_quotas(&inner.quotas_in(&parent, &file_name), usage);
+          parent.inner.write().touch_modified(inner.next_time());
           Ok(())
         }
-        _ => Err(Error::new(ErrorKind::Other, "Not a file")),
+        DirectoryEntry::Directory(_) => {
+          Err(Error::new(ErrorKind::Other, "Not a file"))
+        }
       },
       Err(_) => Err(Error::new(ErrorKind::NotFound, "File not found")),
     }
@@ -1112,8 +3109,29 @@ impl BaseFsRemoveFile for InMemorySys {
 }
 
 impl BaseFsRename for InMemorySys {
+  #[inline]
   fn base_fs_rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
-    let mut inner = self.0.write();
+    self.base_fs_rename_with_options(from, to, &RenameOptions::default())
+  }
+}
+
+impl BaseFsRenameWithOptions for InMemorySys {
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> std::io::Result<()> {
+    if options.no_replace && options.exchange {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        "Cannot use no_replace and exchange together",
+      ));
+    }
+    // everything below happens under the write lock, which makes it atomic
+    let inner = self.0.write();
+    let time = inner.next_time();
+    let case_sensitivity = inner.case_sensitivity;
     let from = inner.to_absolute_path(from.as_ref());
     let to = inner.to_absolute_path(to.as_ref());
 
@@ -1157,28 +3175,40 @@ impl BaseFsRename for InMemorySys {
 
     // gather source/destination kinds up front so we can validate without
     // having to roll back a partial mutation
-    let source_is_dir = match inner.lookup_entry_detail(&from)? {
-      LookupEntry::Found(_, entry) => {
-        matches!(entry, DirectoryEntry::Directory(_))
-      }
-      LookupEntry::NotFound(_) => {
-        return Err(Error::new(ErrorKind::NotFound, "Source not found"));
-      }
-    };
+    let (source_is_dir, source_entry) =
+      match inner.lookup_entry_detail(&from)? {
+        LookupEntry::Found(_, entry) => {
+          (matches!(entry, DirectoryEntry::Directory(_)), entry)
+        }
+        LookupEntry::NotFound(_) => {
+          return Err(Error::new(ErrorKind::NotFound, "Source not found"));
+        }
+      };
 
     // prevent moving a directory into itself or one of its descendants
-    if source_is_dir && to.starts_with(&from) {
+    if source_is_dir && case_sensitivity.is_descendant(&to, &from) {
       return Err(Error::new(
         ErrorKind::Other,
         "Cannot rename a directory into itself or a subdirectory",
       ));
     }
 
+    if options.exchange {
+      return exchange_entries(
+        &inner,
+        (&from, from_parent_path, &from_file_name),
+        (&to, to_parent_path, &to_file_name),
+        time,
+      );
+    }
+
     let dest_state = match inner.lookup_entry_detail(&to)? {
+      // the destination is the source (ex. a case-only rename)
+      LookupEntry::Found(_, entry) if entry.is_same(&source_entry) => None,
       LookupEntry::Found(_, entry) => {
         let is_dir = matches!(entry, DirectoryEntry::Directory(_));
         let dir_empty = match entry {
-          DirectoryEntry::Directory(d) => d.entries.is_empty(),
+          DirectoryEntry::Directory(d) => d.entries.read().is_empty(),
           _ => false,
         };
         Some((is_dir, dir_empty))
@@ -1186,6 +3216,13 @@ impl BaseFsRename for InMemorySys {
       LookupEntry::NotFound(_) => None,
     };
 
+    if options.no_replace && dest_state.is_some() {
+      return Err(Error::new(
+        ErrorKind::AlreadyExists,
+        format!("Destination already exists: '{}'", to.display()),
+      ));
+    }
+
     if let Some((dest_is_dir, dest_dir_empty)) = dest_state {
       if source_is_dir && !dest_is_dir {
         return Err(Error::new(
@@ -1208,41 +3245,161 @@ impl BaseFsRename for InMemorySys {
     }
 
     // remove the source entry
-    let from_parent = inner.find_directory_mut(from_parent_path, false)?;
-    let from_idx = match from_parent
-      .entries
-      .binary_search_by(|e| e.name().cmp(&from_file_name))
-    {
-      Ok(pos) => pos,
-      Err(_) => {
-        return Err(Error::new(ErrorKind::NotFound, "Source not found"));
-      }
+    let from_parent = inner.find_directory(from_parent_path, None)?;
+    let (from_idx, mut entry) = {
+      let mut entries = from_parent.entries.write();
+      let from_idx = match entries
+        .binary_search_by(|e| case_sensitivity.cmp(e.name(), &from_file_name))
+      {
+        Ok(pos) => pos,
+        Err(_) => {
+          return Err(Error::new(ErrorKind::NotFound, "Source not found"));
+        }
+      };
+      (from_idx, entries.remove(from_idx))
     };
-    let mut entry = from_parent.entries.remove(from_idx);
 
     // insert into the destination parent, replacing any existing entry
-    let to_parent = inner.find_directory_mut(to_parent_path, true)?;
-    let pos = to_parent
-      .entries
-      .binary_search_by(|e| e.name().cmp(&to_file_name));
-    set_entry_name(&mut entry, to_file_name.into_owned());
+    let to_parent = inner.find_directory(to_parent_path, Some(time))?;
+    let mut entries = to_parent.entries.write();
+    let pos = entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), &to_file_name));
+    let refresh_quotas = match inner.move_quota_usage(
+      (&from_parent, &from_file_name),
+      (&to_parent, &to_file_name),
+      &entry,
+      pos.ok().map(|pos| &entries[pos]),
+    ) {
+      Ok(refresh_quotas) => refresh_quotas,
+      Err(err) => {
+        // restore the source
+        drop(entries);
+        from_parent.entries.write().insert(from_idx, entry);
+        return Err(err);
+      }
+    };
+    set_entry_location(&mut entry, &to_parent, to_file_name.into_owned());
+    entry.touch_changed(time);
     match pos {
       Ok(pos) => {
-        to_parent.entries[pos] = entry;
+        let replaced = std::mem::replace(&mut entries[pos], entry);
+        if let DirectoryEntry::Directory(dir) = &replaced {
+          unlink_directory(dir);
+        }
       }
       Err(insert_pos) => {
-        to_parent.entries.insert(insert_pos, entry);
+        entries.insert(insert_pos, entry);
       }
     }
+    drop(entries);
+    from_parent.inner.write().touch_modified(time);
+    to_parent.inner.write().touch_modified(time);
+    if refresh_quotas {
+      inner.refresh_quotas();
+    }
 
     Ok(())
   }
 }
 
-fn set_entry_name(entry: &mut DirectoryEntry, name: String) {
+/// Swaps two entries, which may be of different kinds.
+fn exchange_entries(
+  inner: &InMemorySysInner,
+  (from, from_parent_path, from_file_name): (&Path, &Path, &str),
+  (to, to_parent_path, to_file_name): (&Path, &Path, &str),
+  time: SystemTime,
+) -> Result<()> {
+  let case_sensitivity = inner.case_sensitivity;
+  if case_sensitivity.is_descendant(from, to) {
+    return Err(Error::new(
+      ErrorKind::InvalidInput,
+      "Cannot exchange a directory with one of its descendants",
+    ));
+  }
+  match inner.lookup_entry_detail(to)? {
+    LookupEntry::Found(..) => {}
+    LookupEntry::NotFound(_) => {
+      return Err(Error::new(ErrorKind::NotFound, "Destination not found"));
+    }
+  }
+
+  let not_found = || Error::new(ErrorKind::NotFound, "Source not found");
+  let from_parent = inner.find_directory(from_parent_path, None)?;
+  let (from_idx, mut from_entry) = {
+    let mut entries = from_parent.entries.write();
+    let from_idx = entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), from_file_name))
+      .map_err(|_| not_found())?;
+    (from_idx, entries.remove(from_idx))
+  };
+  let to_parent = inner.find_directory(to_parent_path, None)?;
+  set_entry_location(&mut from_entry, &to_parent, to_file_name.to_string());
+  from_entry.touch_changed(time);
+
+  let (mut to_entry, refresh_quotas) = {
+    let mut entries = to_parent.entries.write();
+    let result = entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), to_file_name))
+      // the destination was found via a symlinked parent
+      .map_err(|_| Error::new(ErrorKind::NotFound, "Destination not found"))
+      .and_then(|to_idx| {
+        let refresh_quotas = inner.exchange_quota_usage(
+          (&from_parent, from_file_name, &from_entry),
+          (&to_parent, to_file_name, &entries[to_idx]),
+        )?;
+        Ok((to_idx, refresh_quotas))
+      });
+    let (to_idx, refresh_quotas) = match result {
+      Ok(result) => result,
+      Err(err) => {
+        // restore the source
+        drop(entries);
+        set_entry_location(
+          &mut from_entry,
+          &from_parent,
+          from_file_name.to_string(),
+        );
+        from_parent.entries.write().insert(from_idx, from_entry);
+        return Err(err);
+      }
+    };
+    (
+      std::mem::replace(&mut entries[to_idx], from_entry),
+      refresh_quotas,
+    )
+  };
+  set_entry_location(&mut to_entry, &from_parent, from_file_name.to_string());
+  to_entry.touch_changed(time);
+
+  let mut entries = from_parent.entries.write();
+  let insert_idx = entries
+    .binary_search_by(|e| case_sensitivity.cmp(e.name(), from_file_name))
+    .unwrap_or_else(|idx| idx);
+  entries.insert(insert_idx, to_entry);
+  drop(entries);
+  from_parent.inner.write().touch_modified(time);
+  to_parent.inner.write().touch_modified(time);
+  if refresh_quotas {
+    inner.refresh_quotas();
+  }
+  Ok(())
+}
+
+/// Updates the name of an entry that's being moved into `parent`.
+fn set_entry_location(
+  entry: &mut DirectoryEntry,
+  parent: &Directory,
+  name: String,
+) {
   match entry {
     DirectoryEntry::File(f) => f.name = name,
-    DirectoryEntry::Directory(d) => d.name = name,
+    DirectoryEntry::Directory(d) => {
+      *d.location.write() = DirectoryLocation {
+        parent: Some(Arc::downgrade(&parent.location)),
+        name: name.clone(),
+      };
+      d.name = name;
+    }
     DirectoryEntry::Symlink(s) => s.name = name,
   }
 }
@@ -1255,14 +3412,15 @@ impl BaseFsSetFileTimes for InMemorySys {
     mtime: SystemTime,
   ) -> io::Result<()> {
     let inner = self.0.read();
-    let entry = inner.lookup_entry_detail(path)?;
+    let path = inner.to_absolute_path(path);
+    let entry = inner.lookup_entry_detail(&path)?;
     match entry {
       LookupEntry::NotFound(path_buf) => Err(Error::new(
         ErrorKind::NotFound,
         format!("Path not found: '{}'", path_buf.display()),
       )),
       LookupEntry::Found(_, directory_entry) => {
-        directory_entry.set_filetimes(atime, mtime);
+        directory_entry.set_filetimes(atime, mtime, inner.next_time());
         Ok(())
       }
     }
@@ -1277,14 +3435,14 @@ impl BaseFsSetSymlinkFileTimes for InMemorySys {
     mtime: SystemTime,
   ) -> io::Result<()> {
     let inner = self.0.read();
-    let entry = inner.lookup_entry_detail_no_follow(path)?;
+    let path = inner.to_absolute_path(path);
+    let entry = inner.lookup_entry_detail_no_follow(&path)?;
     match entry {
       LookupNoFollowEntry::Symlink { entry, .. } => {
-        let mut inner = entry.inner.write();
-        inner.accessed = atime;
-        inner.changed = atime;
-        inner.created = atime;
-        inner.modified = mtime;
+        entry
+          .inner
+          .write()
+          .set_times(atime, mtime, inner.next_time());
         Ok(())
       }
       LookupNoFollowEntry::NotFound(path) => Err(Error::new(
@@ -1292,7 +3450,7 @@ impl BaseFsSetSymlinkFileTimes for InMemorySys {
         format!("Path not found: '{}'", path.display()),
       )),
       LookupNoFollowEntry::Found(_, directory_entry) => {
-        directory_entry.set_filetimes(atime, mtime);
+        directory_entry.set_filetimes(atime, mtime, inner.next_time());
         Ok(())
       }
     }
@@ -1308,19 +3466,23 @@ impl BaseFsSetPermissions for InMemorySys {
     let inner = self.0.read();
     let path = inner.to_absolute_path(path);
     let (_, entry) = inner.lookup_entry(&path)?;
+    let time = inner.next_time();
 
     match entry {
       DirectoryEntry::File(f) => {
         let mut inner = f.inner.write();
         inner.mode = mode;
+        inner.touch_changed(time);
       }
       DirectoryEntry::Directory(d) => {
         let mut inner = d.inner.write();
         inner.mode = mode;
+        inner.touch_changed(time);
       }
       DirectoryEntry::Symlink(s) => {
         let mut inner = s.inner.write();
         inner.mode = mode;
+        inner.touch_changed(time);
       }
     }
     Ok(())
@@ -1343,17 +3505,19 @@ impl BaseFsSymlinkFile for InMemorySys {
     original: &Path,
     link: &Path,
   ) -> std::io::Result<()> {
-    let mut inner = self.0.write();
-    let time = inner.time_now();
+    let inner = self.0.read();
+    let case_sensitivity = inner.case_sensitivity;
+    let time = inner.next_time();
     let link = inner.to_absolute_path(link.as_ref());
-    let parent = inner.find_directory_mut(link.parent().unwrap(), false)?;
+    let parent = inner.find_directory(link.parent().unwrap(), None)?;
     let file_name = link.file_name().unwrap().to_string_lossy();
-    match parent
-      .entries
-      .binary_search_by(|e| e.name().cmp(&file_name))
+    let mut entries = parent.entries.write();
+    entries.check_linked()?;
+    match entries
+      .binary_search_by(|e| case_sensitivity.cmp(e.name(), &file_name))
     {
       Ok(overwrite_pos) => {
-        match &parent.entries[overwrite_pos] {
+        match &entries[overwrite_pos] {
           DirectoryEntry::Directory(directory) => {
             return Err(Error::new(
               ErrorKind::AlreadyExists,
@@ -1361,38 +3525,53 @@ impl BaseFsSymlinkFile for InMemorySys {
             ));
           }
           DirectoryEntry::File(_) | DirectoryEntry::Symlink(_) => {
-            // do nothing
+            // the symlink takes the place of the entry's inode
+            let mut usage = set_entry_quotas(&entries[overwrite_pos], &[]);
+            usage.inodes = 0;
+            release_quotas(&inner.quotas_in(&parent, &file_name), usage);
           }
         }
 
-        parent.entries[overwrite_pos] = DirectoryEntry::Symlink(Symlink {
+        entries[overwrite_pos] = DirectoryEntry::Symlink(Symlink {
           name: file_name.into_owned(),
           target: original.to_path_buf(),
-          inner: RwLock::new(SymlinkInner {
+          inner: Arc::new(RwLock::new(SymlinkInner {
             accessed: time,
             changed: time,
             created: time,
             modified: time,
             mode: 0o777,
-          }),
+            xattrs: Default::default(),
+          })),
         });
+        parent.inner.write().touch_modified(time);
         Ok(())
       }
       Err(insert_index) => {
-        parent.entries.insert(
+        charge_quotas(
+          &inner.quotas_in(&parent, &file_name),
+          DiskUsage::default(),
+          DiskUsage {
+            bytes: 0,
+            inodes: 1,
+          },
+        )?;
+        entries.insert(
           insert_index,
           DirectoryEntry::Symlink(Symlink {
             name: file_name.into_owned(),
             target: original.to_path_buf(),
-            inner: RwLock::new(SymlinkInner {
+            inner: Arc::new(RwLock::new(SymlinkInner {
               accessed: time,
               changed: time,
               created: time,
               modified: time,
               mode: 0o777,
-            }),
+              xattrs: Default::default(),
+            })),
           }),
         );
+        parent.inner.write().touch_modified(time);
         Ok(())
       }
     }
@@ -1401,90 +3580,443 @@ impl BaseFsSymlinkFile for InMemorySys {
 
 impl BaseFsWrite for InMemorySys {
   fn base_fs_write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+    // the existing data is replaced below rather than truncated so
+    // it's kept when the new data would exceed a quota
     let opts = OpenOptions {
       write: true,
       create: true,
-      truncate: true,
+      truncate: false,
       append: false,
       read: false,
       create_new: false,
       ..Default::default()
     };
-    let time_now = self.sys_time_now();
-    let file = self.fs_open(path, &opts)?;
-    let mut inner = file.inner.write();
-    inner.data.clear();
-    inner.data.extend_from_slice(data.as_ref());
-    inner.modified = time_now;
+    let time_now = self.0.read().next_time();
+    let data = FileData::new(Arc::new(data.to_vec()));
+    let (file, created) =
+      self.open_file(path, &opts, data.clone(), time_now)?;
+    if !created {
+      file.set_data(data, time_now)?;
+    }
+    Ok(())
+  }
+}
+
+impl InMemorySys {
+  /// Provides the extended attributes of the entry at the path, where
+  /// `modifies` updates the entry's change time when `f` succeeds.
+  fn with_xattrs<R>(
+    &self,
+    path: &Path,
+    follow: bool,
+    modifies: bool,
+    f: impl FnOnce(&mut BTreeMap<OsString, Vec<u8>>) -> Result<R>,
+  ) -> Result<R> {
+    let inner = self.0.read();
+    let path = inner.to_absolute_path(path);
+    let entry = if follow {
+      inner.lookup_entry(&path)?.1
+    } else {
+      match inner.lookup_entry_detail_no_follow(&path)? {
+        LookupNoFollowEntry::NotFound(path) => {
+          return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Path not found: '{}'", path.display()),
+          ));
+        }
+        LookupNoFollowEntry::Symlink { entry, .. } => {
+          DirectoryEntry::Symlink(entry)
+        }
+        LookupNoFollowEntry::Found(_, entry) => entry,
+      }
+    };
+    let result = entry.with_xattrs(f)?;
+    if modifies {
+      entry.touch_changed(inner.next_time());
+    }
+    Ok(result)
+  }
+
+  fn get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    follow: bool,
+  ) -> Result<Option<Vec<u8>>> {
+    self
+      .with_xattrs(path, follow, false, |xattrs| Ok(xattrs.get(name).cloned()))
+  }
+
+  fn list_xattr(&self, path: &Path, follow: bool) -> Result<Vec<OsString>> {
+    self.with_xattrs(path, follow, false, |xattrs| {
+      Ok(xattrs.keys().cloned().collect())
+    })
+  }
+
+  fn remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    follow: bool,
+  ) -> Result<()> {
+    self.with_xattrs(path, follow, true, |xattrs| match xattrs.remove(name) {
+      Some(_) => Ok(()),
+      None => Err(missing_xattr_error(name)),
+    })
+  }
+
+  fn set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+    follow: bool,
+  ) -> Result<()> {
+    if name.is_empty() {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        "Extended attribute name cannot be empty",
+      ));
+    }
+    self.with_xattrs(path, follow, true, |xattrs| {
+      xattrs.insert(name.to_os_string(), value.to_vec());
+      Ok(())
+    })
+  }
+}
+
+impl BaseFsGetXattr for InMemorySys {
+  #[inline]
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.get_xattr(path, name, true)
+  }
+
+  #[inline]
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.get_xattr(path, name, false)
+  }
+}
+
+impl BaseFsListXattr for InMemorySys {
+  #[inline]
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>> {
+    self.list_xattr(path, true)
+  }
+
+  #[inline]
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    self.list_xattr(path, false)
+  }
+}
+
+impl BaseFsRemoveXattr for InMemorySys {
+  #[inline]
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+    self.remove_xattr(path, name, true)
+  }
+
+  #[inline]
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()> {
+    self.remove_xattr(path, name, false)
+  }
+}
+
+impl BaseFsSetXattr for InMemorySys {
+  #[inline]
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.set_xattr(path, name, value, true)
+  }
+
+  #[inline]
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.set_xattr(path, name, value, false)
+  }
+}
+
+// File System File
+
+impl FsFileAsRaw for InMemoryFile {
+  #[cfg(windows)]
+  #[inline]
+  fn fs_file_as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+    None
+  }
+
+  #[cfg(unix)]
+  #[inline]
+  fn fs_file_as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
+    None
+  }
+}
+
+impl FsFileLock for InMemoryFile {
+  fn fs_file_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    let id = self.lock.id;
+    self
+      .lock
+      .lock
+      .lock(|holders| holders.try_acquire(id, &mode));
+    Ok(())
+  }
+
+  fn fs_file_try_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    let id = self.lock.id;
+    self
+      .lock
+      .lock
+      .try_lock(|holders| holders.try_acquire(id, &mode))
+  }
+
+  fn fs_file_unlock(&mut self) -> io::Result<()> {
+    let id = self.lock.id;
+    self.lock.lock.unlock(|holders| holders.release(id));
+    Ok(())
+  }
+}
+
+impl FsFileLockRange for InMemoryFile {
+  fn fs_file_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    let id = self.lock.id;
+    let end = lock_range_end(offset, len);
+    self
+      .lock
+      .lock
+      .lock(|holders| holders.try_acquire_range(id, &mode, offset, end));
+    Ok(())
+  }
+
+  fn fs_file_try_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    let id = self.lock.id;
+    let end = lock_range_end(offset, len);
+    self
+      .lock
+      .lock
+      .try_lock(|holders| holders.try_acquire_range(id, &mode, offset, end))
+  }
+
+  fn fs_file_unlock_range(&mut self, offset: u64, len: u64) -> io::Result<()> {
+    let id = self.lock.id;
+    let end = lock_range_end(offset, len);
+    self
+      .lock
+      .lock
+      .unlock(|holders| holders.release_range(id, offset, end));
     Ok(())
   }
 }
 
-// File System File
+impl FsFileIsTerminal for InMemoryFile {
+  #[inline]
+  fn fs_file_is_terminal(&self) -> bool {
+    false
+  }
+}
+
+impl FsFileMmap for InMemoryFile {
+  type Mmap = InMemoryMmap;
+  type MmapMut = InMemoryMmapMut;
+
+  unsafe fn fs_file_mmap(&self) -> io::Result<Self::Mmap> {
+    if !self.readable {
+      return Err(Error::new(
+        ErrorKind::PermissionDenied,
+        "File was not opened for reading",
+      ));
+    }
+    Ok(InMemoryMmap(self.inner.read().data.to_shared()))
+  }
+
+  unsafe fn fs_file_mmap_mut(&mut self) -> io::Result<Self::MmapMut> {
+    if !self.readable || !self.writable {
+      return Err(Error::new(
+        ErrorKind::PermissionDenied,
+        "File was not opened for reading and writing",
+      ));
+    }
+    let mapped = self.inner.read().data.clone();
+    Ok(InMemoryMmapMut {
+      sys: self.sys.clone(),
+      inner: self.inner.clone(),
+      buf: mapped.to_vec(),
+      mapped: Mutex::new(mapped),
+      dirty: AtomicBool::new(false),
+    })
+  }
+}
+
+/// A read-only view of an `InMemoryFile`'s data, which shares the file's
+/// buffer as it was when mapped. Later writes to the file copy the buffer
+/// so they're not visible in the view.
+#[derive(Debug, Clone)]
+pub struct InMemoryMmap(FsSharedBytes);
 
-impl FsFileAsRaw for InMemoryFile {
-  #[cfg(windows)]
-  #[inline]
-  fn fs_file_as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
-    None
-  }
+impl std::ops::Deref for InMemoryMmap {
+  type Target = [u8];
 
-  #[cfg(unix)]
   #[inline]
-  fn fs_file_as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
-    None
+  fn deref(&self) -> &[u8] {
+    &self.0
   }
 }
 
-impl FsFileLock for InMemoryFile {
+/// A writable view of an `InMemoryFile`'s data, which is a copy of the
+/// data written back to the file when flushed or dropped, so the file
+/// can still be used while it's mapped.
+#[derive(Debug)]
+pub struct InMemoryMmapMut {
+  sys: InMemorySys,
+  inner: Arc<RwLock<FileInner>>,
+  buf: Vec<u8>,
+  /// The data as of when it was mapped or last flushed, so only the
+  /// chunks changed through the map are written back.
+  mapped: Mutex<FileData>,
+  /// If the data was borrowed mutably since it was last flushed.
+  dirty: AtomicBool,
+}
+
+impl std::ops::Deref for InMemoryMmapMut {
+  type Target = [u8];
+
   #[inline]
-  fn fs_file_lock(&mut self, _mode: FsFileLockMode) -> io::Result<()> {
-    Ok(())
+  fn deref(&self) -> &[u8] {
+    &self.buf
   }
+}
+
+impl std::ops::DerefMut for InMemoryMmapMut {
   #[inline]
-  fn fs_file_try_lock(&mut self, _mode: FsFileLockMode) -> io::Result<()> {
-    Ok(())
+  fn deref_mut(&mut self) -> &mut [u8] {
+    *self.dirty.get_mut() = true;
+    &mut self.buf
   }
-  #[inline]
-  fn fs_file_unlock(&mut self) -> io::Result<()> {
+}
+
+impl FsMmapMut for InMemoryMmapMut {
+  fn fs_mmap_flush(&self) -> io::Result<()> {
+    if !self.dirty.swap(false, Ordering::Relaxed) {
+      return Ok(());
+    }
+    let mut mapped = self.mapped.lock();
+    let sys = self.sys.0.read();
+    let mut inner = self.inner.write();
+    // like a real map, bytes past the file's current length are dropped
+    let len = inner.data.len().min(self.buf.len() as u64) as usize;
+    let mut current = vec![0; COPY_CHUNK_SIZE as usize];
+    let mut changed = false;
+    for (index, chunk) in
+      self.buf[..len].chunks(COPY_CHUNK_SIZE as usize).enumerate()
+    {
+      let offset = index as u64 * COPY_CHUNK_SIZE;
+      let current = &mut current[..chunk.len()];
+      mapped.read_at(offset, current);
+      if current != chunk {
+        let stored = inner
+          .data
+          .stored_len_after_write(offset, chunk.len() as u64);
+        if let Err(err) = inner.charge_stored(stored) {
+          if changed {
+            inner.touch_modified(sys.next_time());
+          }
+          return Err(err);
+        }
+        inner.data.write_at(offset, chunk);
+        mapped.write_at(offset, chunk);
+        changed = true;
+      }
+    }
+    if changed {
+      inner.touch_modified(sys.next_time());
+    }
     Ok(())
   }
 }
 
-impl FsFileIsTerminal for InMemoryFile {
-  #[inline]
-  fn fs_file_is_terminal(&self) -> bool {
-    false
+impl Drop for InMemoryMmapMut {
+  fn drop(&mut self) {
+    let _ = self.fs_mmap_flush();
   }
 }
 
 impl FsFileSetLen for InMemoryFile {
   fn fs_file_set_len(&mut self, size: u64) -> std::io::Result<()> {
+    let time = self.sys.sys_time_now();
+    // keeps the quotas from being recomputed until the file is charged
+    let _sys = self.sys.0.read();
     let mut inner = self.inner.write();
-    inner.data.resize(size as usize, 0);
+    let stored = inner.data.stored_len_after_set_len(size);
+    inner.charge_stored(stored)?;
+    inner.data.set_len(size);
+    inner.touch_modified(time);
     Ok(())
   }
 }
 
 impl FsFileMetadata for InMemoryFile {
   fn fs_file_metadata(&self) -> std::io::Result<BoxedFsMetadataValue> {
-    self
-      .sys
-      .base_fs_metadata(&self.path)
-      .map(BoxedFsMetadataValue::new)
+    // the path is already resolved, so look it up directly
+    let inner = self.sys.0.read();
+    let (_, entry) = inner.lookup_entry(&self.path)?;
+    Ok(BoxedFsMetadataValue::new(InMemoryMetadata {
+      file_type: entry.file_type(),
+      len: entry.len(),
+      blocks: entry.blocks(),
+      accessed: entry.accessed(),
+      changed: entry.changed(),
+      created: entry.created(),
+      modified: entry.modified(),
+      mode: entry.mode(),
+    }))
   }
 }
 
 impl FsFileSetPermissions for InMemoryFile {
   fn fs_file_set_permissions(&mut self, mode: u32) -> std::io::Result<()> {
+    let time = self.sys.sys_time_now();
     let mut inner = self.inner.write();
     inner.mode = mode;
+    inner.touch_changed(time);
     Ok(())
   }
 }
 
 impl FsFileSetTimes for InMemoryFile {
   fn fs_file_set_times(&mut self, times: FsFileTimes) -> std::io::Result<()> {
+    let time = self.sys.sys_time_now();
     let mut inner = self.inner.write();
     if let Some(accessed) = times.accessed {
       inner.accessed = accessed;
@@ -1492,6 +4024,7 @@ impl FsFileSetTimes for InMemoryFile {
     if let Some(modified) = times.modified {
       inner.modified = modified;
     }
+    inner.touch_changed(time);
     Ok(())
   }
 }
@@ -1514,7 +4047,7 @@ impl std::io::Seek for InMemoryFile {
   fn seek(&mut self, pos: std::io::SeekFrom) -> Result<u64> {
     match pos {
       std::io::SeekFrom::Start(n) => {
-        self.pos = n as usize;
+        self.pos = n;
       }
       std::io::SeekFrom::End(n) => {
         let inner = self.inner.read();
@@ -1524,26 +4057,29 @@ impl std::io::Seek for InMemoryFile {
             "Seeking before start of file",
           ));
         }
-        self.pos = (inner.data.len() as i64 + n) as usize;
+        self.pos = (inner.data.len() as i64 + n) as u64;
       }
       std::io::SeekFrom::Current(n) => {
-        self.pos = self.pos.wrapping_add(n as usize);
+        self.pos = self.pos.wrapping_add(n as u64);
       }
     }
-    Ok(self.pos as u64)
+    Ok(self.pos)
   }
 }
 
 impl std::io::Write for InMemoryFile {
   fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
     let time = self.sys.sys_time_now();
+    // keeps the quotas from being recomputed until the file is charged
+    let _sys = self.sys.0.read();
     let mut inner = self.inner.write();
-    if self.pos > inner.data.len() {
-      inner.data.resize(self.pos, 0);
-    }
-    inner.data.splice(self.pos.., buf.as_ref().iter().cloned());
-    inner.modified = time;
-    self.pos += buf.as_ref().len();
+    let stored = inner
+      .data
+      .stored_len_after_write(self.pos, buf.len() as u64);
+    inner.charge_stored(stored)?;
+    inner.data.write_at(self.pos, buf);
+    inner.touch_modified(time);
+    self.pos += buf.len() as u64;
     Ok(buf.len())
   }
 
@@ -1554,14 +4090,14 @@ impl std::io::Write for InMemoryFile {
 
 impl std::io::Read for InMemoryFile {
   fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-    let inner = self.inner.read();
-    if self.pos > inner.data.len() {
+    if buf.is_empty() {
       return Ok(0);
     }
-    let data = &inner.data[self.pos..];
-    let len = std::cmp::min(data.len(), buf.len());
-    buf[..len].copy_from_slice(&data[..len]);
-    self.pos += len;
+    let (mode, time) = self.sys.read_time();
+    let mut inner = self.inner.write();
+    let len = inner.data.read_at(self.pos, buf);
+    inner.touch_accessed(mode, time);
+    self.pos += len as u64;
     Ok(len)
   }
 }
@@ -1570,25 +4106,17 @@ impl std::io::Read for InMemoryFile {
 
 impl SystemTimeNow for InMemorySys {
   fn sys_time_now(&self) -> SystemTime {
-    self.0.read().time_now()
+    self.0.read().next_time()
   }
 }
 
 impl SystemRandom for InMemorySys {
   fn sys_random(&self, buf: &mut [u8]) -> std::io::Result<()> {
-    fn random_with_seed(seed: u64, buf: &mut [u8]) {
-      // not the best, but good enough for now
-      let mut state = seed;
-      for byte in buf.iter_mut() {
-        // simple linear congruential generator
-        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
-        *byte = (state >> 24) as u8; // use the top 8 bits
-      }
-    }
-
-    match self.0.read().random_seed {
-      Some(seed) => {
-        random_with_seed(seed, buf);
+    let inner = self.0.read();
+    let mut random = inner.random.lock();
+    match random.as_mut() {
+      Some(random) => {
+        random.fill(buf);
         Ok(())
       }
       None => {
@@ -1599,7 +4127,7 @@ impl SystemRandom for InMemorySys {
         }
         #[cfg(not(feature = "getrandom"))]
         {
-          random_with_seed(0, buf);
+          random.insert(SplitMix64(0)).fill(buf);
           Ok(())
         }
       }
@@ -1615,12 +4143,137 @@ impl ProcessExit for InMemorySys {
 
 impl ThreadSleep for InMemorySys {
   fn thread_sleep(&self, dur: std::time::Duration) {
-    if self.0.read().thread_sleep_enabled {
+    let inner = self.0.read();
+    if inner.thread_sleep_enabled {
+      drop(inner);
       std::thread::sleep(dur);
+    } else {
+      inner.clock.lock().advance(dur);
     }
   }
 }
 
+/// Gets the normal components of an internal path.
+fn internal_components(path: &Path) -> Vec<String> {
+  path
+    .components()
+    .filter_map(|c| match c {
+      Component::Normal(c) => Some(c.to_string_lossy().into_owned()),
+      _ => None,
+    })
+    .collect()
+}
+
+/// Gets the number of components making up the drive or UNC share
+/// of an internal path when emulating Windows.
+fn internal_prefix_len(components: &[String]) -> usize {
+  if components.first().map(|c| c.as_str()) == Some("UNC") {
+    components.len().min(3)
+  } else {
+    components.len().min(1)
+  }
+}
+
+/// Gets the drive letter of an internal path when emulating Windows.
+fn internal_drive_letter(path: &Path) -> Option<char> {
+  let components = internal_components(path);
+  let first = components.first()?;
+  let mut chars = first.chars();
+  match (chars.next(), chars.next()) {
+    (Some(letter), None) => Some(letter),
+    _ => None,
+  }
+}
+
+/// Gets if the options ask to not follow a symlink in the final component
+/// (`O_NOFOLLOW`).
+fn is_no_follow(options: &OpenOptions) -> bool {
+  #[cfg(all(unix, feature = "libc"))]
+  {
+    options.no_follow
+      || options
+        .custom_flags
+        .is_some_and(|flags| flags & libc::O_NOFOLLOW != 0)
+  }
+  #[cfg(not(all(unix, feature = "libc")))]
+  {
+    options.no_follow
+  }
+}
+
+/// Maximum number of symlinks followed when resolving a path, which
+/// matches Linux's `MAXSYMLINKS`.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Creates the error a real file system surfaces when it encounters too
+/// many levels of symlinks (`ELOOP`).
+fn symlink_loop_error(path: &Path) -> Error {
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  const CODE: Option<i32> = Some(40);
+  #[cfg(any(
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+  ))]
+  const CODE: Option<i32> = Some(62);
+  // ERROR_CANT_RESOLVE_FILENAME
+  #[cfg(windows)]
+  const CODE: Option<i32> = Some(1921);
+  #[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly",
+    windows
+  )))]
+  const CODE: Option<i32> = None;
+
+  match CODE {
+    // use the raw OS error so the error kind matches the real one
+    Some(code) => Error::from_raw_os_error(code),
+    None => Error::new(
+      ErrorKind::Other,
+      format!(
+        "Too many levels of symbolic links resolving '{}'",
+        path.display()
+      ),
+    ),
+  }
+}
+
+/// Creates the error a real file system surfaces when removing an
+/// extended attribute that doesn't exist (`ENODATA`/`ENOATTR`).
+fn missing_xattr_error(name: &OsStr) -> Error {
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  const CODE: Option<i32> = Some(61);
+  #[cfg(any(target_vendor = "apple", target_os = "netbsd"))]
+  const CODE: Option<i32> = Some(93);
+  #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+  const CODE: Option<i32> = Some(87);
+  #[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "netbsd",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+  )))]
+  const CODE: Option<i32> = None;
+
+  match CODE {
+    Some(code) => Error::from_raw_os_error(code),
+    None => Error::new(
+      ErrorKind::NotFound,
+      format!("Extended attribute not found: '{}'", name.to_string_lossy()),
+    ),
+  }
+}
+
 /// Normalize all intermediate components of the path (ie. remove "./" and "../" components).
 /// Similar to `fs::canonicalize()` but doesn't resolve symlinks.
 ///
@@ -1659,7 +4312,9 @@ fn normalize_path(path: &Path) -> PathBuf {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use std::io::Read;
   use std::io::Seek;
+  use std::io::SeekFrom;
   use std::io::Write;
   use std::path::Path;
   use std::time::Duration;
@@ -1712,7 +4367,7 @@ mod tests {
     let file = sys.fs_open(file_path, &opts).unwrap();
     // file is truncated at open, so should be empty
     let guard = file.inner.read();
-    assert!(guard.data.is_empty());
+    assert_eq!(guard.data.len(), 0);
   }
 
   #[test]
@@ -1769,6 +4424,96 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_timestamps() {
+    let sys = InMemorySys::new_with_cwd("/");
+    let start = SystemTime::UNIX_EPOCH;
+    let at = |secs| start + Duration::from_secs(secs);
+    sys.set_time(Some(start));
+    sys.fs_create_dir_all("/dir").unwrap();
+    sys.fs_write("/dir/file.txt", "data").unwrap();
+
+    // creating, renaming and removing entries updates the parent directory
+    sys.set_time(Some(at(1)));
+    sys.fs_write("/dir/other.txt", "").unwrap();
+    let dir = sys.fs_metadata("/dir").unwrap();
+    assert_eq!(dir.modified().unwrap(), at(1));
+    assert_eq!(dir.changed().unwrap(), at(1));
+    sys.set_time(Some(at(2)));
+    sys.fs_rename("/dir/other.txt", "/other.txt").unwrap();
+    assert_eq!(sys.fs_metadata("/dir").unwrap().modified().unwrap(), at(2));
+    assert_eq!(
+      sys.fs_metadata("/other.txt").unwrap().changed().unwrap(),
+      at(2)
+    );
+    assert_eq!(
+      sys.fs_metadata("/other.txt").unwrap().modified().unwrap(),
+      at(1)
+    );
+    sys.set_time(Some(at(3)));
+    sys.fs_remove_file("/other.txt").unwrap();
+    assert_eq!(sys.fs_metadata("/").unwrap().modified().unwrap(), at(3));
+
+    // writes update the modified and changed times
+    sys.set_time(Some(at(4)));
+    sys.fs_write("/dir/file.txt", "new data").unwrap();
+    let metadata = sys.fs_metadata("/dir/file.txt").unwrap();
+    assert_eq!(metadata.modified().unwrap(), at(4));
+    assert_eq!(metadata.changed().unwrap(), at(4));
+    assert_eq!(metadata.accessed().unwrap(), start);
+    assert_eq!(metadata.created().unwrap(), start);
+
+    // metadata changes only update the changed time
+    sys.set_time(Some(at(5)));
+    sys.fs_set_permissions("/dir/file.txt", 0o600).unwrap();
+    let metadata = sys.fs_metadata("/dir/file.txt").unwrap();
+    assert_eq!(metadata.modified().unwrap(), at(4));
+    assert_eq!(metadata.changed().unwrap(), at(5));
+
+    // relatime only updates the access time when it's older than the
+    // modified or changed time
+    sys.set_time(Some(at(6)));
+    sys.fs_read("/dir/file.txt").unwrap();
+    let accessed = || {
+      sys
+        .fs_metadata("/dir/file.txt")
+        .unwrap()
+        .accessed()
+        .unwrap()
+    };
+    assert_eq!(accessed(), at(6));
+    sys.set_time(Some(at(7)));
+    sys.fs_read("/dir/file.txt").unwrap();
+    assert_eq!(accessed(), at(6));
+    sys.set_time(Some(at(6 + 24 * 60 * 60)));
+    sys.fs_read("/dir/file.txt").unwrap();
+    assert_eq!(accessed(), at(6 + 24 * 60 * 60));
+
+    // posix updates the access time on every read
+    sys.set_timestamp_mode(InMemoryTimestampMode::Posix);
+    sys.set_time(Some(at(7 + 24 * 60 * 60)));
+    sys.fs_read("/dir/file.txt").unwrap();
+    assert_eq!(accessed(), at(7 + 24 * 60 * 60));
+
+    // noatime never updates it
+    sys.set_timestamp_mode(InMemoryTimestampMode::Noatime);
+    sys.fs_write("/dir/file.txt", "data").unwrap();
+    sys.set_time(Some(at(8 + 24 * 60 * 60)));
+    sys.fs_read("/dir/file.txt").unwrap();
+    assert_eq!(accessed(), at(7 + 24 * 60 * 60));
+
+    // setting the times explicitly keeps the creation time
+    sys.set_time(Some(at(100_000)));
+    sys
+      .fs_set_file_times("/dir/file.txt", at(10), at(20))
+      .unwrap();
+    let metadata = sys.fs_metadata("/dir/file.txt").unwrap();
+    assert_eq!(metadata.accessed().unwrap(), at(10));
+    assert_eq!(metadata.modified().unwrap(), at(20));
+    assert_eq!(metadata.changed().unwrap(), at(100_000));
+    assert_eq!(metadata.created().unwrap(), start);
+  }
+
   #[test]
   fn test_exists_no_err() {
     let sys = InMemorySys::default();
@@ -1899,6 +4644,39 @@ mod tests {
     assert_ne!(buf2, [0u8; 8]);
   }
 
+  #[test]
+  fn test_sys_random_seed() {
+    let sys = InMemorySys::default();
+    sys.set_seed(Some(42));
+    let first = sys.sys_random_u64().unwrap();
+    let second = sys.sys_random_u64().unwrap();
+    assert_ne!(first, second);
+    // setting the seed restarts the sequence
+    sys.set_seed(Some(42));
+    assert_eq!(sys.sys_random_u64().unwrap(), first);
+    assert_eq!(sys.sys_random_u64().unwrap(), second);
+    // partial reads still advance it
+    let mut buf = [0u8; 3];
+    sys.sys_random(&mut buf).unwrap();
+    assert_ne!(sys.sys_random_u64().unwrap(), first);
+
+    for _ in 0..100 {
+      let value = sys.sys_random_range(10..13).unwrap();
+      assert!((10..13).contains(&value));
+    }
+    assert_eq!(sys.sys_random_range(5..6).unwrap(), 5);
+    assert_eq!(
+      sys.sys_random_range(5..5).unwrap_err().kind(),
+      ErrorKind::InvalidInput
+    );
+
+    let mut items = (0..20).collect::<Vec<_>>();
+    sys.sys_random_shuffle(&mut items).unwrap();
+    assert_ne!(items, (0..20).collect::<Vec<_>>());
+    items.sort();
+    assert_eq!(items, (0..20).collect::<Vec<_>>());
+  }
+
   #[test]
   fn test_thread_sleep_no_op() {
     let sys = InMemorySys::default();
@@ -1913,6 +4691,68 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_thread_sleep_advances_set_time() {
+    let sys = InMemorySys::default();
+    sys.disable_thread_sleep();
+    sys.set_time(Some(SystemTime::UNIX_EPOCH));
+    sys.thread_sleep(Duration::from_secs(5));
+    assert_eq!(
+      sys.sys_time_now(),
+      SystemTime::UNIX_EPOCH + Duration::from_secs(5)
+    );
+    // stops advancing instead of overflowing
+    sys.thread_sleep(Duration::MAX);
+    assert_eq!(
+      sys.sys_time_now(),
+      SystemTime::UNIX_EPOCH + Duration::from_secs(5)
+    );
+  }
+
+  #[test]
+  fn test_time_step() {
+    let sys = InMemorySys::new_with_cwd("/");
+    let start = SystemTime::UNIX_EPOCH;
+    let at = |secs| start + Duration::from_secs(secs);
+    // does nothing without a set time
+    sys.set_time_step(Duration::from_secs(1));
+    sys.advance_time(Duration::from_secs(1));
+    assert!(sys.sys_time_now() > at(1_000_000));
+
+    sys.set_time(Some(start));
+    assert_eq!(sys.sys_time_now(), at(0));
+    assert_eq!(sys.sys_time_now(), at(1));
+    // each change advances the time once while reads don't advance it
+    sys.fs_write("/a.txt", "a").unwrap();
+    sys.fs_write("/b.txt", "b").unwrap();
+    assert_eq!(sys.fs_read_to_string("/a.txt").unwrap(), "a");
+    sys
+      .fs_create_dir("/dir", &CreateDirOptions::default())
+      .unwrap();
+    sys.fs_rename("/a.txt", "/dir/a.txt").unwrap();
+    let times = |path: &str| {
+      let metadata = sys.fs_metadata(path).unwrap();
+      (metadata.modified().unwrap(), metadata.changed().unwrap())
+    };
+    assert_eq!(times("/dir/a.txt"), (at(2), at(5)));
+    assert_eq!(times("/b.txt"), (at(3), at(3)));
+    assert_eq!(times("/dir"), (at(5), at(5)));
+    assert_eq!(times("/"), (at(5), at(5)));
+
+    sys.advance_time(Duration::from_secs(100));
+    sys.fs_remove_file("/b.txt").unwrap();
+    assert_eq!(times("/"), (at(106), at(106)));
+    assert_eq!(sys.sys_time_now(), at(107));
+
+    // a zero step keeps the time fixed
+    sys.set_time(Some(start));
+    sys.set_time_step(Duration::ZERO);
+    assert_eq!(sys.sys_time_now(), at(0));
+    assert_eq!(sys.sys_time_now(), at(0));
+    sys.advance_time(Duration::from_secs(5));
+    assert_eq!(sys.sys_time_now(), at(5));
+  }
+
   #[test]
   fn test_rename_file_to_existing_file() {
     let sys = InMemorySys::default();
@@ -1927,93 +4767,739 @@ mod tests {
   }
 
   #[test]
-  fn test_rename_directory() {
-    let sys = InMemorySys::default();
-    sys.fs_create_dir_all("/a/old").unwrap();
-    sys.fs_write("/a/old/inner.txt", b"hi").unwrap();
-    sys.fs_create_dir_all("/a/old/nested").unwrap();
-    sys.fs_write("/a/old/nested/deep.txt", b"deep").unwrap();
-
-    sys.fs_rename("/a/old", "/a/new").unwrap();
+  fn test_rename_directory() {
+    let sys = InMemorySys::default();
+    sys.fs_create_dir_all("/a/old").unwrap();
+    sys.fs_write("/a/old/inner.txt", b"hi").unwrap();
+    sys.fs_create_dir_all("/a/old/nested").unwrap();
+    sys.fs_write("/a/old/nested/deep.txt", b"deep").unwrap();
+
+    sys.fs_rename("/a/old", "/a/new").unwrap();
+
+    assert!(!sys.fs_exists_no_err("/a/old"));
+    assert!(sys.fs_is_dir_no_err("/a/new"));
+    assert_eq!(&*sys.fs_read_to_string("/a/new/inner.txt").unwrap(), "hi");
+    assert_eq!(
+      &*sys.fs_read_to_string("/a/new/nested/deep.txt").unwrap(),
+      "deep"
+    );
+  }
+
+  #[test]
+  fn test_rename_directory_across_parents() {
+    let sys = InMemorySys::default();
+    sys.fs_create_dir_all("/src/inner").unwrap();
+    sys.fs_write("/src/inner/x.txt", b"x").unwrap();
+    sys.fs_create_dir_all("/dst").unwrap();
+
+    sys.fs_rename("/src/inner", "/dst/inner").unwrap();
+
+    assert!(!sys.fs_exists_no_err("/src/inner"));
+    assert!(sys.fs_is_dir_no_err("/dst/inner"));
+    assert_eq!(&*sys.fs_read_to_string("/dst/inner/x.txt").unwrap(), "x");
+  }
+
+  #[test]
+  fn test_rename_directory_onto_empty_directory() {
+    let sys = InMemorySys::default();
+    sys.fs_create_dir_all("/from").unwrap();
+    sys.fs_write("/from/keep.txt", b"keep").unwrap();
+    sys.fs_create_dir_all("/to").unwrap();
+
+    sys.fs_rename("/from", "/to").unwrap();
+
+    assert!(!sys.fs_exists_no_err("/from"));
+    assert_eq!(&*sys.fs_read_to_string("/to/keep.txt").unwrap(), "keep");
+  }
+
+  #[test]
+  fn test_rename_directory_onto_non_empty_directory_fails() {
+    let sys = InMemorySys::default();
+    sys.fs_create_dir_all("/from").unwrap();
+    sys.fs_create_dir_all("/to").unwrap();
+    sys.fs_write("/to/keeper.txt", b"keep").unwrap();
+
+    let err = sys.fs_rename("/from", "/to").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    assert!(sys.fs_is_dir_no_err("/from"));
+    assert!(sys.fs_exists_no_err("/to/keeper.txt"));
+  }
+
+  #[test]
+  fn test_rename_directory_into_itself_fails() {
+    let sys = InMemorySys::default();
+    sys.fs_create_dir_all("/a").unwrap();
+    let err = sys.fs_rename("/a", "/a/b").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
+    assert!(sys.fs_is_dir_no_err("/a"));
+  }
+
+  #[test]
+  fn test_rename_directory_onto_file_fails() {
+    let sys = InMemorySys::default();
+    sys.fs_create_dir_all("/dir").unwrap();
+    sys.fs_write("/file", b"data").unwrap();
+    let err = sys.fs_rename("/dir", "/file").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
+    assert!(sys.fs_is_dir_no_err("/dir"));
+    assert!(sys.fs_is_file_no_err("/file"));
+  }
+
+  #[test]
+  fn test_rename_file_onto_directory_fails() {
+    let sys = InMemorySys::default();
+    sys.fs_create_dir_all("/dir").unwrap();
+    sys.fs_write("/file", b"data").unwrap();
+    let err = sys.fs_rename("/file", "/dir").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
+    assert!(sys.fs_is_file_no_err("/file"));
+    assert!(sys.fs_is_dir_no_err("/dir"));
+  }
+
+  #[test]
+  fn test_rename_no_replace() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_write("/a", b"a").unwrap();
+    sys.fs_write("/b", b"b").unwrap();
+    let options = RenameOptions::new_no_replace();
+    let err = sys
+      .fs_rename_with_options("/a", "/b", &options)
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(sys.fs_read_to_string("/b").unwrap(), "b");
+    sys.fs_rename_with_options("/a", "/c", &options).unwrap();
+    assert!(!sys.fs_exists_no_err("/a"));
+    assert_eq!(sys.fs_read_to_string("/c").unwrap(), "a");
+  }
+
+  #[test]
+  fn test_rename_exchange() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_create_dir_all("/dir/sub").unwrap();
+    sys.fs_write("/dir/sub/file", b"nested").unwrap();
+    sys.fs_write("/file", b"data").unwrap();
+    let options = RenameOptions::new_exchange();
+    sys
+      .fs_rename_with_options("/file", "/dir", &options)
+      .unwrap();
+    assert_eq!(sys.fs_read_to_string("/dir").unwrap(), "data");
+    assert_eq!(sys.fs_read_to_string("/file/sub/file").unwrap(), "nested");
+
+    // across parents
+    sys
+      .fs_rename_with_options("/dir", "/file/sub/file", &options)
+      .unwrap();
+    assert_eq!(sys.fs_read_to_string("/dir").unwrap(), "nested");
+    assert_eq!(sys.fs_read_to_string("/file/sub/file").unwrap(), "data");
+
+    let err = sys
+      .fs_rename_with_options("/dir", "/missing", &options)
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let err = sys
+      .fs_rename_with_options("/file/sub/file", "/file", &options)
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn test_symlink_loop() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_create_dir_all("/dir").unwrap();
+    sys.fs_write("/dir/file", b"data").unwrap();
+    sys.fs_symlink_file("/b", "/a").unwrap();
+    sys.fs_symlink_file("/a", "/b").unwrap();
+    sys.fs_symlink_dir("/self", "/self").unwrap();
+    let assert_loop = |err: Error| {
+      #[cfg(all(unix, feature = "libc"))]
+      assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+      #[cfg(not(all(unix, feature = "libc")))]
+      assert_eq!(err.kind(), symlink_loop_error(Path::new("/")).kind());
+    };
+    assert_loop(sys.fs_read("/a").unwrap_err());
+    assert_loop(sys.fs_metadata("/a").unwrap_err());
+    assert_loop(sys.fs_read("/self/file").unwrap_err());
+    assert_loop(sys.fs_symlink_metadata("/self/file").unwrap_err());
+    // the final component isn't followed
+    assert!(sys
+      .fs_symlink_metadata("/a")
+      .unwrap()
+      .file_type()
+      .is_symlink());
+
+    // symlinks in the middle of a path are resolved with the rest of it
+    sys.fs_symlink_dir("/", "/root").unwrap();
+    assert_eq!(
+      sys.fs_read_to_string("/root/root/root/dir/file").unwrap(),
+      "data"
+    );
+    // but only up to the hop limit
+    let mut path = PathBuf::from("/");
+    for _ in 0..=MAX_SYMLINK_HOPS {
+      path.push("root");
+    }
+    assert_loop(sys.fs_read(path.join("dir/file")).unwrap_err());
+    path.pop();
+    assert_eq!(
+      sys.fs_read_to_string(path.join("dir/file")).unwrap(),
+      "data"
+    );
+  }
+
+  #[test]
+  fn test_open_symlink() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_write("/file", b"data").unwrap();
+    sys.fs_symlink_file("/file", "/link").unwrap();
+    sys.fs_symlink_file("/created", "/dangling").unwrap();
+    assert_eq!(sys.fs_read_to_string("/link").unwrap(), "data");
+    sys.fs_write("/link", b"new").unwrap();
+    assert_eq!(sys.fs_read_to_string("/file").unwrap(), "new");
+    // writing through a dangling symlink creates the target
+    sys.fs_write("/dangling", b"created").unwrap();
+    assert_eq!(sys.fs_read_to_string("/created").unwrap(), "created");
+
+    let err = sys
+      .fs_open("/link", OpenOptions::new_write().create_new())
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    #[cfg(all(unix, feature = "libc"))]
+    {
+      let mut options = OpenOptions::new_read();
+      options.custom_flags(libc::O_NOFOLLOW);
+      let err = sys.fs_open("/link", &options).unwrap_err();
+      assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+      assert!(sys.fs_open("/file", &options).is_ok());
+    }
+  }
+
+  #[test]
+  fn test_open_no_follow_and_directory() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_create_dir_all("/dir").unwrap();
+    sys.fs_write("/dir/file", b"data").unwrap();
+    sys.fs_symlink_file("/dir/file", "/link").unwrap();
+    sys.fs_symlink_dir("/dir", "/dir_link").unwrap();
+
+    let err = sys
+      .fs_open("/link", OpenOptions::new_read().no_follow())
+      .unwrap_err();
+    #[cfg(all(unix, feature = "libc"))]
+    assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+    #[cfg(not(all(unix, feature = "libc")))]
+    assert_eq!(err.kind(), symlink_loop_error(Path::new("/link")).kind());
+    // only the final component is affected
+    let mut file = sys
+      .fs_open("/dir_link/file", OpenOptions::new_read().no_follow())
+      .unwrap();
+    let mut text = String::new();
+    std::io::Read::read_to_string(&mut file, &mut text).unwrap();
+    assert_eq!(text, "data");
+
+    let err = sys
+      .fs_open("/dir/file", OpenOptions::new_read().directory())
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotADirectory);
+    let err = sys
+      .fs_open("/missing", OpenOptions::new_read().directory())
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    // unlike RealSys, directories can't be opened as files
+    let err = sys
+      .fs_open("/dir", OpenOptions::new_read().directory())
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+  }
+
+  #[test]
+  fn test_concurrent_operations() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_create_dir_all("/shared").unwrap();
+    let threads = (0..8)
+      .map(|i| {
+        let sys = sys.clone();
+        std::thread::spawn(move || {
+          let root = PathBuf::from(format!("/thread{i}"));
+          for j in 0..100 {
+            let dir = root.join(format!("dir{}", j % 5));
+            sys.fs_create_dir_all(&dir).unwrap();
+            let file = dir.join(format!("file{j}"));
+            sys.fs_write(&file, format!("{i}-{j}")).unwrap();
+            let mut opened =
+              sys.fs_open(&file, &OpenOptions::new_append()).unwrap();
+            opened.write_all(b"!").unwrap();
+            let renamed = dir.join(format!("renamed{j}"));
+            sys.fs_rename(&file, &renamed).unwrap();
+            let link = dir.join(format!("link{j}"));
+            sys.fs_symlink_file(&renamed, &link).unwrap();
+            assert_eq!(
+              sys.fs_read_to_string(&link).unwrap(),
+              format!("{i}-{j}!")
+            );
+            sys.fs_remove_file(&link).unwrap();
+            if j % 10 == 9 {
+              sys.fs_remove_dir_all(&dir).unwrap();
+            }
+            // every thread also adds to the same directory
+            sys.fs_write(format!("/shared/{i}-{j}"), "data").unwrap();
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    for thread in threads {
+      thread.join().unwrap();
+    }
+
+    assert_eq!(sys.fs_read_dir("/shared").unwrap().count(), 800);
+    for i in 0..8 {
+      let root = PathBuf::from(format!("/thread{i}"));
+      let names = sys
+        .fs_read_dir(&root)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_owned())
+        .collect::<Vec<_>>();
+      // the last directory was removed every ten iterations
+      assert_eq!(names, vec!["dir0", "dir1", "dir2", "dir3"]);
+      for name in names {
+        assert_eq!(sys.fs_read_dir(root.join(name)).unwrap().count(), 20);
+      }
+    }
+  }
+
+  #[test]
+  fn test_concurrent_remove_dir() {
+    let sys = InMemorySys::new_with_cwd("/");
+    for i in 0..200 {
+      let dir = format!("/dir{i}");
+      sys.fs_create_dir_all(&dir).unwrap();
+      let barrier = Arc::new(std::sync::Barrier::new(2));
+      let remover = {
+        let sys = sys.clone();
+        let dir = dir.clone();
+        let barrier = barrier.clone();
+        std::thread::spawn(move || {
+          barrier.wait();
+          sys.fs_remove_dir(&dir).is_ok()
+        })
+      };
+      barrier.wait();
+      let file = format!("{dir}/file");
+      let written = sys.fs_write(&file, "data").is_ok();
+      let removed = remover.join().unwrap();
+      // either the file was created first so the directory isn't empty,
+      // or the directory was removed first so the file can't be created
+      assert_ne!(written, removed);
+      assert_eq!(sys.fs_exists_no_err(&file), written);
+    }
 
-    assert!(!sys.fs_exists_no_err("/a/old"));
-    assert!(sys.fs_is_dir_no_err("/a/new"));
-    assert_eq!(&*sys.fs_read_to_string("/a/new/inner.txt").unwrap(), "hi");
+    // entries can't be added anywhere in a removed tree, even by
+    // operations that found the directories beforehand
+    sys.fs_create_dir_all("/tree/a/b").unwrap();
+    let dir = sys
+      .0
+      .read()
+      .find_directory(Path::new("/tree/a/b"), None)
+      .unwrap();
+    let writer = {
+      let sys = sys.clone();
+      std::thread::spawn(move || {
+        for i in 0.. {
+          if let Err(err) = sys.fs_write(format!("/tree/a/b/{i}"), "") {
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+            break;
+          }
+        }
+      })
+    };
+    sys.fs_remove_dir_all("/tree").unwrap();
+    writer.join().unwrap();
+    assert!(dir.entries.read().unlinked);
     assert_eq!(
-      &*sys.fs_read_to_string("/a/new/nested/deep.txt").unwrap(),
-      "deep"
+      dir.entries.read().check_linked().unwrap_err().kind(),
+      ErrorKind::NotFound
     );
+    assert!(!sys.fs_exists_no_err("/tree"));
   }
 
   #[test]
-  fn test_rename_directory_across_parents() {
-    let sys = InMemorySys::default();
-    sys.fs_create_dir_all("/src/inner").unwrap();
-    sys.fs_write("/src/inner/x.txt", b"x").unwrap();
-    sys.fs_create_dir_all("/dst").unwrap();
-
-    sys.fs_rename("/src/inner", "/dst/inner").unwrap();
-
-    assert!(!sys.fs_exists_no_err("/src/inner"));
-    assert!(sys.fs_is_dir_no_err("/dst/inner"));
-    assert_eq!(&*sys.fs_read_to_string("/dst/inner/x.txt").unwrap(), "x");
+  fn test_file_lock() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_write("/file", b"data").unwrap();
+    let open = || sys.fs_open("/file", &OpenOptions::new_read()).unwrap();
+    let mut a = open();
+    let mut b = open();
+
+    a.fs_file_try_lock(FsFileLockMode::Shared).unwrap();
+    b.fs_file_try_lock(FsFileLockMode::Shared).unwrap();
+    let err = b.fs_file_try_lock(FsFileLockMode::Exclusive).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    a.fs_file_unlock().unwrap();
+    // converts the shared lock
+    b.fs_file_try_lock(FsFileLockMode::Exclusive).unwrap();
+    let err = a.fs_file_try_lock(FsFileLockMode::Shared).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+    // clones share the lock and it's released once they're all dropped
+    let mut b_clone = b.clone();
+    b_clone.fs_file_try_lock(FsFileLockMode::Exclusive).unwrap();
+    drop(b);
+    let err = a.fs_file_try_lock(FsFileLockMode::Shared).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    drop(b_clone);
+    a.fs_file_try_lock(FsFileLockMode::Exclusive).unwrap();
+
+    // blocks until released on another thread
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn({
+      let mut file = open();
+      move || {
+        file.fs_file_lock(FsFileLockMode::Exclusive).unwrap();
+        sender.send(()).unwrap();
+      }
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+    a.fs_file_unlock().unwrap();
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    handle.join().unwrap();
+    // the thread's file was dropped
+    a.fs_file_try_lock(FsFileLockMode::Exclusive).unwrap();
+
+    // downgrading wakes threads waiting for a shared lock
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn({
+      let mut file = open();
+      move || {
+        file.fs_file_lock(FsFileLockMode::Shared).unwrap();
+        sender.send(()).unwrap();
+      }
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+    a.fs_file_try_lock(FsFileLockMode::Shared).unwrap();
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    handle.join().unwrap();
   }
 
   #[test]
-  fn test_rename_directory_onto_empty_directory() {
-    let sys = InMemorySys::default();
-    sys.fs_create_dir_all("/from").unwrap();
-    sys.fs_write("/from/keep.txt", b"keep").unwrap();
-    sys.fs_create_dir_all("/to").unwrap();
+  fn test_file_lock_range() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_write("/file", b"data").unwrap();
+    let open = || sys.fs_open("/file", &OpenOptions::new_read()).unwrap();
+    let mut a = open();
+    let mut b = open();
+    let assert_would_block = |result: io::Result<()>| {
+      assert_eq!(result.unwrap_err().kind(), ErrorKind::WouldBlock);
+    };
 
-    sys.fs_rename("/from", "/to").unwrap();
+    a.fs_file_try_lock_range(FsFileLockMode::Exclusive, 0, 10)
+      .unwrap();
+    // non-overlapping ranges and whole file locks don't conflict
+    b.fs_file_try_lock_range(FsFileLockMode::Exclusive, 10, 10)
+      .unwrap();
+    b.fs_file_try_lock(FsFileLockMode::Exclusive).unwrap();
+    assert_would_block(b.fs_file_try_lock_range(FsFileLockMode::Shared, 5, 10));
 
-    assert!(!sys.fs_exists_no_err("/from"));
-    assert_eq!(&*sys.fs_read_to_string("/to/keep.txt").unwrap(), "keep");
+    // unlocking the middle splits the lock
+    a.fs_file_unlock_range(3, 4).unwrap();
+    b.fs_file_try_lock_range(FsFileLockMode::Shared, 3, 4)
+      .unwrap();
+    assert_would_block(b.fs_file_try_lock_range(FsFileLockMode::Shared, 2, 1));
+    assert_would_block(b.fs_file_try_lock_range(FsFileLockMode::Shared, 7, 1));
+    // shared locks can overlap
+    a.fs_file_try_lock_range(FsFileLockMode::Shared, 3, 4)
+      .unwrap();
+
+    // a length of 0 extends to the end of the file and beyond
+    b.fs_file_try_lock_range(FsFileLockMode::Exclusive, 20, 0)
+      .unwrap();
+    assert_would_block(a.fs_file_try_lock_range(
+      FsFileLockMode::Shared,
+      u64::MAX - 1,
+      1,
+    ));
+    drop(b);
+    a.fs_file_try_lock_range(FsFileLockMode::Exclusive, 0, 0)
+      .unwrap();
+
+    // blocks until released on another thread
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn({
+      let mut file = open();
+      move || {
+        file
+          .fs_file_lock_range(FsFileLockMode::Shared, 100, 1)
+          .unwrap();
+        sender.send(()).unwrap();
+      }
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+    a.fs_file_unlock_range(50, 100).unwrap();
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    handle.join().unwrap();
+
+    // downgrading wakes threads waiting for a shared lock
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn({
+      let mut file = open();
+      move || {
+        file
+          .fs_file_lock_range(FsFileLockMode::Shared, 0, 1)
+          .unwrap();
+        sender.send(()).unwrap();
+      }
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+    a.fs_file_try_lock_range(FsFileLockMode::Shared, 0, 10)
+      .unwrap();
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    handle.join().unwrap();
   }
 
   #[test]
-  fn test_rename_directory_onto_non_empty_directory_fails() {
-    let sys = InMemorySys::default();
-    sys.fs_create_dir_all("/from").unwrap();
-    sys.fs_create_dir_all("/to").unwrap();
-    sys.fs_write("/to/keeper.txt", b"keep").unwrap();
+  fn test_file_mmap() {
+    let sys = InMemorySys::new_with_cwd("/");
+    let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    sys.set_time(Some(at(0)));
+    sys.fs_write("/file", b"data").unwrap();
+    let options = OpenOptions {
+      read: true,
+      write: true,
+      ..Default::default()
+    };
+    let mut file = sys.fs_open("/file", &options).unwrap();
+    // SAFETY: the file is only accessed through one map at a time
+    let map = unsafe { file.fs_file_mmap() }.unwrap();
+    assert_eq!(&*map, b"data");
+    // shares the file's buffer without copying
+    assert!(map.0.ptr_eq(&file.inner.read().data.to_shared()));
+
+    sys.set_time(Some(at(1)));
+    let modified = || sys.fs_metadata("/file").unwrap().modified().unwrap();
+    {
+      let mut map_mut = unsafe { file.fs_file_mmap_mut() }.unwrap();
+      assert_eq!(&*map_mut, b"data");
+      map_mut[0] = b'D';
+      // the file can be used while mapped, and changes are written back
+      // when flushed
+      assert_eq!(modified(), at(0));
+      assert_eq!(sys.fs_read_to_string("/file").unwrap(), "data");
+      map_mut.fs_mmap_flush().unwrap();
+      assert_eq!(sys.fs_read_to_string("/file").unwrap(), "Data");
+      // or dropped, but not past the file's current length
+      map_mut[3] = b'A';
+      map_mut[1] = b'A';
+      file.fs_file_set_len(2).unwrap();
+    }
+    assert_eq!(sys.fs_read_to_string("/file").unwrap(), "DA");
+    assert_eq!(modified(), at(1));
+    // the read-only view is of the data when it was mapped
+    assert_eq!(&*map, b"data");
+    // the times only change when written through
+    sys.set_time(Some(at(2)));
+    drop(unsafe { file.fs_file_mmap_mut() }.unwrap());
+    assert_eq!(modified(), at(1));
+
+    // maps require the file to be opened with matching access
+    let write_only = sys.fs_open("/file", &OpenOptions::new_append()).unwrap();
+    let err = unsafe { write_only.fs_file_mmap() }.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    let mut read_only = sys.fs_open("/file", &OpenOptions::new_read()).unwrap();
+    let err = unsafe { read_only.fs_file_mmap_mut() }.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+    file.fs_file_set_len(0).unwrap();
+    assert!(unsafe { file.fs_file_mmap() }.unwrap().is_empty());
+  }
 
-    let err = sys.fs_rename("/from", "/to").unwrap_err();
-    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
-    assert!(sys.fs_is_dir_no_err("/from"));
-    assert!(sys.fs_exists_no_err("/to/keeper.txt"));
+  #[test]
+  fn test_sparse_file() {
+    const GIB: u64 = 1024 * 1024 * 1024;
+    let sys = InMemorySys::new_with_cwd("/");
+    let options = OpenOptions {
+      read: true,
+      write: true,
+      create: true,
+      ..Default::default()
+    };
+    let mut file = sys.fs_open("/file", &options).unwrap();
+    // holes don't use any memory
+    file.fs_file_set_len(10 * GIB).unwrap();
+    let metadata = sys.fs_metadata("/file").unwrap();
+    assert_eq!(metadata.len(), 10 * GIB);
+    assert_eq!(metadata.blocks().unwrap(), 0);
+
+    file.seek(SeekFrom::Start(5 * GIB)).unwrap();
+    file.write_all(b"data").unwrap();
+    assert_eq!(sys.fs_metadata("/file").unwrap().blocks().unwrap(), 8);
+    file.seek(SeekFrom::Start(5 * GIB - 2)).unwrap();
+    let mut buf = [1; 6];
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"\0\0data");
+
+    // writing past the end extends the file
+    file.seek(SeekFrom::End(GIB as i64)).unwrap();
+    file.write_all(b"end").unwrap();
+    assert_eq!(file.fs_file_metadata().unwrap().len(), 11 * GIB + 3);
+    assert_eq!(sys.fs_metadata("/file").unwrap().blocks().unwrap(), 16);
+
+    file.fs_file_set_len(5 * GIB + 2).unwrap();
+    assert_eq!(sys.fs_metadata("/file").unwrap().blocks().unwrap(), 8);
+    file.seek(SeekFrom::Start(5 * GIB)).unwrap();
+    let mut text = String::new();
+    file.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "da");
+
+    // copies keep the holes
+    assert_eq!(sys.fs_copy("/file", "/copy").unwrap(), 5 * GIB + 2);
+    let metadata = sys.fs_metadata("/copy").unwrap();
+    assert_eq!(metadata.len(), 5 * GIB + 2);
+    assert_eq!(metadata.blocks().unwrap(), 8);
   }
 
   #[test]
-  fn test_rename_directory_into_itself_fails() {
-    let sys = InMemorySys::default();
-    sys.fs_create_dir_all("/a").unwrap();
-    let err = sys.fs_rename("/a", "/a/b").unwrap_err();
-    assert_eq!(err.kind(), ErrorKind::Other);
-    assert!(sys.fs_is_dir_no_err("/a"));
+  fn test_file_data_write_at() {
+    let mut data = FileData::new(Arc::new(b"hello world".to_vec()));
+    // overwrites without truncating
+    data.write_at(0, b"J");
+    assert_eq!(data.to_vec(), b"Jello world");
+
+    // writing to shared data only copies the chunks that are written to
+    let chunk = COPY_CHUNK_SIZE as usize;
+    let mut data = FileData::new(Arc::new(vec![1; chunk * 3]));
+    let shared = data.clone();
+    data.write_at(chunk as u64 + 5, b"x");
+    assert_eq!(data.runs.len(), 3);
+    assert_eq!(data.runs[&(chunk as u64)].len, chunk);
+    assert!(Arc::ptr_eq(&data.runs[&0].buf, &shared.runs[&0].buf));
+    assert!(Arc::ptr_eq(
+      &data.runs[&(chunk as u64 * 2)].buf,
+      &shared.runs[&0].buf
+    ));
+    // and later writes to the copied chunk are made in place
+    data.write_at(chunk as u64 + 6, b"y");
+    assert_eq!(data.runs.len(), 3);
+    let mut expected = vec![1; chunk * 3];
+    expected[chunk + 5..chunk + 7].copy_from_slice(b"xy");
+    assert_eq!(data.to_vec(), expected);
+    assert_eq!(shared.to_vec(), vec![1; chunk * 3]);
+
+    let mut data = FileData::default();
+    data.write_at(0, b"aaaa");
+    data.write_at(10, b"bbbb");
+    assert_eq!(data.runs.len(), 2);
+    let mut buf = [9; 14];
+    assert_eq!(data.read_at(0, &mut buf), 14);
+    assert_eq!(&buf, b"aaaa\0\0\0\0\0\0bbbb");
+    // joins the runs it overlaps
+    data.write_at(2, b"cccccccccc");
+    assert_eq!(data.runs.len(), 1);
+    assert_eq!(data.to_vec(), b"aaccccccccccbb");
+    // and runs it touches
+    data.write_at(14, b"d");
+    assert_eq!(data.runs.len(), 1);
+    assert_eq!(data.to_vec(), b"aaccccccccccbbd");
   }
 
   #[test]
-  fn test_rename_directory_onto_file_fails() {
-    let sys = InMemorySys::default();
-    sys.fs_create_dir_all("/dir").unwrap();
+  fn test_read_shared() {
+    let sys = InMemorySys::new_with_cwd("/");
     sys.fs_write("/file", b"data").unwrap();
-    let err = sys.fs_rename("/dir", "/file").unwrap_err();
-    assert_eq!(err.kind(), ErrorKind::Other);
-    assert!(sys.fs_is_dir_no_err("/dir"));
-    assert!(sys.fs_is_file_no_err("/file"));
+    let first = sys.fs_read_shared("/file").unwrap();
+    let second = sys.fs_read_shared("/file").unwrap();
+    assert_eq!(&*first, b"data");
+    assert!(first.ptr_eq(&second));
+
+    // mutations copy the buffer rather than changing what was read
+    let mut file = sys.fs_open("/file", &OpenOptions::new_append()).unwrap();
+    file.write_all(b"!").unwrap();
+    assert_eq!(&*first, b"data");
+    let third = sys.fs_read_shared("/file").unwrap();
+    assert_eq!(&*third, b"data!");
+    assert!(!first.ptr_eq(&third));
+    drop(file);
+    assert_eq!(first.into_vec(), b"data");
+
+    // truncating keeps sharing the buffer
+    let mut file = sys.fs_open("/file", &OpenOptions::new_append()).unwrap();
+    file.fs_file_set_len(2).unwrap();
+    let fourth = sys.fs_read_shared("/file").unwrap();
+    assert_eq!(&*fourth, b"da");
+    assert!(Arc::ptr_eq(&fourth.data, &third.data));
+    assert_eq!(&*third, b"data!");
+
+    let err = sys.fs_read_shared("/missing").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
   }
 
   #[test]
-  fn test_rename_file_onto_directory_fails() {
-    let sys = InMemorySys::default();
+  fn test_open_dir() {
+    let sys = InMemorySys::new_with_cwd("/");
     sys.fs_create_dir_all("/dir").unwrap();
+    sys.fs_write("/dir/file", b"data").unwrap();
+    let handle = sys.fs_open_dir("/dir").unwrap();
+
+    // the handle keeps referring to the directory after it's moved
+    sys.fs_rename("/dir", "/moved").unwrap();
+    let mut file = handle.open_at("file", &OpenOptions::new_read()).unwrap();
+    let mut text = String::new();
+    std::io::Read::read_to_string(&mut file, &mut text).unwrap();
+    assert_eq!(text, "data");
+    assert!(handle.metadata_at("file").unwrap().file_type().is_file());
+
+    handle.create_dir_at("sub").unwrap();
+    assert!(sys.fs_is_dir("/moved/sub").unwrap());
+    let sub = handle.open_dir_at("sub").unwrap();
+    handle.rename_at("file", &sub, "renamed").unwrap();
+    assert_eq!(sys.fs_read_to_string("/moved/sub/renamed").unwrap(), "data");
+    sub.symlink_at("renamed", "link").unwrap();
+    assert!(sub
+      .symlink_metadata_at("link")
+      .unwrap()
+      .file_type()
+      .is_symlink());
+    let mut names = sub
+      .read_dir()
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_owned())
+      .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["link", "renamed"]);
+
+    sub.remove_at("link").unwrap();
+    sub.remove_at("renamed").unwrap();
+    handle.remove_at("sub").unwrap();
+    assert!(!sys.fs_exists("/moved/sub").unwrap());
+
+    // moving an ancestor or exchanging it keeps the handle working
+    handle.create_dir_at("sub").unwrap();
+    let sub = handle.open_dir_at("sub").unwrap();
+    sys.fs_create_dir_all("/other").unwrap();
+    sys.fs_rename("/moved", "/other/moved").unwrap();
+    sub.create_dir_at("a").unwrap();
+    assert!(sys.fs_is_dir("/other/moved/sub/a").unwrap());
+    sys.fs_create_dir_all("/swap").unwrap();
+    sys
+      .fs_rename_with_options(
+        "/other",
+        "/swap",
+        &RenameOptions {
+          exchange: true,
+          ..Default::default()
+        },
+      )
+      .unwrap();
+    sub.create_dir_at("b").unwrap();
+    assert!(sys.fs_is_dir("/swap/moved/sub/b").unwrap());
+
+    let err = sys.fs_open_dir("/missing").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
     sys.fs_write("/file", b"data").unwrap();
-    let err = sys.fs_rename("/file", "/dir").unwrap_err();
-    assert_eq!(err.kind(), ErrorKind::Other);
-    assert!(sys.fs_is_file_no_err("/file"));
-    assert!(sys.fs_is_dir_no_err("/dir"));
+    let err = sys.fs_open_dir("/file").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotADirectory);
+    sys.fs_remove_dir_all("/swap/moved").unwrap();
+    let err = handle.metadata_at("file").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let err = sub.create_dir_at("c").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
   }
 
   #[test]
@@ -2252,6 +5738,545 @@ mod tests {
     assert!(!sys.fs_exists_no_err("/test"));
   }
 
+  #[test]
+  fn test_remove_file_symlink() {
+    let sys = InMemorySys::default();
+    sys.fs_insert("/test/file.txt", "content");
+    sys
+      .fs_symlink_file("/test/file.txt", "/test/link.txt")
+      .unwrap();
+    sys.fs_remove_file("/test/link.txt").unwrap();
+    assert!(!sys.fs_exists_no_err("/test/link.txt"));
+    assert!(sys.fs_exists_no_err("/test/file.txt"));
+    assert!(sys.fs_remove_file("/test").is_err());
+  }
+
+  #[test]
+  fn test_case_insensitive() {
+    let sys = InMemorySys::default();
+    sys.set_case_insensitive(true);
+    sys.fs_insert("/Project/Src/Main.rs", "fn main() {}");
+    assert_eq!(
+      sys.fs_read_to_string("/project/src/main.RS").unwrap(),
+      "fn main() {}"
+    );
+    assert_eq!(
+      sys.fs_canonicalize("/PROJECT/src/main.rs").unwrap(),
+      PathBuf::from("/Project/Src/Main.rs")
+    );
+
+    // writing to a differently cased path overwrites the existing file
+    // and keeps the original casing
+    sys.fs_write("/project/src/MAIN.rs", "changed").unwrap();
+    sys.fs_create_dir_all("/PROJECT/SRC").unwrap();
+    let entries = sys
+      .fs_read_dir("/project/src")
+      .unwrap()
+      .map(|e| e.unwrap().file_name().into_owned())
+      .collect::<Vec<_>>();
+    assert_eq!(entries, vec![OsString::from("Main.rs")]);
+    assert_eq!(
+      sys.fs_read_to_string("/Project/Src/Main.rs").unwrap(),
+      "changed"
+    );
+    let mut options = OpenOptions::new_write();
+    options.create_new = true;
+    assert_eq!(
+      sys
+        .fs_open("/project/src/main.rs", &options)
+        .unwrap_err()
+        .kind(),
+      ErrorKind::AlreadyExists
+    );
+
+    // sorting is case-insensitive
+    sys.fs_write("/project/b.txt", "").unwrap();
+    sys.fs_write("/project/C.txt", "").unwrap();
+    sys.fs_write("/project/a.txt", "").unwrap();
+    let entries = sys
+      .fs_read_dir("/project")
+      .unwrap()
+      .map(|e| e.unwrap().file_name().into_owned())
+      .collect::<Vec<_>>();
+    assert_eq!(entries, vec!["a.txt", "b.txt", "C.txt", "Src"]);
+
+    // case-only rename updates the casing
+    sys.fs_rename("/project/a.txt", "/project/A.txt").unwrap();
+    sys.fs_rename("/project/src", "/project/SRC").unwrap();
+    assert_eq!(
+      sys.fs_canonicalize("/project/a.txt").unwrap(),
+      PathBuf::from("/Project/A.txt")
+    );
+    assert_eq!(
+      sys.fs_canonicalize("/project/src/main.rs").unwrap(),
+      PathBuf::from("/Project/SRC/Main.rs")
+    );
+    // renaming onto a differently cased existing file replaces it
+    sys.fs_rename("/project/b.txt", "/project/c.TXT").unwrap();
+    assert!(!sys.fs_exists_no_err("/project/b.txt"));
+    assert_eq!(
+      sys.fs_canonicalize("/project/c.txt").unwrap(),
+      PathBuf::from("/Project/c.TXT")
+    );
+    // can't move a directory into itself
+    assert!(sys.fs_rename("/project/src", "/PROJECT/Src/inner").is_err());
+
+    sys.fs_remove_file("/PROJECT/A.TXT").unwrap();
+    sys.fs_remove_dir_all("/project/src").unwrap();
+    assert!(!sys.fs_exists_no_err("/Project/SRC"));
+  }
+
+  #[test]
+  fn test_case_sensitive_by_default() {
+    let sys = InMemorySys::default();
+    sys.fs_insert("/dir/File.txt", "a");
+    sys.fs_insert("/dir/file.txt", "b");
+    assert!(!sys.fs_exists_no_err("/dir/FILE.txt"));
+    assert_eq!(sys.fs_read_to_string("/dir/File.txt").unwrap(), "a");
+    assert_eq!(sys.fs_read_to_string("/dir/file.txt").unwrap(), "b");
+  }
+
+  #[test]
+  fn test_windows_paths() {
+    let sys = InMemorySys::new_windows_with_cwd(r"C:\Users\test");
+    assert_eq!(
+      sys.env_current_dir().unwrap(),
+      PathBuf::from(r"C:\Users\test")
+    );
+
+    // separators, relative paths, and casing
+    sys.fs_insert(r"C:\Users\test\dir\file.txt", "a");
+    assert_eq!(sys.fs_read_to_string(r"dir\file.txt").unwrap(), "a");
+    assert_eq!(
+      sys.fs_read_to_string("c:/users/TEST/dir/file.txt").unwrap(),
+      "a"
+    );
+    assert_eq!(
+      sys
+        .fs_read_to_string(r"\Users\test\.\dir\..\dir\file.txt")
+        .unwrap(),
+      "a"
+    );
+    assert_eq!(
+      sys
+        .fs_read_to_string(r"\\?\C:\Users\test\dir\file.txt")
+        .unwrap(),
+      "a"
+    );
+    // can't go above the drive root
+    assert_eq!(
+      sys
+        .fs_read_to_string(r"C:\..\..\Users\test\dir\file.txt")
+        .unwrap(),
+      "a"
+    );
+
+    // read dir uses the provided path
+    let entries = sys
+      .fs_read_dir(r"C:\Users\test")
+      .unwrap()
+      .map(|e| e.unwrap().path().into_owned())
+      .collect::<Vec<_>>();
+    assert_eq!(entries, vec![PathBuf::from(r"C:\Users\test\dir")]);
+
+    // per-drive current directory
+    sys.fs_insert(r"D:\other\data.txt", "d");
+    assert_eq!(sys.fs_read_to_string(r"C:dir\file.txt").unwrap(), "a");
+    sys.env_set_current_dir(r"D:\other").unwrap();
+    assert_eq!(sys.env_current_dir().unwrap(), PathBuf::from(r"D:\other"));
+    assert_eq!(sys.fs_read_to_string("data.txt").unwrap(), "d");
+    assert_eq!(sys.fs_read_to_string(r"C:dir\file.txt").unwrap(), "a");
+    sys.env_set_current_dir("C:").unwrap();
+    assert_eq!(
+      sys.env_current_dir().unwrap(),
+      PathBuf::from(r"C:\Users\test")
+    );
+
+    // unc paths
+    sys.fs_insert(r"\\server\share\file.txt", "unc");
+    assert_eq!(
+      sys
+        .fs_read_to_string(r"\\?\UNC\SERVER\share\file.txt")
+        .unwrap(),
+      "unc"
+    );
+    assert!(!sys.fs_exists_no_err(r"\\server\other\file.txt"));
+
+    // symlinks
+    sys
+      .fs_symlink_file(r"D:\other\data.txt", "link.txt")
+      .unwrap();
+    assert!(sys.fs_is_file("link.txt").unwrap());
+    assert_eq!(
+      sys.fs_read_link("link.txt").unwrap(),
+      PathBuf::from(r"D:\other\data.txt")
+    );
+
+    // canonicalize returns a verbatim path unless strip_unc is enabled
+    let canonicalized = sys.fs_canonicalize(r"dir\FILE.TXT").unwrap();
+    let unc_canonicalized =
+      sys.fs_canonicalize(r"\\server\share\file.txt").unwrap();
+    if cfg!(feature = "strip_unc") {
+      assert_eq!(canonicalized, PathBuf::from(r"C:\Users\test\dir\file.txt"));
+      assert_eq!(unc_canonicalized, PathBuf::from(r"\\server\share\file.txt"));
+    } else {
+      assert_eq!(
+        canonicalized,
+        PathBuf::from(r"\\?\C:\Users\test\dir\file.txt")
+      );
+      assert_eq!(
+        unc_canonicalized,
+        PathBuf::from(r"\\?\UNC\server\share\file.txt")
+      );
+    }
+
+    assert_eq!(sys.env_temp_dir().unwrap(), PathBuf::from(r"C:\tmp"));
+  }
+
+  #[test]
+  fn test_quota() {
+    let sys = InMemorySys::new_with_cwd("/dir");
+    sys.set_quota(Some(InMemoryQuota {
+      max_bytes: Some(10),
+      max_inodes: None,
+    }));
+    assert_eq!(
+      sys.fs_disk_space("/dir").unwrap(),
+      DiskSpace {
+        total: 10,
+        free: 10,
+        available: 10,
+      }
+    );
+
+    sys.fs_write("file.txt", "123456").unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 4);
+    let err = sys.fs_copy("file.txt", "copy.txt").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert!(!sys.fs_exists_no_err("copy.txt"));
+    let err = sys.fs_write("file.txt", "a".repeat(11)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert_eq!(sys.fs_read_to_string("file.txt").unwrap(), "123456");
+    // overwriting only counts the growth
+    sys.fs_write("file.txt", "1234567890").unwrap();
+
+    let mut file = sys.fs_open("file.txt", &OpenOptions::new_append()).unwrap();
+    assert_eq!(file.write(b"a").unwrap_err().kind(), ErrorKind::StorageFull);
+    // extending the file only adds a hole
+    file.fs_file_set_len(11).unwrap();
+    file.fs_file_set_len(5).unwrap();
+    file.seek(std::io::SeekFrom::End(0)).unwrap();
+    file.write_all(b"abc").unwrap();
+    assert_eq!(sys.fs_read_to_string("file.txt").unwrap(), "12345abc");
+
+    sys.set_quota(None);
+    sys.fs_write("file.txt", "a".repeat(100)).unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, u64::MAX);
+  }
+
+  #[test]
+  fn test_quota_sparse_file() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.set_quota(Some(InMemoryQuota {
+      max_bytes: Some(10),
+      max_inodes: None,
+    }));
+    let options = OpenOptions {
+      read: true,
+      write: true,
+      create: true,
+      ..Default::default()
+    };
+    let mut file = sys.fs_open("/file", &options).unwrap();
+    // holes aren't counted
+    file.seek(SeekFrom::Start(1_000_000)).unwrap();
+    file.write_all(b"a").unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 9);
+    file.fs_file_set_len(2_000_000).unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 9);
+
+    // but filling them in is
+    file.seek(SeekFrom::Start(999_996)).unwrap();
+    file.write_all(b"bbbb").unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 5);
+    file.seek(SeekFrom::Start(999_996)).unwrap();
+    let err = file.write_all(b"ccccccccccc").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    // overwriting stored bytes isn't
+    file.seek(SeekFrom::Start(999_996)).unwrap();
+    file.write_all(b"ccccc").unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 5);
+
+    // copies count what's stored
+    sys.fs_copy("/file", "/copy").unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 0);
+    file.fs_file_set_len(999_998).unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 3);
+  }
+
+  #[test]
+  fn test_path_quota() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_create_dir_all("/mount").unwrap();
+    sys.set_path_quota(
+      "/mount",
+      Some(InMemoryQuota {
+        max_bytes: Some(100),
+        max_inodes: Some(2),
+      }),
+    );
+
+    sys
+      .fs_create_dir("/mount/sub", &CreateDirOptions::default())
+      .unwrap();
+    sys.fs_write("/mount/sub/a.txt", "a").unwrap();
+    let err = sys.fs_write("/mount/b.txt", "b").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    let err = sys
+      .fs_symlink_file("/mount/sub/a.txt", "/mount/link")
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    let err = sys.fs_create_dir_all("/mount/sub/x/y").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    // existing files may still be written
+    sys.fs_write("/mount/sub/a.txt", "updated").unwrap();
+    assert_eq!(sys.fs_disk_space("/mount/sub").unwrap().free, 93);
+
+    let stat = sys.fs_statvfs("/mount/sub/a.txt").unwrap();
+    assert_eq!(stat.total_bytes, 100);
+    assert_eq!(stat.free_bytes, 93);
+    assert_eq!(stat.total_inodes, 2);
+    assert_eq!(stat.free_inodes, 0);
+
+    // outside the quota
+    sys.fs_write("/other.txt", "a".repeat(200)).unwrap();
+    assert_eq!(sys.fs_disk_space("/").unwrap().total, u64::MAX);
+
+    // removing entries frees their space
+    sys.fs_remove_dir_all("/mount/sub").unwrap();
+    let stat = sys.fs_statvfs("/mount").unwrap();
+    assert_eq!(stat.free_bytes, 100);
+    assert_eq!(stat.free_inodes, 2);
+  }
+
+  #[test]
+  fn test_quota_rename() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_create_dir_all("/mount").unwrap();
+    sys.set_path_quota(
+      "/mount",
+      Some(InMemoryQuota {
+        max_bytes: Some(10),
+        max_inodes: None,
+      }),
+    );
+    sys.fs_write("/mount/a.txt", "12345").unwrap();
+    sys.fs_write("/big.txt", "12345678901").unwrap();
+
+    // moving data into the directory counts against its quota
+    let err = sys.fs_rename("/big.txt", "/mount/big.txt").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert!(sys.fs_exists_no_err("/big.txt"));
+    assert!(!sys.fs_exists_no_err("/mount/big.txt"));
+    let err = sys
+      .fs_rename_with_options(
+        "/big.txt",
+        "/mount/a.txt",
+        &RenameOptions {
+          exchange: true,
+          ..Default::default()
+        },
+      )
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert_eq!(sys.fs_read_to_string("/mount/a.txt").unwrap(), "12345");
+    // replacing a file only counts the growth
+    sys.fs_write("/b.txt", "1234567").unwrap();
+    sys.fs_rename("/b.txt", "/mount/a.txt").unwrap();
+    assert_eq!(sys.fs_disk_space("/mount").unwrap().free, 3);
+
+    // open files count against the quota they were moved to
+    let mut file = sys
+      .fs_open("/mount/a.txt", &OpenOptions::new_append())
+      .unwrap();
+    sys.fs_rename("/mount/a.txt", "/a.txt").unwrap();
+    assert_eq!(sys.fs_disk_space("/mount").unwrap().free, 10);
+    file.write_all(&[0; 20]).unwrap();
+    sys.fs_write("/mount/c.txt", "1234567890").unwrap();
+    assert_eq!(sys.fs_disk_space("/mount").unwrap().free, 0);
+    let err = sys.fs_rename("/a.txt", "/mount/a.txt").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+
+    // the quota follows its path rather than the directory
+    sys.fs_rename("/mount", "/moved").unwrap();
+    assert_eq!(sys.fs_disk_space("/moved").unwrap().total, u64::MAX);
+    assert_eq!(sys.fs_disk_space("/").unwrap().total, u64::MAX);
+    sys.fs_create_dir_all("/mount").unwrap();
+    assert_eq!(sys.fs_disk_space("/mount").unwrap().free, 10);
+    sys.fs_rename("/moved/c.txt", "/mount/c.txt").unwrap();
+    assert_eq!(sys.fs_disk_space("/mount").unwrap().free, 0);
+  }
+
+  #[test]
+  fn test_quota_concurrent_writes() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.set_quota(Some(InMemoryQuota {
+      max_bytes: Some(1000),
+      max_inodes: None,
+    }));
+    std::thread::scope(|scope| {
+      for i in 0..8 {
+        let sys = sys.clone();
+        scope.spawn(move || {
+          let options = OpenOptions {
+            create: true,
+            ..OpenOptions::new_append()
+          };
+          let mut file = sys.fs_open(format!("/{}.txt", i), &options).unwrap();
+          while file.write(&[0; 7]).is_ok() {}
+        });
+      }
+    });
+    let total = (0..8)
+      .map(|i| sys.fs_metadata(format!("/{}.txt", i)).unwrap().len())
+      .sum::<u64>();
+    assert!(total <= 1000 && total > 1000 - 7);
+    assert_eq!(sys.fs_disk_space("/").unwrap().free, 1000 - total);
+  }
+
+  #[test]
+  fn test_copy_with_options() {
+    let sys = InMemorySys::new_with_cwd("/");
+    let start = SystemTime::UNIX_EPOCH;
+    sys.set_time(Some(start));
+    sys.fs_write("/file.txt", "data").unwrap();
+    sys.fs_symlink_file("/file.txt", "/link").unwrap();
+    sys.set_time(Some(start + Duration::from_secs(10)));
+
+    let progress = std::cell::Cell::new(0);
+    let on_progress = |bytes| progress.set(bytes);
+    let mut options = CopyOptions::new();
+    options.preserve_times = true;
+    options.on_progress = Some(&on_progress);
+    assert_eq!(
+      sys
+        .fs_copy_with_options("/file.txt", "/copy.txt", &options)
+        .unwrap(),
+      4
+    );
+    assert_eq!(progress.get(), 4);
+    assert_eq!(sys.fs_read_to_string("/copy.txt").unwrap(), "data");
+    assert_eq!(
+      sys.fs_metadata("/copy.txt").unwrap().modified().unwrap(),
+      start
+    );
+
+    let mut options = CopyOptions::new();
+    options.create_new = true;
+    let err = sys
+      .fs_copy_with_options("/file.txt", "/copy.txt", &options)
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    // symlinks are followed unless copy_symlinks is set
+    sys
+      .fs_copy_with_options("/link", "/followed", &CopyOptions::new())
+      .unwrap();
+    assert!(sys
+      .fs_symlink_metadata("/followed")
+      .unwrap()
+      .file_type()
+      .is_file());
+    options.copy_symlinks = true;
+    assert_eq!(
+      sys
+        .fs_copy_with_options("/link", "/link2", &options)
+        .unwrap(),
+      0
+    );
+    assert_eq!(
+      sys.fs_read_link("/link2").unwrap(),
+      PathBuf::from("/file.txt")
+    );
+  }
+
+  #[test]
+  fn test_clone_file() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_write("/file.txt", "data").unwrap();
+    sys.fs_clone_file("/file.txt", "/cloned.txt").unwrap();
+    let from = sys.fs_open("/file.txt", &OpenOptions::new_read()).unwrap();
+    let to = sys
+      .fs_open("/cloned.txt", &OpenOptions::new_read())
+      .unwrap();
+    assert!(from
+      .inner
+      .read()
+      .data
+      .to_shared()
+      .ptr_eq(&to.inner.read().data.to_shared()));
+
+    // writing to the clone leaves the original untouched
+    let mut file = sys
+      .fs_open("/cloned.txt", &OpenOptions::new_append())
+      .unwrap();
+    file.write_all(b"!").unwrap();
+    assert_eq!(sys.fs_read_to_string("/file.txt").unwrap(), "data");
+    assert_eq!(sys.fs_read_to_string("/cloned.txt").unwrap(), "data!");
+
+    let err = sys.fs_clone_file("/file.txt", "/cloned.txt").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    sys.fs_create_dir_all("/dir").unwrap();
+    let err = sys.fs_clone_file("/dir", "/dir2").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
+  }
+
+  #[test]
+  fn test_xattrs() {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.fs_write("/file.txt", "data").unwrap();
+    sys.fs_symlink_file("/file.txt", "/link").unwrap();
+
+    sys.fs_set_xattr("/link", "user.hash", b"abc").unwrap();
+    sys
+      .fs_symlink_set_xattr("/link", "user.link", b"1")
+      .unwrap();
+    assert_eq!(
+      sys.fs_get_xattr("/file.txt", "user.hash").unwrap(),
+      Some(b"abc".to_vec())
+    );
+    assert_eq!(sys.fs_get_xattr("/file.txt", "user.link").unwrap(), None);
+    assert_eq!(
+      sys.fs_list_xattr("/link").unwrap(),
+      vec![OsString::from("user.hash")]
+    );
+    assert_eq!(
+      sys.fs_symlink_list_xattr("/link").unwrap(),
+      vec![OsString::from("user.link")]
+    );
+
+    // preserved when renamed
+    sys.fs_rename("/file.txt", "/renamed.txt").unwrap();
+    sys.fs_set_xattr("/renamed.txt", "user.other", b"").unwrap();
+    assert_eq!(
+      sys.fs_list_xattr("/renamed.txt").unwrap(),
+      vec![OsString::from("user.hash"), OsString::from("user.other")]
+    );
+    sys.fs_remove_xattr("/renamed.txt", "user.hash").unwrap();
+    let err = sys
+      .fs_remove_xattr("/renamed.txt", "user.hash")
+      .unwrap_err();
+    #[cfg(all(target_os = "linux", feature = "libc"))]
+    assert_eq!(err.raw_os_error(), Some(libc::ENODATA));
+    #[cfg(not(all(target_os = "linux", feature = "libc")))]
+    assert_eq!(
+      err.kind(),
+      missing_xattr_error(OsStr::new("user.hash")).kind()
+    );
+    let err = sys.fs_get_xattr("/missing", "user.hash").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+  }
+
   #[test]
   fn test_new_with_cwd() {
     let cwd = if cfg!(windows) { "C:\\dir" } else { "/dir" };
@@ -24,15 +24,27 @@ mod in_memory;
 mod real;
 #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
 mod wasm;
+#[cfg(feature = "memory")]
+mod windows_path;
 
 #[cfg(feature = "memory")]
 pub use in_memory::InMemoryDirEntry;
 #[cfg(feature = "memory")]
+pub use in_memory::InMemoryDirHandle;
+#[cfg(feature = "memory")]
 pub use in_memory::InMemoryFile;
 #[cfg(feature = "memory")]
 pub use in_memory::InMemoryMetadata;
 #[cfg(feature = "memory")]
+pub use in_memory::InMemoryMmap;
+#[cfg(feature = "memory")]
+pub use in_memory::InMemoryMmapMut;
+#[cfg(feature = "memory")]
+pub use in_memory::InMemoryQuota;
+#[cfg(feature = "memory")]
 pub use in_memory::InMemorySys;
+#[cfg(feature = "memory")]
+pub use in_memory::InMemoryTimestampMode;
 
 #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
 pub use wasm::is_windows;
@@ -72,6 +84,49 @@ pub type RealFsDirEntry = wasm::WasmFsDirEntry;
 ))]
 pub type RealFsDirEntry = real::RealFsDirEntry;
 
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub type RealFsDirHandle = wasm::WasmDirHandle;
+#[cfg(all(
+  feature = "real",
+  not(target_arch = "wasm32"),
+  not(feature = "wasm")
+))]
+pub type RealFsDirHandle = real::RealFsDirHandle;
+#[cfg(all(
+  feature = "real",
+  not(target_arch = "wasm32"),
+  not(feature = "wasm"),
+  unix,
+  feature = "libc"
+))]
+pub type RealFsDirHandleEntry = real::dir_handle::RealFsDirHandleEntry;
+#[cfg(all(
+  feature = "real",
+  not(target_arch = "wasm32"),
+  not(feature = "wasm"),
+  unix,
+  feature = "libc"
+))]
+pub type RealFsStatMetadata = real::dir_handle::RealFsStatMetadata;
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub type RealFsMmap = wasm::WasmMmap;
+#[cfg(all(
+  feature = "real",
+  not(target_arch = "wasm32"),
+  not(feature = "wasm")
+))]
+pub type RealFsMmap = real::mmap::RealFsMmap;
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub type RealFsMmapMut = wasm::WasmMmapMut;
+#[cfg(all(
+  feature = "real",
+  not(target_arch = "wasm32"),
+  not(feature = "wasm")
+))]
+pub type RealFsMmapMut = real::mmap::RealFsMmapMut;
+
 /// Helper that converts a string to a path for Wasm.
 ///
 /// This will handle converting Windows-style paths received from JS
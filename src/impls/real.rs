@@ -301,7 +301,54 @@ impl BaseFsCloneFile for RealSys {
   }
 }
 
-#[cfg(not(all(target_vendor = "apple", feature = "libc")))]
+#[cfg(all(target_os = "linux", feature = "libc"))]
+impl BaseFsCloneFile for RealSys {
+  fn base_fs_clone_file(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let from_file = fs::File::open(from)?;
+    let mode = from_file.metadata()?.permissions().mode();
+    // match clonefile by failing when the destination exists
+    let to_file = fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .mode(mode)
+      .open(to)?;
+    // SAFETY: both file descriptors are valid for the duration of the call
+    let ret = unsafe {
+      libc::ioctl(
+        to_file.as_raw_fd(),
+        libc::FICLONE as _,
+        from_file.as_raw_fd(),
+      )
+    };
+    if ret == 0 {
+      return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    drop(to_file);
+    _ = fs::remove_file(to);
+    match err.raw_os_error() {
+      // the file system doesn't support reflinks or the files are
+      // on different file systems, so the caller should fall back
+      // to copying
+      Some(libc::EOPNOTSUPP | libc::ENOTTY | libc::EXDEV | libc::EINVAL) => {
+        Err(std::io::Error::new(
+          ErrorKind::Unsupported,
+          format!("FICLONE is not supported for these files: {}", err),
+        ))
+      }
+      _ => Err(err),
+    }
+  }
+}
+
+#[cfg(not(all(
+  any(target_vendor = "apple", target_os = "linux"),
+  feature = "libc"
+)))]
 impl BaseFsCloneFile for RealSys {
   fn base_fs_clone_file(&self, _from: &Path, _to: &Path) -> io::Result<()> {
     Err(std::io::Error::new(
@@ -318,6 +365,309 @@ impl BaseFsCopy for RealSys {
   }
 }
 
+impl BaseFsCopyWithOptions for RealSys {
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> std::io::Result<u64> {
+    if options.copy_symlinks {
+      let metadata = fs::symlink_metadata(from)?;
+      if metadata.file_type().is_symlink() {
+        copy_symlink(from, to, &metadata, options)?;
+        return Ok(0);
+      }
+    }
+    let mut from_file = fs::File::open(from)?;
+    let metadata = from_file.metadata()?;
+    if !metadata.is_file() {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        "the source path is not an existing regular file",
+      ));
+    }
+    let mut open_options = fs::OpenOptions::new();
+    open_options.write(true);
+    if options.create_new {
+      open_options.create_new(true);
+    } else {
+      open_options.create(true).truncate(true);
+    }
+    let mut to_file = open_options.open(to)?;
+    to_file.set_permissions(metadata.permissions())?;
+    let len =
+      copy_data::copy(&mut from_file, &mut to_file, &metadata, options)?;
+    #[cfg(unix)]
+    if options.preserve_ownership {
+      use std::os::unix::fs::MetadataExt;
+      std::os::unix::fs::fchown(
+        &to_file,
+        Some(metadata.uid()),
+        Some(metadata.gid()),
+      )?;
+    }
+    if options.preserve_times {
+      to_file.set_times(
+        fs::FileTimes::new()
+          .set_accessed(metadata.accessed()?)
+          .set_modified(metadata.modified()?),
+      )?;
+    }
+    Ok(len)
+  }
+}
+
+fn copy_symlink(
+  from: &Path,
+  to: &Path,
+  metadata: &fs::Metadata,
+  options: &CopyOptions,
+) -> std::io::Result<()> {
+  let target = fs::read_link(from)?;
+  if !options.create_new {
+    match fs::remove_file(to) {
+      Ok(()) => {}
+      Err(err) if err.kind() == ErrorKind::NotFound => {}
+      Err(err) => return Err(err),
+    }
+  }
+  #[cfg(windows)]
+  {
+    use std::os::windows::fs::FileTypeExt;
+    if metadata.file_type().is_symlink_dir() {
+      std::os::windows::fs::symlink_dir(&target, to)?;
+    } else {
+      std::os::windows::fs::symlink_file(&target, to)?;
+    }
+  }
+  #[cfg(not(windows))]
+  {
+    std::os::unix::fs::symlink(&target, to)?;
+    if options.preserve_ownership {
+      use std::os::unix::fs::MetadataExt;
+      std::os::unix::fs::lchown(
+        to,
+        Some(metadata.uid()),
+        Some(metadata.gid()),
+      )?;
+    }
+  }
+  if options.preserve_times {
+    #[cfg(feature = "filetime")]
+    {
+      filetime::set_symlink_file_times(
+        to,
+        filetime::FileTime::from_last_access_time(metadata),
+        filetime::FileTime::from_last_modification_time(metadata),
+      )?;
+    }
+    #[cfg(not(feature = "filetime"))]
+    {
+      return Err(Error::new(
+        ErrorKind::Unsupported,
+        "preserving symlink times requires the filetime feature in sys_traits",
+      ));
+    }
+  }
+  Ok(())
+}
+
+mod copy_data {
+  use std::fs;
+  use std::io;
+  use std::io::Read;
+  use std::io::Seek;
+  use std::io::SeekFrom;
+  use std::io::Write;
+
+  use crate::CopyOptions;
+
+  const BUF_SIZE: usize = 64 * 1024;
+
+  #[cfg(all(target_os = "linux", feature = "libc"))]
+  pub fn copy(
+    from: &mut fs::File,
+    to: &mut fs::File,
+    metadata: &fs::Metadata,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    let len = metadata.len();
+    if len == 0 {
+      // pseudo files (ex. procfs) report no length, so read until the end
+      return copy_buffered(from, to, options);
+    }
+    let result = if options.sparse {
+      copy_data_ranges(from, to, len, options)?
+    } else {
+      copy_file_range(from, to, 0, len, options)?
+    };
+    match result {
+      Some(len) => Ok(len),
+      None => {
+        // start over when the file system can't do it in the kernel
+        from.seek(SeekFrom::Start(0))?;
+        to.set_len(0)?;
+        to.seek(SeekFrom::Start(0))?;
+        copy_buffered(from, to, options)
+      }
+    }
+  }
+
+  #[cfg(not(all(target_os = "linux", feature = "libc")))]
+  pub fn copy(
+    from: &mut fs::File,
+    to: &mut fs::File,
+    _metadata: &fs::Metadata,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    copy_buffered(from, to, options)
+  }
+
+  /// Copies through a buffer, seeking over zeroed chunks when
+  /// `sparse` is set so the destination gets holes.
+  fn copy_buffered(
+    from: &mut fs::File,
+    to: &mut fs::File,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    let mut buf = vec![0u8; BUF_SIZE];
+    let mut total = 0;
+    loop {
+      let n = match from.read(&mut buf) {
+        Ok(0) => break,
+        Ok(n) => n,
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err),
+      };
+      let chunk = &buf[..n];
+      if options.sparse && chunk.iter().all(|b| *b == 0) {
+        to.seek(SeekFrom::Current(n as i64))?;
+      } else {
+        to.write_all(chunk)?;
+      }
+      total += n as u64;
+      options.report_progress(total);
+    }
+    if options.sparse {
+      // extend over a trailing hole
+      to.set_len(total)?;
+    }
+    Ok(total)
+  }
+
+  /// Copies only the data segments of the source file, leaving holes
+  /// in the destination.
+  ///
+  /// Returns `None` when the file system doesn't support this.
+  #[cfg(all(target_os = "linux", feature = "libc"))]
+  fn copy_data_ranges(
+    from: &fs::File,
+    to: &fs::File,
+    len: u64,
+    options: &CopyOptions,
+  ) -> io::Result<Option<u64>> {
+    use std::os::unix::io::AsRawFd;
+
+    let seek = |offset: u64, whence: libc::c_int| {
+      // SAFETY: the file descriptor is valid for the duration of the call
+      let result =
+        unsafe { libc::lseek(from.as_raw_fd(), offset as libc::off_t, whence) };
+      if result < 0 {
+        Err(io::Error::last_os_error())
+      } else {
+        Ok(result as u64)
+      }
+    };
+    let mut offset = 0;
+    while offset < len {
+      let data_start = match seek(offset, libc::SEEK_DATA) {
+        Ok(data_start) => data_start,
+        // the rest of the file is a hole
+        Err(err) if err.raw_os_error() == Some(libc::ENXIO) => break,
+        Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {
+          return Ok(None)
+        }
+        Err(err) => return Err(err),
+      };
+      let data_end = seek(data_start, libc::SEEK_HOLE)?.min(len);
+      let Some(end) =
+        copy_file_range(from, to, data_start, data_end - data_start, options)?
+      else {
+        return Ok(None);
+      };
+      offset = end;
+      if end < data_end {
+        break; // the source shrank
+      }
+    }
+    to.set_len(len)?;
+    options.report_progress(len);
+    Ok(Some(len))
+  }
+
+  /// Copies a range in the kernel, returning the offset copied up to.
+  ///
+  /// Returns `None` when `copy_file_range` isn't supported between
+  /// these files or copies nothing on the first call (ex. procfs),
+  /// before anything was copied.
+  #[cfg(all(target_os = "linux", feature = "libc"))]
+  fn copy_file_range(
+    from: &fs::File,
+    to: &fs::File,
+    offset: u64,
+    len: u64,
+    options: &CopyOptions,
+  ) -> io::Result<Option<u64>> {
+    use std::os::unix::io::AsRawFd;
+
+    // keep chunks small enough to report progress regularly
+    const CHUNK_SIZE: u64 = 16 * BUF_SIZE as u64;
+
+    let end = offset + len;
+    let mut off_in = offset as libc::loff_t;
+    let mut off_out = off_in;
+    while (off_in as u64) < end {
+      let chunk = (end - off_in as u64).min(CHUNK_SIZE) as usize;
+      // SAFETY: the file descriptors are valid and the offsets point
+      // to live locals for the duration of the call
+      let n = unsafe {
+        libc::copy_file_range(
+          from.as_raw_fd(),
+          &mut off_in,
+          to.as_raw_fd(),
+          &mut off_out,
+          chunk,
+          0,
+        )
+      };
+      if n < 0 {
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+          Some(libc::EINTR) => continue,
+          Some(
+            libc::EXDEV
+            | libc::ENOSYS
+            | libc::EOPNOTSUPP
+            | libc::EINVAL
+            | libc::EPERM,
+          ) if off_in as u64 == offset => return Ok(None),
+          _ => return Err(err),
+        }
+      }
+      if n == 0 {
+        if off_in as u64 == offset {
+          // some file systems report success without copying anything
+          return Ok(None);
+        }
+        break; // the source shrank
+      }
+      options.report_progress(off_in as u64);
+    }
+    Ok(Some(off_in as u64))
+  }
+}
+
 impl BaseFsCreateDir for RealSys {
   fn base_fs_create_dir(
     &self,
@@ -373,6 +723,57 @@ impl BaseFsCreateJunction for RealSys {
   }
 }
 
+impl BaseFsDiskSpace for RealSys {
+  #[inline]
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+    let stat = self.base_fs_statvfs(path)?;
+    Ok(DiskSpace {
+      total: stat.total_bytes,
+      free: stat.free_bytes,
+      available: stat.available_bytes,
+    })
+  }
+}
+
+#[cfg(all(unix, feature = "libc"))]
+impl BaseFsStatVfs for RealSys {
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a valid C string and `stat` is zeroed memory
+    // that statvfs fills in on success
+    let stat = unsafe {
+      let mut stat: libc::statvfs = std::mem::zeroed();
+      if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+        return Err(Error::last_os_error());
+      }
+      stat
+    };
+    let fragment_size = stat.f_frsize as u64;
+    Ok(StatVfs {
+      total_bytes: stat.f_blocks as u64 * fragment_size,
+      free_bytes: stat.f_bfree as u64 * fragment_size,
+      available_bytes: stat.f_bavail as u64 * fragment_size,
+      total_inodes: stat.f_files as u64,
+      free_inodes: stat.f_ffree as u64,
+      available_inodes: stat.f_favail as u64,
+      block_size: stat.f_bsize as u64,
+      max_name_len: Some(stat.f_namemax as u64),
+      read_only: Some(stat.f_flag & libc::ST_RDONLY != 0),
+    })
+  }
+}
+
+#[cfg(not(all(unix, feature = "libc")))]
+impl BaseFsStatVfs for RealSys {
+  fn base_fs_statvfs(&self, _path: &Path) -> io::Result<StatVfs> {
+    Err(Error::new(
+      ErrorKind::Unsupported,
+      "statvfs is not supported on this platform or the libc feature in sys_traits is not enabled",
+    ))
+  }
+}
+
 macro_rules! unix_metadata_prop {
   ($id:ident, $type:ident) => {
     #[inline]
@@ -613,15 +1014,69 @@ impl BaseFsOpen for RealSys {
       #[cfg(not(windows))]
       let _ = value;
     }
-    builder
+    if options.no_follow || options.directory {
+      #[cfg(all(unix, feature = "libc"))]
+      {
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut flags = options.custom_flags.unwrap_or(0);
+        if options.no_follow {
+          flags |= libc::O_NOFOLLOW;
+        }
+        if options.directory {
+          flags |= libc::O_DIRECTORY;
+        }
+        builder.custom_flags(flags);
+      }
+      #[cfg(windows)]
+      {
+        use std::os::windows::fs::OpenOptionsExt;
+        let mut flags = options.custom_flags.unwrap_or(0);
+        if options.no_follow {
+          // FILE_FLAG_OPEN_REPARSE_POINT so the symlink itself is opened
+          // and can be rejected below
+          flags |= 0x00200000;
+        }
+        if options.directory {
+          // FILE_FLAG_BACKUP_SEMANTICS, which is required to open
+          // directories
+          flags |= 0x02000000;
+          // check up front so a file isn't created or truncated before
+          // failing, while the check below handles races
+          if !fs::metadata(path)?.is_dir() {
+            return Err(Error::new(
+              ErrorKind::NotADirectory,
+              "Not a directory",
+            ));
+          }
+        }
+        builder.custom_flags(flags);
+      }
+      #[cfg(not(any(all(unix, feature = "libc"), windows)))]
+      return Err(Error::new(
+        ErrorKind::Unsupported,
+        "opening with no_follow or directory is not supported on this platform or the libc feature in sys_traits is not enabled",
+      ));
+    }
+    let file = builder
       .read(options.read)
       .write(options.write)
       .create(options.create)
       .truncate(options.truncate)
       .append(options.append)
       .create_new(options.create_new)
-      .open(path)
-      .map(RealFsFile)
+      .open(path)?;
+    #[cfg(windows)]
+    if options.no_follow || options.directory {
+      let file_type = file.metadata()?.file_type();
+      if options.no_follow && file_type.is_symlink() {
+        // ERROR_CANT_RESOLVE_FILENAME
+        return Err(Error::from_raw_os_error(1921));
+      }
+      if options.directory && !file_type.is_dir() {
+        return Err(Error::new(ErrorKind::NotADirectory, "Not a directory"));
+      }
+    }
+    Ok(RealFsFile(file))
   }
 }
 
@@ -632,6 +1087,13 @@ impl BaseFsRead for RealSys {
   }
 }
 
+impl BaseFsReadShared for RealSys {
+  #[inline]
+  fn base_fs_read_shared(&self, path: &Path) -> Result<FsSharedBytes> {
+    fs::read(path).map(FsSharedBytes::from)
+  }
+}
+
 #[derive(Debug)]
 pub struct RealFsDirEntry(fs::DirEntry);
 
@@ -708,6 +1170,79 @@ impl BaseFsRename for RealSys {
   }
 }
 
+#[cfg(all(target_os = "linux", feature = "libc"))]
+impl BaseFsRenameWithOptions for RealSys {
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut flags = 0;
+    if options.no_replace {
+      flags |= libc::RENAME_NOREPLACE;
+    }
+    if options.exchange {
+      flags |= libc::RENAME_EXCHANGE;
+    }
+    if flags == 0 {
+      return fs::rename(from, to);
+    }
+    let from_c = std::ffi::CString::new(from.as_os_str().as_bytes())?;
+    let to_c = std::ffi::CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: `from_c` and `to_c` are valid C strings. The syscall is
+    // used directly because older glibc and musl lack a wrapper.
+    let ret = unsafe {
+      libc::syscall(
+        libc::SYS_renameat2,
+        libc::AT_FDCWD,
+        from_c.as_ptr(),
+        libc::AT_FDCWD,
+        to_c.as_ptr(),
+        flags,
+      )
+    };
+    if ret == 0 {
+      return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    let is_unsupported = match err.raw_os_error() {
+      Some(libc::ENOSYS) => true,
+      // also returned when renaming a directory into itself
+      Some(libc::EINVAL) => !to.starts_with(from) && !from.starts_with(to),
+      _ => false,
+    };
+    if is_unsupported {
+      Err(Error::new(
+        ErrorKind::Unsupported,
+        format!("renameat2 is not supported for these paths: {}", err),
+      ))
+    } else {
+      Err(err)
+    }
+  }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "libc")))]
+impl BaseFsRenameWithOptions for RealSys {
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> std::io::Result<()> {
+    if options.no_replace || options.exchange {
+      return Err(Error::new(
+        ErrorKind::Unsupported,
+        "renaming with no_replace or exchange is not supported on this platform or the libc feature in sys_traits is not enabled",
+      ));
+    }
+    fs::rename(from, to)
+  }
+}
+
 #[cfg(feature = "filetime")]
 impl BaseFsSetFileTimes for RealSys {
   #[inline]
@@ -807,103 +1342,948 @@ impl BaseFsWrite for RealSys {
   }
 }
 
-// ==== File System File ====
+#[cfg(all(target_os = "linux", feature = "libc"))]
+mod xattr {
+  use std::ffi::CString;
+  use std::ffi::OsStr;
+  use std::ffi::OsString;
+  use std::io;
+  use std::os::unix::ffi::OsStrExt;
+  use std::os::unix::ffi::OsStringExt;
+  use std::path::Path;
 
-/// A wrapper type is used in order to force usages to
-/// `use sys_traits::FsFile` so that the code
-/// compiles under Wasm.
-#[derive(Debug)]
-pub struct RealFsFile(fs::File);
+  fn to_cstring(value: &OsStr) -> io::Result<CString> {
+    Ok(CString::new(value.as_bytes())?)
+  }
 
-impl RealFsFile {
-  pub fn from_raw(file: fs::File) -> Self {
-    Self(file)
+  /// Calls a function that fills a buffer, growing the buffer when
+  /// the value changes size between calls.
+  fn read_into_buf(
+    mut f: impl FnMut(*mut libc::c_void, usize) -> libc::ssize_t,
+  ) -> io::Result<Vec<u8>> {
+    loop {
+      let len = f(std::ptr::null_mut(), 0);
+      if len < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      let mut buf = vec![0u8; len as usize];
+      let len = f(buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+      if len < 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ERANGE) {
+          continue; // grew since the size was queried
+        }
+        return Err(err);
+      }
+      buf.truncate(len as usize);
+      return Ok(buf);
+    }
   }
-}
 
-impl FsFile for RealFsFile {}
+  pub fn get(
+    path: &Path,
+    name: &OsStr,
+    follow: bool,
+  ) -> io::Result<Option<Vec<u8>>> {
+    let path = to_cstring(path.as_os_str())?;
+    let name = to_cstring(name)?;
+    let result = read_into_buf(|buf, size| {
+      // SAFETY: `path` and `name` are valid C strings and `buf` is
+      // either null with a size of 0 or valid for `size` bytes
+      unsafe {
+        if follow {
+          libc::getxattr(path.as_ptr(), name.as_ptr(), buf, size)
+        } else {
+          libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf, size)
+        }
+      }
+    });
+    match result {
+      Ok(value) => Ok(Some(value)),
+      Err(err) if err.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
 
-impl FsFileAsRaw for RealFsFile {
-  #[cfg(windows)]
-  #[inline]
-  fn fs_file_as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
-    use std::os::windows::io::AsRawHandle;
-    Some(self.0.as_raw_handle())
+  pub fn list(path: &Path, follow: bool) -> io::Result<Vec<OsString>> {
+    let path = to_cstring(path.as_os_str())?;
+    let names = read_into_buf(|buf, size| {
+      // SAFETY: `path` is a valid C string and `buf` is either null
+      // with a size of 0 or valid for `size` bytes
+      unsafe {
+        if follow {
+          libc::listxattr(path.as_ptr(), buf as *mut libc::c_char, size)
+        } else {
+          libc::llistxattr(path.as_ptr(), buf as *mut libc::c_char, size)
+        }
+      }
+    })?;
+    // names are nul terminated
+    Ok(
+      names
+        .split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| OsString::from_vec(name.to_vec()))
+        .collect(),
+    )
+  }
+
+  pub fn remove(path: &Path, name: &OsStr, follow: bool) -> io::Result<()> {
+    let path = to_cstring(path.as_os_str())?;
+    let name = to_cstring(name)?;
+    // SAFETY: `path` and `name` are valid C strings
+    let result = unsafe {
+      if follow {
+        libc::removexattr(path.as_ptr(), name.as_ptr())
+      } else {
+        libc::lremovexattr(path.as_ptr(), name.as_ptr())
+      }
+    };
+    if result != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
   }
 
-  #[cfg(unix)]
-  #[inline]
-  fn fs_file_as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
-    use std::os::fd::AsRawFd;
-    Some(self.0.as_raw_fd())
+  pub fn set(
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+    follow: bool,
+  ) -> io::Result<()> {
+    let path = to_cstring(path.as_os_str())?;
+    let name = to_cstring(name)?;
+    let value_ptr = value.as_ptr() as *const libc::c_void;
+    // SAFETY: `path` and `name` are valid C strings and `value_ptr`
+    // is valid for `value.len()` bytes
+    let result = unsafe {
+      if follow {
+        libc::setxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0)
+      } else {
+        libc::lsetxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0)
+      }
+    };
+    if result != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
   }
 }
 
-impl FsFileIsTerminal for RealFsFile {
-  #[inline]
-  fn fs_file_is_terminal(&self) -> bool {
-    self.0.is_terminal()
+#[cfg(not(all(target_os = "linux", feature = "libc")))]
+mod xattr {
+  use std::ffi::OsStr;
+  use std::ffi::OsString;
+  use std::io;
+  use std::path::Path;
+
+  fn unsupported<T>() -> io::Result<T> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "Extended attributes are not supported on this platform or the libc feature in sys_traits is not enabled",
+    ))
   }
-}
 
-impl FsFileLock for RealFsFile {
-  fn fs_file_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
-    lock_file(&self.0, mode, false)
+  pub fn get(
+    _path: &Path,
+    _name: &OsStr,
+    _follow: bool,
+  ) -> io::Result<Option<Vec<u8>>> {
+    unsupported()
   }
 
-  fn fs_file_try_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
-    lock_file(&self.0, mode, true)
+  pub fn list(_path: &Path, _follow: bool) -> io::Result<Vec<OsString>> {
+    unsupported()
   }
 
-  fn fs_file_unlock(&mut self) -> io::Result<()> {
-    unlock_file(&self.0)
+  pub fn remove(_path: &Path, _name: &OsStr, _follow: bool) -> io::Result<()> {
+    unsupported()
+  }
+
+  pub fn set(
+    _path: &Path,
+    _name: &OsStr,
+    _value: &[u8],
+    _follow: bool,
+  ) -> io::Result<()> {
+    unsupported()
   }
 }
 
-#[cfg(all(unix, feature = "libc"))]
-fn lock_file(
-  file: &fs::File,
-  mode: FsFileLockMode,
-  try_lock: bool,
-) -> Result<()> {
-  let operation = match mode {
-    FsFileLockMode::Shared => libc::LOCK_SH,
-    FsFileLockMode::Exclusive => libc::LOCK_EX,
-  } | if try_lock { libc::LOCK_NB } else { 0 };
+impl BaseFsGetXattr for RealSys {
+  #[inline]
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    xattr::get(path, name, true)
+  }
 
-  flock(file, operation)
+  #[inline]
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    xattr::get(path, name, false)
+  }
 }
 
-#[cfg(all(unix, feature = "libc"))]
-#[inline]
-fn unlock_file(file: &fs::File) -> Result<()> {
-  flock(file, libc::LOCK_UN)
+impl BaseFsListXattr for RealSys {
+  #[inline]
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>> {
+    xattr::list(path, true)
+  }
+
+  #[inline]
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    xattr::list(path, false)
+  }
 }
 
-#[cfg(all(unix, feature = "libc"))]
-fn flock(file: &fs::File, operation: i32) -> Result<()> {
-  use std::os::unix::io::AsRawFd;
+impl BaseFsRemoveXattr for RealSys {
+  #[inline]
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+    xattr::remove(path, name, true)
+  }
 
-  // SAFETY: libc calls
-  unsafe {
-    let fd = file.as_raw_fd();
-    let result = libc::flock(fd, operation);
-    if result < 0 {
-      Err(Error::last_os_error())
-    } else {
-      Ok(())
-    }
+  #[inline]
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()> {
+    xattr::remove(path, name, false)
   }
 }
 
-#[cfg(all(windows, feature = "winapi"))]
-fn lock_file(
-  file: &fs::File,
-  mode: FsFileLockMode,
-  try_lock: bool,
-) -> Result<()> {
-  use std::os::windows::io::AsRawHandle;
-
+impl BaseFsSetXattr for RealSys {
+  #[inline]
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    xattr::set(path, name, value, true)
+  }
+
+  #[inline]
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    xattr::set(path, name, value, false)
+  }
+}
+
+#[cfg(all(unix, feature = "libc"))]
+pub(super) mod dir_handle {
+  use std::borrow::Cow;
+  use std::ffi::CStr;
+  use std::ffi::CString;
+  use std::ffi::OsStr;
+  use std::ffi::OsString;
+  use std::fs;
+  use std::io;
+  use std::os::fd::AsRawFd;
+  use std::os::fd::FromRawFd;
+  use std::os::fd::OwnedFd;
+  use std::os::fd::RawFd;
+  use std::os::unix::ffi::OsStrExt;
+  use std::os::unix::ffi::OsStringExt;
+  use std::path::Path;
+  use std::path::PathBuf;
+  use std::sync::Arc;
+  use std::time::Duration;
+  use std::time::SystemTime;
+
+  use super::RealFsFile;
+  use crate::FileType;
+  use crate::FsDirEntry;
+  use crate::FsDirHandle;
+  use crate::FsMetadataValue;
+  use crate::OpenOptions;
+
+  fn to_cstring(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+  }
+
+  fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+      Err(io::Error::last_os_error())
+    } else {
+      Ok(result)
+    }
+  }
+
+  fn stat_at(
+    fd: RawFd,
+    path: &Path,
+    flags: libc::c_int,
+  ) -> io::Result<RealFsStatMetadata> {
+    let path = to_cstring(path)?;
+    // SAFETY: a zeroed stat is a valid value for fstatat to fill
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    // SAFETY: `path` is a valid C string and `stat` is valid for writes
+    cvt(unsafe { libc::fstatat(fd, path.as_ptr(), &mut stat, flags) })?;
+    Ok(RealFsStatMetadata(stat))
+  }
+
+  fn open_dir_at(
+    fd: RawFd,
+    path: &Path,
+    display_path: PathBuf,
+  ) -> io::Result<RealFsDirHandle> {
+    let c_path = to_cstring(path)?;
+    // SAFETY: `c_path` is a valid C string
+    let dir_fd = cvt(unsafe {
+      libc::openat(
+        fd,
+        c_path.as_ptr(),
+        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+      )
+    })?;
+    Ok(RealFsDirHandle {
+      // SAFETY: the file descriptor was just opened and is owned here
+      fd: Arc::new(unsafe { OwnedFd::from_raw_fd(dir_fd) }),
+      path: display_path,
+    })
+  }
+
+  pub fn open_dir(path: &Path) -> io::Result<RealFsDirHandle> {
+    open_dir_at(libc::AT_FDCWD, path, path.to_path_buf())
+  }
+
+  /// A handle to an open directory backed by a file descriptor.
+  #[derive(Debug, Clone)]
+  pub struct RealFsDirHandle {
+    fd: Arc<OwnedFd>,
+    /// The path the directory was opened with, which is only used for
+    /// the paths of read entries.
+    path: PathBuf,
+  }
+
+  impl FsDirHandle for RealFsDirHandle {
+    type File = RealFsFile;
+    type Metadata = RealFsStatMetadata;
+    type ReadDirEntry = RealFsDirHandleEntry;
+
+    fn open_at(
+      &self,
+      path: impl AsRef<Path>,
+      options: &OpenOptions,
+    ) -> io::Result<Self::File> {
+      let mut flags = match (options.read, options.write || options.append) {
+        (true, false) => libc::O_RDONLY,
+        (false, true) => libc::O_WRONLY,
+        (true, true) => libc::O_RDWR,
+        (false, false) => {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "must specify at least one of read, write, or append access",
+          ));
+        }
+      };
+      flags |= libc::O_CLOEXEC;
+      if options.append {
+        flags |= libc::O_APPEND;
+      }
+      if options.truncate {
+        flags |= libc::O_TRUNC;
+      }
+      if options.create_new {
+        flags |= libc::O_CREAT | libc::O_EXCL;
+      } else if options.create {
+        flags |= libc::O_CREAT;
+      }
+      if options.no_follow {
+        flags |= libc::O_NOFOLLOW;
+      }
+      if options.directory {
+        flags |= libc::O_DIRECTORY;
+      }
+      if let Some(custom_flags) = options.custom_flags {
+        flags |= custom_flags;
+      }
+      let mode = options.mode.unwrap_or(0o666) as libc::c_uint;
+      let path = to_cstring(path.as_ref())?;
+      // SAFETY: `path` is a valid C string
+      let fd = cvt(unsafe {
+        libc::openat(self.fd.as_raw_fd(), path.as_ptr(), flags, mode)
+      })?;
+      // SAFETY: the file descriptor was just opened and is owned here
+      let file = fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+      Ok(RealFsFile(file))
+    }
+
+    fn open_dir_at(&self, path: impl AsRef<Path>) -> io::Result<Self> {
+      let path = path.as_ref();
+      open_dir_at(self.fd.as_raw_fd(), path, self.path.join(path))
+    }
+
+    fn metadata_at(
+      &self,
+      path: impl AsRef<Path>,
+    ) -> io::Result<Self::Metadata> {
+      stat_at(self.fd.as_raw_fd(), path.as_ref(), 0)
+    }
+
+    fn symlink_metadata_at(
+      &self,
+      path: impl AsRef<Path>,
+    ) -> io::Result<Self::Metadata> {
+      stat_at(
+        self.fd.as_raw_fd(),
+        path.as_ref(),
+        libc::AT_SYMLINK_NOFOLLOW,
+      )
+    }
+
+    fn read_dir(
+      &self,
+    ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>>
+    {
+      // fdopendir takes ownership, so give it a new open file description
+      // because a duplicate would share its position with other reads
+      // SAFETY: the file descriptor is valid and the path is a C string
+      let fd = cvt(unsafe {
+        libc::openat(
+          self.fd.as_raw_fd(),
+          c".".as_ptr(),
+          libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+      })?;
+      // SAFETY: `fd` is an open directory file descriptor
+      let dir = unsafe { libc::fdopendir(fd) };
+      if dir.is_null() {
+        let err = io::Error::last_os_error();
+        // SAFETY: `fd` is still owned here when fdopendir fails
+        unsafe { libc::close(fd) };
+        return Err(err);
+      }
+      Ok(Box::new(ReadDir {
+        dir,
+        done: false,
+        fd: self.fd.clone(),
+        path: self.path.clone(),
+      }))
+    }
+
+    fn create_dir_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+      let path = to_cstring(path.as_ref())?;
+      // SAFETY: `path` is a valid C string
+      cvt(unsafe { libc::mkdirat(self.fd.as_raw_fd(), path.as_ptr(), 0o777) })?;
+      Ok(())
+    }
+
+    fn remove_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+      let path = to_cstring(path.as_ref())?;
+      let fd = self.fd.as_raw_fd();
+      // SAFETY: `path` is a valid C string
+      match cvt(unsafe { libc::unlinkat(fd, path.as_ptr(), 0) }) {
+        Ok(_) => Ok(()),
+        // directories need AT_REMOVEDIR (macOS reports EPERM)
+        Err(err)
+          if matches!(err.raw_os_error(), Some(libc::EISDIR | libc::EPERM)) =>
+        {
+          // SAFETY: `path` is a valid C string
+          cvt(unsafe { libc::unlinkat(fd, path.as_ptr(), libc::AT_REMOVEDIR) })
+            .map(|_| ())
+            .map_err(|dir_err| {
+              if dir_err.raw_os_error() == Some(libc::ENOTDIR) {
+                err
+              } else {
+                dir_err
+              }
+            })
+        }
+        Err(err) => Err(err),
+      }
+    }
+
+    fn rename_at(
+      &self,
+      from: impl AsRef<Path>,
+      to_dir: &Self,
+      to: impl AsRef<Path>,
+    ) -> io::Result<()> {
+      let from = to_cstring(from.as_ref())?;
+      let to = to_cstring(to.as_ref())?;
+      // SAFETY: `from` and `to` are valid C strings
+      cvt(unsafe {
+        libc::renameat(
+          self.fd.as_raw_fd(),
+          from.as_ptr(),
+          to_dir.fd.as_raw_fd(),
+          to.as_ptr(),
+        )
+      })?;
+      Ok(())
+    }
+
+    fn symlink_at(
+      &self,
+      original: impl AsRef<Path>,
+      link: impl AsRef<Path>,
+    ) -> io::Result<()> {
+      let original = to_cstring(original.as_ref())?;
+      let link = to_cstring(link.as_ref())?;
+      // SAFETY: `original` and `link` are valid C strings
+      cvt(unsafe {
+        libc::symlinkat(original.as_ptr(), self.fd.as_raw_fd(), link.as_ptr())
+      })?;
+      Ok(())
+    }
+  }
+
+  struct ReadDir {
+    dir: *mut libc::DIR,
+    done: bool,
+    fd: Arc<OwnedFd>,
+    path: PathBuf,
+  }
+
+  impl Iterator for ReadDir {
+    type Item = io::Result<RealFsDirHandleEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+      while !self.done {
+        clear_errno();
+        // SAFETY: `dir` is a valid directory stream until dropped
+        let entry = unsafe { libc::readdir(self.dir) };
+        if entry.is_null() {
+          self.done = true;
+          let err = io::Error::last_os_error();
+          return match err.raw_os_error() {
+            Some(0) | None => None,
+            Some(_) => Some(Err(err)),
+          };
+        }
+        // SAFETY: readdir returned a valid entry with a nul terminated name
+        let (name, d_type) = unsafe {
+          (
+            CStr::from_ptr((*entry).d_name.as_ptr()).to_bytes(),
+            (*entry).d_type,
+          )
+        };
+        if name == b"." || name == b".." {
+          continue;
+        }
+        let file_type = match d_type {
+          libc::DT_REG => Some(FileType::File),
+          libc::DT_DIR => Some(FileType::Dir),
+          libc::DT_LNK => Some(FileType::Symlink),
+          libc::DT_UNKNOWN => None,
+          _ => Some(FileType::Unknown),
+        };
+        return Some(Ok(RealFsDirHandleEntry {
+          fd: self.fd.clone(),
+          dir_path: self.path.clone(),
+          name: OsString::from_vec(name.to_vec()),
+          file_type,
+        }));
+      }
+      None
+    }
+  }
+
+  impl Drop for ReadDir {
+    fn drop(&mut self) {
+      // SAFETY: `dir` is a valid directory stream that's not used after this
+      unsafe { libc::closedir(self.dir) };
+    }
+  }
+
+  fn clear_errno() {
+    // SAFETY: the errno location is valid for the current thread
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe {
+      *libc::__errno_location() = 0;
+    }
+    // SAFETY: the errno location is valid for the current thread
+    #[cfg(target_vendor = "apple")]
+    unsafe {
+      *libc::__error() = 0;
+    }
+  }
+
+  /// An entry read from a [`RealFsDirHandle`].
+  #[derive(Debug)]
+  pub struct RealFsDirHandleEntry {
+    fd: Arc<OwnedFd>,
+    dir_path: PathBuf,
+    name: OsString,
+    file_type: Option<FileType>,
+  }
+
+  impl FsDirEntry for RealFsDirHandleEntry {
+    type Metadata = RealFsStatMetadata;
+
+    fn file_name(&self) -> Cow<'_, OsStr> {
+      Cow::Borrowed(&self.name)
+    }
+
+    fn file_type(&self) -> io::Result<FileType> {
+      match self.file_type {
+        Some(file_type) => Ok(file_type),
+        None => self.metadata().map(|m| m.file_type()),
+      }
+    }
+
+    fn metadata(&self) -> io::Result<Self::Metadata> {
+      stat_at(
+        self.fd.as_raw_fd(),
+        Path::new(&self.name),
+        libc::AT_SYMLINK_NOFOLLOW,
+      )
+    }
+
+    fn path(&self) -> Cow<'_, Path> {
+      Cow::Owned(self.dir_path.join(&self.name))
+    }
+  }
+
+  /// Metadata from `fstatat`.
+  #[derive(Clone)]
+  pub struct RealFsStatMetadata(libc::stat);
+
+  impl std::fmt::Debug for RealFsStatMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      f.debug_struct("RealFsStatMetadata")
+        .field("file_type", &self.file_type())
+        .field("len", &self.len())
+        .field("mode", &self.0.st_mode)
+        .finish()
+    }
+  }
+
+  fn to_system_time(secs: i64, nsecs: i64) -> SystemTime {
+    let nsecs = Duration::from_nanos(nsecs as u64);
+    if secs >= 0 {
+      SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64) + nsecs
+    } else {
+      SystemTime::UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nsecs
+    }
+  }
+
+  // the stat field types vary by platform
+  #[allow(clippy::unnecessary_cast)]
+  impl FsMetadataValue for RealFsStatMetadata {
+    fn file_type(&self) -> FileType {
+      match self.0.st_mode & libc::S_IFMT {
+        libc::S_IFREG => FileType::File,
+        libc::S_IFDIR => FileType::Dir,
+        libc::S_IFLNK => FileType::Symlink,
+        _ => FileType::Unknown,
+      }
+    }
+
+    fn len(&self) -> u64 {
+      self.0.st_size as u64
+    }
+
+    fn accessed(&self) -> io::Result<SystemTime> {
+      Ok(to_system_time(
+        self.0.st_atime as i64,
+        self.0.st_atime_nsec as i64,
+      ))
+    }
+
+    fn created(&self) -> io::Result<SystemTime> {
+      #[cfg(target_vendor = "apple")]
+      {
+        Ok(to_system_time(
+          self.0.st_birthtime as i64,
+          self.0.st_birthtime_nsec as i64,
+        ))
+      }
+      #[cfg(not(target_vendor = "apple"))]
+      {
+        Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          "creation time is not available from fstatat on this platform",
+        ))
+      }
+    }
+
+    fn changed(&self) -> io::Result<SystemTime> {
+      Ok(to_system_time(
+        self.0.st_ctime as i64,
+        self.0.st_ctime_nsec as i64,
+      ))
+    }
+
+    fn modified(&self) -> io::Result<SystemTime> {
+      Ok(to_system_time(
+        self.0.st_mtime as i64,
+        self.0.st_mtime_nsec as i64,
+      ))
+    }
+
+    fn dev(&self) -> io::Result<u64> {
+      Ok(self.0.st_dev as u64)
+    }
+
+    fn ino(&self) -> io::Result<u64> {
+      Ok(self.0.st_ino as u64)
+    }
+
+    fn mode(&self) -> io::Result<u32> {
+      Ok(self.0.st_mode as u32)
+    }
+
+    fn nlink(&self) -> io::Result<u64> {
+      Ok(self.0.st_nlink as u64)
+    }
+
+    fn uid(&self) -> io::Result<u32> {
+      Ok(self.0.st_uid)
+    }
+
+    fn gid(&self) -> io::Result<u32> {
+      Ok(self.0.st_gid)
+    }
+
+    fn rdev(&self) -> io::Result<u64> {
+      Ok(self.0.st_rdev as u64)
+    }
+
+    fn blksize(&self) -> io::Result<u64> {
+      Ok(self.0.st_blksize as u64)
+    }
+
+    fn blocks(&self) -> io::Result<u64> {
+      Ok(self.0.st_blocks as u64)
+    }
+
+    fn is_block_device(&self) -> io::Result<bool> {
+      Ok(self.0.st_mode & libc::S_IFMT == libc::S_IFBLK)
+    }
+
+    fn is_char_device(&self) -> io::Result<bool> {
+      Ok(self.0.st_mode & libc::S_IFMT == libc::S_IFCHR)
+    }
+
+    fn is_fifo(&self) -> io::Result<bool> {
+      Ok(self.0.st_mode & libc::S_IFMT == libc::S_IFIFO)
+    }
+
+    fn is_socket(&self) -> io::Result<bool> {
+      Ok(self.0.st_mode & libc::S_IFMT == libc::S_IFSOCK)
+    }
+
+    fn file_attributes(&self) -> io::Result<u32> {
+      Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file_attributes is not supported on this platform",
+      ))
+    }
+  }
+}
+
+#[cfg(not(all(unix, feature = "libc")))]
+pub(super) mod dir_handle {
+  use std::convert::Infallible;
+  use std::io;
+  use std::path::Path;
+
+  use super::RealFsDirEntry;
+  use super::RealFsFile;
+  use super::RealFsMetadata;
+  use crate::FsDirHandle;
+  use crate::OpenOptions;
+
+  pub fn open_dir(_path: &Path) -> io::Result<RealFsDirHandle> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "Directory handles are not supported on this platform or the libc feature in sys_traits is not enabled",
+    ))
+  }
+
+  /// A directory handle, which can't be opened on this platform.
+  #[derive(Debug, Clone)]
+  pub struct RealFsDirHandle(Infallible);
+
+  impl FsDirHandle for RealFsDirHandle {
+    type File = RealFsFile;
+    type Metadata = RealFsMetadata;
+    type ReadDirEntry = RealFsDirEntry;
+
+    fn open_at(
+      &self,
+      _path: impl AsRef<Path>,
+      _options: &OpenOptions,
+    ) -> io::Result<Self::File> {
+      match self.0 {}
+    }
+
+    fn open_dir_at(&self, _path: impl AsRef<Path>) -> io::Result<Self> {
+      match self.0 {}
+    }
+
+    fn metadata_at(
+      &self,
+      _path: impl AsRef<Path>,
+    ) -> io::Result<Self::Metadata> {
+      match self.0 {}
+    }
+
+    fn symlink_metadata_at(
+      &self,
+      _path: impl AsRef<Path>,
+    ) -> io::Result<Self::Metadata> {
+      match self.0 {}
+    }
+
+    fn read_dir(
+      &self,
+    ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>>
+    {
+      match self.0 {}
+    }
+
+    fn create_dir_at(&self, _path: impl AsRef<Path>) -> io::Result<()> {
+      match self.0 {}
+    }
+
+    fn remove_at(&self, _path: impl AsRef<Path>) -> io::Result<()> {
+      match self.0 {}
+    }
+
+    fn rename_at(
+      &self,
+      _from: impl AsRef<Path>,
+      _to_dir: &Self,
+      _to: impl AsRef<Path>,
+    ) -> io::Result<()> {
+      match self.0 {}
+    }
+
+    fn symlink_at(
+      &self,
+      _original: impl AsRef<Path>,
+      _link: impl AsRef<Path>,
+    ) -> io::Result<()> {
+      match self.0 {}
+    }
+  }
+}
+
+pub use dir_handle::RealFsDirHandle;
+
+impl BaseFsOpenDir for RealSys {
+  type DirHandle = RealFsDirHandle;
+
+  #[inline]
+  fn base_fs_open_dir(&self, path: &Path) -> Result<Self::DirHandle> {
+    dir_handle::open_dir(path)
+  }
+}
+
+// ==== File System File ====
+
+/// A wrapper type is used in order to force usages to
+/// `use sys_traits::FsFile` so that the code
+/// compiles under Wasm.
+#[derive(Debug)]
+pub struct RealFsFile(fs::File);
+
+impl RealFsFile {
+  pub fn from_raw(file: fs::File) -> Self {
+    Self(file)
+  }
+}
+
+impl FsFile for RealFsFile {}
+
+impl FsFileAsRaw for RealFsFile {
+  #[cfg(windows)]
+  #[inline]
+  fn fs_file_as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+    use std::os::windows::io::AsRawHandle;
+    Some(self.0.as_raw_handle())
+  }
+
+  #[cfg(unix)]
+  #[inline]
+  fn fs_file_as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
+    use std::os::fd::AsRawFd;
+    Some(self.0.as_raw_fd())
+  }
+}
+
+impl FsFileIsTerminal for RealFsFile {
+  #[inline]
+  fn fs_file_is_terminal(&self) -> bool {
+    self.0.is_terminal()
+  }
+}
+
+impl FsFileLock for RealFsFile {
+  fn fs_file_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    lock_file(&self.0, mode, false)
+  }
+
+  fn fs_file_try_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    lock_file(&self.0, mode, true)
+  }
+
+  fn fs_file_unlock(&mut self) -> io::Result<()> {
+    unlock_file(&self.0)
+  }
+}
+
+#[cfg(all(unix, feature = "libc"))]
+fn lock_file(
+  file: &fs::File,
+  mode: FsFileLockMode,
+  try_lock: bool,
+) -> Result<()> {
+  let operation = match mode {
+    FsFileLockMode::Shared => libc::LOCK_SH,
+    FsFileLockMode::Exclusive => libc::LOCK_EX,
+  } | if try_lock { libc::LOCK_NB } else { 0 };
+
+  flock(file, operation)
+}
+
+#[cfg(all(unix, feature = "libc"))]
+#[inline]
+fn unlock_file(file: &fs::File) -> Result<()> {
+  flock(file, libc::LOCK_UN)
+}
+
+#[cfg(all(unix, feature = "libc"))]
+fn flock(file: &fs::File, operation: i32) -> Result<()> {
+  use std::os::unix::io::AsRawFd;
+
+  // SAFETY: libc calls
+  unsafe {
+    let fd = file.as_raw_fd();
+    let result = libc::flock(fd, operation);
+    if result < 0 {
+      Err(Error::last_os_error())
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[cfg(all(windows, feature = "winapi"))]
+fn lock_file(
+  file: &fs::File,
+  mode: FsFileLockMode,
+  try_lock: bool,
+) -> Result<()> {
+  use std::os::windows::io::AsRawHandle;
+
   use windows_sys::Win32::Foundation::FALSE;
   use windows_sys::Win32::Storage::FileSystem::LockFileEx;
   use windows_sys::Win32::Storage::FileSystem::LOCKFILE_EXCLUSIVE_LOCK;
@@ -975,6 +2355,319 @@ fn unlock_file(_file: &fs::File) -> Result<()> {
   ))
 }
 
+impl FsFileLockRange for RealFsFile {
+  fn fs_file_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    lock_file_range(&self.0, Some(mode), offset, len, false)
+  }
+
+  fn fs_file_try_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    lock_file_range(&self.0, Some(mode), offset, len, true)
+  }
+
+  fn fs_file_unlock_range(&mut self, offset: u64, len: u64) -> io::Result<()> {
+    lock_file_range(&self.0, None, offset, len, true)
+  }
+}
+
+/// Sets an open file description lock on the range, or unlocks it when
+/// the mode is `None`.
+#[cfg(all(target_os = "linux", feature = "libc"))]
+fn lock_file_range(
+  file: &fs::File,
+  mode: Option<FsFileLockMode>,
+  offset: u64,
+  len: u64,
+  try_lock: bool,
+) -> Result<()> {
+  use std::os::unix::io::AsRawFd;
+
+  let to_off_t = |value: u64| {
+    libc::off_t::try_from(value).map_err(|_| {
+      Error::new(
+        ErrorKind::InvalidInput,
+        "lock range exceeds the maximum file offset",
+      )
+    })
+  };
+  // SAFETY: flock is a plain C struct that's valid when zeroed, which
+  // also leaves l_pid as 0 as required for open file description locks
+  let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+  lock.l_type = match mode {
+    None => libc::F_UNLCK,
+    Some(FsFileLockMode::Shared) => libc::F_RDLCK,
+    Some(FsFileLockMode::Exclusive) => libc::F_WRLCK,
+  } as libc::c_short;
+  lock.l_whence = libc::SEEK_SET as libc::c_short;
+  lock.l_start = to_off_t(offset)?;
+  lock.l_len = to_off_t(len)?;
+  let command = if try_lock {
+    libc::F_OFD_SETLK
+  } else {
+    libc::F_OFD_SETLKW
+  };
+
+  loop {
+    // SAFETY: libc call with a valid fd and flock struct
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), command, &lock) };
+    if result == 0 {
+      return Ok(());
+    }
+    let err = Error::last_os_error();
+    match err.raw_os_error() {
+      // a blocking wait was interrupted by a signal
+      Some(libc::EINTR) => continue,
+      // conflicts are reported as either of these
+      Some(libc::EACCES) => return Err(Error::from_raw_os_error(libc::EAGAIN)),
+      _ => return Err(err),
+    }
+  }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "libc")))]
+fn lock_file_range(
+  _file: &fs::File,
+  _mode: Option<FsFileLockMode>,
+  _offset: u64,
+  _len: u64,
+  _try_lock: bool,
+) -> Result<()> {
+  Err(Error::new(
+    ErrorKind::Unsupported,
+    "byte range locking is not supported on this platform or the libc feature in sys_traits is not enabled",
+  ))
+}
+
+impl FsFileMmap for RealFsFile {
+  type Mmap = mmap::RealFsMmap;
+  type MmapMut = mmap::RealFsMmapMut;
+
+  #[inline]
+  unsafe fn fs_file_mmap(&self) -> io::Result<Self::Mmap> {
+    mmap::map(&self.0)
+  }
+
+  #[inline]
+  unsafe fn fs_file_mmap_mut(&mut self) -> io::Result<Self::MmapMut> {
+    mmap::map_mut(&self.0)
+  }
+}
+
+#[cfg(all(unix, feature = "libc"))]
+pub(super) mod mmap {
+  use std::fs;
+  use std::io;
+  use std::ops::Deref;
+  use std::ops::DerefMut;
+  use std::os::fd::AsRawFd;
+  use std::ptr::NonNull;
+
+  use crate::FsMmapMut;
+
+  /// A read-only memory map of a file.
+  #[derive(Debug)]
+  pub struct RealFsMmap(Mapping);
+
+  impl Deref for RealFsMmap {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+      self.0.as_slice()
+    }
+  }
+
+  /// A writable memory map of a file, which shares changes with the file.
+  #[derive(Debug)]
+  pub struct RealFsMmapMut(Mapping);
+
+  impl Deref for RealFsMmapMut {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+      self.0.as_slice()
+    }
+  }
+
+  impl DerefMut for RealFsMmapMut {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+      self.0.as_mut_slice()
+    }
+  }
+
+  impl FsMmapMut for RealFsMmapMut {
+    fn fs_mmap_flush(&self) -> io::Result<()> {
+      if self.0.len == 0 {
+        return Ok(());
+      }
+      // SAFETY: the pointer and length describe a live mapping
+      let result =
+        unsafe { libc::msync(self.0.ptr.as_ptr(), self.0.len, libc::MS_SYNC) };
+      if result != 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(())
+    }
+  }
+
+  /// # Safety
+  ///
+  /// See [`crate::FsFileMmap::fs_file_mmap`].
+  pub unsafe fn map(file: &fs::File) -> io::Result<RealFsMmap> {
+    Mapping::new(file, libc::PROT_READ).map(RealFsMmap)
+  }
+
+  /// # Safety
+  ///
+  /// See [`crate::FsFileMmap::fs_file_mmap_mut`].
+  pub unsafe fn map_mut(file: &fs::File) -> io::Result<RealFsMmapMut> {
+    Mapping::new(file, libc::PROT_READ | libc::PROT_WRITE).map(RealFsMmapMut)
+  }
+
+  #[derive(Debug)]
+  struct Mapping {
+    ptr: NonNull<libc::c_void>,
+    len: usize,
+  }
+
+  // SAFETY: the mapping is owned and only accessed through the references
+  // handed out by the wrapper types
+  unsafe impl Send for Mapping {}
+  unsafe impl Sync for Mapping {}
+
+  impl Mapping {
+    fn new(file: &fs::File, prot: libc::c_int) -> io::Result<Self> {
+      let len = usize::try_from(file.metadata()?.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "File is too large to map")
+      })?;
+      if len == 0 {
+        // mmap doesn't support empty mappings
+        return Ok(Self {
+          ptr: NonNull::dangling(),
+          len: 0,
+        });
+      }
+      // SAFETY: libc call with a valid fd, which may be closed afterwards
+      let ptr = unsafe {
+        libc::mmap(
+          std::ptr::null_mut(),
+          len,
+          prot,
+          libc::MAP_SHARED,
+          file.as_raw_fd(),
+          0,
+        )
+      };
+      if ptr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(Self {
+        ptr: NonNull::new(ptr).ok_or_else(io::Error::last_os_error)?,
+        len,
+      })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+      // SAFETY: the pointer is valid for `len` bytes (or dangling when
+      // empty) for the lifetime of the mapping
+      unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast(), self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+      // SAFETY: same as above and the mapping is borrowed mutably
+      unsafe {
+        std::slice::from_raw_parts_mut(self.ptr.as_ptr().cast(), self.len)
+      }
+    }
+  }
+
+  impl Drop for Mapping {
+    fn drop(&mut self) {
+      if self.len > 0 {
+        // SAFETY: the pointer and length describe a live mapping
+        unsafe {
+          libc::munmap(self.ptr.as_ptr(), self.len);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(not(all(unix, feature = "libc")))]
+pub(super) mod mmap {
+  use std::convert::Infallible;
+  use std::fs;
+  use std::io;
+  use std::ops::Deref;
+  use std::ops::DerefMut;
+
+  use crate::FsMmapMut;
+
+  /// A read-only memory map of a file, which can't be created on
+  /// this platform.
+  #[derive(Debug)]
+  pub struct RealFsMmap(Infallible);
+
+  impl Deref for RealFsMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+      match self.0 {}
+    }
+  }
+
+  /// A writable memory map of a file, which can't be created on
+  /// this platform.
+  #[derive(Debug)]
+  pub struct RealFsMmapMut(Infallible);
+
+  impl Deref for RealFsMmapMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+      match self.0 {}
+    }
+  }
+
+  impl DerefMut for RealFsMmapMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+      match self.0 {}
+    }
+  }
+
+  impl FsMmapMut for RealFsMmapMut {
+    fn fs_mmap_flush(&self) -> io::Result<()> {
+      match self.0 {}
+    }
+  }
+
+  pub fn map(_file: &fs::File) -> io::Result<RealFsMmap> {
+    Err(unsupported())
+  }
+
+  pub fn map_mut(_file: &fs::File) -> io::Result<RealFsMmapMut> {
+    Err(unsupported())
+  }
+
+  fn unsupported() -> io::Error {
+    io::Error::new(
+      io::ErrorKind::Unsupported,
+      "memory mapping is not supported on this platform or the libc feature in sys_traits is not enabled",
+    )
+  }
+}
+
 impl FsFileSetLen for RealFsFile {
   #[inline]
   fn fs_file_set_len(&mut self, size: u64) -> std::io::Result<()> {
@@ -1182,6 +2875,117 @@ mod test {
     assert!(!RealSys.fs_exists_no_err("Cargo2.toml"));
   }
 
+  #[test]
+  fn test_copy_with_options() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path();
+    let data = vec![1u8; 200_000];
+    RealSys.fs_write(path.join("file.bin"), &data).unwrap();
+    let modified =
+      SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    fs::File::options()
+      .write(true)
+      .open(path.join("file.bin"))
+      .unwrap()
+      .set_modified(modified)
+      .unwrap();
+
+    let progress = std::cell::Cell::new(0);
+    let on_progress = |bytes| progress.set(bytes);
+    let mut options = CopyOptions::new();
+    options.preserve_times = true;
+    options.on_progress = Some(&on_progress);
+    let len = RealSys
+      .fs_copy_with_options(
+        path.join("file.bin"),
+        path.join("copy.bin"),
+        &options,
+      )
+      .unwrap();
+    assert_eq!(len, 200_000);
+    assert_eq!(progress.get(), 200_000);
+    assert_eq!(
+      RealSys.fs_read(path.join("copy.bin")).unwrap().as_ref(),
+      data
+    );
+    assert_eq!(
+      RealSys
+        .fs_metadata(path.join("copy.bin"))
+        .unwrap()
+        .modified()
+        .unwrap(),
+      modified
+    );
+
+    options.create_new = true;
+    let err = RealSys
+      .fs_copy_with_options(
+        path.join("file.bin"),
+        path.join("copy.bin"),
+        &options,
+      )
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    // trailing zeros become a hole
+    let mut sparse_data = vec![0u8; 1024 * 1024];
+    sparse_data[..4].copy_from_slice(b"data");
+    RealSys
+      .fs_write(path.join("sparse.bin"), &sparse_data)
+      .unwrap();
+    let mut options = CopyOptions::new();
+    options.sparse = true;
+    RealSys
+      .fs_copy_with_options(
+        path.join("sparse.bin"),
+        path.join("sparse_copy.bin"),
+        &options,
+      )
+      .unwrap();
+    assert_eq!(
+      RealSys
+        .fs_read(path.join("sparse_copy.bin"))
+        .unwrap()
+        .as_ref(),
+      sparse_data
+    );
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn test_copy_with_options_procfs() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("version");
+    let len = RealSys
+      .fs_copy_with_options("/proc/version", &path, &CopyOptions::new())
+      .unwrap();
+    let expected = RealSys.fs_read("/proc/version").unwrap();
+    assert!(len > 0);
+    assert_eq!(len, expected.len() as u64);
+    assert_eq!(RealSys.fs_read(&path).unwrap(), expected);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_copy_with_options_symlink() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path();
+    RealSys.fs_write(path.join("file.txt"), "data").unwrap();
+    RealSys
+      .fs_symlink_file("file.txt", path.join("link"))
+      .unwrap();
+    let mut options = CopyOptions::new();
+    options.copy_symlinks = true;
+    let len = RealSys
+      .fs_copy_with_options(path.join("link"), path.join("link2"), &options)
+      .unwrap();
+    assert_eq!(len, 0);
+    assert_eq!(
+      RealSys.fs_read_link(path.join("link2")).unwrap(),
+      PathBuf::from("file.txt")
+    );
+  }
+
   #[test]
   fn test_clone_file() {
     let temp_dir = tempfile::tempdir().unwrap();
@@ -1195,11 +2999,293 @@ mod test {
         RealSys.fs_read_to_string(path.join("cloned.txt")).unwrap(),
         "data"
       );
+    } else if cfg!(all(target_os = "linux", feature = "libc")) {
+      // depends on whether the temp dir's file system supports reflinks
+      match result {
+        Ok(()) => assert_eq!(
+          RealSys.fs_read_to_string(path.join("cloned.txt")).unwrap(),
+          "data"
+        ),
+        Err(err) => {
+          assert_eq!(err.kind(), ErrorKind::Unsupported);
+          // the partially created destination is cleaned up
+          assert!(!RealSys.fs_exists_no_err(path.join("cloned.txt")));
+        }
+      }
     } else {
       assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
     }
   }
 
+  #[cfg(all(unix, feature = "libc"))]
+  #[test]
+  fn test_statvfs() {
+    // a directory we could create is on a writable file system
+    let temp_dir = tempfile::tempdir().unwrap();
+    let stat = RealSys.fs_statvfs(temp_dir.path()).unwrap();
+    assert!(stat.total_bytes > 0);
+    assert!(stat.available_bytes <= stat.free_bytes);
+    assert!(stat.free_bytes <= stat.total_bytes);
+    assert!(stat.block_size > 0);
+    assert!(stat.max_name_len.unwrap() > 0);
+    assert_eq!(stat.read_only, Some(false));
+  }
+
+  #[cfg(all(unix, feature = "libc"))]
+  #[test]
+  fn test_disk_space() {
+    let space = RealSys.fs_disk_space(".").unwrap();
+    assert!(space.total > 0);
+    assert!(space.free <= space.total);
+    assert!(space.available <= space.free);
+    let err = RealSys.fs_disk_space("non_existent_dir").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+  }
+
+  #[test]
+  fn test_rename_with_options() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path();
+    RealSys.fs_write(path.join("a"), "a").unwrap();
+    RealSys.fs_write(path.join("b"), "b").unwrap();
+    let result = RealSys.fs_rename_with_options(
+      path.join("a"),
+      path.join("b"),
+      &RenameOptions::new_exchange(),
+    );
+    if cfg!(not(all(target_os = "linux", feature = "libc"))) {
+      assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+      return;
+    }
+    match result {
+      Ok(()) => {
+        assert_eq!(RealSys.fs_read_to_string(path.join("a")).unwrap(), "b");
+        assert_eq!(RealSys.fs_read_to_string(path.join("b")).unwrap(), "a");
+      }
+      // the temp dir's file system might not support renameat2 flags
+      Err(err) if err.kind() == ErrorKind::Unsupported => return,
+      Err(err) => panic!("{:#}", err),
+    }
+    let err = RealSys
+      .fs_rename_with_options(
+        path.join("a"),
+        path.join("b"),
+        &RenameOptions::new_no_replace(),
+      )
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+  }
+
+  #[cfg(all(target_os = "linux", feature = "libc"))]
+  #[test]
+  fn test_xattr() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("file.txt");
+    RealSys.fs_write(&path, "data").unwrap();
+    match RealSys.fs_set_xattr(&path, "user.sys_traits", b"value") {
+      Ok(()) => {}
+      // the temp dir's file system might not support user xattrs
+      Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return,
+      Err(err) => panic!("{:#}", err),
+    }
+    assert_eq!(
+      RealSys.fs_get_xattr(&path, "user.sys_traits").unwrap(),
+      Some(b"value".to_vec())
+    );
+    assert_eq!(RealSys.fs_get_xattr(&path, "user.other").unwrap(), None);
+    assert!(RealSys
+      .fs_list_xattr(&path)
+      .unwrap()
+      .contains(&OsString::from("user.sys_traits")));
+    RealSys.fs_remove_xattr(&path, "user.sys_traits").unwrap();
+    assert_eq!(
+      RealSys.fs_get_xattr(&path, "user.sys_traits").unwrap(),
+      None
+    );
+  }
+
+  #[cfg(all(unix, feature = "libc"))]
+  #[test]
+  fn test_open_no_follow_and_directory() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path();
+    RealSys.fs_write(path.join("file"), "data").unwrap();
+    RealSys
+      .fs_symlink_file(path.join("file"), path.join("link"))
+      .unwrap();
+    let err = RealSys
+      .fs_open(path.join("link"), OpenOptions::new_read().no_follow())
+      .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+    assert!(RealSys
+      .fs_open(path.join("file"), OpenOptions::new_read().no_follow())
+      .is_ok());
+
+    let err = RealSys
+      .fs_open(path.join("file"), OpenOptions::new_read().directory())
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotADirectory);
+    let file = RealSys
+      .fs_open(path, OpenOptions::new_read().directory())
+      .unwrap();
+    assert!(file.fs_file_metadata().unwrap().file_type().is_dir());
+  }
+
+  #[cfg(all(unix, feature = "libc"))]
+  #[test]
+  fn test_open_dir() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path();
+    RealSys.fs_create_dir_all(path.join("dir")).unwrap();
+    RealSys.fs_write(path.join("dir/file"), "data").unwrap();
+    let handle = RealSys.fs_open_dir(path.join("dir")).unwrap();
+
+    // the handle keeps referring to the directory after it's moved
+    RealSys
+      .fs_rename(path.join("dir"), path.join("moved"))
+      .unwrap();
+    let mut file = handle.open_at("file", &OpenOptions::new_read()).unwrap();
+    let mut text = String::new();
+    std::io::Read::read_to_string(&mut file, &mut text).unwrap();
+    assert_eq!(text, "data");
+    let metadata = handle.metadata_at("file").unwrap();
+    assert!(metadata.file_type().is_file());
+    assert_eq!(metadata.len(), 4);
+
+    handle.create_dir_at("sub").unwrap();
+    assert!(RealSys.fs_is_dir(path.join("moved/sub")).unwrap());
+    let sub = handle.open_dir_at("sub").unwrap();
+    handle.rename_at("file", &sub, "renamed").unwrap();
+    assert_eq!(
+      RealSys
+        .fs_read_to_string(path.join("moved/sub/renamed"))
+        .unwrap(),
+      "data"
+    );
+    sub.symlink_at("renamed", "link").unwrap();
+    assert!(sub
+      .symlink_metadata_at("link")
+      .unwrap()
+      .file_type()
+      .is_symlink());
+    let mut names = sub
+      .read_dir()
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_owned())
+      .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["link", "renamed"]);
+    // iterating again and concurrently doesn't depend on previous reads
+    let mut first = sub.read_dir().unwrap();
+    first.next().unwrap().unwrap();
+    assert_eq!(sub.read_dir().unwrap().count(), 2);
+    assert_eq!(first.count(), 1);
+
+    sub.remove_at("link").unwrap();
+    sub.remove_at("renamed").unwrap();
+    handle.remove_at("sub").unwrap();
+    assert!(!RealSys.fs_exists(path.join("moved/sub")).unwrap());
+
+    let err = RealSys.fs_open_dir(path.join("missing")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    RealSys.fs_write(path.join("file"), "data").unwrap();
+    let err = RealSys.fs_open_dir(path.join("file")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotADirectory);
+  }
+
+  #[test]
+  fn test_read_shared() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("file.txt");
+    RealSys.fs_write(&path, "data").unwrap();
+    let data = RealSys.fs_read_shared(&path).unwrap();
+    assert_eq!(&*data, b"data");
+    assert!(data.ptr_eq(&data.clone()));
+  }
+
+  #[test]
+  fn test_file_mmap() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("file");
+    RealSys.fs_write(&path, "data").unwrap();
+    let mut file = RealSys.fs_open(&path, &OpenOptions::new_read()).unwrap();
+    // SAFETY: nothing else modifies the file while it's mapped
+    let result = unsafe { file.fs_file_mmap() };
+    if cfg!(not(all(unix, feature = "libc"))) {
+      assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+      return;
+    }
+    assert_eq!(&*result.unwrap(), b"data");
+    // read-only files can't be mapped for writing
+    assert!(unsafe { file.fs_file_mmap_mut() }.is_err());
+
+    let options = OpenOptions {
+      read: true,
+      write: true,
+      ..Default::default()
+    };
+    let mut file = RealSys.fs_open(&path, &options).unwrap();
+    {
+      let mut map = unsafe { file.fs_file_mmap_mut() }.unwrap();
+      map[0] = b'D';
+      map.fs_mmap_flush().unwrap();
+    }
+    assert_eq!(RealSys.fs_read_to_string(&path).unwrap(), "Data");
+
+    file.fs_file_set_len(0).unwrap();
+    assert!(unsafe { file.fs_file_mmap() }.unwrap().is_empty());
+  }
+
+  #[cfg(all(target_os = "linux", feature = "libc"))]
+  #[test]
+  fn test_file_lock_range() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("file");
+    RealSys.fs_write(&path, "data").unwrap();
+    // shared locks require read access
+    let options = OpenOptions::new_write().read().clone();
+    let mut a = RealSys.fs_open(&path, &options).unwrap();
+    let mut b = RealSys.fs_open(&path, &options).unwrap();
+
+    a.fs_file_try_lock_range(FsFileLockMode::Exclusive, 0, 10)
+      .unwrap();
+    b.fs_file_try_lock_range(FsFileLockMode::Exclusive, 10, 10)
+      .unwrap();
+    let err = b
+      .fs_file_try_lock_range(FsFileLockMode::Shared, 5, 10)
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    a.fs_file_unlock_range(0, 10).unwrap();
+    b.fs_file_try_lock_range(FsFileLockMode::Shared, 5, 10)
+      .unwrap();
+  }
+
+  #[cfg(any(feature = "winapi", feature = "libc"))]
+  #[test]
+  fn test_lock_file() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join(".lock");
+    let lock = RealSys
+      .fs_lock_file(&path, &LockFileOptions::new())
+      .unwrap();
+    assert_eq!(lock.owner().pid, std::process::id());
+    let err = RealSys
+      .fs_lock_file(
+        &path,
+        LockFileOptions::new().timeout(std::time::Duration::ZERO),
+      )
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    lock.release().unwrap();
+    let lock = RealSys
+      .fs_lock_file(
+        &path,
+        LockFileOptions::new().timeout(std::time::Duration::ZERO),
+      )
+      .unwrap();
+    assert_eq!(lock.previous_owner(), None);
+  }
+
   #[test]
   fn test_fs_canonicalize_empty() {
     let result = RealSys.fs_canonicalize("");
@@ -56,6 +56,12 @@ extern "C" {
     flags: &str,
     mode: Option<u32>,
   ) -> std::result::Result<i32, JsValue>;
+  #[wasm_bindgen(js_name = openSync, catch)]
+  fn node_open_sync_with_flags(
+    path: &str,
+    flags: i32,
+    mode: Option<u32>,
+  ) -> std::result::Result<i32, JsValue>;
   #[wasm_bindgen(js_name = readFileSync, catch)]
   fn node_read_file_sync(
     path: &str,
@@ -99,6 +105,8 @@ extern "C" {
     path: &str,
     data: &[u8],
   ) -> std::result::Result<(), JsValue>;
+  #[wasm_bindgen(js_name = constants, thread_local_v2)]
+  static NODE_FS_CONSTANTS: JsValue;
   #[wasm_bindgen(js_name = utimesSync, catch)]
   fn node_utimes_sync(
     path: &str,
@@ -195,6 +203,17 @@ extern "C" {
   fn node_tmpdir() -> std::result::Result<String, JsValue>;
 }
 
+// statfsSync was added in Node 18.15, so look it up dynamically
+// rather than failing to load on older runtimes
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+#[wasm_bindgen(
+  inline_js = "import * as fs from 'node:fs'; export function node_statfs_sync(path) { return typeof fs.statfsSync === 'function' ? fs.statfsSync(path) : undefined; }"
+)]
+extern "C" {
+  #[wasm_bindgen(catch)]
+  fn node_statfs_sync(path: &str) -> std::result::Result<JsValue, JsValue>;
+}
+
 #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
 #[wasm_bindgen(
   inline_js = "export function copy_bytes(from, to, dst) { new Uint8Array(to.buffer).set(from, dst) }"
@@ -397,6 +416,20 @@ impl BaseFsCopy for RealSys {
   }
 }
 
+impl BaseFsCopyWithOptions for RealSys {
+  fn base_fs_copy_with_options(
+    &self,
+    _from: &Path,
+    _to: &Path,
+    _options: &CopyOptions,
+  ) -> std::io::Result<u64> {
+    Err(Error::new(
+      ErrorKind::Unsupported,
+      "fs_copy_with_options is not supported in Wasm",
+    ))
+  }
+}
+
 impl BaseFsCloneFile for RealSys {
   #[inline]
   fn base_fs_clone_file(
@@ -449,6 +482,127 @@ impl BaseFsCreateJunction for RealSys {
   }
 }
 
+impl BaseFsDiskSpace for RealSys {
+  #[inline]
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+    let stat = self.base_fs_statvfs(path)?;
+    Ok(DiskSpace {
+      total: stat.total_bytes,
+      free: stat.free_bytes,
+      available: stat.available_bytes,
+    })
+  }
+}
+
+impl BaseFsStatVfs for RealSys {
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs> {
+    let stats = node_statfs_sync(&wasm_path_to_str(path))
+      .map_err(js_value_to_io_error)?;
+    if stats.is_undefined() {
+      return Err(Error::new(
+        ErrorKind::Unsupported,
+        "statfs is not supported by this JavaScript runtime",
+      ));
+    }
+    let get = |name: &str| {
+      js_sys::Reflect::get(&stats, &JsValue::from_str(name))
+        .ok()
+        .and_then(|value| value.as_f64())
+        .unwrap_or(0.0) as u64
+    };
+    let block_size = get("bsize");
+    let free_inodes = get("ffree");
+    Ok(StatVfs {
+      total_bytes: get("blocks") * block_size,
+      free_bytes: get("bfree") * block_size,
+      available_bytes: get("bavail") * block_size,
+      total_inodes: get("files"),
+      free_inodes,
+      // not provided by statfs
+      available_inodes: free_inodes,
+      block_size,
+      max_name_len: None,
+      read_only: None,
+    })
+  }
+}
+
+fn xattr_unsupported<T>() -> io::Result<T> {
+  Err(Error::new(
+    ErrorKind::Unsupported,
+    "Extended attributes are not supported in Wasm",
+  ))
+}
+
+impl BaseFsGetXattr for RealSys {
+  fn base_fs_get_xattr(
+    &self,
+    _path: &Path,
+    _name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    xattr_unsupported()
+  }
+
+  fn base_fs_symlink_get_xattr(
+    &self,
+    _path: &Path,
+    _name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    xattr_unsupported()
+  }
+}
+
+impl BaseFsListXattr for RealSys {
+  fn base_fs_list_xattr(&self, _path: &Path) -> io::Result<Vec<OsString>> {
+    xattr_unsupported()
+  }
+
+  fn base_fs_symlink_list_xattr(
+    &self,
+    _path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    xattr_unsupported()
+  }
+}
+
+impl BaseFsRemoveXattr for RealSys {
+  fn base_fs_remove_xattr(
+    &self,
+    _path: &Path,
+    _name: &OsStr,
+  ) -> io::Result<()> {
+    xattr_unsupported()
+  }
+
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    _path: &Path,
+    _name: &OsStr,
+  ) -> io::Result<()> {
+    xattr_unsupported()
+  }
+}
+
+impl BaseFsSetXattr for RealSys {
+  fn base_fs_set_xattr(
+    &self,
+    _path: &Path,
+    _name: &OsStr,
+    _value: &[u8],
+  ) -> io::Result<()> {
+    xattr_unsupported()
+  }
+
+  fn base_fs_symlink_set_xattr(
+    &self,
+    _path: &Path,
+    _name: &OsStr,
+    _value: &[u8],
+  ) -> io::Result<()> {
+    xattr_unsupported()
+  }
+}
+
 impl From<&Stats> for FileType {
   fn from(value: &Stats) -> Self {
     if value.is_file() {
@@ -706,6 +860,81 @@ impl BaseFsMetadata for RealSys {
   }
 }
 
+/// A directory handle, which can't be opened in Wasm.
+#[derive(Debug, Clone)]
+pub struct WasmDirHandle(std::convert::Infallible);
+
+impl FsDirHandle for WasmDirHandle {
+  type File = WasmFile;
+  type Metadata = WasmMetadata;
+  type ReadDirEntry = WasmFsDirEntry;
+
+  fn open_at(
+    &self,
+    _path: impl AsRef<Path>,
+    _options: &OpenOptions,
+  ) -> Result<Self::File> {
+    match self.0 {}
+  }
+
+  fn open_dir_at(&self, _path: impl AsRef<Path>) -> Result<Self> {
+    match self.0 {}
+  }
+
+  fn metadata_at(&self, _path: impl AsRef<Path>) -> Result<Self::Metadata> {
+    match self.0 {}
+  }
+
+  fn symlink_metadata_at(
+    &self,
+    _path: impl AsRef<Path>,
+  ) -> Result<Self::Metadata> {
+    match self.0 {}
+  }
+
+  fn read_dir(
+    &self,
+  ) -> Result<Box<dyn Iterator<Item = Result<Self::ReadDirEntry>>>> {
+    match self.0 {}
+  }
+
+  fn create_dir_at(&self, _path: impl AsRef<Path>) -> Result<()> {
+    match self.0 {}
+  }
+
+  fn remove_at(&self, _path: impl AsRef<Path>) -> Result<()> {
+    match self.0 {}
+  }
+
+  fn rename_at(
+    &self,
+    _from: impl AsRef<Path>,
+    _to_dir: &Self,
+    _to: impl AsRef<Path>,
+  ) -> Result<()> {
+    match self.0 {}
+  }
+
+  fn symlink_at(
+    &self,
+    _original: impl AsRef<Path>,
+    _link: impl AsRef<Path>,
+  ) -> Result<()> {
+    match self.0 {}
+  }
+}
+
+impl BaseFsOpenDir for RealSys {
+  type DirHandle = WasmDirHandle;
+
+  fn base_fs_open_dir(&self, _path: &Path) -> Result<Self::DirHandle> {
+    Err(Error::new(
+      ErrorKind::Unsupported,
+      "fs_open_dir is not supported in Wasm",
+    ))
+  }
+}
+
 impl BaseFsOpen for RealSys {
   type File = WasmFile;
 
@@ -734,7 +963,13 @@ impl BaseFsOpen for RealSys {
     };
 
     let mode = options.mode;
-    let fd = node_open_sync(&s, flags, mode).map_err(js_value_to_io_error)?;
+    let fd = if options.no_follow || options.directory {
+      let flags = numeric_open_flags(flags, options)?;
+      node_open_sync_with_flags(&s, flags, mode)
+    } else {
+      node_open_sync(&s, flags, mode)
+    }
+    .map_err(js_value_to_io_error)?;
 
     // Set initial position based on flags
     let initial_position = if options.append {
@@ -756,6 +991,38 @@ impl BaseFsOpen for RealSys {
   }
 }
 
+/// Converts the Node.js string flags to their numeric form in order to add
+/// flags that don't have a string equivalent.
+fn numeric_open_flags(flags: &str, options: &OpenOptions) -> Result<i32> {
+  NODE_FS_CONSTANTS.with(|constants| {
+    let get = |name: &'static str| -> Result<i32> {
+      match get_prop(constants, name)?.as_f64() {
+        Some(value) => Ok(value as i32),
+        None => Err(Error::new(
+          ErrorKind::Unsupported,
+          format!("fs.constants.{} is not supported on this platform", name),
+        )),
+      }
+    };
+    let mut value = match flags {
+      "wx" => {
+        get("O_WRONLY")? | get("O_CREAT")? | get("O_TRUNC")? | get("O_EXCL")?
+      }
+      "a" => get("O_WRONLY")? | get("O_CREAT")? | get("O_APPEND")?,
+      "w" => get("O_WRONLY")? | get("O_CREAT")? | get("O_TRUNC")?,
+      "r+" => get("O_RDWR")?,
+      _ => get("O_RDONLY")?,
+    };
+    if options.no_follow {
+      value |= get("O_NOFOLLOW")?;
+    }
+    if options.directory {
+      value |= get("O_DIRECTORY")?;
+    }
+    Ok(value)
+  })
+}
+
 impl BaseFsRead for RealSys {
   fn base_fs_read(&self, path: &Path) -> Result<Cow<'static, [u8]>> {
     let s = wasm_path_to_str(path);
@@ -773,6 +1040,13 @@ impl BaseFsRead for RealSys {
   }
 }
 
+impl BaseFsReadShared for RealSys {
+  #[inline]
+  fn base_fs_read_shared(&self, path: &Path) -> Result<FsSharedBytes> {
+    self.base_fs_read(path).map(FsSharedBytes::from)
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct ObjectBuilder {
   object: js_sys::Object,
@@ -955,6 +1229,23 @@ impl BaseFsRename for RealSys {
   }
 }
 
+impl BaseFsRenameWithOptions for RealSys {
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> std::io::Result<()> {
+    if options.no_replace || options.exchange {
+      return Err(Error::new(
+        ErrorKind::Unsupported,
+        "renaming with no_replace or exchange is not supported in Wasm",
+      ));
+    }
+    self.base_fs_rename(from, to)
+  }
+}
+
 impl BaseFsSetFileTimes for RealSys {
   #[inline]
   fn base_fs_set_file_times(
@@ -1104,6 +1395,98 @@ impl FsFileLock for WasmFile {
   }
 }
 
+impl FsFileLockRange for WasmFile {
+  fn fs_file_lock_range(
+    &mut self,
+    _mode: FsFileLockMode,
+    _offset: u64,
+    _len: u64,
+  ) -> io::Result<()> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "file locking is not supported",
+    ))
+  }
+
+  fn fs_file_try_lock_range(
+    &mut self,
+    _mode: FsFileLockMode,
+    _offset: u64,
+    _len: u64,
+  ) -> io::Result<()> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "file locking is not supported",
+    ))
+  }
+
+  fn fs_file_unlock_range(
+    &mut self,
+    _offset: u64,
+    _len: u64,
+  ) -> io::Result<()> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "file locking is not supported",
+    ))
+  }
+}
+
+/// A read-only memory map of a file, which can't be created in Wasm.
+#[derive(Debug)]
+pub struct WasmMmap(std::convert::Infallible);
+
+impl std::ops::Deref for WasmMmap {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self.0 {}
+  }
+}
+
+/// A writable memory map of a file, which can't be created in Wasm.
+#[derive(Debug)]
+pub struct WasmMmapMut(std::convert::Infallible);
+
+impl std::ops::Deref for WasmMmapMut {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self.0 {}
+  }
+}
+
+impl std::ops::DerefMut for WasmMmapMut {
+  fn deref_mut(&mut self) -> &mut [u8] {
+    match self.0 {}
+  }
+}
+
+impl FsMmapMut for WasmMmapMut {
+  fn fs_mmap_flush(&self) -> io::Result<()> {
+    match self.0 {}
+  }
+}
+
+impl FsFileMmap for WasmFile {
+  type Mmap = WasmMmap;
+  type MmapMut = WasmMmapMut;
+
+  unsafe fn fs_file_mmap(&self) -> io::Result<Self::Mmap> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "memory mapping is not supported in Wasm",
+    ))
+  }
+
+  unsafe fn fs_file_mmap_mut(&mut self) -> io::Result<Self::MmapMut> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "memory mapping is not supported in Wasm",
+    ))
+  }
+}
+
 impl FsFileSetLen for WasmFile {
   fn fs_file_set_len(&mut self, size: u64) -> std::io::Result<()> {
     node_ftruncate_sync(self.fd, size as u32).map_err(js_value_to_io_error)
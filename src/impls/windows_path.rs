@@ -0,0 +1,271 @@
+//! Host independent parsing of Windows paths.
+//!
+//! This is used by the in-memory file system in order to emulate
+//! Windows path semantics on any operating system.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum WindowsPrefix {
+  /// `C:`
+  Disk(char),
+  /// `\\server\share`
+  Unc { server: String, share: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct WindowsPath {
+  pub prefix: Option<WindowsPrefix>,
+  /// If the path starts at the root of its prefix or the current drive.
+  pub has_root: bool,
+  pub components: Vec<String>,
+}
+
+fn is_separator(c: char) -> bool {
+  c == '\\' || c == '/'
+}
+
+fn split_disk(path: &str) -> Option<(char, &str)> {
+  let mut chars = path.chars();
+  let letter = chars.next()?;
+  if letter.is_ascii_alphabetic() && chars.next() == Some(':') {
+    Some((letter.to_ascii_uppercase(), &path[2..]))
+  } else {
+    None
+  }
+}
+
+/// Splits off the next component when parsing the server and share
+/// of a UNC path.
+fn split_component(path: &str, verbatim: bool) -> (&str, &str) {
+  let end = if verbatim {
+    path.find('\\')
+  } else {
+    path.find(is_separator)
+  };
+  match end {
+    Some(end) => (&path[..end], &path[end + 1..]),
+    None => (path, ""),
+  }
+}
+
+/// Parses a Windows path.
+///
+/// Verbatim (`\\?\`) and device (`\\.\`) prefixes are resolved to the
+/// disk or UNC path they refer to. Device names and volume GUIDs are
+/// represented as a UNC path with a server of `.` or `?`.
+pub(super) fn parse(path: &str) -> WindowsPath {
+  let (prefix, rest, verbatim) = if let Some(rest) = path
+    .strip_prefix(r"\\?\")
+    .or_else(|| path.strip_prefix(r"\\.\"))
+  {
+    let is_verbatim = path.as_bytes()[2] == b'?';
+    let server = if is_verbatim { "?" } else { "." };
+    if let Some((letter, rest)) = split_disk(rest) {
+      (Some(WindowsPrefix::Disk(letter)), rest, is_verbatim)
+    } else if rest.len() >= 4 && rest[..4].eq_ignore_ascii_case(r"UNC\") {
+      let (server, rest) = split_component(&rest[4..], is_verbatim);
+      let (share, rest) = split_component(rest, is_verbatim);
+      let prefix = WindowsPrefix::Unc {
+        server: server.to_string(),
+        share: share.to_string(),
+      };
+      (Some(prefix), rest, is_verbatim)
+    } else {
+      let (device, rest) = split_component(rest, is_verbatim);
+      let prefix = WindowsPrefix::Unc {
+        server: server.to_string(),
+        share: device.to_string(),
+      };
+      (Some(prefix), rest, is_verbatim)
+    }
+  } else if path.len() >= 2
+    && path.chars().take(2).all(is_separator)
+    && !path[2..].starts_with(is_separator)
+  {
+    let (server, rest) = split_component(&path[2..], false);
+    let (share, rest) = split_component(rest, false);
+    let prefix = WindowsPrefix::Unc {
+      server: server.to_string(),
+      share: share.to_string(),
+    };
+    (Some(prefix), rest, false)
+  } else if let Some((letter, rest)) = split_disk(path) {
+    (Some(WindowsPrefix::Disk(letter)), rest, false)
+  } else {
+    (None, path, false)
+  };
+
+  let has_root = match &prefix {
+    // UNC paths are always rooted
+    Some(WindowsPrefix::Unc { .. }) => true,
+    Some(WindowsPrefix::Disk(_)) if verbatim => true,
+    _ => rest.starts_with(is_separator),
+  };
+  let components = if verbatim {
+    // verbatim paths only use backslashes and aren't normalized
+    rest
+      .split('\\')
+      .filter(|c| !c.is_empty())
+      .map(|c| c.to_string())
+      .collect()
+  } else {
+    rest
+      .split(is_separator)
+      .filter(|c| !c.is_empty() && *c != ".")
+      .map(|c| c.to_string())
+      .collect()
+  };
+  WindowsPath {
+    prefix,
+    has_root,
+    components,
+  }
+}
+
+/// Formats an absolute path with the provided prefix and components.
+pub(super) fn format(
+  prefix: &WindowsPrefix,
+  components: &[String],
+  verbatim: bool,
+) -> String {
+  let mut text = match (prefix, verbatim) {
+    (WindowsPrefix::Disk(letter), false) => format!(r"{}:\", letter),
+    (WindowsPrefix::Disk(letter), true) => format!(r"\\?\{}:\", letter),
+    (WindowsPrefix::Unc { server, share }, false) => {
+      format!(r"\\{}\{}\", server, share)
+    }
+    (WindowsPrefix::Unc { server, share }, true) => {
+      format!(r"\\?\UNC\{}\{}\", server, share)
+    }
+  };
+  text.push_str(&components.join(r"\"));
+  text
+}
+
+/// Joins a file name onto a Windows path.
+pub(super) fn join(path: &str, name: &str) -> String {
+  if path.is_empty()
+    || path.ends_with(is_separator)
+    || (path.len() == 2 && split_disk(path).is_some())
+  {
+    format!("{}{}", path, name)
+  } else {
+    format!(r"{}\{}", path, name)
+  }
+}
+
+/// Strips the verbatim prefix (ex. `\\?\`) from a Windows path
+/// following the same rules as the `strip_unc` feature.
+pub(super) fn strip_unc_prefix(path: &str) -> String {
+  let Some(rest) = path.strip_prefix(r"\\?\") else {
+    return path.to_string();
+  };
+  if split_disk(rest).is_some() {
+    // \\?\c:\path
+    rest.to_string()
+  } else if rest.len() >= 4 && rest[..4].eq_ignore_ascii_case(r"UNC\") {
+    // \\?\UNC\hostname\share_name\path
+    format!(r"\\{}", &rest[4..])
+  } else {
+    // \\?\device
+    format!(r"\\{}", rest)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn disk(letter: char, has_root: bool, components: &[&str]) -> WindowsPath {
+    WindowsPath {
+      prefix: Some(WindowsPrefix::Disk(letter)),
+      has_root,
+      components: components.iter().map(|c| c.to_string()).collect(),
+    }
+  }
+
+  fn unc(server: &str, share: &str, components: &[&str]) -> WindowsPath {
+    WindowsPath {
+      prefix: Some(WindowsPrefix::Unc {
+        server: server.to_string(),
+        share: share.to_string(),
+      }),
+      has_root: true,
+      components: components.iter().map(|c| c.to_string()).collect(),
+    }
+  }
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(parse(r"C:\Users\test"), disk('C', true, &["Users", "test"]));
+    assert_eq!(
+      parse("c:/Users//test/"),
+      disk('C', true, &["Users", "test"])
+    );
+    assert_eq!(parse(r"D:dir\.\file"), disk('D', false, &["dir", "file"]));
+    assert_eq!(parse(r"C:\a\..\b"), disk('C', true, &["a", "..", "b"]));
+    assert_eq!(parse(r"\\?\C:\a\.\b"), disk('C', true, &["a", ".", "b"]));
+    assert_eq!(
+      parse(r"\\server\share\dir"),
+      unc("server", "share", &["dir"])
+    );
+    assert_eq!(parse("//server/share"), unc("server", "share", &[]));
+    assert_eq!(
+      parse(r"\\?\UNC\server\share\a/b"),
+      unc("server", "share", &["a/b"])
+    );
+    assert_eq!(parse(r"\\.\COM1"), unc(".", "COM1", &[]));
+    assert_eq!(
+      parse(r"\\?\Volume{abc}\dir"),
+      unc("?", "Volume{abc}", &["dir"])
+    );
+    assert_eq!(
+      parse(r"\dir\file"),
+      WindowsPath {
+        prefix: None,
+        has_root: true,
+        components: vec!["dir".to_string(), "file".to_string()],
+      }
+    );
+    assert_eq!(
+      parse(r"dir/file"),
+      WindowsPath {
+        prefix: None,
+        has_root: false,
+        components: vec!["dir".to_string(), "file".to_string()],
+      }
+    );
+  }
+
+  #[test]
+  fn test_format() {
+    let components = vec!["a".to_string(), "b".to_string()];
+    let drive = WindowsPrefix::Disk('C');
+    assert_eq!(format(&drive, &components, false), r"C:\a\b");
+    assert_eq!(format(&drive, &[], true), r"\\?\C:\");
+    let unc = WindowsPrefix::Unc {
+      server: "server".to_string(),
+      share: "share".to_string(),
+    };
+    assert_eq!(format(&unc, &components, false), r"\\server\share\a\b");
+    assert_eq!(format(&unc, &components, true), r"\\?\UNC\server\share\a\b");
+  }
+
+  #[test]
+  fn test_join() {
+    assert_eq!(join(r"C:\", "a"), r"C:\a");
+    assert_eq!(join(r"C:\dir", "a"), r"C:\dir\a");
+    assert_eq!(join("C:", "a"), "C:a");
+    assert_eq!(join("dir/", "a"), "dir/a");
+  }
+
+  #[test]
+  fn test_strip_unc_prefix() {
+    assert_eq!(strip_unc_prefix(r"\\?\C:\dir"), r"C:\dir");
+    assert_eq!(
+      strip_unc_prefix(r"\\?\UNC\server\share\dir"),
+      r"\\server\share\dir"
+    );
+    assert_eq!(strip_unc_prefix(r"\\?\Volume{abc}\"), r"\\Volume{abc}\");
+    assert_eq!(strip_unc_prefix(r"C:\dir"), r"C:\dir");
+  }
+}
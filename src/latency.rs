@@ -0,0 +1,1092 @@
+//! Artificial latency wrapper for simulating slow file systems.
+//!
+//! This module provides [`LatencySys`], a wrapper that delays every
+//! forwarded file system call according to a [`LatencyProfile`]. Delays
+//! go through the wrapped system's [`ThreadSleep`] implementation, so when
+//! wrapping an in-memory system with thread sleeping disabled the delays
+//! only advance virtual time.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sys_traits::LatencyProfile;
+//! use sys_traits::LatencySys;
+//! # #[cfg(feature = "real")]
+//! use sys_traits::impls::RealSys;
+//!
+//! # #[cfg(feature = "real")]
+//! # fn example() {
+//! let sys = LatencySys::new(
+//!   RealSys,
+//!   LatencyProfile::new()
+//!     .per_call(Duration::from_millis(5))
+//!     .bytes_per_second(10 * 1024 * 1024)
+//!     .clone(),
+//! );
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::boxed::BoxedFsMetadataValue;
+use crate::BaseEnvRemoveVar;
+use crate::BaseEnvSetCurrentDir;
+use crate::BaseEnvSetVar;
+use crate::BaseEnvVar;
+use crate::BaseFsCanonicalize;
+use crate::BaseFsChown;
+use crate::BaseFsCloneFile;
+use crate::BaseFsCopy;
+use crate::BaseFsCopyWithOptions;
+use crate::BaseFsCreateDir;
+use crate::BaseFsCreateJunction;
+use crate::BaseFsDiskSpace;
+use crate::BaseFsGetXattr;
+use crate::BaseFsHardLink;
+use crate::BaseFsListXattr;
+use crate::BaseFsMetadata;
+use crate::BaseFsOpen;
+use crate::BaseFsOpenDir;
+use crate::BaseFsRead;
+use crate::BaseFsReadDir;
+use crate::BaseFsReadLink;
+use crate::BaseFsReadShared;
+use crate::BaseFsRemoveDir;
+use crate::BaseFsRemoveDirAll;
+use crate::BaseFsRemoveFile;
+use crate::BaseFsRemoveXattr;
+use crate::BaseFsRename;
+use crate::BaseFsRenameWithOptions;
+use crate::BaseFsSetFileTimes;
+use crate::BaseFsSetPermissions;
+use crate::BaseFsSetSymlinkFileTimes;
+use crate::BaseFsSetXattr;
+use crate::BaseFsStatVfs;
+use crate::BaseFsSymlinkChown;
+use crate::BaseFsSymlinkDir;
+use crate::BaseFsSymlinkFile;
+use crate::BaseFsWrite;
+use crate::CopyOptions;
+use crate::CreateDirOptions;
+use crate::DiskSpace;
+use crate::EnvCacheDir;
+use crate::EnvCurrentDir;
+use crate::EnvHomeDir;
+use crate::EnvProgramsDir;
+use crate::EnvSetUmask;
+use crate::EnvTempDir;
+use crate::EnvUmask;
+use crate::EnvVars;
+use crate::FsDirHandle;
+use crate::FsFile;
+use crate::FsFileAsRaw;
+use crate::FsFileIsTerminal;
+use crate::FsFileLock;
+use crate::FsFileLockMode;
+use crate::FsFileLockRange;
+use crate::FsFileMetadata;
+use crate::FsFileMmap;
+use crate::FsFileSetLen;
+use crate::FsFileSetPermissions;
+use crate::FsFileSetTimes;
+use crate::FsFileSyncAll;
+use crate::FsFileSyncData;
+use crate::FsFileTimes;
+use crate::FsSharedBytes;
+use crate::OpenOptions;
+use crate::ProcessExit;
+use crate::RenameOptions;
+use crate::StatVfs;
+use crate::SystemRandom;
+use crate::SystemTimeNow;
+use crate::ThreadSleep;
+
+/// Describes how much latency [`LatencySys`] adds to each call.
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive] // so we can add properties without breaking people
+pub struct LatencyProfile {
+  /// Delay applied to every forwarded file system call.
+  pub per_call: Duration,
+  /// Throughput used to add a delay proportional to the number of
+  /// bytes read or written. `None` or `0` means unlimited throughput.
+  pub bytes_per_second: Option<u64>,
+}
+
+impl LatencyProfile {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  #[inline]
+  pub fn per_call(&mut self, value: Duration) -> &mut Self {
+    self.per_call = value;
+    self
+  }
+
+  #[inline]
+  pub fn bytes_per_second(&mut self, value: u64) -> &mut Self {
+    self.bytes_per_second = Some(value);
+    self
+  }
+
+  /// Gets the delay for a call that transferred `len` bytes.
+  pub fn delay_for(&self, len: usize) -> Duration {
+    let transfer = match self.bytes_per_second {
+      Some(0) | None => Duration::ZERO,
+      Some(bytes_per_second) => {
+        let nanos = (len as u128 * 1_000_000_000) / bytes_per_second as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+      }
+    };
+    self.per_call.saturating_add(transfer)
+  }
+}
+
+/// A wrapper that delays file system calls to simulate slow or
+/// network file systems.
+///
+/// Environment and system calls are forwarded without a delay.
+#[derive(Debug, Clone)]
+pub struct LatencySys<T> {
+  sys: T,
+  profile: LatencyProfile,
+}
+
+impl<T> LatencySys<T> {
+  pub fn new(sys: T, profile: LatencyProfile) -> Self {
+    Self { sys, profile }
+  }
+
+  /// Returns the latency profile.
+  pub fn profile(&self) -> &LatencyProfile {
+    &self.profile
+  }
+
+  /// Returns a reference to the inner system.
+  pub fn inner(&self) -> &T {
+    &self.sys
+  }
+
+  /// Consumes the wrapper and returns the inner system.
+  pub fn into_inner(self) -> T {
+    self.sys
+  }
+}
+
+impl<T: ThreadSleep> LatencySys<T> {
+  #[inline]
+  fn delay(&self) {
+    self.delay_bytes(0);
+  }
+
+  fn delay_bytes(&self, len: usize) {
+    let duration = self.profile.delay_for(len);
+    if !duration.is_zero() {
+      self.sys.thread_sleep(duration);
+    }
+  }
+}
+
+// ==== Environment ====
+
+impl<T: EnvCurrentDir> EnvCurrentDir for LatencySys<T> {
+  #[inline]
+  fn env_current_dir(&self) -> io::Result<PathBuf> {
+    self.sys.env_current_dir()
+  }
+}
+
+impl<T: BaseEnvSetCurrentDir> BaseEnvSetCurrentDir for LatencySys<T> {
+  #[inline]
+  fn base_env_set_current_dir(&self, path: &Path) -> io::Result<()> {
+    self.sys.base_env_set_current_dir(path)
+  }
+}
+
+impl<T: BaseEnvVar> BaseEnvVar for LatencySys<T> {
+  #[inline]
+  fn base_env_var_os(&self, key: &OsStr) -> Option<OsString> {
+    self.sys.base_env_var_os(key)
+  }
+}
+
+impl<T: EnvVars> EnvVars for LatencySys<T> {
+  type EnvVarsOs = T::EnvVarsOs;
+
+  #[inline]
+  fn env_vars_os(&self) -> Self::EnvVarsOs {
+    self.sys.env_vars_os()
+  }
+}
+
+impl<T: BaseEnvRemoveVar> BaseEnvRemoveVar for LatencySys<T> {
+  #[inline]
+  fn base_env_remove_var(&self, key: &OsStr) {
+    self.sys.base_env_remove_var(key)
+  }
+}
+
+impl<T: BaseEnvSetVar> BaseEnvSetVar for LatencySys<T> {
+  #[inline]
+  fn base_env_set_var(&self, key: &OsStr, value: &OsStr) {
+    self.sys.base_env_set_var(key, value)
+  }
+}
+
+impl<T: EnvUmask> EnvUmask for LatencySys<T> {
+  #[inline]
+  fn env_umask(&self) -> io::Result<u32> {
+    self.sys.env_umask()
+  }
+}
+
+impl<T: EnvSetUmask> EnvSetUmask for LatencySys<T> {
+  #[inline]
+  fn env_set_umask(&self, umask: u32) -> io::Result<u32> {
+    self.sys.env_set_umask(umask)
+  }
+}
+
+impl<T: EnvCacheDir> EnvCacheDir for LatencySys<T> {
+  #[inline]
+  fn env_cache_dir(&self) -> Option<PathBuf> {
+    self.sys.env_cache_dir()
+  }
+}
+
+impl<T: EnvHomeDir> EnvHomeDir for LatencySys<T> {
+  #[inline]
+  fn env_home_dir(&self) -> Option<PathBuf> {
+    self.sys.env_home_dir()
+  }
+}
+
+impl<T: EnvProgramsDir> EnvProgramsDir for LatencySys<T> {
+  #[inline]
+  fn env_programs_dir(&self) -> Option<PathBuf> {
+    self.sys.env_programs_dir()
+  }
+}
+
+impl<T: EnvTempDir> EnvTempDir for LatencySys<T> {
+  #[inline]
+  fn env_temp_dir(&self) -> io::Result<PathBuf> {
+    self.sys.env_temp_dir()
+  }
+}
+
+// ==== File System ====
+
+impl<T: BaseFsCanonicalize + ThreadSleep> BaseFsCanonicalize for LatencySys<T> {
+  fn base_fs_canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+    self.delay();
+    self.sys.base_fs_canonicalize(path)
+  }
+}
+
+impl<T: BaseFsChown + ThreadSleep> BaseFsChown for LatencySys<T> {
+  fn base_fs_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_chown(path, uid, gid)
+  }
+}
+
+impl<T: BaseFsSymlinkChown + ThreadSleep> BaseFsSymlinkChown for LatencySys<T> {
+  fn base_fs_symlink_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_symlink_chown(path, uid, gid)
+  }
+}
+
+impl<T: BaseFsCloneFile + ThreadSleep> BaseFsCloneFile for LatencySys<T> {
+  fn base_fs_clone_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_clone_file(from, to)
+  }
+}
+
+impl<T: BaseFsCopy + ThreadSleep> BaseFsCopy for LatencySys<T> {
+  fn base_fs_copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+    let len = self.sys.base_fs_copy(from, to)?;
+    self.delay_bytes(len as usize);
+    Ok(len)
+  }
+}
+
+impl<T: BaseFsCopyWithOptions + ThreadSleep> BaseFsCopyWithOptions
+  for LatencySys<T>
+{
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    let len = self.sys.base_fs_copy_with_options(from, to, options)?;
+    self.delay_bytes(len as usize);
+    Ok(len)
+  }
+}
+
+impl<T: BaseFsCreateDir + ThreadSleep> BaseFsCreateDir for LatencySys<T> {
+  fn base_fs_create_dir(
+    &self,
+    path: &Path,
+    options: &CreateDirOptions,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_create_dir(path, options)
+  }
+}
+
+impl<T: BaseFsHardLink + ThreadSleep> BaseFsHardLink for LatencySys<T> {
+  fn base_fs_hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_hard_link(src, dst)
+  }
+}
+
+impl<T: BaseFsCreateJunction + ThreadSleep> BaseFsCreateJunction
+  for LatencySys<T>
+{
+  fn base_fs_create_junction(
+    &self,
+    original: &Path,
+    junction: &Path,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_create_junction(original, junction)
+  }
+}
+
+impl<T: BaseFsDiskSpace + ThreadSleep> BaseFsDiskSpace for LatencySys<T> {
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+    self.delay();
+    self.sys.base_fs_disk_space(path)
+  }
+}
+
+impl<T: BaseFsStatVfs + ThreadSleep> BaseFsStatVfs for LatencySys<T> {
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs> {
+    self.delay();
+    self.sys.base_fs_statvfs(path)
+  }
+}
+
+impl<T: BaseFsMetadata + ThreadSleep> BaseFsMetadata for LatencySys<T> {
+  type Metadata = T::Metadata;
+
+  fn base_fs_metadata(&self, path: &Path) -> io::Result<Self::Metadata> {
+    self.delay();
+    self.sys.base_fs_metadata(path)
+  }
+
+  fn base_fs_symlink_metadata(
+    &self,
+    path: &Path,
+  ) -> io::Result<Self::Metadata> {
+    self.delay();
+    self.sys.base_fs_symlink_metadata(path)
+  }
+
+  fn base_fs_exists(&self, path: &Path) -> io::Result<bool> {
+    self.delay();
+    self.sys.base_fs_exists(path)
+  }
+
+  fn base_fs_exists_no_err(&self, path: &Path) -> bool {
+    self.delay();
+    self.sys.base_fs_exists_no_err(path)
+  }
+}
+
+impl<T: BaseFsOpen + ThreadSleep + Clone + 'static> BaseFsOpen
+  for LatencySys<T>
+{
+  type File = LatencyFile<T::File, T>;
+
+  fn base_fs_open(
+    &self,
+    path: &Path,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    self.delay();
+    let file = self.sys.base_fs_open(path, options)?;
+    Ok(LatencyFile {
+      file,
+      sys: self.clone(),
+    })
+  }
+}
+
+impl<T: BaseFsOpenDir + ThreadSleep + Clone + std::fmt::Debug + 'static>
+  BaseFsOpenDir for LatencySys<T>
+{
+  type DirHandle = LatencyDirHandle<T::DirHandle, T>;
+
+  fn base_fs_open_dir(&self, path: &Path) -> io::Result<Self::DirHandle> {
+    self.delay();
+    let dir = self.sys.base_fs_open_dir(path)?;
+    Ok(LatencyDirHandle {
+      dir,
+      sys: self.clone(),
+    })
+  }
+}
+
+impl<T: BaseFsRead + ThreadSleep> BaseFsRead for LatencySys<T> {
+  fn base_fs_read(&self, path: &Path) -> io::Result<Cow<'static, [u8]>> {
+    let data = self.sys.base_fs_read(path)?;
+    self.delay_bytes(data.len());
+    Ok(data)
+  }
+}
+
+impl<T: BaseFsReadShared + ThreadSleep> BaseFsReadShared for LatencySys<T> {
+  fn base_fs_read_shared(&self, path: &Path) -> io::Result<FsSharedBytes> {
+    let data = self.sys.base_fs_read_shared(path)?;
+    self.delay_bytes(data.len());
+    Ok(data)
+  }
+}
+
+impl<T: BaseFsReadDir + ThreadSleep> BaseFsReadDir for LatencySys<T> {
+  type ReadDirEntry = T::ReadDirEntry;
+
+  fn base_fs_read_dir(
+    &self,
+    path: &Path,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    self.delay();
+    self.sys.base_fs_read_dir(path)
+  }
+}
+
+impl<T: BaseFsReadLink + ThreadSleep> BaseFsReadLink for LatencySys<T> {
+  fn base_fs_read_link(&self, path: &Path) -> io::Result<PathBuf> {
+    self.delay();
+    self.sys.base_fs_read_link(path)
+  }
+}
+
+impl<T: BaseFsRemoveDir + ThreadSleep> BaseFsRemoveDir for LatencySys<T> {
+  fn base_fs_remove_dir(&self, path: &Path) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_remove_dir(path)
+  }
+}
+
+impl<T: BaseFsRemoveDirAll + ThreadSleep> BaseFsRemoveDirAll for LatencySys<T> {
+  fn base_fs_remove_dir_all(&self, path: &Path) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_remove_dir_all(path)
+  }
+}
+
+impl<T: BaseFsRemoveFile + ThreadSleep> BaseFsRemoveFile for LatencySys<T> {
+  fn base_fs_remove_file(&self, path: &Path) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_remove_file(path)
+  }
+}
+
+impl<T: BaseFsRename + ThreadSleep> BaseFsRename for LatencySys<T> {
+  fn base_fs_rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_rename(from, to)
+  }
+}
+
+impl<T: BaseFsRenameWithOptions + ThreadSleep> BaseFsRenameWithOptions
+  for LatencySys<T>
+{
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_rename_with_options(from, to, options)
+  }
+}
+
+impl<T: BaseFsSetFileTimes + ThreadSleep> BaseFsSetFileTimes for LatencySys<T> {
+  fn base_fs_set_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_set_file_times(path, atime, mtime)
+  }
+}
+
+impl<T: BaseFsSetSymlinkFileTimes + ThreadSleep> BaseFsSetSymlinkFileTimes
+  for LatencySys<T>
+{
+  fn base_fs_set_symlink_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_set_symlink_file_times(path, atime, mtime)
+  }
+}
+
+impl<T: BaseFsSetPermissions + ThreadSleep> BaseFsSetPermissions
+  for LatencySys<T>
+{
+  fn base_fs_set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_set_permissions(path, mode)
+  }
+}
+
+impl<T: BaseFsSymlinkDir + ThreadSleep> BaseFsSymlinkDir for LatencySys<T> {
+  fn base_fs_symlink_dir(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_symlink_dir(original, link)
+  }
+}
+
+impl<T: BaseFsSymlinkFile + ThreadSleep> BaseFsSymlinkFile for LatencySys<T> {
+  fn base_fs_symlink_file(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_symlink_file(original, link)
+  }
+}
+
+impl<T: BaseFsWrite + ThreadSleep> BaseFsWrite for LatencySys<T> {
+  fn base_fs_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+    self.delay_bytes(data.len());
+    self.sys.base_fs_write(path, data)
+  }
+}
+
+impl<T: BaseFsGetXattr + ThreadSleep> BaseFsGetXattr for LatencySys<T> {
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.delay();
+    self.sys.base_fs_get_xattr(path, name)
+  }
+
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.delay();
+    self.sys.base_fs_symlink_get_xattr(path, name)
+  }
+}
+
+impl<T: BaseFsListXattr + ThreadSleep> BaseFsListXattr for LatencySys<T> {
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>> {
+    self.delay();
+    self.sys.base_fs_list_xattr(path)
+  }
+
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    self.delay();
+    self.sys.base_fs_symlink_list_xattr(path)
+  }
+}
+
+impl<T: BaseFsRemoveXattr + ThreadSleep> BaseFsRemoveXattr for LatencySys<T> {
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_remove_xattr(path, name)
+  }
+
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_symlink_remove_xattr(path, name)
+  }
+}
+
+impl<T: BaseFsSetXattr + ThreadSleep> BaseFsSetXattr for LatencySys<T> {
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_set_xattr(path, name, value)
+  }
+
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.delay();
+    self.sys.base_fs_symlink_set_xattr(path, name, value)
+  }
+}
+
+// ==== File System File ====
+
+/// A file returned from [`LatencySys`] that delays reads and writes
+/// proportional to the number of bytes transferred.
+#[derive(Debug)]
+pub struct LatencyFile<F, T> {
+  file: F,
+  sys: LatencySys<T>,
+}
+
+impl<F, T> LatencyFile<F, T> {
+  /// Returns a reference to the inner file.
+  pub fn inner(&self) -> &F {
+    &self.file
+  }
+
+  /// Consumes the wrapper and returns the inner file.
+  pub fn into_inner(self) -> F {
+    self.file
+  }
+}
+
+impl<F: io::Read, T: ThreadSleep> io::Read for LatencyFile<F, T> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let len = self.file.read(buf)?;
+    self.sys.delay_bytes(len);
+    Ok(len)
+  }
+}
+
+impl<F: io::Write, T: ThreadSleep> io::Write for LatencyFile<F, T> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let len = self.file.write(buf)?;
+    self.sys.delay_bytes(len);
+    Ok(len)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.sys.delay();
+    self.file.flush()
+  }
+}
+
+impl<F: io::Seek, T> io::Seek for LatencyFile<F, T> {
+  #[inline]
+  fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+    self.file.seek(pos)
+  }
+}
+
+impl<F: FsFileAsRaw, T> FsFileAsRaw for LatencyFile<F, T> {
+  #[cfg(windows)]
+  #[inline]
+  fn fs_file_as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+    self.file.fs_file_as_raw_handle()
+  }
+
+  #[cfg(unix)]
+  #[inline]
+  fn fs_file_as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
+    self.file.fs_file_as_raw_fd()
+  }
+}
+
+impl<F: FsFileIsTerminal, T> FsFileIsTerminal for LatencyFile<F, T> {
+  #[inline]
+  fn fs_file_is_terminal(&self) -> bool {
+    self.file.fs_file_is_terminal()
+  }
+}
+
+impl<F: FsFileLock, T: ThreadSleep> FsFileLock for LatencyFile<F, T> {
+  fn fs_file_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_lock(mode)
+  }
+
+  fn fs_file_try_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_try_lock(mode)
+  }
+
+  fn fs_file_unlock(&mut self) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_unlock()
+  }
+}
+
+impl<F: FsFileMmap, T: ThreadSleep> FsFileMmap for LatencyFile<F, T> {
+  type Mmap = F::Mmap;
+  type MmapMut = F::MmapMut;
+
+  unsafe fn fs_file_mmap(&self) -> io::Result<Self::Mmap> {
+    self.sys.delay();
+    self.file.fs_file_mmap()
+  }
+
+  unsafe fn fs_file_mmap_mut(&mut self) -> io::Result<Self::MmapMut> {
+    self.sys.delay();
+    self.file.fs_file_mmap_mut()
+  }
+}
+
+impl<F: FsFileLockRange, T: ThreadSleep> FsFileLockRange for LatencyFile<F, T> {
+  fn fs_file_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_lock_range(mode, offset, len)
+  }
+
+  fn fs_file_try_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_try_lock_range(mode, offset, len)
+  }
+
+  fn fs_file_unlock_range(&mut self, offset: u64, len: u64) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_unlock_range(offset, len)
+  }
+}
+
+impl<F: FsFileMetadata, T: ThreadSleep> FsFileMetadata for LatencyFile<F, T> {
+  fn fs_file_metadata(&self) -> io::Result<BoxedFsMetadataValue> {
+    self.sys.delay();
+    self.file.fs_file_metadata()
+  }
+}
+
+impl<F: FsFileSetLen, T: ThreadSleep> FsFileSetLen for LatencyFile<F, T> {
+  fn fs_file_set_len(&mut self, size: u64) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_set_len(size)
+  }
+}
+
+impl<F: FsFileSetPermissions, T: ThreadSleep> FsFileSetPermissions
+  for LatencyFile<F, T>
+{
+  fn fs_file_set_permissions(&mut self, mode: u32) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_set_permissions(mode)
+  }
+}
+
+impl<F: FsFileSetTimes, T: ThreadSleep> FsFileSetTimes for LatencyFile<F, T> {
+  fn fs_file_set_times(&mut self, times: FsFileTimes) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_set_times(times)
+  }
+}
+
+impl<F: FsFileSyncAll, T: ThreadSleep> FsFileSyncAll for LatencyFile<F, T> {
+  fn fs_file_sync_all(&mut self) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_sync_all()
+  }
+}
+
+impl<F: FsFileSyncData, T: ThreadSleep> FsFileSyncData for LatencyFile<F, T> {
+  fn fs_file_sync_data(&mut self) -> io::Result<()> {
+    self.sys.delay();
+    self.file.fs_file_sync_data()
+  }
+}
+
+impl<F: FsFile, T: ThreadSleep> FsFile for LatencyFile<F, T> {}
+
+// ==== File System Directory Handle ====
+
+/// A directory handle returned from [`LatencySys`] that delays each
+/// operation made through it.
+#[derive(Debug)]
+pub struct LatencyDirHandle<D, T> {
+  dir: D,
+  sys: LatencySys<T>,
+}
+
+impl<D, T> LatencyDirHandle<D, T> {
+  /// Returns a reference to the inner directory handle.
+  pub fn inner(&self) -> &D {
+    &self.dir
+  }
+
+  /// Consumes the wrapper and returns the inner directory handle.
+  pub fn into_inner(self) -> D {
+    self.dir
+  }
+}
+
+impl<D: FsDirHandle, T: ThreadSleep + Clone + std::fmt::Debug + 'static>
+  FsDirHandle for LatencyDirHandle<D, T>
+{
+  type File = LatencyFile<D::File, T>;
+  type Metadata = D::Metadata;
+  type ReadDirEntry = D::ReadDirEntry;
+
+  fn open_at(
+    &self,
+    path: impl AsRef<Path>,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    self.sys.delay();
+    let file = self.dir.open_at(path, options)?;
+    Ok(LatencyFile {
+      file,
+      sys: self.sys.clone(),
+    })
+  }
+
+  fn open_dir_at(&self, path: impl AsRef<Path>) -> io::Result<Self> {
+    self.sys.delay();
+    let dir = self.dir.open_dir_at(path)?;
+    Ok(LatencyDirHandle {
+      dir,
+      sys: self.sys.clone(),
+    })
+  }
+
+  fn metadata_at(&self, path: impl AsRef<Path>) -> io::Result<Self::Metadata> {
+    self.sys.delay();
+    self.dir.metadata_at(path)
+  }
+
+  fn symlink_metadata_at(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Self::Metadata> {
+    self.sys.delay();
+    self.dir.symlink_metadata_at(path)
+  }
+
+  fn read_dir(
+    &self,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    self.sys.delay();
+    self.dir.read_dir()
+  }
+
+  fn create_dir_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    self.sys.delay();
+    self.dir.create_dir_at(path)
+  }
+
+  fn remove_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    self.sys.delay();
+    self.dir.remove_at(path)
+  }
+
+  fn rename_at(
+    &self,
+    from: impl AsRef<Path>,
+    to_dir: &Self,
+    to: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    self.sys.delay();
+    self.dir.rename_at(from, &to_dir.dir, to)
+  }
+
+  fn symlink_at(
+    &self,
+    original: impl AsRef<Path>,
+    link: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    self.sys.delay();
+    self.dir.symlink_at(original, link)
+  }
+}
+
+// ==== System ====
+
+impl<T: SystemTimeNow> SystemTimeNow for LatencySys<T> {
+  #[inline]
+  fn sys_time_now(&self) -> SystemTime {
+    self.sys.sys_time_now()
+  }
+}
+
+impl<T: SystemRandom> SystemRandom for LatencySys<T> {
+  #[inline]
+  fn sys_random(&self, buf: &mut [u8]) -> io::Result<()> {
+    self.sys.sys_random(buf)
+  }
+}
+
+impl<T: ProcessExit> ProcessExit for LatencySys<T> {
+  #[inline]
+  fn process_exit(&self, code: i32) -> ! {
+    self.sys.process_exit(code)
+  }
+}
+
+impl<T: ThreadSleep> ThreadSleep for LatencySys<T> {
+  #[inline]
+  fn thread_sleep(&self, duration: Duration) {
+    self.sys.thread_sleep(duration)
+  }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+  use std::io::Read;
+  use std::io::Write;
+
+  use super::*;
+  use crate::impls::InMemorySys;
+  use crate::FsCreateDirAll;
+  use crate::FsMetadata;
+  use crate::FsMetadataValue;
+  use crate::FsOpen;
+  use crate::FsOpenDir;
+  use crate::FsRead;
+  use crate::FsWrite;
+
+  fn create_sys(profile: LatencyProfile) -> LatencySys<InMemorySys> {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.set_time(Some(SystemTime::UNIX_EPOCH));
+    sys.disable_thread_sleep();
+    LatencySys::new(sys, profile)
+  }
+
+  fn elapsed(sys: &LatencySys<InMemorySys>) -> Duration {
+    sys
+      .sys_time_now()
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .unwrap()
+  }
+
+  #[test]
+  fn test_delay_for() {
+    let profile = LatencyProfile::new()
+      .per_call(Duration::from_millis(2))
+      .bytes_per_second(1000)
+      .clone();
+    assert_eq!(profile.delay_for(0), Duration::from_millis(2));
+    assert_eq!(profile.delay_for(500), Duration::from_millis(502));
+    assert_eq!(LatencyProfile::new().delay_for(1000), Duration::ZERO);
+    let profile = LatencyProfile::new().bytes_per_second(0).clone();
+    assert_eq!(profile.delay_for(1000), Duration::ZERO);
+  }
+
+  #[test]
+  fn test_per_call_delay() {
+    let sys = create_sys(
+      LatencyProfile::new()
+        .per_call(Duration::from_secs(1))
+        .clone(),
+    );
+    sys.fs_create_dir_all("/dir").unwrap();
+    assert!(sys.fs_exists("/dir").unwrap());
+    assert!(sys.fs_is_dir("/dir").unwrap());
+    assert_eq!(elapsed(&sys), Duration::from_secs(3));
+  }
+
+  #[test]
+  fn test_dir_handle_delay() {
+    let sys = create_sys(
+      LatencyProfile::new()
+        .per_call(Duration::from_secs(1))
+        .bytes_per_second(10)
+        .clone(),
+    );
+    let dir = sys.fs_open_dir("/").unwrap();
+    dir.create_dir_at("sub").unwrap();
+    assert!(dir.metadata_at("sub").unwrap().file_type().is_dir());
+    assert_eq!(elapsed(&sys), Duration::from_secs(3));
+    let mut file = dir.open_at("file", &OpenOptions::new_write()).unwrap();
+    file.write_all(b"0123456789").unwrap();
+    assert_eq!(elapsed(&sys), Duration::from_secs(6));
+  }
+
+  #[test]
+  fn test_byte_delay() {
+    let sys = create_sys(LatencyProfile::new().bytes_per_second(10).clone());
+    sys.fs_write("/file.txt", b"0123456789").unwrap();
+    assert_eq!(elapsed(&sys), Duration::from_secs(1));
+    assert_eq!(&*sys.fs_read("/file.txt").unwrap(), b"0123456789");
+    assert_eq!(elapsed(&sys), Duration::from_secs(2));
+  }
+
+  #[test]
+  fn test_file_read_write_delay() {
+    let sys = create_sys(
+      LatencyProfile::new()
+        .per_call(Duration::from_millis(100))
+        .bytes_per_second(100)
+        .clone(),
+    );
+    let mut file = sys.fs_open("/file.txt", &OpenOptions::new_write()).unwrap();
+    // open
+    assert_eq!(elapsed(&sys), Duration::from_millis(100));
+    file.write_all(&[0; 50]).unwrap();
+    assert_eq!(elapsed(&sys), Duration::from_millis(700));
+    drop(file);
+
+    let mut file = sys.fs_open("/file.txt", &OpenOptions::new_read()).unwrap();
+    let mut buf = [0; 25];
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(elapsed(&sys), Duration::from_millis(1150));
+  }
+
+  #[test]
+  fn test_env_not_delayed() {
+    let sys = create_sys(
+      LatencyProfile::new()
+        .per_call(Duration::from_secs(1))
+        .clone(),
+    );
+    sys.base_env_set_var(OsStr::new("KEY"), OsStr::new("value"));
+    assert_eq!(
+      sys.base_env_var_os(OsStr::new("KEY")),
+      Some(OsString::from("value"))
+    );
+    assert_eq!(elapsed(&sys), Duration::ZERO);
+  }
+}
@@ -8,11 +8,18 @@ use std::io::Error;
 use std::io::ErrorKind;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::SystemTime;
 
 pub mod boxed;
 pub mod ctx;
+pub mod env_overlay;
 pub mod impls;
+pub mod latency;
+pub mod lock_file;
+#[cfg(feature = "serde_json")]
+pub mod replay;
+pub mod transaction;
 
 pub use sys_traits_macros::auto_impl;
 
@@ -21,6 +28,24 @@ pub use self::ctx::OperationError;
 pub use self::ctx::OperationErrorKind;
 pub use self::ctx::PathsInErrorsExt;
 pub use self::ctx::SysWithPathsInErrors;
+pub use self::env_overlay::EnvOverlaySys;
+pub use self::latency::LatencyDirHandle;
+pub use self::latency::LatencyFile;
+pub use self::latency::LatencyProfile;
+pub use self::latency::LatencySys;
+pub use self::lock_file::FsLockFile;
+pub use self::lock_file::LockFile;
+pub use self::lock_file::LockFileOptions;
+pub use self::lock_file::LockFileOwner;
+#[cfg(feature = "serde_json")]
+pub use self::replay::RecordingSys;
+#[cfg(feature = "serde_json")]
+pub use self::replay::ReplaySys;
+#[cfg(feature = "serde_json")]
+pub use self::replay::Trace;
+pub use self::transaction::TransactionBackupSys;
+pub use self::transaction::TransactionDirHandle;
+pub use self::transaction::TransactionSys;
 
 use self::boxed::BoxedFsFile;
 use self::boxed::BoxedFsMetadataValue;
@@ -200,6 +225,15 @@ pub struct OpenOptions {
   pub truncate: bool,
   pub append: bool,
   pub create_new: bool,
+  /// Fail when the final component of the path is a symlink instead of
+  /// following it (`O_NOFOLLOW` on Unix).
+  pub no_follow: bool,
+  /// Fail when the path is not a directory (`O_DIRECTORY` on Unix).
+  ///
+  /// `InMemorySys` can't open directories as files, so it fails with
+  /// `ErrorKind::Unsupported` when the path is a directory. Use
+  /// [`FsOpenDir`] to get a handle to a directory there.
+  pub directory: bool,
   /// Unix only. Ignored on Windows.
   pub mode: Option<u32>,
   /// Custom flags to set on Unix or Windows.
@@ -295,6 +329,18 @@ impl OpenOptions {
     self
   }
 
+  #[inline]
+  pub fn no_follow(&mut self) -> &mut Self {
+    self.no_follow = true;
+    self
+  }
+
+  #[inline]
+  pub fn directory(&mut self) -> &mut Self {
+    self.directory = true;
+    self
+  }
+
   #[inline]
   pub fn mode(&mut self, mode: u32) -> &mut Self {
     self.mode = Some(mode);
@@ -408,6 +454,12 @@ pub trait BaseFsCloneFile {
 }
 
 pub trait FsCloneFile: BaseFsCloneFile {
+  /// Creates a copy-on-write clone of a file, erroring when the
+  /// destination already exists.
+  ///
+  /// Returns an error of kind `ErrorKind::Unsupported` when the platform
+  /// or file system can't clone files, in which case callers may fall
+  /// back to `fs_copy`.
   #[inline]
   fn fs_clone_file(
     &self,
@@ -440,6 +492,83 @@ pub trait FsCopy: BaseFsCopy {
 
 impl<T: BaseFsCopy> FsCopy for T {}
 
+// == FsCopyWithOptions ==
+
+#[derive(Default, Clone, Copy)]
+#[non_exhaustive] // so we can add properties without breaking people
+pub struct CopyOptions<'a> {
+  /// Error with `ErrorKind::AlreadyExists` when the destination exists
+  /// instead of overwriting it.
+  pub create_new: bool,
+  /// Copy the accessed and modified times to the destination.
+  pub preserve_times: bool,
+  /// Unix only. Copy the owner and group to the destination, which
+  /// usually requires elevated privileges.
+  pub preserve_ownership: bool,
+  /// Copy a symlink as a symlink instead of copying the file it
+  /// points to.
+  pub copy_symlinks: bool,
+  /// Skip over holes in the source file so the destination stays
+  /// sparse on file systems that support it.
+  pub sparse: bool,
+  /// Called with the total number of bytes copied so far.
+  pub on_progress: Option<&'a dyn Fn(u64)>,
+}
+
+impl std::fmt::Debug for CopyOptions<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("CopyOptions")
+      .field("create_new", &self.create_new)
+      .field("preserve_times", &self.preserve_times)
+      .field("preserve_ownership", &self.preserve_ownership)
+      .field("copy_symlinks", &self.copy_symlinks)
+      .field("sparse", &self.sparse)
+      .field("on_progress", &self.on_progress.is_some())
+      .finish()
+  }
+}
+
+impl CopyOptions<'_> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Calls `on_progress` when set. Useful for implementors.
+  #[inline]
+  pub fn report_progress(&self, bytes: u64) {
+    if let Some(on_progress) = self.on_progress {
+      on_progress(bytes);
+    }
+  }
+}
+
+pub trait BaseFsCopyWithOptions {
+  #[doc(hidden)]
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> io::Result<u64>;
+}
+
+pub trait FsCopyWithOptions: BaseFsCopyWithOptions {
+  /// Copies a file, returning the number of bytes copied.
+  ///
+  /// Copying a symlink with `copy_symlinks` returns 0.
+  #[inline]
+  fn fs_copy_with_options(
+    &self,
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    self.base_fs_copy_with_options(from.as_ref(), to.as_ref(), options)
+  }
+}
+
+impl<T: BaseFsCopyWithOptions> FsCopyWithOptions for T {}
+
 // == FsCreateDir ==
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -557,9 +686,84 @@ pub trait FsCreateJunction: BaseFsCreateJunction {
 
 impl<T: BaseFsCreateJunction> FsCreateJunction for T {}
 
+// == FsDiskSpace ==
+
+/// Space information for the file system containing a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct DiskSpace {
+  /// Total size of the file system in bytes.
+  pub total: u64,
+  /// Free bytes, including those reserved for privileged users.
+  pub free: u64,
+  /// Free bytes available to unprivileged users.
+  pub available: u64,
+}
+
+pub trait BaseFsDiskSpace {
+  #[doc(hidden)]
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace>;
+}
+
+pub trait FsDiskSpace: BaseFsDiskSpace {
+  /// Gets the total and free space of the file system containing
+  /// the provided path.
+  #[inline]
+  fn fs_disk_space(&self, path: impl AsRef<Path>) -> io::Result<DiskSpace> {
+    self.base_fs_disk_space(path.as_ref())
+  }
+}
+
+impl<T: BaseFsDiskSpace> FsDiskSpace for T {}
+
+// == FsStatVfs ==
+
+/// Statistics for the file system containing a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct StatVfs {
+  /// Total size of the file system in bytes.
+  pub total_bytes: u64,
+  /// Free bytes, including those reserved for privileged users.
+  pub free_bytes: u64,
+  /// Free bytes available to unprivileged users.
+  pub available_bytes: u64,
+  /// Total number of inodes.
+  pub total_inodes: u64,
+  /// Free inodes, including those reserved for privileged users.
+  pub free_inodes: u64,
+  /// Free inodes available to unprivileged users.
+  pub available_inodes: u64,
+  /// Preferred block size for I/O.
+  pub block_size: u64,
+  /// Maximum length of a file name or `None` when unknown.
+  pub max_name_len: Option<u64>,
+  /// If the file system is mounted read-only or `None` when unknown.
+  pub read_only: Option<bool>,
+}
+
+pub trait BaseFsStatVfs {
+  #[doc(hidden)]
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs>;
+}
+
+pub trait FsStatVfs: BaseFsStatVfs {
+  /// Gets statistics about the file system containing the provided path.
+  #[inline]
+  fn fs_statvfs(&self, path: impl AsRef<Path>) -> io::Result<StatVfs> {
+    self.base_fs_statvfs(path.as_ref())
+  }
+}
+
+impl<T: BaseFsStatVfs> FsStatVfs for T {}
+
 // == FsMetadata ==
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
 pub enum FileType {
   File,
   Dir,
@@ -765,6 +969,86 @@ pub trait FsOpen: BaseFsOpen {
 
 impl<T: BaseFsOpen> FsOpen for T {}
 
+// == FsOpenDir ==
+
+/// A handle to an open directory.
+///
+/// Relative paths are resolved against the directory itself instead of
+/// its path, so operations keep applying to the same directory even when
+/// it's moved or replaced (ex. with a symlink) after being opened.
+pub trait FsDirHandle: std::fmt::Debug + Sized {
+  type File: FsFile + Sized + 'static;
+  type Metadata: FsMetadataValue;
+  type ReadDirEntry: FsDirEntry + 'static;
+
+  /// Opens a file relative to this directory.
+  fn open_at(
+    &self,
+    path: impl AsRef<Path>,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File>;
+
+  /// Opens a directory relative to this directory.
+  fn open_dir_at(&self, path: impl AsRef<Path>) -> io::Result<Self>;
+
+  /// Gets the metadata of an entry relative to this directory.
+  fn metadata_at(&self, path: impl AsRef<Path>) -> io::Result<Self::Metadata>;
+
+  /// Gets the metadata of an entry relative to this directory without
+  /// following a symlink at the end of the path.
+  fn symlink_metadata_at(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Self::Metadata>;
+
+  /// Reads the entries of this directory.
+  #[allow(clippy::type_complexity)]
+  fn read_dir(
+    &self,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>>;
+
+  /// Creates a directory relative to this directory.
+  fn create_dir_at(&self, path: impl AsRef<Path>) -> io::Result<()>;
+
+  /// Removes a file, symlink, or empty directory relative to this
+  /// directory.
+  fn remove_at(&self, path: impl AsRef<Path>) -> io::Result<()>;
+
+  /// Renames an entry relative to this directory to a path relative
+  /// to `to_dir`, which may be this directory.
+  fn rename_at(
+    &self,
+    from: impl AsRef<Path>,
+    to_dir: &Self,
+    to: impl AsRef<Path>,
+  ) -> io::Result<()>;
+
+  /// Creates a symlink at `link` relative to this directory that
+  /// points to `original`.
+  fn symlink_at(
+    &self,
+    original: impl AsRef<Path>,
+    link: impl AsRef<Path>,
+  ) -> io::Result<()>;
+}
+
+pub trait BaseFsOpenDir {
+  type DirHandle: FsDirHandle + 'static;
+
+  #[doc(hidden)]
+  fn base_fs_open_dir(&self, path: &Path) -> io::Result<Self::DirHandle>;
+}
+
+pub trait FsOpenDir: BaseFsOpenDir {
+  /// Opens a handle to a directory, following symlinks.
+  #[inline]
+  fn fs_open_dir(&self, path: impl AsRef<Path>) -> io::Result<Self::DirHandle> {
+    self.base_fs_open_dir(path.as_ref())
+  }
+}
+
+impl<T: BaseFsOpenDir> FsOpenDir for T {}
+
 // == FsRead ==
 
 pub trait BaseFsRead {
@@ -820,6 +1104,116 @@ pub trait FsRead: BaseFsRead {
 
 impl<T: BaseFsRead> FsRead for T {}
 
+// == FsReadShared ==
+
+/// File contents that can be cloned without copying the bytes.
+#[derive(Clone, Default)]
+pub struct FsSharedBytes {
+  data: Arc<Vec<u8>>,
+  /// The part of the buffer that holds the contents.
+  range: std::ops::Range<usize>,
+}
+
+impl FsSharedBytes {
+  /// Creates a view of part of a buffer that may be shared.
+  #[cfg(feature = "memory")]
+  pub(crate) fn from_shared(
+    data: Arc<Vec<u8>>,
+    range: std::ops::Range<usize>,
+  ) -> Self {
+    Self { data, range }
+  }
+
+  /// Gets if both handles share the same underlying buffer.
+  pub fn ptr_eq(&self, other: &Self) -> bool {
+    Arc::ptr_eq(&self.data, &other.data) && self.range == other.range
+  }
+
+  /// Gets the bytes, only copying when the buffer is still shared.
+  pub fn into_vec(self) -> Vec<u8> {
+    match Arc::try_unwrap(self.data) {
+      Ok(mut data) if self.range.start == 0 => {
+        data.truncate(self.range.end);
+        data
+      }
+      Ok(data) => data[self.range].to_vec(),
+      Err(data) => data[self.range].to_vec(),
+    }
+  }
+}
+
+impl std::fmt::Debug for FsSharedBytes {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_tuple("FsSharedBytes").field(&&**self).finish()
+  }
+}
+
+impl PartialEq for FsSharedBytes {
+  fn eq(&self, other: &Self) -> bool {
+    **self == **other
+  }
+}
+
+impl Eq for FsSharedBytes {}
+
+impl std::hash::Hash for FsSharedBytes {
+  fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    (**self).hash(state);
+  }
+}
+
+impl std::ops::Deref for FsSharedBytes {
+  type Target = [u8];
+
+  #[inline]
+  fn deref(&self) -> &[u8] {
+    &self.data[self.range.clone()]
+  }
+}
+
+impl AsRef<[u8]> for FsSharedBytes {
+  #[inline]
+  fn as_ref(&self) -> &[u8] {
+    self
+  }
+}
+
+impl From<Vec<u8>> for FsSharedBytes {
+  fn from(data: Vec<u8>) -> Self {
+    Self {
+      range: 0..data.len(),
+      data: Arc::new(data),
+    }
+  }
+}
+
+impl From<Cow<'static, [u8]>> for FsSharedBytes {
+  fn from(data: Cow<'static, [u8]>) -> Self {
+    Self::from(data.into_owned())
+  }
+}
+
+pub trait BaseFsReadShared {
+  #[doc(hidden)]
+  fn base_fs_read_shared(&self, path: &Path) -> io::Result<FsSharedBytes>;
+}
+
+pub trait FsReadShared: BaseFsReadShared {
+  /// Reads the file into a handle that's cheap to clone.
+  ///
+  /// Implementations that already keep the file contents in memory
+  /// (ex. `InMemorySys`) return their buffer without copying it.
+  #[inline]
+  fn fs_read_shared(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<FsSharedBytes> {
+    self.base_fs_read_shared(path.as_ref())
+  }
+}
+
+impl<T: BaseFsReadShared> FsReadShared for T {}
+
 // == FsReadDir ==
 
 pub trait FsDirEntry: std::fmt::Debug {
@@ -937,6 +1331,68 @@ pub trait FsRename: BaseFsRename {
 
 impl<T: BaseFsRename> FsRename for T {}
 
+// == FsRenameWithOptions ==
+
+#[derive(Default, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
+#[non_exhaustive] // so we can add properties without breaking people
+pub struct RenameOptions {
+  /// Atomically fail with `ErrorKind::AlreadyExists` when the
+  /// destination exists instead of replacing it.
+  pub no_replace: bool,
+  /// Atomically swap the source and destination, which must both exist.
+  pub exchange: bool,
+}
+
+impl RenameOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn new_no_replace() -> Self {
+    Self {
+      no_replace: true,
+      exchange: false,
+    }
+  }
+
+  pub fn new_exchange() -> Self {
+    Self {
+      no_replace: false,
+      exchange: true,
+    }
+  }
+}
+
+pub trait BaseFsRenameWithOptions {
+  #[doc(hidden)]
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> io::Result<()>;
+}
+
+pub trait FsRenameWithOptions: BaseFsRenameWithOptions {
+  /// Renames an entry with the provided options.
+  ///
+  /// Errors with `ErrorKind::Unsupported` when the platform or file
+  /// system can't perform the rename atomically.
+  #[inline]
+  fn fs_rename_with_options(
+    &self,
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    options: &RenameOptions,
+  ) -> io::Result<()> {
+    self.base_fs_rename_with_options(from.as_ref(), to.as_ref(), options)
+  }
+}
+
+impl<T: BaseFsRenameWithOptions> FsRenameWithOptions for T {}
+
 // == FsSetFileTimes ==
 
 pub trait BaseFsSetFileTimes {
@@ -1073,6 +1529,168 @@ pub trait FsWrite: BaseFsWrite {
 
 impl<T: BaseFsWrite> FsWrite for T {}
 
+// == FsGetXattr ==
+
+pub trait BaseFsGetXattr {
+  #[doc(hidden)]
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>>;
+
+  #[doc(hidden)]
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>>;
+}
+
+pub trait FsGetXattr: BaseFsGetXattr {
+  /// Gets the value of an extended attribute or `None` when the
+  /// attribute does not exist.
+  #[inline]
+  fn fs_get_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.base_fs_get_xattr(path.as_ref(), name.as_ref())
+  }
+
+  /// Gets the value of an extended attribute without following symlinks.
+  #[inline]
+  fn fs_symlink_get_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.base_fs_symlink_get_xattr(path.as_ref(), name.as_ref())
+  }
+}
+
+impl<T: BaseFsGetXattr> FsGetXattr for T {}
+
+// == FsListXattr ==
+
+pub trait BaseFsListXattr {
+  #[doc(hidden)]
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>>;
+
+  #[doc(hidden)]
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>>;
+}
+
+pub trait FsListXattr: BaseFsListXattr {
+  /// Gets the names of the extended attributes of an entry.
+  #[inline]
+  fn fs_list_xattr(&self, path: impl AsRef<Path>) -> io::Result<Vec<OsString>> {
+    self.base_fs_list_xattr(path.as_ref())
+  }
+
+  /// Gets the names of the extended attributes of an entry without
+  /// following symlinks.
+  #[inline]
+  fn fs_symlink_list_xattr(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Vec<OsString>> {
+    self.base_fs_symlink_list_xattr(path.as_ref())
+  }
+}
+
+impl<T: BaseFsListXattr> FsListXattr for T {}
+
+// == FsRemoveXattr ==
+
+pub trait BaseFsRemoveXattr {
+  #[doc(hidden)]
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()>;
+
+  #[doc(hidden)]
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()>;
+}
+
+pub trait FsRemoveXattr: BaseFsRemoveXattr {
+  /// Removes an extended attribute.
+  ///
+  /// Errors when the attribute does not exist.
+  #[inline]
+  fn fs_remove_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<()> {
+    self.base_fs_remove_xattr(path.as_ref(), name.as_ref())
+  }
+
+  /// Removes an extended attribute without following symlinks.
+  #[inline]
+  fn fs_symlink_remove_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+  ) -> io::Result<()> {
+    self.base_fs_symlink_remove_xattr(path.as_ref(), name.as_ref())
+  }
+}
+
+impl<T: BaseFsRemoveXattr> FsRemoveXattr for T {}
+
+// == FsSetXattr ==
+
+pub trait BaseFsSetXattr {
+  #[doc(hidden)]
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()>;
+
+  #[doc(hidden)]
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()>;
+}
+
+pub trait FsSetXattr: BaseFsSetXattr {
+  /// Sets an extended attribute, replacing any existing value.
+  #[inline]
+  fn fs_set_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+    value: impl AsRef<[u8]>,
+  ) -> io::Result<()> {
+    self.base_fs_set_xattr(path.as_ref(), name.as_ref(), value.as_ref())
+  }
+
+  /// Sets an extended attribute without following symlinks.
+  #[inline]
+  fn fs_symlink_set_xattr(
+    &self,
+    path: impl AsRef<Path>,
+    name: impl AsRef<OsStr>,
+    value: impl AsRef<[u8]>,
+  ) -> io::Result<()> {
+    self.base_fs_symlink_set_xattr(path.as_ref(), name.as_ref(), value.as_ref())
+  }
+}
+
+impl<T: BaseFsSetXattr> FsSetXattr for T {}
+
 // #### FILE SYSTEM FILE ####
 
 pub trait FsFileAsRaw {
@@ -1102,6 +1720,66 @@ pub trait FsFileLock {
   fn fs_file_unlock(&mut self) -> io::Result<()>;
 }
 
+/// Advisory locks on byte ranges of a file, which are owned by the open
+/// file (similar to open file description locks on Linux).
+///
+/// A `len` of 0 covers from the offset to the end of the file, including
+/// bytes that are later appended. These are independent of the whole
+/// file locks in [`FsFileLock`].
+///
+/// This isn't required by [`FsFile`], so add it as a bound where needed.
+pub trait FsFileLockRange {
+  /// Acquires a lock on the range, blocking until it's available.
+  fn fs_file_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()>;
+  /// Acquires a lock on the range or errors with `ErrorKind::WouldBlock`
+  /// when it conflicts with a lock held elsewhere.
+  fn fs_file_try_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()>;
+  /// Releases locks held in the range, which may split a larger lock.
+  fn fs_file_unlock_range(&mut self, offset: u64, len: u64) -> io::Result<()>;
+}
+
+/// Memory maps the contents of a file for zero-copy access.
+///
+/// The map covers the length of the file when it was mapped.
+pub trait FsFileMmap {
+  type Mmap: std::ops::Deref<Target = [u8]>;
+  type MmapMut: FsMmapMut;
+
+  /// Maps the file read-only, which requires it to be opened for reading.
+  ///
+  /// # Safety
+  ///
+  /// The map is shared with the file, so the caller must ensure the file
+  /// isn't truncated or modified, by this or any other process, while the
+  /// map is alive. Accessing a truncated region raises `SIGBUS` and a
+  /// concurrent modification is a data race.
+  unsafe fn fs_file_mmap(&self) -> io::Result<Self::Mmap>;
+  /// Maps the file for reading and writing, which requires it to be opened
+  /// for both.
+  ///
+  /// # Safety
+  ///
+  /// Same as [`FsFileMmap::fs_file_mmap`], and the file also must not be
+  /// read by anything else while the map is alive.
+  unsafe fn fs_file_mmap_mut(&mut self) -> io::Result<Self::MmapMut>;
+}
+
+/// A writable memory map of a file.
+pub trait FsMmapMut: std::ops::DerefMut<Target = [u8]> {
+  /// Flushes changes made through the map to the file.
+  fn fs_mmap_flush(&self) -> io::Result<()>;
+}
+
 pub trait FsFileMetadata {
   /// Gets the file metadata.
   ///
@@ -1178,6 +1856,39 @@ pub trait SystemRandom {
     self.sys_random(&mut buf)?;
     Ok(u64::from_le_bytes(buf))
   }
+
+  /// Gets a uniformly distributed random number within the range.
+  ///
+  /// Errors with `ErrorKind::InvalidInput` when the range is empty.
+  fn sys_random_range(&self, range: std::ops::Range<u64>) -> io::Result<u64> {
+    if range.is_empty() {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Cannot get a random number from an empty range",
+      ));
+    }
+    let span = range.end - range.start;
+    // reject values below 2^64 % span to avoid favoring low numbers
+    let threshold = span.wrapping_neg() % span;
+    loop {
+      let value = self.sys_random_u64()?;
+      if value >= threshold {
+        return Ok(range.start + value % span);
+      }
+    }
+  }
+
+  /// Randomly reorders the items (Fisher-Yates shuffle).
+  fn sys_random_shuffle<T>(&self, items: &mut [T]) -> io::Result<()>
+  where
+    Self: Sized,
+  {
+    for i in (1..items.len()).rev() {
+      let j = self.sys_random_range(0..i as u64 + 1)? as usize;
+      items.swap(i, j);
+    }
+    Ok(())
+  }
 }
 
 pub trait ProcessExit {
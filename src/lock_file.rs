@@ -0,0 +1,481 @@
+//! Path based lock files for coordinating access to a shared resource.
+//!
+//! This module provides [`FsLockFile`], which acquires an exclusive
+//! [`FsFileLock`] on a lock file (ex. a `.lock` file in a cache directory)
+//! and records who owns it. It only relies on other traits in this crate,
+//! so it works the same on a real and an in-memory file system.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sys_traits::FsLockFile;
+//! use sys_traits::LockFileOptions;
+//! # #[cfg(feature = "real")]
+//! use sys_traits::impls::RealSys;
+//!
+//! # #[cfg(feature = "real")]
+//! # fn example() -> std::io::Result<()> {
+//! let lock = RealSys.fs_lock_file(
+//!   "cache/.lock",
+//!   LockFileOptions::new()
+//!     .timeout(Duration::from_secs(30))
+//!     .stale_after(Duration::from_secs(60 * 10)),
+//! )?;
+//! // ...use the cache...
+//! lock.release()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(not(windows))]
+use std::sync::atomic::AtomicU64;
+#[cfg(not(windows))]
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::BaseFsOpen;
+use crate::BaseFsRead;
+use crate::BaseFsRemoveFile;
+use crate::BaseFsRename;
+use crate::FsFile;
+use crate::FsFileLock;
+use crate::FsFileLockMode;
+use crate::FsFileSetLen;
+use crate::FsOpen;
+use crate::FsRead;
+#[cfg(not(windows))]
+use crate::FsRemoveFile;
+#[cfg(not(windows))]
+use crate::FsRename;
+use crate::OpenOptions;
+use crate::SystemTimeNow;
+use crate::ThreadSleep;
+
+/// Options for acquiring a lock file.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive] // so we can add properties without breaking people
+pub struct LockFileOptions {
+  /// Maximum time to wait for the lock. `None` waits indefinitely.
+  pub timeout: Option<Duration>,
+  /// How long to sleep between attempts while the lock is held elsewhere.
+  pub poll_interval: Duration,
+  /// Age after which the owner of a held lock is considered hung and the
+  /// lock file is removed so it can be acquired. `None` never breaks
+  /// held locks.
+  ///
+  /// This is ignored on Windows, where file locks are mandatory, so a held
+  /// lock file can't be read to find its owner or removed.
+  pub stale_after: Option<Duration>,
+}
+
+impl Default for LockFileOptions {
+  fn default() -> Self {
+    Self {
+      timeout: None,
+      poll_interval: Duration::from_millis(50),
+      stale_after: None,
+    }
+  }
+}
+
+impl LockFileOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  #[inline]
+  pub fn timeout(&mut self, value: Duration) -> &mut Self {
+    self.timeout = Some(value);
+    self
+  }
+
+  #[inline]
+  pub fn poll_interval(&mut self, value: Duration) -> &mut Self {
+    self.poll_interval = value;
+    self
+  }
+
+  #[inline]
+  pub fn stale_after(&mut self, value: Duration) -> &mut Self {
+    self.stale_after = Some(value);
+    self
+  }
+}
+
+/// Owner information written to a lock file once it's acquired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LockFileOwner {
+  /// Process id of the owner or 0 on platforms without process ids.
+  pub pid: u32,
+  /// When the owner acquired the lock.
+  pub acquired: SystemTime,
+}
+
+impl LockFileOwner {
+  pub fn new(pid: u32, acquired: SystemTime) -> Self {
+    Self { pid, acquired }
+  }
+
+  /// Parses the owner information from the text of a lock file.
+  pub fn parse(text: &str) -> Option<Self> {
+    let mut lines = text.lines();
+    let pid = lines.next()?.parse().ok()?;
+    let millis = lines.next()?.parse().ok()?;
+    Some(Self {
+      pid,
+      acquired: UNIX_EPOCH + Duration::from_millis(millis),
+    })
+  }
+
+  /// Gets the text written to the lock file.
+  pub fn to_text(&self) -> String {
+    let millis = self
+      .acquired
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis();
+    format!("{}\n{}\n", self.pid, millis)
+  }
+
+  fn is_stale(&self, now: SystemTime, stale_after: Duration) -> bool {
+    now
+      .duration_since(self.acquired)
+      .is_ok_and(|age| age >= stale_after)
+  }
+}
+
+/// An acquired lock file, which is released on drop.
+#[derive(Debug)]
+pub struct LockFile<TFile: FsFile> {
+  file: TFile,
+  path: PathBuf,
+  owner: LockFileOwner,
+  previous_owner: Option<LockFileOwner>,
+  released: bool,
+}
+
+impl<TFile: FsFile> LockFile<TFile> {
+  /// Path of the lock file.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Owner information written to the lock file.
+  pub fn owner(&self) -> &LockFileOwner {
+    &self.owner
+  }
+
+  /// Owner information left behind by a previous owner that didn't
+  /// release the lock, which means it crashed or was killed while
+  /// holding it.
+  pub fn previous_owner(&self) -> Option<&LockFileOwner> {
+    self.previous_owner.as_ref()
+  }
+
+  /// Releases the lock, surfacing any error that would be ignored
+  /// when dropped.
+  pub fn release(mut self) -> io::Result<()> {
+    self.release_inner()
+  }
+
+  fn release_inner(&mut self) -> io::Result<()> {
+    if self.released {
+      return Ok(());
+    }
+    self.released = true;
+    // clear the owner so the next owner doesn't think this one crashed
+    let result = self.file.fs_file_set_len(0);
+    self.file.fs_file_unlock()?;
+    result
+  }
+}
+
+impl<TFile: FsFile> Drop for LockFile<TFile> {
+  fn drop(&mut self) {
+    let _ = self.release_inner();
+  }
+}
+
+pub trait FsLockFile:
+  BaseFsOpen
+  + BaseFsRead
+  + BaseFsRemoveFile
+  + BaseFsRename
+  + SystemTimeNow
+  + ThreadSleep
+  + Sized
+{
+  /// Creates the file at the path if necessary and acquires an exclusive
+  /// lock on it, waiting according to the options.
+  ///
+  /// Errors with `ErrorKind::TimedOut` when the timeout elapses.
+  fn fs_lock_file(
+    &self,
+    path: impl AsRef<Path>,
+    options: &LockFileOptions,
+  ) -> io::Result<LockFile<Self::File>> {
+    let path = path.as_ref();
+    let start = self.sys_time_now();
+    loop {
+      let mut file = self.fs_open(
+        path,
+        &OpenOptions {
+          read: true,
+          write: true,
+          create: true,
+          ..Default::default()
+        },
+      )?;
+      match file.fs_file_try_lock(FsFileLockMode::Exclusive) {
+        Ok(()) => {
+          // read through the locked file because the lock is mandatory
+          // on Windows, so other handles can't read it
+          let mut previous_text = String::new();
+          file.seek(SeekFrom::Start(0))?;
+          let previous_owner = match file.read_to_string(&mut previous_text) {
+            Ok(_) => LockFileOwner::parse(&previous_text),
+            Err(_) => None,
+          };
+          let owner = LockFileOwner::new(current_pid(), self.sys_time_now());
+          let text = owner.to_text();
+          file.fs_file_set_len(0)?;
+          file.seek(SeekFrom::Start(0))?;
+          file.write_all(text.as_bytes())?;
+          // the locked file might have been removed as stale by someone
+          // else after it was opened, so ensure it's still at the path
+          // (stale locks aren't broken on Windows)
+          let is_at_path = cfg!(windows)
+            || self.fs_read(path).ok().as_deref() == Some(text.as_bytes());
+          if is_at_path {
+            return Ok(LockFile {
+              file,
+              path: path.to_path_buf(),
+              owner,
+              previous_owner,
+              released: false,
+            });
+          }
+        }
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+          let now = self.sys_time_now();
+          #[cfg(not(windows))]
+          if let Some(stale_after) = options.stale_after {
+            if let Some(owner) = read_owner(self, path) {
+              if owner.is_stale(now, stale_after) {
+                break_stale_lock(self, path, &owner)?;
+                continue;
+              }
+            }
+          }
+          if let Some(timeout) = options.timeout {
+            if now.duration_since(start).unwrap_or_default() >= timeout {
+              return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Timed out waiting for lock file '{}'", path.display()),
+              ));
+            }
+          }
+          drop(file);
+          self.thread_sleep(options.poll_interval);
+        }
+        Err(err) => return Err(err),
+      }
+    }
+  }
+}
+
+impl<
+    T: BaseFsOpen
+      + BaseFsRead
+      + BaseFsRemoveFile
+      + BaseFsRename
+      + SystemTimeNow
+      + ThreadSleep,
+  > FsLockFile for T
+{
+}
+
+/// Removes the lock file at the path when it's still owned by the stale
+/// owner.
+///
+/// Someone else might have broken the lock and acquired a new one since
+/// the owner was read, so the file is first moved aside to check who
+/// owns it without anyone else removing it, then put back when it's not
+/// the stale one.
+#[cfg(not(windows))]
+fn break_stale_lock(
+  sys: &(impl BaseFsRead + BaseFsRemoveFile + BaseFsRename),
+  path: &Path,
+  stale_owner: &LockFileOwner,
+) -> io::Result<()> {
+  static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+  let mut side_path = path.as_os_str().to_owned();
+  side_path.push(format!(
+    ".{}.{}.stale",
+    current_pid(),
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+  ));
+  let side_path = PathBuf::from(side_path);
+  match sys.fs_rename(path, &side_path) {
+    Ok(()) => {}
+    // already broken by someone else
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+    Err(err) => return Err(err),
+  }
+  if read_owner(sys, &side_path).as_ref() == Some(stale_owner) {
+    sys.fs_remove_file(&side_path)
+  } else {
+    sys.fs_rename(&side_path, path)
+  }
+}
+
+#[cfg(not(windows))]
+fn read_owner(sys: &impl BaseFsRead, path: &Path) -> Option<LockFileOwner> {
+  let data = sys.fs_read(path).ok()?;
+  LockFileOwner::parse(std::str::from_utf8(&data).ok()?)
+}
+
+fn current_pid() -> u32 {
+  // process ids aren't available on Wasm and std panics when asking
+  #[cfg(target_family = "wasm")]
+  {
+    0
+  }
+  #[cfg(not(target_family = "wasm"))]
+  {
+    std::process::id()
+  }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+  use super::*;
+  use crate::impls::InMemorySys;
+  use crate::FsReadDir;
+  use crate::FsWrite;
+
+  fn create_sys() -> InMemorySys {
+    let sys = InMemorySys::new_with_cwd("/");
+    sys.set_time(Some(UNIX_EPOCH + Duration::from_secs(1_000)));
+    sys.disable_thread_sleep();
+    sys
+  }
+
+  #[test]
+  fn acquires_and_releases() {
+    let sys = create_sys();
+    let lock = sys.fs_lock_file("/.lock", &LockFileOptions::new()).unwrap();
+    assert_eq!(lock.path(), Path::new("/.lock"));
+    assert_eq!(lock.previous_owner(), None);
+    assert_eq!(
+      LockFileOwner::parse(&sys.fs_read_to_string("/.lock").unwrap()),
+      Some(*lock.owner())
+    );
+    let err = sys
+      .fs_lock_file("/.lock", LockFileOptions::new().timeout(Duration::ZERO))
+      .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    lock.release().unwrap();
+    assert_eq!(sys.fs_read_to_string("/.lock").unwrap(), "");
+
+    let lock = sys.fs_lock_file("/.lock", &LockFileOptions::new()).unwrap();
+    assert_eq!(lock.previous_owner(), None);
+    drop(lock);
+    sys.fs_lock_file("/.lock", &LockFileOptions::new()).unwrap();
+  }
+
+  #[test]
+  fn times_out_polling() {
+    let sys = create_sys();
+    let _lock = sys.fs_lock_file("/.lock", &LockFileOptions::new()).unwrap();
+    let start = sys.sys_time_now();
+    let err = sys
+      .fs_lock_file(
+        "/.lock",
+        LockFileOptions::new()
+          .timeout(Duration::from_secs(1))
+          .poll_interval(Duration::from_millis(300)),
+      )
+      .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    let elapsed = sys.sys_time_now().duration_since(start).unwrap();
+    assert_eq!(elapsed, Duration::from_millis(1_200));
+  }
+
+  #[test]
+  fn reports_previous_owner() {
+    let sys = create_sys();
+    sys.fs_write("/.lock", "123\n5000\n").unwrap();
+    let lock = sys.fs_lock_file("/.lock", &LockFileOptions::new()).unwrap();
+    assert_eq!(
+      lock.previous_owner(),
+      Some(&LockFileOwner::new(
+        123,
+        UNIX_EPOCH + Duration::from_secs(5)
+      ))
+    );
+  }
+
+  #[cfg(not(windows))]
+  #[test]
+  fn breaks_stale_lock() {
+    let sys = create_sys();
+    let hung = sys.fs_lock_file("/.lock", &LockFileOptions::new()).unwrap();
+    let mut options = LockFileOptions::new();
+    options
+      .poll_interval(Duration::from_secs(1))
+      .stale_after(Duration::from_secs(5));
+    let lock = sys.fs_lock_file("/.lock", &options).unwrap();
+    assert_eq!(
+      lock.owner().acquired,
+      hung.owner().acquired + Duration::from_secs(5)
+    );
+    // releasing the hung lock doesn't affect the new lock file
+    drop(hung);
+    assert_eq!(
+      LockFileOwner::parse(&sys.fs_read_to_string("/.lock").unwrap()),
+      Some(*lock.owner())
+    );
+  }
+
+  #[cfg(not(windows))]
+  #[test]
+  fn breaks_only_the_stale_lock() {
+    let sys = create_sys();
+    let hung = sys.fs_lock_file("/.lock", &LockFileOptions::new()).unwrap();
+    let mut options = LockFileOptions::new();
+    options
+      .poll_interval(Duration::from_secs(1))
+      .stale_after(Duration::from_secs(5));
+    // one waiter reads the stale owner, then another breaks the lock and
+    // acquires it before the first one gets to break it
+    sys.advance_time(Duration::from_secs(5));
+    let stale_owner = read_owner(&sys, Path::new("/.lock")).unwrap();
+    assert_eq!(stale_owner, *hung.owner());
+    let lock = sys.fs_lock_file("/.lock", &options).unwrap();
+    break_stale_lock(&sys, Path::new("/.lock"), &stale_owner).unwrap();
+
+    // so the new lock file is left in place
+    assert_eq!(
+      LockFileOwner::parse(&sys.fs_read_to_string("/.lock").unwrap()),
+      Some(*lock.owner())
+    );
+    assert_eq!(sys.fs_read_dir("/").unwrap().count(), 1);
+    let err = sys
+      .fs_lock_file("/.lock", LockFileOptions::new().timeout(Duration::ZERO))
+      .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+  }
+}
@@ -0,0 +1,2813 @@
+//! Record-and-replay of system calls.
+//!
+//! [`RecordingSys`] wraps a system (typically `RealSys`) and captures
+//! every trait call along with its result into a [`Trace`] that can be
+//! serialized to JSON. [`ReplaySys`] later implements all the traits by
+//! returning the recorded results in order, panicking as soon as the
+//! calls diverge from the trace.
+//!
+//! This allows capturing the exact environment of a bug report and
+//! replaying it deterministically in a unit test. Calls must happen in
+//! the same order as when recording, so multi-threaded code is only
+//! replayable when its calls are deterministically ordered.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io;
+use std::io::ErrorKind;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::boxed::BoxedFsMetadataValue;
+use crate::BaseEnvRemoveVar;
+use crate::BaseEnvSetCurrentDir;
+use crate::BaseEnvSetVar;
+use crate::BaseEnvVar;
+use crate::BaseFsCanonicalize;
+use crate::BaseFsChown;
+use crate::BaseFsCloneFile;
+use crate::BaseFsCopy;
+use crate::BaseFsCopyWithOptions;
+use crate::BaseFsCreateDir;
+use crate::BaseFsCreateJunction;
+use crate::BaseFsDiskSpace;
+use crate::BaseFsGetXattr;
+use crate::BaseFsHardLink;
+use crate::BaseFsListXattr;
+use crate::BaseFsMetadata;
+use crate::BaseFsOpen;
+use crate::BaseFsOpenDir;
+use crate::BaseFsRead;
+use crate::BaseFsReadDir;
+use crate::BaseFsReadLink;
+use crate::BaseFsReadShared;
+use crate::BaseFsRemoveDir;
+use crate::BaseFsRemoveDirAll;
+use crate::BaseFsRemoveFile;
+use crate::BaseFsRemoveXattr;
+use crate::BaseFsRename;
+use crate::BaseFsRenameWithOptions;
+use crate::BaseFsSetFileTimes;
+use crate::BaseFsSetPermissions;
+use crate::BaseFsSetSymlinkFileTimes;
+use crate::BaseFsSetXattr;
+use crate::BaseFsStatVfs;
+use crate::BaseFsSymlinkChown;
+use crate::BaseFsSymlinkDir;
+use crate::BaseFsSymlinkFile;
+use crate::BaseFsWrite;
+use crate::CopyOptions;
+use crate::CreateDirOptions;
+use crate::DiskSpace;
+use crate::EnvCacheDir;
+use crate::EnvCurrentDir;
+use crate::EnvHomeDir;
+use crate::EnvProgramsDir;
+use crate::EnvSetUmask;
+use crate::EnvTempDir;
+use crate::EnvUmask;
+use crate::EnvVars;
+use crate::FileType;
+use crate::FsDirEntry;
+use crate::FsDirHandle;
+use crate::FsFile;
+use crate::FsFileAsRaw;
+use crate::FsFileIsTerminal;
+use crate::FsFileLock;
+use crate::FsFileLockMode;
+use crate::FsFileLockRange;
+use crate::FsFileMetadata;
+use crate::FsFileSetLen;
+use crate::FsFileSetPermissions;
+use crate::FsFileSetTimes;
+use crate::FsFileSyncAll;
+use crate::FsFileSyncData;
+use crate::FsFileTimes;
+use crate::FsMetadataValue;
+use crate::FsSharedBytes;
+use crate::OpenOptions;
+use crate::ProcessExit;
+use crate::RenameOptions;
+use crate::StatVfs;
+use crate::SystemRandom;
+use crate::SystemTimeNow;
+use crate::ThreadSleep;
+
+// == Trace ==
+
+/// A recorded sequence of system calls.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Trace {
+  pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).unwrap()
+  }
+
+  pub fn from_json(text: &str) -> serde_json::Result<Self> {
+    serde_json::from_str(text)
+  }
+}
+
+/// A single recorded call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEvent {
+  /// Name of the operation (ex. `fs_read`).
+  pub op: String,
+  /// Arguments the operation was called with.
+  #[serde(default, skip_serializing_if = "Value::is_null")]
+  pub args: Value,
+  /// What the operation returned.
+  #[serde(default, skip_serializing_if = "Value::is_null")]
+  pub result: Value,
+}
+
+/// A serializable [`io::Error`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceError {
+  pub kind: String,
+  pub message: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub raw_os_error: Option<i32>,
+  /// The OS the raw error code came from (ex. `linux`), since codes
+  /// differ between platforms.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub os: Option<String>,
+}
+
+impl From<&io::Error> for TraceError {
+  fn from(err: &io::Error) -> Self {
+    let raw_os_error = err.raw_os_error();
+    Self {
+      kind: format!("{:?}", err.kind()),
+      message: err.to_string(),
+      raw_os_error,
+      os: raw_os_error.map(|_| std::env::consts::OS.to_string()),
+    }
+  }
+}
+
+impl From<TraceError> for io::Error {
+  fn from(err: TraceError) -> Self {
+    // raw codes are only meaningful on the OS they were recorded on
+    if let Some(code) = err.raw_os_error {
+      if err.os.as_deref() == Some(std::env::consts::OS) {
+        return io::Error::from_raw_os_error(code);
+      }
+    }
+    let kind = match err.kind.as_str() {
+      "NotFound" => ErrorKind::NotFound,
+      "PermissionDenied" => ErrorKind::PermissionDenied,
+      "AlreadyExists" => ErrorKind::AlreadyExists,
+      "WouldBlock" => ErrorKind::WouldBlock,
+      "NotADirectory" => ErrorKind::NotADirectory,
+      "IsADirectory" => ErrorKind::IsADirectory,
+      "DirectoryNotEmpty" => ErrorKind::DirectoryNotEmpty,
+      "ReadOnlyFilesystem" => ErrorKind::ReadOnlyFilesystem,
+      "StorageFull" => ErrorKind::StorageFull,
+      "CrossesDevices" => ErrorKind::CrossesDevices,
+      "InvalidInput" => ErrorKind::InvalidInput,
+      "InvalidData" => ErrorKind::InvalidData,
+      "TimedOut" => ErrorKind::TimedOut,
+      "WriteZero" => ErrorKind::WriteZero,
+      "Interrupted" => ErrorKind::Interrupted,
+      "Unsupported" => ErrorKind::Unsupported,
+      "UnexpectedEof" => ErrorKind::UnexpectedEof,
+      "OutOfMemory" => ErrorKind::OutOfMemory,
+      _ => ErrorKind::Other,
+    };
+    io::Error::new(kind, err.message)
+  }
+}
+
+/// Bytes stored as a string when they're valid UTF-8 in order to keep
+/// traces readable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum TraceBytes {
+  Text(String),
+  Binary(Vec<u8>),
+}
+
+impl TraceBytes {
+  fn new(bytes: &[u8]) -> Self {
+    match std::str::from_utf8(bytes) {
+      Ok(text) => Self::Text(text.to_string()),
+      Err(_) => Self::Binary(bytes.to_vec()),
+    }
+  }
+
+  fn into_vec(self) -> Vec<u8> {
+    match self {
+      Self::Text(text) => text.into_bytes(),
+      Self::Binary(bytes) => bytes,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TraceDirEntry {
+  name: String,
+  path: String,
+}
+
+fn path_str(path: &Path) -> String {
+  path.to_string_lossy().into_owned()
+}
+
+fn os_str(value: &OsStr) -> String {
+  value.to_string_lossy().into_owned()
+}
+
+fn encode_result<T: Serialize>(result: Result<T, &io::Error>) -> Value {
+  serde_json::to_value(result.map_err(TraceError::from)).unwrap()
+}
+
+fn encode_lock_mode(mode: &FsFileLockMode) -> &'static str {
+  match mode {
+    FsFileLockMode::Shared => "shared",
+    FsFileLockMode::Exclusive => "exclusive",
+  }
+}
+
+fn lock_range_args(
+  mode: Option<&FsFileLockMode>,
+  offset: u64,
+  len: u64,
+) -> Value {
+  json!({
+    "mode": mode.map(encode_lock_mode),
+    "offset": offset,
+    "len": len,
+  })
+}
+
+fn encode_seek(pos: SeekFrom) -> Value {
+  match pos {
+    SeekFrom::Start(offset) => json!({ "start": offset }),
+    SeekFrom::End(offset) => json!({ "end": offset }),
+    SeekFrom::Current(offset) => json!({ "current": offset }),
+  }
+}
+
+fn open_args(path: &Path, options: &OpenOptions) -> Value {
+  json!({
+    "path": path_str(path),
+    "read": options.read,
+    "write": options.write,
+    "create": options.create,
+    "truncate": options.truncate,
+    "append": options.append,
+    "createNew": options.create_new,
+    "noFollow": options.no_follow,
+    "directory": options.directory,
+    "mode": options.mode,
+    "customFlags": options.custom_flags,
+    "accessMode": options.access_mode,
+    "shareMode": options.share_mode,
+    "attributes": options.attributes,
+    "securityQosFlags": options.security_qos_flags,
+  })
+}
+
+fn create_dir_args(path: &Path, options: &CreateDirOptions) -> Value {
+  json!({
+    "path": path_str(path),
+    "recursive": options.recursive,
+    "mode": options.mode,
+  })
+}
+
+fn copy_args(from: &Path, to: &Path, options: &CopyOptions) -> Value {
+  json!({
+    "from": path_str(from),
+    "to": path_str(to),
+    "createNew": options.create_new,
+    "preserveTimes": options.preserve_times,
+    "preserveOwnership": options.preserve_ownership,
+    "copySymlinks": options.copy_symlinks,
+    "sparse": options.sparse,
+  })
+}
+
+fn rename_args(from: &Path, to: &Path, options: &RenameOptions) -> Value {
+  json!({
+    "from": path_str(from),
+    "to": path_str(to),
+    "noReplace": options.no_replace,
+    "exchange": options.exchange,
+  })
+}
+
+fn two_paths_args(from: &Path, to: &Path) -> Value {
+  json!({ "from": path_str(from), "to": path_str(to) })
+}
+
+fn rename_at_args(from: &Path, to_dir: usize, to: &Path) -> Value {
+  json!({ "from": path_str(from), "to_dir": to_dir, "to": path_str(to) })
+}
+
+fn chown_args(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Value {
+  json!({ "path": path_str(path), "uid": uid, "gid": gid })
+}
+
+fn file_times_args(path: &Path, atime: SystemTime, mtime: SystemTime) -> Value {
+  json!({ "path": path_str(path), "atime": atime, "mtime": mtime })
+}
+
+// == ReplayMetadata ==
+
+/// Metadata captured in a trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayMetadata {
+  file_type: FileType,
+  len: u64,
+  accessed: Result<SystemTime, TraceError>,
+  created: Result<SystemTime, TraceError>,
+  changed: Result<SystemTime, TraceError>,
+  modified: Result<SystemTime, TraceError>,
+  dev: Result<u64, TraceError>,
+  ino: Result<u64, TraceError>,
+  mode: Result<u32, TraceError>,
+  nlink: Result<u64, TraceError>,
+  uid: Result<u32, TraceError>,
+  gid: Result<u32, TraceError>,
+  rdev: Result<u64, TraceError>,
+  blksize: Result<u64, TraceError>,
+  blocks: Result<u64, TraceError>,
+  is_block_device: Result<bool, TraceError>,
+  is_char_device: Result<bool, TraceError>,
+  is_fifo: Result<bool, TraceError>,
+  is_socket: Result<bool, TraceError>,
+  file_attributes: Result<u32, TraceError>,
+}
+
+impl ReplayMetadata {
+  pub fn from_metadata<M: FsMetadataValue + ?Sized>(metadata: &M) -> Self {
+    fn capture<T>(result: io::Result<T>) -> Result<T, TraceError> {
+      result.map_err(|err| TraceError::from(&err))
+    }
+
+    Self {
+      file_type: metadata.file_type(),
+      len: metadata.len(),
+      accessed: capture(metadata.accessed()),
+      created: capture(metadata.created()),
+      changed: capture(metadata.changed()),
+      modified: capture(metadata.modified()),
+      dev: capture(metadata.dev()),
+      ino: capture(metadata.ino()),
+      mode: capture(metadata.mode()),
+      nlink: capture(metadata.nlink()),
+      uid: capture(metadata.uid()),
+      gid: capture(metadata.gid()),
+      rdev: capture(metadata.rdev()),
+      blksize: capture(metadata.blksize()),
+      blocks: capture(metadata.blocks()),
+      is_block_device: capture(metadata.is_block_device()),
+      is_char_device: capture(metadata.is_char_device()),
+      is_fifo: capture(metadata.is_fifo()),
+      is_socket: capture(metadata.is_socket()),
+      file_attributes: capture(metadata.file_attributes()),
+    }
+  }
+}
+
+fn replay_value<T: Clone>(value: &Result<T, TraceError>) -> io::Result<T> {
+  value.clone().map_err(io::Error::from)
+}
+
+impl FsMetadataValue for ReplayMetadata {
+  fn file_type(&self) -> FileType {
+    self.file_type
+  }
+
+  fn len(&self) -> u64 {
+    self.len
+  }
+
+  fn accessed(&self) -> io::Result<SystemTime> {
+    replay_value(&self.accessed)
+  }
+
+  fn created(&self) -> io::Result<SystemTime> {
+    replay_value(&self.created)
+  }
+
+  fn changed(&self) -> io::Result<SystemTime> {
+    replay_value(&self.changed)
+  }
+
+  fn modified(&self) -> io::Result<SystemTime> {
+    replay_value(&self.modified)
+  }
+
+  fn dev(&self) -> io::Result<u64> {
+    replay_value(&self.dev)
+  }
+
+  fn ino(&self) -> io::Result<u64> {
+    replay_value(&self.ino)
+  }
+
+  fn mode(&self) -> io::Result<u32> {
+    replay_value(&self.mode)
+  }
+
+  fn nlink(&self) -> io::Result<u64> {
+    replay_value(&self.nlink)
+  }
+
+  fn uid(&self) -> io::Result<u32> {
+    replay_value(&self.uid)
+  }
+
+  fn gid(&self) -> io::Result<u32> {
+    replay_value(&self.gid)
+  }
+
+  fn rdev(&self) -> io::Result<u64> {
+    replay_value(&self.rdev)
+  }
+
+  fn blksize(&self) -> io::Result<u64> {
+    replay_value(&self.blksize)
+  }
+
+  fn blocks(&self) -> io::Result<u64> {
+    replay_value(&self.blocks)
+  }
+
+  fn is_block_device(&self) -> io::Result<bool> {
+    replay_value(&self.is_block_device)
+  }
+
+  fn is_char_device(&self) -> io::Result<bool> {
+    replay_value(&self.is_char_device)
+  }
+
+  fn is_fifo(&self) -> io::Result<bool> {
+    replay_value(&self.is_fifo)
+  }
+
+  fn is_socket(&self) -> io::Result<bool> {
+    replay_value(&self.is_socket)
+  }
+
+  fn file_attributes(&self) -> io::Result<u32> {
+    replay_value(&self.file_attributes)
+  }
+}
+
+fn encode_metadata<M: FsMetadataValue + ?Sized>(
+  result: Result<&M, &io::Error>,
+) -> Value {
+  encode_result(result.map(|m| ReplayMetadata::from_metadata(m)))
+}
+
+// == RecordingSys ==
+
+#[derive(Debug, Default)]
+struct Recorder {
+  events: Mutex<Vec<TraceEvent>>,
+}
+
+impl Recorder {
+  /// Records the event and returns its index in the trace.
+  fn record(&self, op: &str, args: Value, result: Value) -> usize {
+    let mut events = self.events.lock().unwrap();
+    events.push(TraceEvent {
+      op: op.to_string(),
+      args,
+      result,
+    });
+    events.len() - 1
+  }
+}
+
+/// Records every call made to the inner system into a [`Trace`].
+#[derive(Debug, Clone)]
+pub struct RecordingSys<T> {
+  sys: T,
+  recorder: Arc<Recorder>,
+}
+
+impl<T> RecordingSys<T> {
+  pub fn new(sys: T) -> Self {
+    Self {
+      sys,
+      recorder: Default::default(),
+    }
+  }
+
+  pub fn inner(&self) -> &T {
+    &self.sys
+  }
+
+  /// Gets the calls recorded so far.
+  pub fn trace(&self) -> Trace {
+    Trace {
+      events: self.recorder.events.lock().unwrap().clone(),
+    }
+  }
+
+  fn record(&self, op: &str, args: Value, result: Value) -> usize {
+    self.recorder.record(op, args, result)
+  }
+}
+
+// ==== Environment ====
+
+impl<T: EnvCurrentDir> EnvCurrentDir for RecordingSys<T> {
+  fn env_current_dir(&self) -> io::Result<PathBuf> {
+    let result = self.sys.env_current_dir();
+    let encoded = encode_result(result.as_ref().map(|p| path_str(p)));
+    self.record("env_current_dir", Value::Null, encoded);
+    result
+  }
+}
+
+impl<T: BaseEnvSetCurrentDir> BaseEnvSetCurrentDir for RecordingSys<T> {
+  fn base_env_set_current_dir(&self, path: &Path) -> io::Result<()> {
+    let result = self.sys.base_env_set_current_dir(path);
+    self.record(
+      "env_set_current_dir",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseEnvVar> BaseEnvVar for RecordingSys<T> {
+  fn base_env_var_os(&self, key: &OsStr) -> Option<OsString> {
+    let result = self.sys.base_env_var_os(key);
+    self.record(
+      "env_var_os",
+      json!({ "key": os_str(key) }),
+      json!(result.as_deref().map(os_str)),
+    );
+    result
+  }
+}
+
+impl<T: EnvVars> EnvVars for RecordingSys<T> {
+  type EnvVarsOs = std::vec::IntoIter<(OsString, OsString)>;
+
+  fn env_vars_os(&self) -> Self::EnvVarsOs {
+    let vars = self.sys.env_vars_os().collect::<Vec<_>>();
+    let encoded = vars
+      .iter()
+      .map(|(key, value)| (os_str(key), os_str(value)))
+      .collect::<Vec<_>>();
+    self.record("env_vars_os", Value::Null, json!(encoded));
+    vars.into_iter()
+  }
+}
+
+impl<T: BaseEnvRemoveVar> BaseEnvRemoveVar for RecordingSys<T> {
+  fn base_env_remove_var(&self, key: &OsStr) {
+    self.sys.base_env_remove_var(key);
+    self.record("env_remove_var", json!({ "key": os_str(key) }), Value::Null);
+  }
+}
+
+impl<T: BaseEnvSetVar> BaseEnvSetVar for RecordingSys<T> {
+  fn base_env_set_var(&self, key: &OsStr, value: &OsStr) {
+    self.sys.base_env_set_var(key, value);
+    self.record(
+      "env_set_var",
+      json!({ "key": os_str(key), "value": os_str(value) }),
+      Value::Null,
+    );
+  }
+}
+
+impl<T: EnvUmask> EnvUmask for RecordingSys<T> {
+  fn env_umask(&self) -> io::Result<u32> {
+    let result = self.sys.env_umask();
+    self.record("env_umask", Value::Null, encode_result(result.as_ref()));
+    result
+  }
+}
+
+impl<T: EnvSetUmask> EnvSetUmask for RecordingSys<T> {
+  fn env_set_umask(&self, umask: u32) -> io::Result<u32> {
+    let result = self.sys.env_set_umask(umask);
+    self.record(
+      "env_set_umask",
+      json!({ "umask": umask }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: EnvCacheDir> EnvCacheDir for RecordingSys<T> {
+  fn env_cache_dir(&self) -> Option<PathBuf> {
+    let result = self.sys.env_cache_dir();
+    let encoded = json!(result.as_deref().map(path_str));
+    self.record("env_cache_dir", Value::Null, encoded);
+    result
+  }
+}
+
+impl<T: EnvHomeDir> EnvHomeDir for RecordingSys<T> {
+  fn env_home_dir(&self) -> Option<PathBuf> {
+    let result = self.sys.env_home_dir();
+    let encoded = json!(result.as_deref().map(path_str));
+    self.record("env_home_dir", Value::Null, encoded);
+    result
+  }
+}
+
+impl<T: EnvProgramsDir> EnvProgramsDir for RecordingSys<T> {
+  fn env_programs_dir(&self) -> Option<PathBuf> {
+    let result = self.sys.env_programs_dir();
+    let encoded = json!(result.as_deref().map(path_str));
+    self.record("env_programs_dir", Value::Null, encoded);
+    result
+  }
+}
+
+impl<T: EnvTempDir> EnvTempDir for RecordingSys<T> {
+  fn env_temp_dir(&self) -> io::Result<PathBuf> {
+    let result = self.sys.env_temp_dir();
+    let encoded = encode_result(result.as_ref().map(|p| path_str(p)));
+    self.record("env_temp_dir", Value::Null, encoded);
+    result
+  }
+}
+
+// ==== File System ====
+
+impl<T: BaseFsCanonicalize> BaseFsCanonicalize for RecordingSys<T> {
+  fn base_fs_canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+    let result = self.sys.base_fs_canonicalize(path);
+    self.record(
+      "fs_canonicalize",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref().map(|p| path_str(p))),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsChown> BaseFsChown for RecordingSys<T> {
+  fn base_fs_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_chown(path, uid, gid);
+    self.record(
+      "fs_chown",
+      chown_args(path, uid, gid),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsSymlinkChown> BaseFsSymlinkChown for RecordingSys<T> {
+  fn base_fs_symlink_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_symlink_chown(path, uid, gid);
+    self.record(
+      "fs_symlink_chown",
+      chown_args(path, uid, gid),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsCloneFile> BaseFsCloneFile for RecordingSys<T> {
+  fn base_fs_clone_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+    let result = self.sys.base_fs_clone_file(from, to);
+    self.record(
+      "fs_clone_file",
+      two_paths_args(from, to),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsCopy> BaseFsCopy for RecordingSys<T> {
+  fn base_fs_copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+    let result = self.sys.base_fs_copy(from, to);
+    self.record(
+      "fs_copy",
+      two_paths_args(from, to),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsCopyWithOptions> BaseFsCopyWithOptions for RecordingSys<T> {
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    let result = self.sys.base_fs_copy_with_options(from, to, options);
+    self.record(
+      "fs_copy_with_options",
+      copy_args(from, to, options),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsCreateDir> BaseFsCreateDir for RecordingSys<T> {
+  fn base_fs_create_dir(
+    &self,
+    path: &Path,
+    options: &CreateDirOptions,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_create_dir(path, options);
+    self.record(
+      "fs_create_dir",
+      create_dir_args(path, options),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsHardLink> BaseFsHardLink for RecordingSys<T> {
+  fn base_fs_hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+    let result = self.sys.base_fs_hard_link(src, dst);
+    self.record(
+      "fs_hard_link",
+      two_paths_args(src, dst),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsCreateJunction> BaseFsCreateJunction for RecordingSys<T> {
+  fn base_fs_create_junction(
+    &self,
+    original: &Path,
+    junction: &Path,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_create_junction(original, junction);
+    self.record(
+      "fs_create_junction",
+      two_paths_args(original, junction),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsDiskSpace> BaseFsDiskSpace for RecordingSys<T> {
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+    let result = self.sys.base_fs_disk_space(path);
+    self.record(
+      "fs_disk_space",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsStatVfs> BaseFsStatVfs for RecordingSys<T> {
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs> {
+    let result = self.sys.base_fs_statvfs(path);
+    self.record(
+      "fs_statvfs",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsMetadata> BaseFsMetadata for RecordingSys<T> {
+  type Metadata = T::Metadata;
+
+  fn base_fs_metadata(&self, path: &Path) -> io::Result<Self::Metadata> {
+    let result = self.sys.base_fs_metadata(path);
+    self.record(
+      "fs_metadata",
+      json!({ "path": path_str(path) }),
+      encode_metadata(result.as_ref()),
+    );
+    result
+  }
+
+  fn base_fs_symlink_metadata(
+    &self,
+    path: &Path,
+  ) -> io::Result<Self::Metadata> {
+    let result = self.sys.base_fs_symlink_metadata(path);
+    self.record(
+      "fs_symlink_metadata",
+      json!({ "path": path_str(path) }),
+      encode_metadata(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsOpen> BaseFsOpen for RecordingSys<T> {
+  type File = RecordingFile<T::File>;
+
+  fn base_fs_open(
+    &self,
+    path: &Path,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    let result = self.sys.base_fs_open(path, options);
+    let id = self.record(
+      "fs_open",
+      open_args(path, options),
+      encode_result(result.as_ref().map(|_| ())),
+    );
+    result.map(|file| RecordingFile {
+      file,
+      id,
+      recorder: self.recorder.clone(),
+    })
+  }
+}
+
+impl<T: BaseFsOpenDir> BaseFsOpenDir for RecordingSys<T> {
+  type DirHandle = RecordingDirHandle<T::DirHandle>;
+
+  fn base_fs_open_dir(&self, path: &Path) -> io::Result<Self::DirHandle> {
+    let result = self.sys.base_fs_open_dir(path);
+    let id = self.record(
+      "fs_open_dir",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref().map(|_| ())),
+    );
+    result.map(|dir| RecordingDirHandle {
+      dir,
+      id,
+      recorder: self.recorder.clone(),
+    })
+  }
+}
+
+impl<T: BaseFsRead> BaseFsRead for RecordingSys<T> {
+  fn base_fs_read(&self, path: &Path) -> io::Result<Cow<'static, [u8]>> {
+    let result = self.sys.base_fs_read(path);
+    self.record(
+      "fs_read",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref().map(|data| TraceBytes::new(data))),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsReadShared> BaseFsReadShared for RecordingSys<T> {
+  fn base_fs_read_shared(&self, path: &Path) -> io::Result<FsSharedBytes> {
+    let result = self.sys.base_fs_read_shared(path);
+    self.record(
+      "fs_read_shared",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref().map(|data| TraceBytes::new(data))),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsReadDir> BaseFsReadDir for RecordingSys<T> {
+  type ReadDirEntry = RecordingDirEntry<T::ReadDirEntry>;
+
+  fn base_fs_read_dir(
+    &self,
+    path: &Path,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    let result = self
+      .sys
+      .base_fs_read_dir(path)
+      .map(|entries| entries.collect::<Vec<_>>());
+    self.record(
+      "fs_read_dir",
+      json!({ "path": path_str(path) }),
+      encode_dir_entries(&result),
+    );
+    record_dir_entries(result, &self.recorder)
+  }
+}
+
+fn encode_dir_entries<E: FsDirEntry>(
+  result: &io::Result<Vec<io::Result<E>>>,
+) -> Value {
+  encode_result(result.as_ref().map(|entries| {
+    entries
+      .iter()
+      .map(|entry| {
+        entry
+          .as_ref()
+          .map(|entry| TraceDirEntry {
+            name: os_str(&entry.file_name()),
+            path: path_str(&entry.path()),
+          })
+          .map_err(TraceError::from)
+      })
+      .collect::<Vec<_>>()
+  }))
+}
+
+#[allow(clippy::type_complexity)]
+fn record_dir_entries<E: FsDirEntry + 'static>(
+  result: io::Result<Vec<io::Result<E>>>,
+  recorder: &Arc<Recorder>,
+) -> io::Result<Box<dyn Iterator<Item = io::Result<RecordingDirEntry<E>>>>> {
+  let recorder = recorder.clone();
+  let entries = result?;
+  Ok(Box::new(entries.into_iter().map(move |entry| {
+    entry.map(|entry| RecordingDirEntry {
+      entry,
+      recorder: recorder.clone(),
+    })
+  })))
+}
+
+impl<T: BaseFsReadLink> BaseFsReadLink for RecordingSys<T> {
+  fn base_fs_read_link(&self, path: &Path) -> io::Result<PathBuf> {
+    let result = self.sys.base_fs_read_link(path);
+    self.record(
+      "fs_read_link",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref().map(|p| path_str(p))),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsRemoveDir> BaseFsRemoveDir for RecordingSys<T> {
+  fn base_fs_remove_dir(&self, path: &Path) -> io::Result<()> {
+    let result = self.sys.base_fs_remove_dir(path);
+    self.record(
+      "fs_remove_dir",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsRemoveDirAll> BaseFsRemoveDirAll for RecordingSys<T> {
+  fn base_fs_remove_dir_all(&self, path: &Path) -> io::Result<()> {
+    let result = self.sys.base_fs_remove_dir_all(path);
+    self.record(
+      "fs_remove_dir_all",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsRemoveFile> BaseFsRemoveFile for RecordingSys<T> {
+  fn base_fs_remove_file(&self, path: &Path) -> io::Result<()> {
+    let result = self.sys.base_fs_remove_file(path);
+    self.record(
+      "fs_remove_file",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsRename> BaseFsRename for RecordingSys<T> {
+  fn base_fs_rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+    let result = self.sys.base_fs_rename(from, to);
+    self.record(
+      "fs_rename",
+      two_paths_args(from, to),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsRenameWithOptions> BaseFsRenameWithOptions for RecordingSys<T> {
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_rename_with_options(from, to, options);
+    self.record(
+      "fs_rename_with_options",
+      rename_args(from, to, options),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsSetFileTimes> BaseFsSetFileTimes for RecordingSys<T> {
+  fn base_fs_set_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_set_file_times(path, atime, mtime);
+    self.record(
+      "fs_set_file_times",
+      file_times_args(path, atime, mtime),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsSetSymlinkFileTimes> BaseFsSetSymlinkFileTimes
+  for RecordingSys<T>
+{
+  fn base_fs_set_symlink_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_set_symlink_file_times(path, atime, mtime);
+    self.record(
+      "fs_set_symlink_file_times",
+      file_times_args(path, atime, mtime),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsSetPermissions> BaseFsSetPermissions for RecordingSys<T> {
+  fn base_fs_set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+    let result = self.sys.base_fs_set_permissions(path, mode);
+    self.record(
+      "fs_set_permissions",
+      json!({ "path": path_str(path), "mode": mode }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsSymlinkDir> BaseFsSymlinkDir for RecordingSys<T> {
+  fn base_fs_symlink_dir(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_symlink_dir(original, link);
+    self.record(
+      "fs_symlink_dir",
+      two_paths_args(original, link),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsSymlinkFile> BaseFsSymlinkFile for RecordingSys<T> {
+  fn base_fs_symlink_file(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_symlink_file(original, link);
+    self.record(
+      "fs_symlink_file",
+      two_paths_args(original, link),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsWrite> BaseFsWrite for RecordingSys<T> {
+  fn base_fs_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+    let result = self.sys.base_fs_write(path, data);
+    self.record(
+      "fs_write",
+      json!({ "path": path_str(path), "data": TraceBytes::new(data) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsGetXattr> BaseFsGetXattr for RecordingSys<T> {
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    let result = self.sys.base_fs_get_xattr(path, name);
+    self.record(
+      "fs_get_xattr",
+      json!({ "path": path_str(path), "name": os_str(name) }),
+      encode_result(
+        result
+          .as_ref()
+          .map(|value| value.as_deref().map(TraceBytes::new)),
+      ),
+    );
+    result
+  }
+
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    let result = self.sys.base_fs_symlink_get_xattr(path, name);
+    self.record(
+      "fs_symlink_get_xattr",
+      json!({ "path": path_str(path), "name": os_str(name) }),
+      encode_result(
+        result
+          .as_ref()
+          .map(|value| value.as_deref().map(TraceBytes::new)),
+      ),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsListXattr> BaseFsListXattr for RecordingSys<T> {
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>> {
+    let result = self.sys.base_fs_list_xattr(path);
+    self.record(
+      "fs_list_xattr",
+      json!({ "path": path_str(path) }),
+      encode_result(
+        result
+          .as_ref()
+          .map(|names| names.iter().map(|n| os_str(n)).collect::<Vec<_>>()),
+      ),
+    );
+    result
+  }
+
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    let result = self.sys.base_fs_symlink_list_xattr(path);
+    self.record(
+      "fs_symlink_list_xattr",
+      json!({ "path": path_str(path) }),
+      encode_result(
+        result
+          .as_ref()
+          .map(|names| names.iter().map(|n| os_str(n)).collect::<Vec<_>>()),
+      ),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsRemoveXattr> BaseFsRemoveXattr for RecordingSys<T> {
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+    let result = self.sys.base_fs_remove_xattr(path, name);
+    self.record(
+      "fs_remove_xattr",
+      json!({ "path": path_str(path), "name": os_str(name) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_symlink_remove_xattr(path, name);
+    self.record(
+      "fs_symlink_remove_xattr",
+      json!({ "path": path_str(path), "name": os_str(name) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<T: BaseFsSetXattr> BaseFsSetXattr for RecordingSys<T> {
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_set_xattr(path, name, value);
+    self.record(
+      "fs_set_xattr",
+      json!({ "path": path_str(path), "name": os_str(name), "value": TraceBytes::new(value) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    let result = self.sys.base_fs_symlink_set_xattr(path, name, value);
+    self.record(
+      "fs_symlink_set_xattr",
+      json!({ "path": path_str(path), "name": os_str(name), "value": TraceBytes::new(value) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+// ==== System ====
+
+impl<T: SystemTimeNow> SystemTimeNow for RecordingSys<T> {
+  fn sys_time_now(&self) -> SystemTime {
+    let result = self.sys.sys_time_now();
+    self.record("sys_time_now", Value::Null, json!(result));
+    result
+  }
+}
+
+impl<T: SystemRandom> SystemRandom for RecordingSys<T> {
+  fn sys_random(&self, buf: &mut [u8]) -> io::Result<()> {
+    let result = self.sys.sys_random(buf);
+    self.record(
+      "sys_random",
+      json!({ "len": buf.len() }),
+      encode_result(result.as_ref().map(|_| buf.to_vec())),
+    );
+    result
+  }
+}
+
+impl<T: ProcessExit> ProcessExit for RecordingSys<T> {
+  fn process_exit(&self, code: i32) -> ! {
+    self.record("process_exit", json!({ "code": code }), Value::Null);
+    self.sys.process_exit(code)
+  }
+}
+
+impl<T: ThreadSleep> ThreadSleep for RecordingSys<T> {
+  fn thread_sleep(&self, duration: Duration) {
+    self.record("thread_sleep", json!({ "duration": duration }), Value::Null);
+    self.sys.thread_sleep(duration)
+  }
+}
+
+// == RecordingDirEntry ==
+
+#[derive(Debug)]
+pub struct RecordingDirEntry<E> {
+  entry: E,
+  recorder: Arc<Recorder>,
+}
+
+impl<E: FsDirEntry> FsDirEntry for RecordingDirEntry<E> {
+  type Metadata = E::Metadata;
+
+  fn file_name(&self) -> Cow<'_, OsStr> {
+    self.entry.file_name()
+  }
+
+  fn file_type(&self) -> io::Result<FileType> {
+    let result = self.entry.file_type();
+    self.recorder.record(
+      "dir_entry_file_type",
+      json!({ "path": path_str(&self.entry.path()) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+
+  fn metadata(&self) -> io::Result<Self::Metadata> {
+    let result = self.entry.metadata();
+    self.recorder.record(
+      "dir_entry_metadata",
+      json!({ "path": path_str(&self.entry.path()) }),
+      encode_metadata(result.as_ref()),
+    );
+    result
+  }
+
+  fn path(&self) -> Cow<'_, Path> {
+    self.entry.path()
+  }
+}
+
+// == RecordingDirHandle ==
+
+/// A directory handle opened through a [`RecordingSys`].
+#[derive(Debug)]
+pub struct RecordingDirHandle<D> {
+  dir: D,
+  /// Index of the event that opened this directory.
+  id: usize,
+  recorder: Arc<Recorder>,
+}
+
+impl<D> RecordingDirHandle<D> {
+  fn record(&self, op: &str, mut args: Value, result: Value) -> usize {
+    match &mut args {
+      Value::Object(map) => {
+        map.insert("dir".to_string(), json!(self.id));
+      }
+      _ => args = json!({ "dir": self.id }),
+    }
+    self.recorder.record(op, args, result)
+  }
+}
+
+impl<D: FsDirHandle> FsDirHandle for RecordingDirHandle<D> {
+  type File = RecordingFile<D::File>;
+  type Metadata = D::Metadata;
+  type ReadDirEntry = RecordingDirEntry<D::ReadDirEntry>;
+
+  fn open_at(
+    &self,
+    path: impl AsRef<Path>,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    let path = path.as_ref();
+    let result = self.dir.open_at(path, options);
+    let id = self.record(
+      "dir_open_at",
+      open_args(path, options),
+      encode_result(result.as_ref().map(|_| ())),
+    );
+    result.map(|file| RecordingFile {
+      file,
+      id,
+      recorder: self.recorder.clone(),
+    })
+  }
+
+  fn open_dir_at(&self, path: impl AsRef<Path>) -> io::Result<Self> {
+    let path = path.as_ref();
+    let result = self.dir.open_dir_at(path);
+    let id = self.record(
+      "dir_open_dir_at",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref().map(|_| ())),
+    );
+    result.map(|dir| RecordingDirHandle {
+      dir,
+      id,
+      recorder: self.recorder.clone(),
+    })
+  }
+
+  fn metadata_at(&self, path: impl AsRef<Path>) -> io::Result<Self::Metadata> {
+    let path = path.as_ref();
+    let result = self.dir.metadata_at(path);
+    self.record(
+      "dir_metadata_at",
+      json!({ "path": path_str(path) }),
+      encode_metadata(result.as_ref()),
+    );
+    result
+  }
+
+  fn symlink_metadata_at(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Self::Metadata> {
+    let path = path.as_ref();
+    let result = self.dir.symlink_metadata_at(path);
+    self.record(
+      "dir_symlink_metadata_at",
+      json!({ "path": path_str(path) }),
+      encode_metadata(result.as_ref()),
+    );
+    result
+  }
+
+  fn read_dir(
+    &self,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    let result = self
+      .dir
+      .read_dir()
+      .map(|entries| entries.collect::<Vec<_>>());
+    self.record("dir_read_dir", Value::Null, encode_dir_entries(&result));
+    record_dir_entries(result, &self.recorder)
+  }
+
+  fn create_dir_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let result = self.dir.create_dir_at(path);
+    self.record(
+      "dir_create_dir_at",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+
+  fn remove_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let result = self.dir.remove_at(path);
+    self.record(
+      "dir_remove_at",
+      json!({ "path": path_str(path) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+
+  fn rename_at(
+    &self,
+    from: impl AsRef<Path>,
+    to_dir: &Self,
+    to: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    let result = self.dir.rename_at(from, &to_dir.dir, to);
+    self.record(
+      "dir_rename_at",
+      rename_at_args(from, to_dir.id, to),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+
+  fn symlink_at(
+    &self,
+    original: impl AsRef<Path>,
+    link: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    let (original, link) = (original.as_ref(), link.as_ref());
+    let result = self.dir.symlink_at(original, link);
+    self.record(
+      "dir_symlink_at",
+      two_paths_args(original, link),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+// == RecordingFile ==
+
+/// A file opened through a [`RecordingSys`].
+#[derive(Debug)]
+pub struct RecordingFile<F> {
+  file: F,
+  /// Index of the event that opened this file.
+  id: usize,
+  recorder: Arc<Recorder>,
+}
+
+impl<F> RecordingFile<F> {
+  fn record(&self, op: &str, mut args: Value, result: Value) {
+    match &mut args {
+      Value::Object(map) => {
+        map.insert("file".to_string(), json!(self.id));
+      }
+      _ => args = json!({ "file": self.id }),
+    }
+    self.recorder.record(op, args, result);
+  }
+}
+
+impl<F: FsFile> FsFile for RecordingFile<F> {}
+
+impl<F: FsFileAsRaw> FsFileAsRaw for RecordingFile<F> {
+  #[cfg(windows)]
+  #[inline]
+  fn fs_file_as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+    self.file.fs_file_as_raw_handle()
+  }
+
+  #[cfg(unix)]
+  #[inline]
+  fn fs_file_as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
+    self.file.fs_file_as_raw_fd()
+  }
+}
+
+impl<F: FsFileIsTerminal> FsFileIsTerminal for RecordingFile<F> {
+  fn fs_file_is_terminal(&self) -> bool {
+    let result = self.file.fs_file_is_terminal();
+    self.record("file_is_terminal", Value::Null, json!(result));
+    result
+  }
+}
+
+impl<F: FsFileLock> FsFileLock for RecordingFile<F> {
+  fn fs_file_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    let args = json!({ "mode": encode_lock_mode(&mode) });
+    let result = self.file.fs_file_lock(mode);
+    self.record("file_lock", args, encode_result(result.as_ref()));
+    result
+  }
+
+  fn fs_file_try_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    let args = json!({ "mode": encode_lock_mode(&mode) });
+    let result = self.file.fs_file_try_lock(mode);
+    self.record("file_try_lock", args, encode_result(result.as_ref()));
+    result
+  }
+
+  fn fs_file_unlock(&mut self) -> io::Result<()> {
+    let result = self.file.fs_file_unlock();
+    self.record("file_unlock", Value::Null, encode_result(result.as_ref()));
+    result
+  }
+}
+
+impl<F: FsFileLockRange> FsFileLockRange for RecordingFile<F> {
+  fn fs_file_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    let args = lock_range_args(Some(&mode), offset, len);
+    let result = self.file.fs_file_lock_range(mode, offset, len);
+    self.record("file_lock_range", args, encode_result(result.as_ref()));
+    result
+  }
+
+  fn fs_file_try_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    let args = lock_range_args(Some(&mode), offset, len);
+    let result = self.file.fs_file_try_lock_range(mode, offset, len);
+    self.record("file_try_lock_range", args, encode_result(result.as_ref()));
+    result
+  }
+
+  fn fs_file_unlock_range(&mut self, offset: u64, len: u64) -> io::Result<()> {
+    let args = lock_range_args(None, offset, len);
+    let result = self.file.fs_file_unlock_range(offset, len);
+    self.record("file_unlock_range", args, encode_result(result.as_ref()));
+    result
+  }
+}
+
+impl<F: FsFileMetadata> FsFileMetadata for RecordingFile<F> {
+  fn fs_file_metadata(&self) -> io::Result<BoxedFsMetadataValue> {
+    let result = self.file.fs_file_metadata();
+    self.record(
+      "file_metadata",
+      Value::Null,
+      encode_metadata(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<F: FsFileSetLen> FsFileSetLen for RecordingFile<F> {
+  fn fs_file_set_len(&mut self, size: u64) -> io::Result<()> {
+    let result = self.file.fs_file_set_len(size);
+    self.record(
+      "file_set_len",
+      json!({ "size": size }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<F: FsFileSetPermissions> FsFileSetPermissions for RecordingFile<F> {
+  fn fs_file_set_permissions(&mut self, mode: u32) -> io::Result<()> {
+    let result = self.file.fs_file_set_permissions(mode);
+    self.record(
+      "file_set_permissions",
+      json!({ "mode": mode }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<F: FsFileSetTimes> FsFileSetTimes for RecordingFile<F> {
+  fn fs_file_set_times(&mut self, times: FsFileTimes) -> io::Result<()> {
+    let args = json!({
+      "accessed": times.accessed,
+      "modified": times.modified,
+    });
+    let result = self.file.fs_file_set_times(times);
+    self.record("file_set_times", args, encode_result(result.as_ref()));
+    result
+  }
+}
+
+impl<F: FsFileSyncAll> FsFileSyncAll for RecordingFile<F> {
+  fn fs_file_sync_all(&mut self) -> io::Result<()> {
+    let result = self.file.fs_file_sync_all();
+    self.record("file_sync_all", Value::Null, encode_result(result.as_ref()));
+    result
+  }
+}
+
+impl<F: FsFileSyncData> FsFileSyncData for RecordingFile<F> {
+  fn fs_file_sync_data(&mut self) -> io::Result<()> {
+    let result = self.file.fs_file_sync_data();
+    self.record(
+      "file_sync_data",
+      Value::Null,
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<F: io::Seek> io::Seek for RecordingFile<F> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let result = self.file.seek(pos);
+    self.record(
+      "file_seek",
+      json!({ "pos": encode_seek(pos) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+}
+
+impl<F: io::Write> io::Write for RecordingFile<F> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let result = self.file.write(buf);
+    self.record(
+      "file_write",
+      json!({ "data": TraceBytes::new(buf) }),
+      encode_result(result.as_ref()),
+    );
+    result
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    let result = self.file.flush();
+    self.record("file_flush", Value::Null, encode_result(result.as_ref()));
+    result
+  }
+}
+
+impl<F: io::Read> io::Read for RecordingFile<F> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let result = self.file.read(buf);
+    self.record(
+      "file_read",
+      json!({ "len": buf.len() }),
+      encode_result(result.as_ref().map(|n| TraceBytes::new(&buf[..*n]))),
+    );
+    result
+  }
+}
+
+// == ReplaySys ==
+
+#[derive(Debug)]
+struct ReplayState {
+  events: Vec<TraceEvent>,
+  position: usize,
+}
+
+/// Implements the system traits by replaying a recorded [`Trace`].
+///
+/// Panics when a call doesn't match the next event in the trace.
+#[derive(Debug, Clone)]
+pub struct ReplaySys(Arc<Mutex<ReplayState>>);
+
+impl ReplaySys {
+  pub fn new(trace: Trace) -> Self {
+    Self(Arc::new(Mutex::new(ReplayState {
+      events: trace.events,
+      position: 0,
+    })))
+  }
+
+  /// Number of events that haven't been replayed yet.
+  pub fn remaining(&self) -> usize {
+    let state = self.0.lock().unwrap();
+    state.events.len() - state.position
+  }
+
+  /// Panics if any events haven't been replayed.
+  pub fn assert_finished(&self) {
+    let state = self.0.lock().unwrap();
+    if let Some(event) = state.events.get(state.position) {
+      panic!(
+        "ReplaySys: {} events were not replayed, starting at event {}: {}({})",
+        state.events.len() - state.position,
+        state.position,
+        event.op,
+        event.args,
+      );
+    }
+  }
+
+  /// Takes the next event, asserting it matches the call, and returns
+  /// its index and result.
+  fn next(&self, op: &str, args: Value) -> (usize, Value) {
+    let mut state = self.0.lock().unwrap();
+    let position = state.position;
+    let message = match state.events.get(position) {
+      Some(event) if event.op == op && event.args == args => {
+        let result = event.result.clone();
+        state.position += 1;
+        return (position, result);
+      }
+      Some(event) => format!(
+        "ReplaySys: diverged from trace at event {}.\n  Expected: {}({})\n  Actual: {}({})",
+        position, event.op, event.args, op, args,
+      ),
+      None => format!(
+        "ReplaySys: unexpected call after end of trace ({} events): {}({})",
+        position, op, args,
+      ),
+    };
+    drop(state);
+    panic!("{}", message);
+  }
+
+  fn replay<R: DeserializeOwned>(&self, op: &str, args: Value) -> R {
+    let (position, result) = self.next(op, args);
+    serde_json::from_value(result).unwrap_or_else(|err| {
+      panic!(
+        "ReplaySys: invalid result for event {} ({}): {:#}",
+        position, op, err
+      )
+    })
+  }
+
+  fn replay_result<R: DeserializeOwned>(
+    &self,
+    op: &str,
+    args: Value,
+  ) -> io::Result<R> {
+    self
+      .replay::<Result<R, TraceError>>(op, args)
+      .map_err(io::Error::from)
+  }
+
+  /// Replays opening a file or directory and returns the index of the
+  /// event, which later calls on it refer to.
+  fn replay_open(&self, op: &str, args: Value) -> io::Result<usize> {
+    let (id, result) = self.next(op, args);
+    let result: Result<(), TraceError> = serde_json::from_value(result)
+      .unwrap_or_else(|err| {
+        panic!(
+          "ReplaySys: invalid result for event {} ({}): {}",
+          id, op, err
+        )
+      });
+    result.map_err(io::Error::from)?;
+    Ok(id)
+  }
+}
+
+// ==== Environment ====
+
+impl EnvCurrentDir for ReplaySys {
+  fn env_current_dir(&self) -> io::Result<PathBuf> {
+    self
+      .replay_result::<String>("env_current_dir", Value::Null)
+      .map(PathBuf::from)
+  }
+}
+
+impl BaseEnvSetCurrentDir for ReplaySys {
+  fn base_env_set_current_dir(&self, path: &Path) -> io::Result<()> {
+    self.replay_result("env_set_current_dir", json!({ "path": path_str(path) }))
+  }
+}
+
+impl BaseEnvVar for ReplaySys {
+  fn base_env_var_os(&self, key: &OsStr) -> Option<OsString> {
+    self
+      .replay::<Option<String>>("env_var_os", json!({ "key": os_str(key) }))
+      .map(OsString::from)
+  }
+}
+
+impl EnvVars for ReplaySys {
+  type EnvVarsOs = std::vec::IntoIter<(OsString, OsString)>;
+
+  fn env_vars_os(&self) -> Self::EnvVarsOs {
+    self
+      .replay::<Vec<(String, String)>>("env_vars_os", Value::Null)
+      .into_iter()
+      .map(|(key, value)| (OsString::from(key), OsString::from(value)))
+      .collect::<Vec<_>>()
+      .into_iter()
+  }
+}
+
+impl BaseEnvRemoveVar for ReplaySys {
+  fn base_env_remove_var(&self, key: &OsStr) {
+    self.replay::<()>("env_remove_var", json!({ "key": os_str(key) }))
+  }
+}
+
+impl BaseEnvSetVar for ReplaySys {
+  fn base_env_set_var(&self, key: &OsStr, value: &OsStr) {
+    self.replay::<()>(
+      "env_set_var",
+      json!({ "key": os_str(key), "value": os_str(value) }),
+    )
+  }
+}
+
+impl EnvUmask for ReplaySys {
+  fn env_umask(&self) -> io::Result<u32> {
+    self.replay_result("env_umask", Value::Null)
+  }
+}
+
+impl EnvSetUmask for ReplaySys {
+  fn env_set_umask(&self, umask: u32) -> io::Result<u32> {
+    self.replay_result("env_set_umask", json!({ "umask": umask }))
+  }
+}
+
+impl EnvCacheDir for ReplaySys {
+  fn env_cache_dir(&self) -> Option<PathBuf> {
+    self
+      .replay::<Option<String>>("env_cache_dir", Value::Null)
+      .map(PathBuf::from)
+  }
+}
+
+impl EnvHomeDir for ReplaySys {
+  fn env_home_dir(&self) -> Option<PathBuf> {
+    self
+      .replay::<Option<String>>("env_home_dir", Value::Null)
+      .map(PathBuf::from)
+  }
+}
+
+impl EnvProgramsDir for ReplaySys {
+  fn env_programs_dir(&self) -> Option<PathBuf> {
+    self
+      .replay::<Option<String>>("env_programs_dir", Value::Null)
+      .map(PathBuf::from)
+  }
+}
+
+impl EnvTempDir for ReplaySys {
+  fn env_temp_dir(&self) -> io::Result<PathBuf> {
+    self
+      .replay_result::<String>("env_temp_dir", Value::Null)
+      .map(PathBuf::from)
+  }
+}
+
+// ==== File System ====
+
+impl BaseFsCanonicalize for ReplaySys {
+  fn base_fs_canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+    self
+      .replay_result::<String>(
+        "fs_canonicalize",
+        json!({ "path": path_str(path) }),
+      )
+      .map(PathBuf::from)
+  }
+}
+
+impl BaseFsChown for ReplaySys {
+  fn base_fs_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    self.replay_result("fs_chown", chown_args(path, uid, gid))
+  }
+}
+
+impl BaseFsSymlinkChown for ReplaySys {
+  fn base_fs_symlink_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    self.replay_result("fs_symlink_chown", chown_args(path, uid, gid))
+  }
+}
+
+impl BaseFsCloneFile for ReplaySys {
+  fn base_fs_clone_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+    self.replay_result("fs_clone_file", two_paths_args(from, to))
+  }
+}
+
+impl BaseFsCopy for ReplaySys {
+  fn base_fs_copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+    self.replay_result("fs_copy", two_paths_args(from, to))
+  }
+}
+
+impl BaseFsCopyWithOptions for ReplaySys {
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    let len: u64 = self
+      .replay_result("fs_copy_with_options", copy_args(from, to, options))?;
+    options.report_progress(len);
+    Ok(len)
+  }
+}
+
+impl BaseFsCreateDir for ReplaySys {
+  fn base_fs_create_dir(
+    &self,
+    path: &Path,
+    options: &CreateDirOptions,
+  ) -> io::Result<()> {
+    self.replay_result("fs_create_dir", create_dir_args(path, options))
+  }
+}
+
+impl BaseFsHardLink for ReplaySys {
+  fn base_fs_hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+    self.replay_result("fs_hard_link", two_paths_args(src, dst))
+  }
+}
+
+impl BaseFsCreateJunction for ReplaySys {
+  fn base_fs_create_junction(
+    &self,
+    original: &Path,
+    junction: &Path,
+  ) -> io::Result<()> {
+    self.replay_result("fs_create_junction", two_paths_args(original, junction))
+  }
+}
+
+impl BaseFsDiskSpace for ReplaySys {
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+    self.replay_result("fs_disk_space", json!({ "path": path_str(path) }))
+  }
+}
+
+impl BaseFsStatVfs for ReplaySys {
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs> {
+    self.replay_result("fs_statvfs", json!({ "path": path_str(path) }))
+  }
+}
+
+impl BaseFsMetadata for ReplaySys {
+  type Metadata = ReplayMetadata;
+
+  fn base_fs_metadata(&self, path: &Path) -> io::Result<Self::Metadata> {
+    self.replay_result("fs_metadata", json!({ "path": path_str(path) }))
+  }
+
+  fn base_fs_symlink_metadata(
+    &self,
+    path: &Path,
+  ) -> io::Result<Self::Metadata> {
+    self.replay_result("fs_symlink_metadata", json!({ "path": path_str(path) }))
+  }
+}
+
+impl BaseFsOpen for ReplaySys {
+  type File = ReplayFile;
+
+  fn base_fs_open(
+    &self,
+    path: &Path,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    let id = self.replay_open("fs_open", open_args(path, options))?;
+    Ok(ReplayFile {
+      sys: self.clone(),
+      id,
+    })
+  }
+}
+
+impl BaseFsOpenDir for ReplaySys {
+  type DirHandle = ReplayDirHandle;
+
+  fn base_fs_open_dir(&self, path: &Path) -> io::Result<Self::DirHandle> {
+    let id =
+      self.replay_open("fs_open_dir", json!({ "path": path_str(path) }))?;
+    Ok(ReplayDirHandle {
+      sys: self.clone(),
+      id,
+    })
+  }
+}
+
+impl BaseFsRead for ReplaySys {
+  fn base_fs_read(&self, path: &Path) -> io::Result<Cow<'static, [u8]>> {
+    self
+      .replay_result::<TraceBytes>("fs_read", json!({ "path": path_str(path) }))
+      .map(|data| Cow::Owned(data.into_vec()))
+  }
+}
+
+impl BaseFsReadShared for ReplaySys {
+  fn base_fs_read_shared(&self, path: &Path) -> io::Result<FsSharedBytes> {
+    self
+      .replay_result::<TraceBytes>(
+        "fs_read_shared",
+        json!({ "path": path_str(path) }),
+      )
+      .map(|data| FsSharedBytes::from(data.into_vec()))
+  }
+}
+
+impl BaseFsReadDir for ReplaySys {
+  type ReadDirEntry = ReplayDirEntry;
+
+  fn base_fs_read_dir(
+    &self,
+    path: &Path,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    let entries =
+      self.replay_result("fs_read_dir", json!({ "path": path_str(path) }))?;
+    Ok(replay_dir_entries(entries, self))
+  }
+}
+
+fn replay_dir_entries(
+  entries: Vec<Result<TraceDirEntry, TraceError>>,
+  sys: &ReplaySys,
+) -> Box<dyn Iterator<Item = io::Result<ReplayDirEntry>>> {
+  let sys = sys.clone();
+  Box::new(entries.into_iter().map(move |entry| {
+    entry
+      .map(|entry| ReplayDirEntry {
+        name: OsString::from(entry.name),
+        path: PathBuf::from(entry.path),
+        sys: sys.clone(),
+      })
+      .map_err(io::Error::from)
+  }))
+}
+
+impl BaseFsReadLink for ReplaySys {
+  fn base_fs_read_link(&self, path: &Path) -> io::Result<PathBuf> {
+    self
+      .replay_result::<String>(
+        "fs_read_link",
+        json!({ "path": path_str(path) }),
+      )
+      .map(PathBuf::from)
+  }
+}
+
+impl BaseFsRemoveDir for ReplaySys {
+  fn base_fs_remove_dir(&self, path: &Path) -> io::Result<()> {
+    self.replay_result("fs_remove_dir", json!({ "path": path_str(path) }))
+  }
+}
+
+impl BaseFsRemoveDirAll for ReplaySys {
+  fn base_fs_remove_dir_all(&self, path: &Path) -> io::Result<()> {
+    self.replay_result("fs_remove_dir_all", json!({ "path": path_str(path) }))
+  }
+}
+
+impl BaseFsRemoveFile for ReplaySys {
+  fn base_fs_remove_file(&self, path: &Path) -> io::Result<()> {
+    self.replay_result("fs_remove_file", json!({ "path": path_str(path) }))
+  }
+}
+
+impl BaseFsRename for ReplaySys {
+  fn base_fs_rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+    self.replay_result("fs_rename", two_paths_args(from, to))
+  }
+}
+
+impl BaseFsRenameWithOptions for ReplaySys {
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> io::Result<()> {
+    self.replay_result("fs_rename_with_options", rename_args(from, to, options))
+  }
+}
+
+impl BaseFsSetFileTimes for ReplaySys {
+  fn base_fs_set_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    self.replay_result("fs_set_file_times", file_times_args(path, atime, mtime))
+  }
+}
+
+impl BaseFsSetSymlinkFileTimes for ReplaySys {
+  fn base_fs_set_symlink_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    self.replay_result(
+      "fs_set_symlink_file_times",
+      file_times_args(path, atime, mtime),
+    )
+  }
+}
+
+impl BaseFsSetPermissions for ReplaySys {
+  fn base_fs_set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+    self.replay_result(
+      "fs_set_permissions",
+      json!({ "path": path_str(path), "mode": mode }),
+    )
+  }
+}
+
+impl BaseFsSymlinkDir for ReplaySys {
+  fn base_fs_symlink_dir(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.replay_result("fs_symlink_dir", two_paths_args(original, link))
+  }
+}
+
+impl BaseFsSymlinkFile for ReplaySys {
+  fn base_fs_symlink_file(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.replay_result("fs_symlink_file", two_paths_args(original, link))
+  }
+}
+
+impl BaseFsWrite for ReplaySys {
+  fn base_fs_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+    self.replay_result(
+      "fs_write",
+      json!({ "path": path_str(path), "data": TraceBytes::new(data) }),
+    )
+  }
+}
+
+impl BaseFsGetXattr for ReplaySys {
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self
+      .replay_result::<Option<TraceBytes>>(
+        "fs_get_xattr",
+        json!({ "path": path_str(path), "name": os_str(name) }),
+      )
+      .map(|value| value.map(TraceBytes::into_vec))
+  }
+
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self
+      .replay_result::<Option<TraceBytes>>(
+        "fs_symlink_get_xattr",
+        json!({ "path": path_str(path), "name": os_str(name) }),
+      )
+      .map(|value| value.map(TraceBytes::into_vec))
+  }
+}
+
+impl BaseFsListXattr for ReplaySys {
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>> {
+    self
+      .replay_result::<Vec<String>>(
+        "fs_list_xattr",
+        json!({ "path": path_str(path) }),
+      )
+      .map(|names| names.into_iter().map(OsString::from).collect())
+  }
+
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    self
+      .replay_result::<Vec<String>>(
+        "fs_symlink_list_xattr",
+        json!({ "path": path_str(path) }),
+      )
+      .map(|names| names.into_iter().map(OsString::from).collect())
+  }
+}
+
+impl BaseFsRemoveXattr for ReplaySys {
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+    self.replay_result(
+      "fs_remove_xattr",
+      json!({ "path": path_str(path), "name": os_str(name) }),
+    )
+  }
+
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()> {
+    self.replay_result(
+      "fs_symlink_remove_xattr",
+      json!({ "path": path_str(path), "name": os_str(name) }),
+    )
+  }
+}
+
+impl BaseFsSetXattr for ReplaySys {
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.replay_result("fs_set_xattr", json!({ "path": path_str(path), "name": os_str(name), "value": TraceBytes::new(value) }))
+  }
+
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    self.replay_result("fs_symlink_set_xattr", json!({ "path": path_str(path), "name": os_str(name), "value": TraceBytes::new(value) }))
+  }
+}
+
+// ==== System ====
+
+impl SystemTimeNow for ReplaySys {
+  fn sys_time_now(&self) -> SystemTime {
+    self.replay("sys_time_now", Value::Null)
+  }
+}
+
+impl SystemRandom for ReplaySys {
+  fn sys_random(&self, buf: &mut [u8]) -> io::Result<()> {
+    let data = self
+      .replay_result::<Vec<u8>>("sys_random", json!({ "len": buf.len() }))?;
+    buf.copy_from_slice(&data);
+    Ok(())
+  }
+}
+
+impl ProcessExit for ReplaySys {
+  fn process_exit(&self, code: i32) -> ! {
+    self.replay::<()>("process_exit", json!({ "code": code }));
+    panic!("ReplaySys: process exited with code {}", code);
+  }
+}
+
+impl ThreadSleep for ReplaySys {
+  fn thread_sleep(&self, duration: Duration) {
+    self.replay::<()>("thread_sleep", json!({ "duration": duration }))
+  }
+}
+
+// == ReplayDirEntry ==
+
+#[derive(Debug)]
+pub struct ReplayDirEntry {
+  name: OsString,
+  path: PathBuf,
+  sys: ReplaySys,
+}
+
+impl FsDirEntry for ReplayDirEntry {
+  type Metadata = ReplayMetadata;
+
+  fn file_name(&self) -> Cow<'_, OsStr> {
+    Cow::Borrowed(&self.name)
+  }
+
+  fn file_type(&self) -> io::Result<FileType> {
+    self.sys.replay_result(
+      "dir_entry_file_type",
+      json!({ "path": path_str(&self.path) }),
+    )
+  }
+
+  fn metadata(&self) -> io::Result<Self::Metadata> {
+    self.sys.replay_result(
+      "dir_entry_metadata",
+      json!({ "path": path_str(&self.path) }),
+    )
+  }
+
+  fn path(&self) -> Cow<'_, Path> {
+    Cow::Borrowed(&self.path)
+  }
+}
+
+// == ReplayDirHandle ==
+
+/// A directory handle opened through a [`ReplaySys`].
+#[derive(Debug)]
+pub struct ReplayDirHandle {
+  sys: ReplaySys,
+  /// Index of the event that opened this directory.
+  id: usize,
+}
+
+impl ReplayDirHandle {
+  fn args(&self, args: Value) -> Value {
+    match args {
+      Value::Object(mut map) => {
+        map.insert("dir".to_string(), json!(self.id));
+        Value::Object(map)
+      }
+      _ => json!({ "dir": self.id }),
+    }
+  }
+
+  fn replay_result<R: DeserializeOwned>(
+    &self,
+    op: &str,
+    args: Value,
+  ) -> io::Result<R> {
+    self.sys.replay_result(op, self.args(args))
+  }
+}
+
+impl FsDirHandle for ReplayDirHandle {
+  type File = ReplayFile;
+  type Metadata = ReplayMetadata;
+  type ReadDirEntry = ReplayDirEntry;
+
+  fn open_at(
+    &self,
+    path: impl AsRef<Path>,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    let args = self.args(open_args(path.as_ref(), options));
+    let id = self.sys.replay_open("dir_open_at", args)?;
+    Ok(ReplayFile {
+      sys: self.sys.clone(),
+      id,
+    })
+  }
+
+  fn open_dir_at(&self, path: impl AsRef<Path>) -> io::Result<Self> {
+    let args = self.args(json!({ "path": path_str(path.as_ref()) }));
+    let id = self.sys.replay_open("dir_open_dir_at", args)?;
+    Ok(ReplayDirHandle {
+      sys: self.sys.clone(),
+      id,
+    })
+  }
+
+  fn metadata_at(&self, path: impl AsRef<Path>) -> io::Result<Self::Metadata> {
+    self.replay_result(
+      "dir_metadata_at",
+      json!({ "path": path_str(path.as_ref()) }),
+    )
+  }
+
+  fn symlink_metadata_at(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Self::Metadata> {
+    self.replay_result(
+      "dir_symlink_metadata_at",
+      json!({ "path": path_str(path.as_ref()) }),
+    )
+  }
+
+  fn read_dir(
+    &self,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    let entries = self.replay_result("dir_read_dir", Value::Null)?;
+    Ok(replay_dir_entries(entries, &self.sys))
+  }
+
+  fn create_dir_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    self.replay_result(
+      "dir_create_dir_at",
+      json!({ "path": path_str(path.as_ref()) }),
+    )
+  }
+
+  fn remove_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    self.replay_result(
+      "dir_remove_at",
+      json!({ "path": path_str(path.as_ref()) }),
+    )
+  }
+
+  fn rename_at(
+    &self,
+    from: impl AsRef<Path>,
+    to_dir: &Self,
+    to: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    self.replay_result(
+      "dir_rename_at",
+      rename_at_args(from.as_ref(), to_dir.id, to.as_ref()),
+    )
+  }
+
+  fn symlink_at(
+    &self,
+    original: impl AsRef<Path>,
+    link: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    self.replay_result(
+      "dir_symlink_at",
+      two_paths_args(original.as_ref(), link.as_ref()),
+    )
+  }
+}
+
+// == ReplayFile ==
+
+/// A file opened through a [`ReplaySys`].
+#[derive(Debug)]
+pub struct ReplayFile {
+  sys: ReplaySys,
+  /// Index of the event that opened this file.
+  id: usize,
+}
+
+impl ReplayFile {
+  fn args(&self, args: Value) -> Value {
+    match args {
+      Value::Object(mut map) => {
+        map.insert("file".to_string(), json!(self.id));
+        Value::Object(map)
+      }
+      _ => json!({ "file": self.id }),
+    }
+  }
+
+  fn replay<R: DeserializeOwned>(&self, op: &str, args: Value) -> R {
+    self.sys.replay(op, self.args(args))
+  }
+
+  fn replay_result<R: DeserializeOwned>(
+    &self,
+    op: &str,
+    args: Value,
+  ) -> io::Result<R> {
+    self.sys.replay_result(op, self.args(args))
+  }
+}
+
+impl FsFile for ReplayFile {}
+
+impl FsFileAsRaw for ReplayFile {
+  #[cfg(windows)]
+  fn fs_file_as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+    None
+  }
+
+  #[cfg(unix)]
+  fn fs_file_as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
+    None
+  }
+}
+
+impl FsFileIsTerminal for ReplayFile {
+  fn fs_file_is_terminal(&self) -> bool {
+    self.replay("file_is_terminal", Value::Null)
+  }
+}
+
+impl FsFileLock for ReplayFile {
+  fn fs_file_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    self.replay_result("file_lock", json!({ "mode": encode_lock_mode(&mode) }))
+  }
+
+  fn fs_file_try_lock(&mut self, mode: FsFileLockMode) -> io::Result<()> {
+    self.replay_result(
+      "file_try_lock",
+      json!({ "mode": encode_lock_mode(&mode) }),
+    )
+  }
+
+  fn fs_file_unlock(&mut self) -> io::Result<()> {
+    self.replay_result("file_unlock", Value::Null)
+  }
+}
+
+impl FsFileLockRange for ReplayFile {
+  fn fs_file_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    self.replay_result(
+      "file_lock_range",
+      lock_range_args(Some(&mode), offset, len),
+    )
+  }
+
+  fn fs_file_try_lock_range(
+    &mut self,
+    mode: FsFileLockMode,
+    offset: u64,
+    len: u64,
+  ) -> io::Result<()> {
+    self.replay_result(
+      "file_try_lock_range",
+      lock_range_args(Some(&mode), offset, len),
+    )
+  }
+
+  fn fs_file_unlock_range(&mut self, offset: u64, len: u64) -> io::Result<()> {
+    self.replay_result("file_unlock_range", lock_range_args(None, offset, len))
+  }
+}
+
+impl FsFileMetadata for ReplayFile {
+  fn fs_file_metadata(&self) -> io::Result<BoxedFsMetadataValue> {
+    self
+      .replay_result::<ReplayMetadata>("file_metadata", Value::Null)
+      .map(BoxedFsMetadataValue::new)
+  }
+}
+
+impl FsFileSetLen for ReplayFile {
+  fn fs_file_set_len(&mut self, size: u64) -> io::Result<()> {
+    self.replay_result("file_set_len", json!({ "size": size }))
+  }
+}
+
+impl FsFileSetPermissions for ReplayFile {
+  fn fs_file_set_permissions(&mut self, mode: u32) -> io::Result<()> {
+    self.replay_result("file_set_permissions", json!({ "mode": mode }))
+  }
+}
+
+impl FsFileSetTimes for ReplayFile {
+  fn fs_file_set_times(&mut self, times: FsFileTimes) -> io::Result<()> {
+    self.replay_result(
+      "file_set_times",
+      json!({
+        "accessed": times.accessed,
+        "modified": times.modified,
+      }),
+    )
+  }
+}
+
+impl FsFileSyncAll for ReplayFile {
+  fn fs_file_sync_all(&mut self) -> io::Result<()> {
+    self.replay_result("file_sync_all", Value::Null)
+  }
+}
+
+impl FsFileSyncData for ReplayFile {
+  fn fs_file_sync_data(&mut self) -> io::Result<()> {
+    self.replay_result("file_sync_data", Value::Null)
+  }
+}
+
+impl io::Seek for ReplayFile {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    self.replay_result("file_seek", json!({ "pos": encode_seek(pos) }))
+  }
+}
+
+impl io::Write for ReplayFile {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.replay_result("file_write", json!({ "data": TraceBytes::new(buf) }))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.replay_result("file_flush", Value::Null)
+  }
+}
+
+impl io::Read for ReplayFile {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let data = self
+      .replay_result::<TraceBytes>("file_read", json!({ "len": buf.len() }))?
+      .into_vec();
+    buf[..data.len()].copy_from_slice(&data);
+    Ok(data.len())
+  }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+  use std::io::Read;
+  use std::io::Seek;
+  use std::io::Write;
+
+  use super::*;
+  use crate::impls::InMemorySys;
+  use crate::EnvSetVar;
+  use crate::EnvVar;
+  use crate::FsCreateDirAll;
+  use crate::FsMetadata;
+  use crate::FsOpen;
+  use crate::FsOpenDir;
+  use crate::FsRead;
+  use crate::FsReadDir;
+  use crate::FsWrite;
+
+  /// Does some work against a system and returns what it observed.
+  fn run<
+    TSys: EnvVar
+      + EnvCurrentDir
+      + FsCreateDirAll
+      + FsMetadata
+      + FsOpen
+      + FsOpenDir
+      + FsRead
+      + FsReadDir
+      + FsWrite
+      + SystemRandom
+      + SystemTimeNow,
+  >(
+    sys: &TSys,
+  ) -> Vec<String> {
+    let mut output = Vec::new();
+    output.push(format!("{:?}", sys.env_var("HOME")));
+    output.push(format!("{:?}", sys.env_current_dir()));
+    sys.fs_create_dir_all("/project/src").unwrap();
+    sys
+      .fs_write("/project/src/main.rs", "fn main() {}")
+      .unwrap();
+    sys
+      .fs_write("/project/data.bin", [0, 159, 146, 150])
+      .unwrap();
+    output.push(format!("{:?}", sys.fs_read("/project/data.bin")));
+    output.push(format!("{:?}", sys.fs_read_to_string("/missing")));
+    let mut entries = sys
+      .fs_read_dir("/project")
+      .unwrap()
+      .map(|entry| {
+        let entry = entry.unwrap();
+        format!(
+          "{} {:?} {}",
+          entry.path().display(),
+          entry.file_type().unwrap(),
+          entry.metadata().unwrap().len()
+        )
+      })
+      .collect::<Vec<_>>();
+    entries.sort();
+    output.extend(entries);
+    let metadata = sys.fs_metadata("/project/src/main.rs").unwrap();
+    output.push(format!(
+      "{} {:?} {:?}",
+      metadata.len(),
+      metadata.modified(),
+      metadata.mode()
+    ));
+    let mut options = OpenOptions::new();
+    options.read().write = true;
+    let mut file = sys.fs_open("/project/src/main.rs", &options).unwrap();
+    file.seek(SeekFrom::End(0)).unwrap();
+    file.write_all(b"\n").unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut text = String::new();
+    file.read_to_string(&mut text).unwrap();
+    output.push(text);
+    let dir = sys.fs_open_dir("/project").unwrap();
+    dir.create_dir_at("out").unwrap();
+    let out = dir.open_dir_at("out").unwrap();
+    let mut file = out.open_at("log.txt", &OpenOptions::new_write()).unwrap();
+    file.write_all(b"log").unwrap();
+    dir.rename_at("data.bin", &out, "data.bin").unwrap();
+    out.symlink_at("log.txt", "link").unwrap();
+    let mut names = out
+      .read_dir()
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_owned())
+      .collect::<Vec<_>>();
+    names.sort();
+    output.push(format!("{:?}", names));
+    let metadata = out.symlink_metadata_at("link").unwrap();
+    output.push(format!("{:?}", metadata.file_type()));
+    out.remove_at("link").unwrap();
+    let err = dir.metadata_at("data.bin").unwrap_err();
+    output.push(format!("{:?}", err.kind()));
+    output.push(format!("{:?}", sys.sys_random_u64()));
+    output.push(format!("{:?}", sys.sys_time_now()));
+    output
+  }
+
+  #[test]
+  fn test_record_and_replay() {
+    let sys = InMemorySys::default();
+    sys.set_seed(Some(42));
+    sys.set_time(Some(SystemTime::UNIX_EPOCH + Duration::from_secs(100)));
+    sys.env_set_var("HOME", "/home/user");
+    let recording_sys = RecordingSys::new(sys);
+    let expected = run(&recording_sys);
+
+    let json = recording_sys.trace().to_json();
+    let trace = Trace::from_json(&json).unwrap();
+    assert_eq!(trace, recording_sys.trace());
+
+    let replay_sys = ReplaySys::new(trace);
+    assert_eq!(run(&replay_sys), expected);
+    assert_eq!(replay_sys.remaining(), 0);
+    replay_sys.assert_finished();
+  }
+
+  #[test]
+  fn test_replay_errors() {
+    let recording_sys = RecordingSys::new(InMemorySys::default());
+    let err = recording_sys.fs_read("/missing").unwrap_err();
+    let replay_sys = ReplaySys::new(recording_sys.trace());
+    let replayed_err = replay_sys.fs_read("/missing").unwrap_err();
+    assert_eq!(replayed_err.kind(), err.kind());
+    assert_eq!(replayed_err.to_string(), err.to_string());
+  }
+
+  #[test]
+  fn test_replay_raw_os_errors() {
+    let err = io::Error::from_raw_os_error(2);
+    let trace_err = TraceError::from(&err);
+    assert_eq!(trace_err.os.as_deref(), Some(std::env::consts::OS));
+    let replayed_err = io::Error::from(trace_err.clone());
+    assert_eq!(replayed_err.raw_os_error(), Some(2));
+
+    // errors recorded on another OS keep their kind and message
+    let replayed_err = io::Error::from(TraceError {
+      os: Some("other".to_string()),
+      ..trace_err
+    });
+    assert_eq!(replayed_err.raw_os_error(), None);
+    assert_eq!(replayed_err.kind(), err.kind());
+    assert_eq!(replayed_err.to_string(), err.to_string());
+  }
+
+  #[test]
+  #[should_panic(expected = "diverged from trace at event 0")]
+  fn test_replay_divergence() {
+    let recording_sys = RecordingSys::new(InMemorySys::new_with_cwd("/"));
+    recording_sys.fs_write("/a.txt", "a").unwrap();
+    let replay_sys = ReplaySys::new(recording_sys.trace());
+    let _ = replay_sys.fs_write("/a.txt", "b");
+  }
+
+  #[test]
+  #[should_panic(expected = "unexpected call after end of trace")]
+  fn test_replay_past_end() {
+    let replay_sys = ReplaySys::new(Trace::default());
+    let _ = replay_sys.fs_read("/a.txt");
+  }
+
+  #[test]
+  #[should_panic(expected = "1 events were not replayed")]
+  fn test_replay_not_finished() {
+    let recording_sys = RecordingSys::new(InMemorySys::default());
+    let _ = recording_sys.sys_time_now();
+    ReplaySys::new(recording_sys.trace()).assert_finished();
+  }
+}
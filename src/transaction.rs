@@ -0,0 +1,1605 @@
+//! Transactional file system wrapper.
+//!
+//! This module provides [`TransactionSys`], a wrapper that journals every
+//! file system mutation so the changes can later be committed or rolled
+//! back. Entries that get overwritten or removed are first saved to a
+//! backup directory inside the system's temp directory.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use sys_traits::FsWrite;
+//! use sys_traits::FsRename;
+//! use sys_traits::TransactionSys;
+//! # #[cfg(feature = "real")]
+//! use sys_traits::impls::RealSys;
+//!
+//! # #[cfg(feature = "real")]
+//! # fn example() -> std::io::Result<()> {
+//! let sys = TransactionSys::new(RealSys);
+//! let result = (|| {
+//!   sys.fs_write("/app/config.json.tmp", "{}")?;
+//!   sys.fs_rename("/app/config.json.tmp", "/app/config.json")
+//! })();
+//! match result {
+//!   Ok(()) => sys.commit()?,
+//!   Err(_) => sys.rollback()?,
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::BaseEnvRemoveVar;
+use crate::BaseEnvSetCurrentDir;
+use crate::BaseEnvSetVar;
+use crate::BaseEnvVar;
+use crate::BaseFsCanonicalize;
+use crate::BaseFsChown;
+use crate::BaseFsCloneFile;
+use crate::BaseFsCopy;
+use crate::BaseFsCopyWithOptions;
+use crate::BaseFsCreateDir;
+use crate::BaseFsCreateJunction;
+use crate::BaseFsDiskSpace;
+use crate::BaseFsGetXattr;
+use crate::BaseFsHardLink;
+use crate::BaseFsListXattr;
+use crate::BaseFsMetadata;
+use crate::BaseFsOpen;
+use crate::BaseFsOpenDir;
+use crate::BaseFsRead;
+use crate::BaseFsReadDir;
+use crate::BaseFsReadLink;
+use crate::BaseFsReadShared;
+use crate::BaseFsRemoveDir;
+use crate::BaseFsRemoveDirAll;
+use crate::BaseFsRemoveFile;
+use crate::BaseFsRemoveXattr;
+use crate::BaseFsRename;
+use crate::BaseFsRenameWithOptions;
+use crate::BaseFsSetFileTimes;
+use crate::BaseFsSetPermissions;
+use crate::BaseFsSetSymlinkFileTimes;
+use crate::BaseFsSetXattr;
+use crate::BaseFsStatVfs;
+use crate::BaseFsSymlinkChown;
+use crate::BaseFsSymlinkDir;
+use crate::BaseFsSymlinkFile;
+use crate::BaseFsWrite;
+use crate::CopyOptions;
+use crate::CreateDirOptions;
+use crate::DiskSpace;
+use crate::EnvCacheDir;
+use crate::EnvCurrentDir;
+use crate::EnvHomeDir;
+use crate::EnvProgramsDir;
+use crate::EnvSetUmask;
+use crate::EnvTempDir;
+use crate::EnvUmask;
+use crate::EnvVars;
+use crate::FileType;
+use crate::FsDirEntry;
+use crate::FsDirHandle;
+use crate::FsFileSetTimes;
+use crate::FsFileTimes;
+use crate::FsMetadataValue;
+use crate::FsSharedBytes;
+use crate::OpenOptions;
+use crate::ProcessExit;
+use crate::RenameOptions;
+use crate::StatVfs;
+use crate::SystemRandom;
+use crate::SystemTimeNow;
+use crate::ThreadSleep;
+
+/// Functionality [`TransactionSys`] needs from the wrapped system in
+/// order to back up and restore entries.
+#[crate::auto_impl]
+pub trait TransactionBackupSys:
+  BaseFsCopy
+  + BaseFsCreateDir
+  + BaseFsMetadata
+  + BaseFsOpen
+  + BaseFsReadDir
+  + BaseFsReadLink
+  + BaseFsRemoveDirAll
+  + BaseFsRemoveFile
+  + BaseFsRename
+  + BaseFsSetPermissions
+  + BaseFsSymlinkDir
+  + BaseFsSymlinkFile
+  + EnvTempDir
+  + SystemTimeNow
+  + 'static
+{
+}
+
+type UndoFn<T> = Box<dyn Fn(&T) -> io::Result<()> + Send>;
+
+struct TransactionState<T> {
+  journal: Vec<UndoFn<T>>,
+  backup_dir: Option<PathBuf>,
+  next_backup_id: usize,
+}
+
+/// A wrapper that journals file system mutations so they can be
+/// committed or rolled back.
+///
+/// Dropping the wrapper without calling [`commit`](Self::commit) or
+/// [`rollback`](Self::rollback) leaves all changes in place and the
+/// backup directory on disk.
+pub struct TransactionSys<T> {
+  sys: T,
+  state: Arc<Mutex<TransactionState<T>>>,
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for TransactionSys<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let state = self.state.lock().unwrap();
+    f.debug_struct("TransactionSys")
+      .field("sys", &self.sys)
+      .field("journal_len", &state.journal.len())
+      .field("backup_dir", &state.backup_dir)
+      .finish()
+  }
+}
+
+impl<T> TransactionSys<T> {
+  pub fn new(sys: T) -> Self {
+    Self {
+      sys,
+      state: Arc::new(Mutex::new(TransactionState {
+        journal: Vec::new(),
+        backup_dir: None,
+        next_backup_id: 0,
+      })),
+    }
+  }
+
+  /// Returns a reference to the inner system.
+  ///
+  /// Changes made directly through the inner system are not journaled.
+  pub fn inner(&self) -> &T {
+    &self.sys
+  }
+
+  /// Number of journaled mutations that have not been committed or
+  /// rolled back.
+  pub fn journal_len(&self) -> usize {
+    self.state.lock().unwrap().journal.len()
+  }
+
+  fn push_undo(&self, undo: UndoFn<T>) {
+    self.state.lock().unwrap().journal.push(undo);
+  }
+}
+
+impl<T: TransactionBackupSys> TransactionSys<T> {
+  /// Keeps all changes made so far and deletes the backups.
+  pub fn commit(&self) -> io::Result<()> {
+    let mut state = self.state.lock().unwrap();
+    state.journal.clear();
+    if let Some(backup_dir) = &state.backup_dir {
+      self.sys.base_fs_remove_dir_all(backup_dir)?;
+      state.backup_dir = None;
+    }
+    Ok(())
+  }
+
+  /// Undoes all changes made so far in reverse order.
+  ///
+  /// If undoing a change fails, the error is returned and the remaining
+  /// changes along with the backup directory are kept so that rolling
+  /// back can be retried.
+  pub fn rollback(&self) -> io::Result<()> {
+    let mut state = self.state.lock().unwrap();
+    while let Some(undo) = state.journal.last() {
+      undo(&self.sys)?;
+      state.journal.pop();
+    }
+    if let Some(backup_dir) = &state.backup_dir {
+      self.sys.base_fs_remove_dir_all(backup_dir)?;
+      state.backup_dir = None;
+    }
+    Ok(())
+  }
+
+  fn next_backup_path(&self) -> io::Result<PathBuf> {
+    static NEXT_TRANSACTION_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let mut state = self.state.lock().unwrap();
+    let backup_dir = match &state.backup_dir {
+      Some(dir) => dir.clone(),
+      None => {
+        let nanos = self
+          .sys
+          .sys_time_now()
+          .duration_since(SystemTime::UNIX_EPOCH)
+          .map(|d| d.as_nanos())
+          .unwrap_or(0);
+        let id = NEXT_TRANSACTION_ID.fetch_add(1, Ordering::Relaxed);
+        let dir = self
+          .sys
+          .env_temp_dir()?
+          .join(format!("sys_traits_transaction_{}_{}", nanos, id));
+        self
+          .sys
+          .base_fs_create_dir(&dir, &CreateDirOptions::new_recursive())?;
+        state.backup_dir = Some(dir.clone());
+        dir
+      }
+    };
+    let id = state.next_backup_id;
+    state.next_backup_id += 1;
+    Ok(backup_dir.join(id.to_string()))
+  }
+
+  /// Gets how to undo any change to `path` by copying what's there
+  /// now to the backup directory, along with the path of that copy.
+  fn prepare_change(
+    &self,
+    path: &Path,
+  ) -> io::Result<(UndoFn<T>, Option<PathBuf>)> {
+    let path = path.to_path_buf();
+    // don't follow symlinks so dangling ones are backed up too
+    let exists = match self.sys.base_fs_symlink_metadata(&path) {
+      Ok(_) => true,
+      Err(err) if err.kind() == ErrorKind::NotFound => false,
+      Err(err) => return Err(err),
+    };
+    if exists {
+      let backup = self.next_backup_path()?;
+      copy_recursive(&self.sys, &path, &backup)?;
+      let backup_path = backup.clone();
+      Ok((
+        Box::new(move |sys| restore(sys, &backup, &path)),
+        Some(backup_path),
+      ))
+    } else {
+      Ok((Box::new(move |sys| remove_existing(sys, &path)), None))
+    }
+  }
+
+  /// Runs `op` after preparing the change to `path`, removing the
+  /// backup when it fails since nothing will refer to it.
+  fn run_prepared<R>(
+    &self,
+    path: &Path,
+    op: impl FnOnce() -> io::Result<R>,
+  ) -> io::Result<(R, UndoFn<T>)> {
+    let (undo, backup) = self.prepare_change(path)?;
+    match op() {
+      Ok(value) => Ok((value, undo)),
+      Err(err) => {
+        if let Some(backup) = backup {
+          // the operation's error is more useful than a cleanup error
+          let _ = remove_existing(&self.sys, &backup);
+        }
+        Err(err)
+      }
+    }
+  }
+
+  /// Runs `op`, which may change `path`, and journals how to undo it.
+  fn journal_change<R>(
+    &self,
+    path: &Path,
+    op: impl FnOnce() -> io::Result<R>,
+  ) -> io::Result<R> {
+    let (value, undo) = self.run_prepared(path, op)?;
+    self.push_undo(undo);
+    Ok(value)
+  }
+
+  /// Moves `path` to the backup directory and journals how to restore it.
+  fn journal_removal(&self, path: &Path) -> io::Result<()> {
+    let backup = self.next_backup_path()?;
+    move_path(&self.sys, path, &backup)?;
+    let path = path.to_path_buf();
+    self.push_undo(Box::new(move |sys| restore(sys, &backup, &path)));
+    Ok(())
+  }
+}
+
+fn remove_existing<T: TransactionBackupSys>(
+  sys: &T,
+  path: &Path,
+) -> io::Result<()> {
+  match sys.base_fs_symlink_metadata(path) {
+    Ok(metadata) => {
+      if metadata.file_type() == FileType::Dir {
+        sys.base_fs_remove_dir_all(path)
+      } else {
+        sys.base_fs_remove_file(path)
+      }
+    }
+    Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+    Err(err) => Err(err),
+  }
+}
+
+fn restore<T: TransactionBackupSys>(
+  sys: &T,
+  backup: &Path,
+  path: &Path,
+) -> io::Result<()> {
+  remove_existing(sys, path)?;
+  move_path(sys, backup, path)
+}
+
+/// Renames `from` to `to`, falling back to copying when renaming
+/// isn't possible (ex. across devices).
+fn move_path<T: TransactionBackupSys>(
+  sys: &T,
+  from: &Path,
+  to: &Path,
+) -> io::Result<()> {
+  if sys.base_fs_rename(from, to).is_ok() {
+    return Ok(());
+  }
+  // clear out anything left behind by a previous attempt
+  remove_existing(sys, to)?;
+  copy_recursive(sys, from, to)?;
+  remove_existing(sys, from)
+}
+
+fn copy_recursive<T: TransactionBackupSys>(
+  sys: &T,
+  from: &Path,
+  to: &Path,
+) -> io::Result<()> {
+  let metadata = sys.base_fs_symlink_metadata(from)?;
+  match metadata.file_type() {
+    FileType::Symlink => {
+      let target = sys.base_fs_read_link(from)?;
+      if sys
+        .base_fs_metadata(from)
+        .map(|m| m.file_type() == FileType::Dir)
+        .unwrap_or(false)
+      {
+        sys.base_fs_symlink_dir(&target, to)
+      } else {
+        sys.base_fs_symlink_file(&target, to)
+      }
+    }
+    FileType::Dir => {
+      sys.base_fs_create_dir(to, &CreateDirOptions::new())?;
+      for entry in sys.base_fs_read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        copy_recursive(sys, &from.join(&name), &to.join(&name))?;
+      }
+      copy_mode(sys, &metadata, to)
+    }
+    FileType::File | FileType::Unknown => {
+      sys.base_fs_copy(from, to)?;
+      // before the mode in case it makes the file read-only
+      copy_times(sys, &metadata, to)?;
+      copy_mode(sys, &metadata, to)
+    }
+  }
+}
+
+fn copy_times<T: TransactionBackupSys>(
+  sys: &T,
+  metadata: &T::Metadata,
+  to: &Path,
+) -> io::Result<()> {
+  let mut times = FsFileTimes::new();
+  if let Ok(accessed) = metadata.accessed() {
+    times.accessed(accessed);
+  }
+  if let Ok(modified) = metadata.modified() {
+    times.modified(modified);
+  }
+  let mut file = sys.base_fs_open(
+    to,
+    &OpenOptions {
+      write: true,
+      ..Default::default()
+    },
+  )?;
+  match file.fs_file_set_times(times) {
+    Err(err) if err.kind() == ErrorKind::Unsupported => Ok(()),
+    result => result,
+  }
+}
+
+fn copy_mode<T: TransactionBackupSys>(
+  sys: &T,
+  metadata: &T::Metadata,
+  to: &Path,
+) -> io::Result<()> {
+  let Ok(mode) = metadata.mode() else {
+    return Ok(());
+  };
+  match sys.base_fs_set_permissions(to, mode) {
+    Err(err) if err.kind() == ErrorKind::Unsupported => Ok(()),
+    result => result,
+  }
+}
+
+/// Creates an undo that restores the current value of an extended
+/// attribute.
+fn prepare_xattr_change<T>(
+  sys: &T,
+  path: &Path,
+  name: &OsStr,
+  follow: bool,
+) -> io::Result<UndoFn<T>>
+where
+  T: BaseFsGetXattr + BaseFsRemoveXattr + BaseFsSetXattr,
+{
+  let value = if follow {
+    sys.base_fs_get_xattr(path, name)?
+  } else {
+    sys.base_fs_symlink_get_xattr(path, name)?
+  };
+  let path = path.to_path_buf();
+  let name = name.to_os_string();
+  Ok(Box::new(move |sys| match (&value, follow) {
+    (Some(value), true) => sys.base_fs_set_xattr(&path, &name, value),
+    (Some(value), false) => sys.base_fs_symlink_set_xattr(&path, &name, value),
+    (None, true) => sys.base_fs_remove_xattr(&path, &name),
+    (None, false) => sys.base_fs_symlink_remove_xattr(&path, &name),
+  }))
+}
+
+// ==== Environment ====
+
+impl<T: EnvCurrentDir> EnvCurrentDir for TransactionSys<T> {
+  #[inline]
+  fn env_current_dir(&self) -> io::Result<PathBuf> {
+    self.sys.env_current_dir()
+  }
+}
+
+impl<T: BaseEnvSetCurrentDir> BaseEnvSetCurrentDir for TransactionSys<T> {
+  #[inline]
+  fn base_env_set_current_dir(&self, path: &Path) -> io::Result<()> {
+    self.sys.base_env_set_current_dir(path)
+  }
+}
+
+impl<T: BaseEnvVar> BaseEnvVar for TransactionSys<T> {
+  #[inline]
+  fn base_env_var_os(&self, key: &OsStr) -> Option<OsString> {
+    self.sys.base_env_var_os(key)
+  }
+}
+
+impl<T: EnvVars> EnvVars for TransactionSys<T> {
+  type EnvVarsOs = T::EnvVarsOs;
+
+  #[inline]
+  fn env_vars_os(&self) -> Self::EnvVarsOs {
+    self.sys.env_vars_os()
+  }
+}
+
+impl<T: BaseEnvRemoveVar> BaseEnvRemoveVar for TransactionSys<T> {
+  #[inline]
+  fn base_env_remove_var(&self, key: &OsStr) {
+    self.sys.base_env_remove_var(key)
+  }
+}
+
+impl<T: BaseEnvSetVar> BaseEnvSetVar for TransactionSys<T> {
+  #[inline]
+  fn base_env_set_var(&self, key: &OsStr, value: &OsStr) {
+    self.sys.base_env_set_var(key, value)
+  }
+}
+
+impl<T: EnvUmask> EnvUmask for TransactionSys<T> {
+  #[inline]
+  fn env_umask(&self) -> io::Result<u32> {
+    self.sys.env_umask()
+  }
+}
+
+impl<T: EnvSetUmask> EnvSetUmask for TransactionSys<T> {
+  #[inline]
+  fn env_set_umask(&self, umask: u32) -> io::Result<u32> {
+    self.sys.env_set_umask(umask)
+  }
+}
+
+impl<T: EnvCacheDir> EnvCacheDir for TransactionSys<T> {
+  #[inline]
+  fn env_cache_dir(&self) -> Option<PathBuf> {
+    self.sys.env_cache_dir()
+  }
+}
+
+impl<T: EnvHomeDir> EnvHomeDir for TransactionSys<T> {
+  #[inline]
+  fn env_home_dir(&self) -> Option<PathBuf> {
+    self.sys.env_home_dir()
+  }
+}
+
+impl<T: EnvProgramsDir> EnvProgramsDir for TransactionSys<T> {
+  #[inline]
+  fn env_programs_dir(&self) -> Option<PathBuf> {
+    self.sys.env_programs_dir()
+  }
+}
+
+impl<T: EnvTempDir> EnvTempDir for TransactionSys<T> {
+  #[inline]
+  fn env_temp_dir(&self) -> io::Result<PathBuf> {
+    self.sys.env_temp_dir()
+  }
+}
+
+// ==== File System ====
+
+impl<T: BaseFsCanonicalize> BaseFsCanonicalize for TransactionSys<T> {
+  #[inline]
+  fn base_fs_canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+    self.sys.base_fs_canonicalize(path)
+  }
+}
+
+impl<T: BaseFsChown + TransactionBackupSys> BaseFsChown for TransactionSys<T> {
+  fn base_fs_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    let metadata = self.sys.base_fs_metadata(path)?;
+    let (old_uid, old_gid) = (metadata.uid()?, metadata.gid()?);
+    self.sys.base_fs_chown(path, uid, gid)?;
+    let path = path.to_path_buf();
+    self.push_undo(Box::new(move |sys: &T| {
+      sys.base_fs_chown(&path, Some(old_uid), Some(old_gid))
+    }));
+    Ok(())
+  }
+}
+
+impl<T: BaseFsSymlinkChown + TransactionBackupSys> BaseFsSymlinkChown
+  for TransactionSys<T>
+{
+  fn base_fs_symlink_chown(
+    &self,
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+  ) -> io::Result<()> {
+    let metadata = self.sys.base_fs_symlink_metadata(path)?;
+    let (old_uid, old_gid) = (metadata.uid()?, metadata.gid()?);
+    self.sys.base_fs_symlink_chown(path, uid, gid)?;
+    let path = path.to_path_buf();
+    self.push_undo(Box::new(move |sys: &T| {
+      sys.base_fs_symlink_chown(&path, Some(old_uid), Some(old_gid))
+    }));
+    Ok(())
+  }
+}
+
+impl<T: BaseFsCloneFile + TransactionBackupSys> BaseFsCloneFile
+  for TransactionSys<T>
+{
+  fn base_fs_clone_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+    self.journal_change(to, || self.sys.base_fs_clone_file(from, to))
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsCopy for TransactionSys<T> {
+  fn base_fs_copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+    self.journal_change(to, || self.sys.base_fs_copy(from, to))
+  }
+}
+
+impl<T: BaseFsCopyWithOptions + TransactionBackupSys> BaseFsCopyWithOptions
+  for TransactionSys<T>
+{
+  fn base_fs_copy_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+  ) -> io::Result<u64> {
+    self.journal_change(to, || {
+      self.sys.base_fs_copy_with_options(from, to, options)
+    })
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsCreateDir for TransactionSys<T> {
+  fn base_fs_create_dir(
+    &self,
+    path: &Path,
+    options: &CreateDirOptions,
+  ) -> io::Result<()> {
+    // find the top-most directory that will be created
+    let mut created = None;
+    for ancestor in path.ancestors() {
+      if ancestor.as_os_str().is_empty()
+        || self.sys.base_fs_exists_no_err(ancestor)
+      {
+        break;
+      }
+      created = Some(ancestor.to_path_buf());
+      if !options.recursive {
+        break;
+      }
+    }
+    self.sys.base_fs_create_dir(path, options)?;
+    if let Some(created) = created {
+      self.push_undo(Box::new(move |sys| remove_existing(sys, &created)));
+    }
+    Ok(())
+  }
+}
+
+impl<T: BaseFsHardLink + TransactionBackupSys> BaseFsHardLink
+  for TransactionSys<T>
+{
+  fn base_fs_hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+    self.journal_change(dst, || self.sys.base_fs_hard_link(src, dst))
+  }
+}
+
+impl<T: BaseFsCreateJunction + TransactionBackupSys> BaseFsCreateJunction
+  for TransactionSys<T>
+{
+  fn base_fs_create_junction(
+    &self,
+    original: &Path,
+    junction: &Path,
+  ) -> io::Result<()> {
+    self.journal_change(junction, || {
+      self.sys.base_fs_create_junction(original, junction)
+    })
+  }
+}
+
+impl<T: BaseFsDiskSpace> BaseFsDiskSpace for TransactionSys<T> {
+  #[inline]
+  fn base_fs_disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+    self.sys.base_fs_disk_space(path)
+  }
+}
+
+impl<T: BaseFsStatVfs> BaseFsStatVfs for TransactionSys<T> {
+  #[inline]
+  fn base_fs_statvfs(&self, path: &Path) -> io::Result<StatVfs> {
+    self.sys.base_fs_statvfs(path)
+  }
+}
+
+impl<T: BaseFsMetadata> BaseFsMetadata for TransactionSys<T> {
+  type Metadata = T::Metadata;
+
+  #[inline]
+  fn base_fs_metadata(&self, path: &Path) -> io::Result<Self::Metadata> {
+    self.sys.base_fs_metadata(path)
+  }
+
+  #[inline]
+  fn base_fs_symlink_metadata(
+    &self,
+    path: &Path,
+  ) -> io::Result<Self::Metadata> {
+    self.sys.base_fs_symlink_metadata(path)
+  }
+
+  #[inline]
+  fn base_fs_exists(&self, path: &Path) -> io::Result<bool> {
+    self.sys.base_fs_exists(path)
+  }
+
+  #[inline]
+  fn base_fs_exists_no_err(&self, path: &Path) -> bool {
+    self.sys.base_fs_exists_no_err(path)
+  }
+}
+
+impl<T: BaseFsOpen + TransactionBackupSys> BaseFsOpen for TransactionSys<T> {
+  type File = T::File;
+
+  fn base_fs_open(
+    &self,
+    path: &Path,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    let may_change = options.write
+      || options.append
+      || options.truncate
+      || options.create
+      || options.create_new;
+    if may_change {
+      self.journal_change(path, || self.sys.base_fs_open(path, options))
+    } else {
+      self.sys.base_fs_open(path, options)
+    }
+  }
+}
+
+impl<
+    T: BaseFsOpenDir
+      + BaseFsRemoveDir
+      + TransactionBackupSys
+      + Clone
+      + std::fmt::Debug,
+  > BaseFsOpenDir for TransactionSys<T>
+{
+  type DirHandle = TransactionDirHandle<T>;
+
+  fn base_fs_open_dir(&self, path: &Path) -> io::Result<Self::DirHandle> {
+    // only opened to surface the same errors as the inner system
+    self.sys.base_fs_open_dir(path)?;
+    Ok(TransactionDirHandle {
+      path: path.to_path_buf(),
+      sys: TransactionSys {
+        sys: self.sys.clone(),
+        state: self.state.clone(),
+      },
+    })
+  }
+}
+
+impl<T: BaseFsRead> BaseFsRead for TransactionSys<T> {
+  #[inline]
+  fn base_fs_read(&self, path: &Path) -> io::Result<Cow<'static, [u8]>> {
+    self.sys.base_fs_read(path)
+  }
+}
+
+impl<T: BaseFsReadShared> BaseFsReadShared for TransactionSys<T> {
+  #[inline]
+  fn base_fs_read_shared(&self, path: &Path) -> io::Result<FsSharedBytes> {
+    self.sys.base_fs_read_shared(path)
+  }
+}
+
+impl<T: BaseFsReadDir> BaseFsReadDir for TransactionSys<T> {
+  type ReadDirEntry = T::ReadDirEntry;
+
+  #[inline]
+  fn base_fs_read_dir(
+    &self,
+    path: &Path,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    self.sys.base_fs_read_dir(path)
+  }
+}
+
+impl<T: BaseFsReadLink> BaseFsReadLink for TransactionSys<T> {
+  #[inline]
+  fn base_fs_read_link(&self, path: &Path) -> io::Result<PathBuf> {
+    self.sys.base_fs_read_link(path)
+  }
+}
+
+impl<T: BaseFsRemoveDir + TransactionBackupSys> BaseFsRemoveDir
+  for TransactionSys<T>
+{
+  fn base_fs_remove_dir(&self, path: &Path) -> io::Result<()> {
+    let mode = self
+      .sys
+      .base_fs_symlink_metadata(path)
+      .and_then(|m| m.mode())
+      .ok();
+    self.sys.base_fs_remove_dir(path)?;
+    let path = path.to_path_buf();
+    self.push_undo(Box::new(move |sys: &T| {
+      let mut options = CreateDirOptions::new();
+      if let Some(mode) = mode {
+        options.mode(mode);
+      }
+      sys.base_fs_create_dir(&path, &options)
+    }));
+    Ok(())
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsRemoveDirAll for TransactionSys<T> {
+  fn base_fs_remove_dir_all(&self, path: &Path) -> io::Result<()> {
+    match self.sys.base_fs_symlink_metadata(path) {
+      // symlinks are removed without following them
+      Ok(metadata)
+        if matches!(
+          metadata.file_type(),
+          FileType::Dir | FileType::Symlink
+        ) =>
+      {
+        self.journal_removal(path)
+      }
+      // let the inner system surface the appropriate error or no-op
+      _ => self.sys.base_fs_remove_dir_all(path),
+    }
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsRemoveFile for TransactionSys<T> {
+  fn base_fs_remove_file(&self, path: &Path) -> io::Result<()> {
+    match self.sys.base_fs_symlink_metadata(path) {
+      Ok(metadata) if metadata.file_type() != FileType::Dir => {
+        self.journal_removal(path)
+      }
+      // let the inner system surface the appropriate error
+      _ => self.sys.base_fs_remove_file(path),
+    }
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsRename for TransactionSys<T> {
+  fn base_fs_rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+    let ((), undo_to) =
+      self.run_prepared(to, || self.sys.base_fs_rename(from, to))?;
+    self.push_undo(undo_to);
+    let (from, to) = (from.to_path_buf(), to.to_path_buf());
+    self.push_undo(Box::new(move |sys: &T| sys.base_fs_rename(&to, &from)));
+    Ok(())
+  }
+}
+
+impl<T: BaseFsRenameWithOptions + TransactionBackupSys> BaseFsRenameWithOptions
+  for TransactionSys<T>
+{
+  fn base_fs_rename_with_options(
+    &self,
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+  ) -> io::Result<()> {
+    if !options.no_replace && !options.exchange {
+      return self.base_fs_rename(from, to);
+    }
+    self.sys.base_fs_rename_with_options(from, to, options)?;
+    let (from, to) = (from.to_path_buf(), to.to_path_buf());
+    if options.exchange {
+      // exchanging again swaps the entries back
+      self.push_undo(Box::new(move |sys: &T| {
+        sys.base_fs_rename_with_options(
+          &from,
+          &to,
+          &RenameOptions::new_exchange(),
+        )
+      }));
+    } else {
+      // nothing was replaced, so only the move needs undoing
+      self.push_undo(Box::new(move |sys: &T| sys.base_fs_rename(&to, &from)));
+    }
+    Ok(())
+  }
+}
+
+impl<T: BaseFsSetFileTimes + TransactionBackupSys> BaseFsSetFileTimes
+  for TransactionSys<T>
+{
+  fn base_fs_set_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    let metadata = self.sys.base_fs_metadata(path)?;
+    let (old_atime, old_mtime) = (metadata.accessed()?, metadata.modified()?);
+    self.sys.base_fs_set_file_times(path, atime, mtime)?;
+    let path = path.to_path_buf();
+    self.push_undo(Box::new(move |sys: &T| {
+      sys.base_fs_set_file_times(&path, old_atime, old_mtime)
+    }));
+    Ok(())
+  }
+}
+
+impl<T: BaseFsSetSymlinkFileTimes + TransactionBackupSys>
+  BaseFsSetSymlinkFileTimes for TransactionSys<T>
+{
+  fn base_fs_set_symlink_file_times(
+    &self,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+  ) -> io::Result<()> {
+    let metadata = self.sys.base_fs_symlink_metadata(path)?;
+    let (old_atime, old_mtime) = (metadata.accessed()?, metadata.modified()?);
+    self
+      .sys
+      .base_fs_set_symlink_file_times(path, atime, mtime)?;
+    let path = path.to_path_buf();
+    self.push_undo(Box::new(move |sys: &T| {
+      sys.base_fs_set_symlink_file_times(&path, old_atime, old_mtime)
+    }));
+    Ok(())
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsSetPermissions for TransactionSys<T> {
+  fn base_fs_set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+    let old_mode = self.sys.base_fs_metadata(path)?.mode()?;
+    self.sys.base_fs_set_permissions(path, mode)?;
+    let path = path.to_path_buf();
+    self.push_undo(Box::new(move |sys: &T| {
+      sys.base_fs_set_permissions(&path, old_mode)
+    }));
+    Ok(())
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsSymlinkDir for TransactionSys<T> {
+  fn base_fs_symlink_dir(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.journal_change(link, || self.sys.base_fs_symlink_dir(original, link))
+  }
+}
+
+impl<T: TransactionBackupSys> BaseFsSymlinkFile for TransactionSys<T> {
+  fn base_fs_symlink_file(
+    &self,
+    original: &Path,
+    link: &Path,
+  ) -> io::Result<()> {
+    self.journal_change(link, || self.sys.base_fs_symlink_file(original, link))
+  }
+}
+
+impl<T: BaseFsWrite + TransactionBackupSys> BaseFsWrite for TransactionSys<T> {
+  fn base_fs_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+    self.journal_change(path, || self.sys.base_fs_write(path, data))
+  }
+}
+
+impl<T: BaseFsGetXattr> BaseFsGetXattr for TransactionSys<T> {
+  #[inline]
+  fn base_fs_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.sys.base_fs_get_xattr(path, name)
+  }
+
+  #[inline]
+  fn base_fs_symlink_get_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<Option<Vec<u8>>> {
+    self.sys.base_fs_symlink_get_xattr(path, name)
+  }
+}
+
+impl<T: BaseFsListXattr> BaseFsListXattr for TransactionSys<T> {
+  #[inline]
+  fn base_fs_list_xattr(&self, path: &Path) -> io::Result<Vec<OsString>> {
+    self.sys.base_fs_list_xattr(path)
+  }
+
+  #[inline]
+  fn base_fs_symlink_list_xattr(
+    &self,
+    path: &Path,
+  ) -> io::Result<Vec<OsString>> {
+    self.sys.base_fs_symlink_list_xattr(path)
+  }
+}
+
+impl<T: BaseFsGetXattr + BaseFsRemoveXattr + BaseFsSetXattr> BaseFsRemoveXattr
+  for TransactionSys<T>
+{
+  fn base_fs_remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+    let undo = prepare_xattr_change(&self.sys, path, name, true)?;
+    self.sys.base_fs_remove_xattr(path, name)?;
+    self.push_undo(undo);
+    Ok(())
+  }
+
+  fn base_fs_symlink_remove_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+  ) -> io::Result<()> {
+    let undo = prepare_xattr_change(&self.sys, path, name, false)?;
+    self.sys.base_fs_symlink_remove_xattr(path, name)?;
+    self.push_undo(undo);
+    Ok(())
+  }
+}
+
+impl<T: BaseFsGetXattr + BaseFsRemoveXattr + BaseFsSetXattr> BaseFsSetXattr
+  for TransactionSys<T>
+{
+  fn base_fs_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    let undo = prepare_xattr_change(&self.sys, path, name, true)?;
+    self.sys.base_fs_set_xattr(path, name, value)?;
+    self.push_undo(undo);
+    Ok(())
+  }
+
+  fn base_fs_symlink_set_xattr(
+    &self,
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+  ) -> io::Result<()> {
+    let undo = prepare_xattr_change(&self.sys, path, name, false)?;
+    self.sys.base_fs_symlink_set_xattr(path, name, value)?;
+    self.push_undo(undo);
+    Ok(())
+  }
+}
+
+// ==== File System Directory Handle ====
+
+/// A directory handle returned from [`TransactionSys`] that journals
+/// changes made through it as part of the transaction.
+///
+/// Undoing changes works with paths, so operations are made relative to
+/// the path the directory was opened at instead of following the
+/// directory when it's moved.
+#[derive(Debug)]
+pub struct TransactionDirHandle<T> {
+  path: PathBuf,
+  sys: TransactionSys<T>,
+}
+
+impl<
+    T: BaseFsOpenDir
+      + BaseFsRemoveDir
+      + TransactionBackupSys
+      + Clone
+      + std::fmt::Debug,
+  > FsDirHandle for TransactionDirHandle<T>
+{
+  type File = T::File;
+  type Metadata = T::Metadata;
+  type ReadDirEntry = T::ReadDirEntry;
+
+  fn open_at(
+    &self,
+    path: impl AsRef<Path>,
+    options: &OpenOptions,
+  ) -> io::Result<Self::File> {
+    self.sys.base_fs_open(&self.path.join(path), options)
+  }
+
+  fn open_dir_at(&self, path: impl AsRef<Path>) -> io::Result<Self> {
+    self.sys.base_fs_open_dir(&self.path.join(path))
+  }
+
+  fn metadata_at(&self, path: impl AsRef<Path>) -> io::Result<Self::Metadata> {
+    self.sys.base_fs_metadata(&self.path.join(path))
+  }
+
+  fn symlink_metadata_at(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> io::Result<Self::Metadata> {
+    self.sys.base_fs_symlink_metadata(&self.path.join(path))
+  }
+
+  fn read_dir(
+    &self,
+  ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>> {
+    self.sys.base_fs_read_dir(&self.path)
+  }
+
+  fn create_dir_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    self
+      .sys
+      .base_fs_create_dir(&self.path.join(path), &CreateDirOptions::new())
+  }
+
+  fn remove_at(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = self.path.join(path);
+    if self.sys.base_fs_symlink_metadata(&path)?.file_type() == FileType::Dir {
+      self.sys.base_fs_remove_dir(&path)
+    } else {
+      self.sys.base_fs_remove_file(&path)
+    }
+  }
+
+  fn rename_at(
+    &self,
+    from: impl AsRef<Path>,
+    to_dir: &Self,
+    to: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    self
+      .sys
+      .base_fs_rename(&self.path.join(from), &to_dir.path.join(to))
+  }
+
+  fn symlink_at(
+    &self,
+    original: impl AsRef<Path>,
+    link: impl AsRef<Path>,
+  ) -> io::Result<()> {
+    self
+      .sys
+      .base_fs_symlink_file(original.as_ref(), &self.path.join(link))
+  }
+}
+
+// ==== System ====
+
+impl<T: SystemTimeNow> SystemTimeNow for TransactionSys<T> {
+  #[inline]
+  fn sys_time_now(&self) -> SystemTime {
+    self.sys.sys_time_now()
+  }
+}
+
+impl<T: SystemRandom> SystemRandom for TransactionSys<T> {
+  #[inline]
+  fn sys_random(&self, buf: &mut [u8]) -> io::Result<()> {
+    self.sys.sys_random(buf)
+  }
+}
+
+impl<T: ProcessExit> ProcessExit for TransactionSys<T> {
+  #[inline]
+  fn process_exit(&self, code: i32) -> ! {
+    self.sys.process_exit(code)
+  }
+}
+
+impl<T: ThreadSleep> ThreadSleep for TransactionSys<T> {
+  #[inline]
+  fn thread_sleep(&self, duration: std::time::Duration) {
+    self.sys.thread_sleep(duration)
+  }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+  use std::io::Write;
+  use std::sync::Arc;
+
+  use super::*;
+  use crate::impls::InMemoryDirEntry;
+  use crate::impls::InMemoryFile;
+  use crate::impls::InMemoryMetadata;
+  use crate::impls::InMemorySys;
+  use crate::FsCopy;
+  use crate::FsCreateDirAll;
+  use crate::FsMetadata;
+  use crate::FsOpen;
+  use crate::FsOpenDir;
+  use crate::FsRead;
+  use crate::FsReadDir;
+  use crate::FsReadLink;
+  use crate::FsRemoveDirAll;
+  use crate::FsRemoveFile;
+  use crate::FsRename;
+  use crate::FsRenameWithOptions;
+  use crate::FsSetPermissions;
+  use crate::FsSymlinkFile;
+  use crate::FsWrite;
+
+  type Snapshot = Vec<(PathBuf, FileType, Vec<u8>, u32, Option<SystemTime>)>;
+
+  /// Gets every entry in the file system except for the temp directory.
+  fn snapshot(sys: &InMemorySys) -> Snapshot {
+    fn visit(sys: &InMemorySys, dir: &Path, entries: &mut Snapshot) {
+      for entry in sys.fs_read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let path = entry.path().into_owned();
+        if path == Path::new("/tmp") {
+          continue;
+        }
+        let file_type = entry.file_type().unwrap();
+        let metadata = sys.fs_symlink_metadata(&path).unwrap();
+        let (data, modified) = if file_type == FileType::File {
+          let data = sys.fs_read(&path).unwrap().into_owned();
+          (data, Some(metadata.modified().unwrap()))
+        } else {
+          (Vec::new(), None)
+        };
+        let mode = metadata.mode().unwrap();
+        entries.push((path.clone(), file_type, data, mode, modified));
+        if file_type == FileType::Dir {
+          visit(sys, &path, entries);
+        }
+      }
+    }
+
+    let mut entries = Vec::new();
+    visit(sys, Path::new("/"), &mut entries);
+    entries
+  }
+
+  fn create_sys() -> InMemorySys {
+    let sys = InMemorySys::new_with_cwd("/");
+    // gives every change a distinct time
+    sys.set_time(Some(SystemTime::UNIX_EPOCH));
+    sys.set_time_step(std::time::Duration::from_secs(1));
+    sys.fs_insert("/app/config.json", "{}");
+    sys.fs_insert("/app/bin/tool", "v1");
+    sys.fs_insert("/app/lib/a.txt", "a");
+    sys.fs_insert("/app/lib/nested/b.txt", "b");
+    sys.fs_set_permissions("/app/bin/tool", 0o755).unwrap();
+    sys
+  }
+
+  /// Wraps an `InMemorySys` to inject a failure into one of the
+  /// changes made to it.
+  #[derive(Debug, Clone)]
+  struct FaultySys {
+    sys: InMemorySys,
+    /// Number of changes to allow before failing once.
+    remaining: Arc<std::sync::atomic::AtomicUsize>,
+  }
+
+  impl FaultySys {
+    fn new(sys: InMemorySys) -> Self {
+      Self {
+        sys,
+        remaining: Arc::new(usize::MAX.into()),
+      }
+    }
+
+    fn fail_after(&self, count: usize) {
+      self.remaining.store(count, Ordering::Relaxed);
+    }
+
+    fn change(&self) -> io::Result<()> {
+      let remaining = self.remaining.load(Ordering::Relaxed);
+      if remaining == 0 {
+        self.remaining.store(usize::MAX, Ordering::Relaxed);
+        Err(io::Error::new(ErrorKind::Other, "injected failure"))
+      } else {
+        if remaining != usize::MAX {
+          self.remaining.store(remaining - 1, Ordering::Relaxed);
+        }
+        Ok(())
+      }
+    }
+  }
+
+  impl BaseFsCopy for FaultySys {
+    fn base_fs_copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+      self.change()?;
+      self.sys.base_fs_copy(from, to)
+    }
+  }
+
+  impl BaseFsCreateDir for FaultySys {
+    fn base_fs_create_dir(
+      &self,
+      path: &Path,
+      options: &CreateDirOptions,
+    ) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_create_dir(path, options)
+    }
+  }
+
+  impl BaseFsMetadata for FaultySys {
+    type Metadata = InMemoryMetadata;
+
+    fn base_fs_metadata(&self, path: &Path) -> io::Result<Self::Metadata> {
+      self.sys.base_fs_metadata(path)
+    }
+
+    fn base_fs_symlink_metadata(
+      &self,
+      path: &Path,
+    ) -> io::Result<Self::Metadata> {
+      self.sys.base_fs_symlink_metadata(path)
+    }
+  }
+
+  impl BaseFsOpen for FaultySys {
+    type File = InMemoryFile;
+
+    fn base_fs_open(
+      &self,
+      path: &Path,
+      options: &OpenOptions,
+    ) -> io::Result<Self::File> {
+      if options.write || options.append {
+        self.change()?;
+      }
+      self.sys.base_fs_open(path, options)
+    }
+  }
+
+  impl BaseFsReadDir for FaultySys {
+    type ReadDirEntry = InMemoryDirEntry;
+
+    fn base_fs_read_dir(
+      &self,
+      path: &Path,
+    ) -> io::Result<Box<dyn Iterator<Item = io::Result<Self::ReadDirEntry>>>>
+    {
+      self.sys.base_fs_read_dir(path)
+    }
+  }
+
+  impl BaseFsReadLink for FaultySys {
+    fn base_fs_read_link(&self, path: &Path) -> io::Result<PathBuf> {
+      self.sys.base_fs_read_link(path)
+    }
+  }
+
+  impl BaseFsRemoveDirAll for FaultySys {
+    fn base_fs_remove_dir_all(&self, path: &Path) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_remove_dir_all(path)
+    }
+  }
+
+  impl BaseFsRemoveFile for FaultySys {
+    fn base_fs_remove_file(&self, path: &Path) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_remove_file(path)
+    }
+  }
+
+  impl BaseFsRename for FaultySys {
+    fn base_fs_rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_rename(from, to)
+    }
+  }
+
+  impl BaseFsSetPermissions for FaultySys {
+    fn base_fs_set_permissions(
+      &self,
+      path: &Path,
+      mode: u32,
+    ) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_set_permissions(path, mode)
+    }
+  }
+
+  impl BaseFsSymlinkDir for FaultySys {
+    fn base_fs_symlink_dir(
+      &self,
+      original: &Path,
+      link: &Path,
+    ) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_symlink_dir(original, link)
+    }
+  }
+
+  impl BaseFsSymlinkFile for FaultySys {
+    fn base_fs_symlink_file(
+      &self,
+      original: &Path,
+      link: &Path,
+    ) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_symlink_file(original, link)
+    }
+  }
+
+  impl BaseFsWrite for FaultySys {
+    fn base_fs_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+      self.change()?;
+      self.sys.base_fs_write(path, data)
+    }
+  }
+
+  impl EnvTempDir for FaultySys {
+    fn env_temp_dir(&self) -> io::Result<PathBuf> {
+      self.sys.env_temp_dir()
+    }
+  }
+
+  impl SystemTimeNow for FaultySys {
+    fn sys_time_now(&self) -> SystemTime {
+      self.sys.sys_time_now()
+    }
+  }
+
+  fn install(sys: &TransactionSys<FaultySys>) -> io::Result<()> {
+    sys.fs_write("/app/config.json", r#"{"v":2}"#)?;
+    sys.fs_create_dir_all("/app/share/docs")?;
+    sys.fs_write("/app/share/docs/README", "docs")?;
+    sys.fs_write("/app/bin/tool.new", "v2")?;
+    sys.fs_rename("/app/bin/tool.new", "/app/bin/tool")?;
+    sys.fs_set_permissions("/app/bin/tool", 0o700)?;
+    sys.fs_remove_dir_all("/app/lib")?;
+    sys.fs_copy("/app/config.json", "/app/config.bak")?;
+    sys.fs_symlink_file("/app/bin/tool", "/app/tool")?;
+    let mut file = sys.fs_open("/app/log.txt", &OpenOptions::new_write())?;
+    file.write_all(b"installed")?;
+    sys.fs_remove_file("/app/config.bak")
+  }
+
+  #[test]
+  fn test_rollback_after_failure() {
+    // fail each change the install makes, including the backups
+    for fail_at in 0.. {
+      let sys = create_sys();
+      let before = snapshot(&sys);
+      let faulty = FaultySys::new(sys.clone());
+      let tx = TransactionSys::new(faulty.clone());
+      faulty.fail_after(fail_at);
+      if install(&tx).is_ok() {
+        assert!(fail_at > 10);
+        break;
+      }
+      tx.rollback().unwrap();
+      assert_eq!(snapshot(&sys), before, "failed at change {}", fail_at);
+      assert_eq!(tx.journal_len(), 0);
+      // backups are cleaned up
+      assert_eq!(sys.fs_read_dir("/tmp").map(|e| e.count()).unwrap_or(0), 0);
+    }
+  }
+
+  #[test]
+  fn test_retry_failed_rollback() {
+    // fail each change the rollback makes
+    let mut failures = 0;
+    for fail_at in 0.. {
+      let sys = create_sys();
+      let before = snapshot(&sys);
+      let faulty = FaultySys::new(sys.clone());
+      let tx = TransactionSys::new(faulty.clone());
+      install(&tx).unwrap();
+      faulty.fail_after(fail_at);
+      let result = tx.rollback();
+      if faulty.remaining.load(Ordering::Relaxed) != usize::MAX {
+        // rolled back without reaching the failure
+        result.unwrap();
+        assert_eq!(snapshot(&sys), before);
+        break;
+      }
+      if result.is_err() {
+        failures += 1;
+        tx.rollback().unwrap();
+      }
+      assert_eq!(snapshot(&sys), before, "failed at change {}", fail_at);
+      assert_eq!(tx.journal_len(), 0);
+      assert_eq!(sys.fs_read_dir("/tmp").map(|e| e.count()).unwrap_or(0), 0);
+    }
+    assert!(failures > 0);
+  }
+
+  #[test]
+  fn test_rollback_restores_symlinks() {
+    let sys = create_sys();
+    sys
+      .fs_symlink_file("/app/missing", "/app/dangling")
+      .unwrap();
+    sys.fs_create_dir_all("/app/dir").unwrap();
+    sys.fs_symlink_file("/app/dir", "/app/dir_link").unwrap();
+    let before = snapshot(&sys);
+    let tx = TransactionSys::new(sys.clone());
+    // overwrites the dangling symlink rather than following it
+    tx.fs_rename("/app/config.json", "/app/dangling").unwrap();
+    tx.fs_remove_dir_all("/app/dir_link").unwrap();
+    assert!(!sys.fs_exists_no_err("/app/dir_link"));
+    assert!(sys.fs_exists_no_err("/app/dir"));
+    tx.rollback().unwrap();
+    assert_eq!(snapshot(&sys), before);
+    assert_eq!(
+      sys.fs_read_link("/app/dangling").unwrap(),
+      Path::new("/app/missing")
+    );
+  }
+
+  #[test]
+  fn test_rollback_dir_handle_changes() {
+    let sys = create_sys();
+    let before = snapshot(&sys);
+    let tx = TransactionSys::new(sys.clone());
+    let dir = tx.fs_open_dir("/app").unwrap();
+    dir.create_dir_at("new").unwrap();
+    let sub = dir.open_dir_at("new").unwrap();
+    let mut file = sub.open_at("file", &OpenOptions::new_write()).unwrap();
+    file.write_all(b"data").unwrap();
+    dir.rename_at("config.json", &sub, "config.json").unwrap();
+    sub.symlink_at("/app/bin", "link").unwrap();
+    dir.remove_at("lib/a.txt").unwrap();
+    assert_eq!(sub.read_dir().unwrap().count(), 3);
+    assert!(tx.journal_len() > 0);
+    tx.rollback().unwrap();
+    assert_eq!(snapshot(&sys), before);
+
+    let err = tx.fs_open_dir("/app/config.json").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotADirectory);
+  }
+
+  #[test]
+  fn test_commit() {
+    let sys = create_sys();
+    let tx = TransactionSys::new(FaultySys::new(sys.clone()));
+    install(&tx).unwrap();
+    tx.commit().unwrap();
+    assert_eq!(tx.journal_len(), 0);
+    assert_eq!(sys.fs_read_to_string("/app/bin/tool").unwrap(), "v2");
+    assert_eq!(sys.fs_read_to_string("/app/log.txt").unwrap(), "installed");
+    assert!(!sys.fs_exists_no_err("/app/lib"));
+    assert_eq!(sys.fs_read_dir("/tmp").map(|e| e.count()).unwrap_or(0), 0);
+
+    // rolling back after a commit does nothing
+    tx.rollback().unwrap();
+    assert_eq!(sys.fs_read_to_string("/app/bin/tool").unwrap(), "v2");
+  }
+
+  #[test]
+  fn test_failed_operation_not_journaled() {
+    let sys = create_sys();
+    let before = snapshot(&sys);
+    let tx = TransactionSys::new(sys.clone());
+    tx.fs_write("/app/new.txt", "new").unwrap();
+    // fails because the parent directory doesn't exist
+    assert!(tx.fs_write("/missing/file.txt", "data").is_err());
+    // fails because the file doesn't exist
+    assert!(tx.fs_remove_file("/app/missing.txt").is_err());
+    // fails after backing up the destination
+    assert!(tx
+      .fs_rename("/app/missing.txt", "/app/config.json")
+      .is_err());
+    assert_eq!(tx.journal_len(), 1);
+    // which doesn't leave the backup behind
+    let backup_dir = tx.state.lock().unwrap().backup_dir.clone().unwrap();
+    assert_eq!(sys.fs_read_dir(&backup_dir).unwrap().count(), 0);
+    tx.rollback().unwrap();
+    assert_eq!(snapshot(&sys), before);
+  }
+
+  #[test]
+  fn test_rollback_restores_overwritten_by_rename() {
+    let sys = create_sys();
+    let tx = TransactionSys::new(sys.clone());
+    tx.fs_rename("/app/config.json", "/app/bin/tool").unwrap();
+    assert!(!sys.fs_exists_no_err("/app/config.json"));
+    assert_eq!(sys.fs_read_to_string("/app/bin/tool").unwrap(), "{}");
+    tx.rollback().unwrap();
+    assert_eq!(sys.fs_read_to_string("/app/config.json").unwrap(), "{}");
+    assert_eq!(sys.fs_read_to_string("/app/bin/tool").unwrap(), "v1");
+    assert_eq!(
+      sys.fs_metadata("/app/bin/tool").unwrap().mode().unwrap(),
+      0o755
+    );
+  }
+
+  #[test]
+  fn test_rollback_rename_with_options() {
+    let sys = create_sys();
+    let before = snapshot(&sys);
+    let tx = TransactionSys::new(sys.clone());
+    tx.fs_rename_with_options(
+      "/app/config.json",
+      "/app/bin/tool",
+      &RenameOptions::new_exchange(),
+    )
+    .unwrap();
+    tx.fs_rename_with_options(
+      "/app/bin/tool",
+      "/app/tool",
+      &RenameOptions::new_no_replace(),
+    )
+    .unwrap();
+    assert_eq!(sys.fs_read_to_string("/app/config.json").unwrap(), "v1");
+    assert_eq!(sys.fs_read_to_string("/app/tool").unwrap(), "{}");
+    tx.rollback().unwrap();
+    assert_eq!(snapshot(&sys), before);
+  }
+}